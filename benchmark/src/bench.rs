@@ -17,11 +17,10 @@ use std::io::Read;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use futures::{Future, Sink, Stream};
+use futures::{stream, Future, Sink, Stream};
 use grpc::{
     self, ClientStreamingSink, DuplexSink, MessageReader, Method, MethodType, RequestStream,
-    RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink, ServiceBuilder, UnarySink,
-    WriteFlags,
+    RpcContext, ServerStreamingSink, ServiceBuilder, UnarySink, WriteFlags,
 };
 use grpc_proto::testing::messages::{SimpleRequest, SimpleResponse};
 use grpc_proto::testing::services_grpc::BenchmarkService;
@@ -60,34 +59,64 @@ impl BenchmarkService for Benchmark {
     fn streaming_from_client(
         &mut self,
         ctx: RpcContext,
-        _: RequestStream<SimpleRequest>,
+        stream: RequestStream<SimpleRequest>,
         sink: ClientStreamingSink<SimpleResponse>,
     ) {
-        let f = sink.fail(RpcStatus::new(RpcStatusCode::UNIMPLEMENTED, None));
+        // Respond to the last request once the client finishes sending.
+        let f = stream
+            .fold(SimpleRequest::default(), |_, req| Ok::<_, grpc::Error>(req))
+            .and_then(move |req| sink.success(gen_resp(&req)));
         let keep_running = self.keep_running.clone();
-        spawn!(ctx, keep_running, "reporting unimplemented method", f)
+        spawn!(ctx, keep_running, "streaming from client", f)
     }
 
     fn streaming_from_server(
         &mut self,
         ctx: RpcContext,
-        _: SimpleRequest,
+        req: SimpleRequest,
         sink: ServerStreamingSink<SimpleResponse>,
     ) {
-        let f = sink.fail(RpcStatus::new(RpcStatusCode::UNIMPLEMENTED, None));
+        // Keep sending until the client cancels the call.
+        let resps = stream::repeat::<_, grpc::Error>((gen_resp(&req), WriteFlags::default()));
+        let f = sink.send_all(resps).map(|_| ()).or_else(ignore_stopped);
         let keep_running = self.keep_running.clone();
-        spawn!(ctx, keep_running, "reporting unimplemented method", f)
+        spawn!(ctx, keep_running, "streaming from server", f)
     }
 
     fn streaming_both_ways(
         &mut self,
         ctx: RpcContext,
-        _: RequestStream<SimpleRequest>,
+        stream: RequestStream<SimpleRequest>,
         sink: DuplexSink<SimpleResponse>,
     ) {
-        let f = sink.fail(RpcStatus::new(RpcStatusCode::UNIMPLEMENTED, None));
+        // The first request decides the response, which is sent repeatedly
+        // while the rest requests are received, until the client finishes.
+        let f = stream
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(req, stream)| {
+                let done = Arc::new(AtomicBool::new(req.is_none()));
+                let resp = gen_resp(&req.unwrap_or_default());
+                let done1 = done.clone();
+                let recv = stream.for_each(|_| Ok(())).then(move |res| {
+                    done1.store(true, Ordering::SeqCst);
+                    res
+                });
+                let resps = stream::repeat::<_, grpc::Error>((resp, WriteFlags::default()))
+                    .take_while(move |_| Ok(!done.load(Ordering::SeqCst)));
+                let send = sink.send_all(resps).map(|_| ());
+                recv.join(send).map(|_| ()).or_else(ignore_stopped)
+            });
         let keep_running = self.keep_running.clone();
-        spawn!(ctx, keep_running, "reporting unimplemented method", f)
+        spawn!(ctx, keep_running, "streaming both ways", f)
+    }
+}
+
+/// Streams that are stopped by clients are not failures.
+fn ignore_stopped(e: grpc::Error) -> grpc::Result<()> {
+    match e {
+        grpc::Error::RemoteStopped => Ok(()),
+        e => Err(e),
     }
 }
 
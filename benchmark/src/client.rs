@@ -19,7 +19,7 @@ use std::time::{Duration, Instant};
 
 use futures::future::Loop;
 use futures::sync::oneshot::{self, Receiver, Sender};
-use futures::{future, Async, Future, Sink, Stream};
+use futures::{future, stream, Async, Future, Sink, Stream};
 use grpc::{
     CallOption, Channel, ChannelBuilder, Client as GrpcClient, EnvBuilder, Environment, WriteFlags,
 };
@@ -309,6 +309,81 @@ impl<B: Backoff + Send + 'static> RequestExecutor<B> {
         .and_then(|(e, r)| r.into_future().map(|_| e).map_err(|(e, _)| Error::from(e)));
         spawn!(client, keep_running, "streaming ping pong", f);
     }
+
+    fn execute_stream_from_client(self) {
+        let client = self.client.clone();
+        let keep_running = self.ctx.keep_running.clone();
+        let (sender, receiver) = self.client.streaming_from_client().unwrap();
+        let f = future::loop_fn((sender, self), move |(sender, mut executor)| {
+            let latency_timer = Instant::now();
+            let send = sender.send((executor.req.clone(), WriteFlags::default()));
+            send.map_err(Error::from).and_then(move |sender| {
+                executor.ctx.observe_latency(latency_timer.elapsed());
+                let time = executor.ctx.backoff_async();
+                let keep_running = executor.ctx.keep_running.clone();
+                backoff_then_loop(time, keep_running, (sender, executor))
+            })
+        })
+        .and_then(|(mut s, e)| future::poll_fn(move || s.close().map_err(Error::from)).map(|_| e))
+        .and_then(|e| receiver.map(|_| e).map_err(Error::from));
+        spawn!(client, keep_running, "streaming from client", f);
+    }
+
+    fn execute_stream_from_server(self) {
+        let client = self.client.clone();
+        let keep_running = self.ctx.keep_running.clone();
+        let receiver = self.client.streaming_from_server(&self.req).unwrap();
+        let mut latency_timer = Instant::now();
+        // The call is cancelled when the receiver is dropped.
+        let f = receiver
+            .map_err(Error::from)
+            .take_while(move |_| {
+                self.ctx.observe_latency(latency_timer.elapsed());
+                latency_timer = Instant::now();
+                Ok(self.ctx.keep_running())
+            })
+            .for_each(|_| Ok(()));
+        spawn!(client, keep_running, "streaming from server", f);
+    }
+
+    fn execute_stream_both_ways(self) {
+        let client = self.client.clone();
+        let keep_running = self.ctx.keep_running.clone();
+        let (sender, receiver) = self.client.streaming_both_ways().unwrap();
+        let keep_sending = keep_running.clone();
+        let reqs = stream::repeat::<_, grpc::Error>((self.req.clone(), WriteFlags::default()))
+            .take_while(move |_| Ok(keep_sending.load(Ordering::Relaxed)));
+        let send = sender.send_all(reqs).map(|_| ()).map_err(Error::from);
+        // Responses are measured by the intervals between them, as they are
+        // not paired with requests.
+        let mut latency_timer = Instant::now();
+        let recv = receiver.map_err(Error::from).for_each(move |_| {
+            self.ctx.observe_latency(latency_timer.elapsed());
+            latency_timer = Instant::now();
+            Ok(())
+        });
+        spawn!(client, keep_running, "streaming both ways", send.join(recv));
+    }
+}
+
+/// Wait for the backoff, then continue the loop with `state` if the
+/// benchmark is still running.
+fn backoff_then_loop<T>(
+    time: Option<Sleep>,
+    keep_running: Arc<AtomicBool>,
+    state: T,
+) -> impl Future<Item = Loop<T, T>, Error = Error> {
+    let wait = match time {
+        Some(t) => future::Either::A(t.map_err(Error::from)),
+        None => future::Either::B(future::ok(())),
+    };
+    wait.map(move |_| {
+        if keep_running.load(Ordering::Relaxed) {
+            Loop::Continue(state)
+        } else {
+            Loop::Break(state)
+        }
+    })
 }
 
 fn execute<B: Backoff + Send + 'static>(
@@ -338,7 +413,18 @@ fn execute<B: Backoff + Send + 'static>(
                     RequestExecutor::new(ctx, ch, cfg).execute_stream_ping_pong()
                 }
             }
-            _ => unimplemented!(),
+            rpc_type => {
+                if cfg.get_payload_config().has_bytebuf_params() {
+                    panic!("only streaming is supported for generic service.");
+                }
+                let executor = RequestExecutor::new(ctx, ch, cfg);
+                match rpc_type {
+                    RpcType::STREAMING_FROM_CLIENT => executor.execute_stream_from_client(),
+                    RpcType::STREAMING_FROM_SERVER => executor.execute_stream_from_server(),
+                    RpcType::STREAMING_BOTH_WAYS => executor.execute_stream_both_ways(),
+                    RpcType::UNARY | RpcType::STREAMING => unreachable!(),
+                }
+            }
         },
         _ => unimplemented!(),
     }
@@ -25,6 +25,10 @@ use crate::bench::{self, Benchmark, Generic};
 use crate::error::Result;
 use crate::util::{self, CpuRecorder};
 
+/// A pseudo channel arg for scenarios to compare request slot scaling, see
+/// `ServerBuilder::max_requests_slot_per_cq`.
+const MAX_REQUEST_SLOTS_ARG: &str = "grpcio.max_requests_slot_per_cq";
+
 pub struct Server {
     server: GrpcServer,
     recorder: CpuRecorder,
@@ -60,6 +64,10 @@ impl Server {
         if !cfg.get_channel_args().is_empty() {
             let mut ch_builder = ChannelBuilder::new(env);
             for arg in cfg.get_channel_args() {
+                if arg.get_name() == MAX_REQUEST_SLOTS_ARG {
+                    builder = builder.max_requests_slot_per_cq(arg.get_int_value() as usize);
+                    continue;
+                }
                 let key = CString::new(arg.get_name()).unwrap();
                 if arg.has_str_value() {
                     ch_builder =
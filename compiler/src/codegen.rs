@@ -33,20 +33,163 @@
 // WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
 
 use protobuf;
 use protobuf::compiler_plugin;
 use protobuf::descriptor::*;
 use protobuf::descriptorx::*;
+use protobuf::Message;
 use protobuf_codegen::code_writer::CodeWriter;
 
 use super::util::{self, fq_grpc, to_snake_case, MethodType};
 
+/// Options of the generated code.
+#[derive(Debug, Default, Clone)]
+pub struct Customize {
+    /// Generate server traits whose methods return the response, a
+    /// `::grpcio::UnaryResponse` for unary and client streaming methods and a
+    /// `::grpcio::StreamingResponse` for the others, instead of taking sinks.
+    ///
+    /// Spawning the response, completing the sink, and dropping the response
+    /// when the call is cancelled are handled by grpcio.
+    pub future_handlers: bool,
+    /// Lay out the generated modules by the packages of the protos, the
+    /// services of `foo/bar.proto` in package `pkg.v1` are generated into
+    /// `pkg/v1/bar_grpc.rs` instead of `bar_grpc.rs`.
+    ///
+    /// The messages are expected to be in the same layout, so that the ones
+    /// in other packages are referred to by relative paths, like
+    /// `super::super::super::other::msgs::Message`.
+    pub package_modules: bool,
+    /// The path of the module that contains the modules of messages, like
+    /// `crate::protos`, which replaces the relative paths starting with
+    /// `super`.
+    pub messages_root: Option<String>,
+    /// Format the generated code with rustfmt, which is found by the
+    /// `RUSTFMT` environment variable or in `PATH`, and leave out the inner
+    /// attributes that silence lints and rustfmt. The code is free of
+    /// warnings, so the files can be committed and reviewed like other
+    /// sources.
+    pub rustfmt: bool,
+}
+
+impl Customize {
+    /// Parse the parameter passed to the plugin, e.g.
+    /// `--grpc_out=future_handlers,messages_root=crate::protos:.`, options
+    /// are separated by commas.
+    pub fn parse_from_parameter(parameter: &str) -> Result<Customize, String> {
+        let mut customize = Customize::default();
+        for opt in parameter
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            match opt {
+                "future_handlers" => customize.future_handlers = true,
+                "package_modules" => customize.package_modules = true,
+                "rustfmt" => customize.rustfmt = true,
+                _ if opt.starts_with("messages_root=") => {
+                    let root = opt["messages_root=".len()..].trim_end_matches("::");
+                    if root.is_empty() {
+                        return Err("messages_root should not be empty".to_owned());
+                    }
+                    customize.messages_root = Some(root.to_owned());
+                }
+                _ => return Err(format!("unknown option: {}", opt)),
+            }
+        }
+        Ok(customize)
+    }
+}
+
+/// The files of well-known types that are shipped with rust-protobuf, and
+/// the modules that they are in.
+const WELL_KNOWN_TYPES: &[(&str, &str)] = &[
+    ("google/protobuf/any.proto", "well_known_types"),
+    ("google/protobuf/api.proto", "well_known_types"),
+    ("google/protobuf/duration.proto", "well_known_types"),
+    ("google/protobuf/empty.proto", "well_known_types"),
+    ("google/protobuf/field_mask.proto", "well_known_types"),
+    ("google/protobuf/source_context.proto", "well_known_types"),
+    ("google/protobuf/struct.proto", "well_known_types"),
+    ("google/protobuf/timestamp.proto", "well_known_types"),
+    ("google/protobuf/type.proto", "well_known_types"),
+    ("google/protobuf/wrappers.proto", "well_known_types"),
+    ("google/protobuf/descriptor.proto", "descriptor"),
+];
+
+/// Get the modules of a package when `Customize::package_modules` is set.
+fn package_modules(package: &str) -> Vec<String> {
+    package
+        .split('.')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            if protobuf::rust::is_rust_keyword(p) {
+                format!("{}_pb", p)
+            } else {
+                p.to_owned()
+            }
+        })
+        .collect()
+}
+
+/// Get the rust type of the message with the fully qualified name, which is
+/// referred to by the services generated for `file`.
+///
+/// Well-known types refer to the ones in the protobuf crate, like
+/// protobuf-codegen does, so that they are shared by all generated crates
+/// instead of being generated for every package. Other messages are
+/// generated by protobuf-codegen in the parent module by default, see
+/// [`Customize`] for the other layouts.
+fn rust_type(
+    root_scope: &RootScope<'_>,
+    proto_type: &str,
+    file: &FileDescriptorProto,
+    customize: &Customize,
+) -> String {
+    let message = root_scope.find_message(proto_type);
+    let message_file = message.get_file_descriptor();
+    if message_file.get_package() == "google.protobuf" {
+        if let Some((_, module)) = WELL_KNOWN_TYPES
+            .iter()
+            .find(|(name, _)| *name == message_file.get_name())
+        {
+            return format!("::protobuf::{}::{}", module, message.rust_name());
+        }
+    }
+
+    let mut path = vec![];
+    if let Some(ref root) = customize.messages_root {
+        path.push(root.clone());
+        if customize.package_modules {
+            path.extend(package_modules(message_file.get_package()));
+        }
+    } else if customize.package_modules && message_file.get_package() != file.get_package() {
+        path.push("super".to_owned());
+        for _ in package_modules(file.get_package()) {
+            path.push("super".to_owned());
+        }
+        path.extend(package_modules(message_file.get_package()));
+    } else {
+        path.push("super".to_owned());
+    }
+    path.push(message.rust_fq_name());
+    path.join("::")
+}
+
 struct MethodGen<'a> {
     proto: &'a MethodDescriptorProto,
     service_name: String,
     service_path: String,
+    file: &'a FileDescriptorProto,
     root_scope: &'a RootScope<'a>,
+    customize: &'a Customize,
 }
 
 impl<'a> MethodGen<'a> {
@@ -54,31 +197,35 @@ impl<'a> MethodGen<'a> {
         proto: &'a MethodDescriptorProto,
         service_name: String,
         service_path: String,
+        file: &'a FileDescriptorProto,
         root_scope: &'a RootScope<'a>,
+        customize: &'a Customize,
     ) -> MethodGen<'a> {
         MethodGen {
             proto,
             service_name,
             service_path,
+            file,
             root_scope,
+            customize,
         }
     }
 
     fn input(&self) -> String {
-        format!(
-            "super::{}",
-            self.root_scope
-                .find_message(self.proto.get_input_type())
-                .rust_fq_name()
+        rust_type(
+            self.root_scope,
+            self.proto.get_input_type(),
+            self.file,
+            self.customize,
         )
     }
 
     fn output(&self) -> String {
-        format!(
-            "super::{}",
-            self.root_scope
-                .find_message(self.proto.get_output_type())
-                .rust_fq_name()
+        rust_type(
+            self.root_scope,
+            self.proto.get_output_type(),
+            self.file,
+            self.customize,
         )
     }
 
@@ -104,6 +251,23 @@ impl<'a> MethodGen<'a> {
         to_snake_case(&self.service_name)
     }
 
+    /// Whether the method is marked by `idempotency_level`, which makes it
+    /// safe to be retried.
+    fn is_idempotent(&self) -> bool {
+        match self.proto.get_options().get_idempotency_level() {
+            MethodOptions_IdempotencyLevel::IDEMPOTENT
+            | MethodOptions_IdempotencyLevel::NO_SIDE_EFFECTS => true,
+            MethodOptions_IdempotencyLevel::IDEMPOTENCY_UNKNOWN => false,
+        }
+    }
+
+    /// Whether the method is marked by `idempotency_level = NO_SIDE_EFFECTS`,
+    /// which makes its responses safe to be cached.
+    fn is_cacheable(&self) -> bool {
+        self.proto.get_options().get_idempotency_level()
+            == MethodOptions_IdempotencyLevel::NO_SIDE_EFFECTS
+    }
+
     fn name(&self) -> String {
         to_snake_case(self.proto.get_name())
     }
@@ -188,6 +352,75 @@ impl<'a> MethodGen<'a> {
         )
     }
 
+    fn unary_with_retry(&self, method_name: &str) -> String {
+        format!(
+            "{}_with_retry(&self, req: &{}, policy: &{}) -> {}<{}>",
+            method_name,
+            self.input(),
+            fq_grpc("RetryPolicy"),
+            fq_grpc("Result"),
+            self.output()
+        )
+    }
+
+    fn unary_with_retry_opt(&self, method_name: &str) -> String {
+        format!(
+            "{}_with_retry_opt(&self, req: &{}, opt: {}, policy: &{}) -> {}<{}>",
+            method_name,
+            self.input(),
+            fq_grpc("CallOption"),
+            fq_grpc("RetryPolicy"),
+            fq_grpc("Result"),
+            self.output()
+        )
+    }
+
+    fn unary_with_retry_async(&self, method_name: &str) -> String {
+        format!(
+            "{}_with_retry_async(&self, req: &{}, policy: &{}) -> {}<{}<{}>>",
+            method_name,
+            self.input(),
+            fq_grpc("RetryPolicy"),
+            fq_grpc("Result"),
+            fq_grpc("RetryUnaryReceiver"),
+            self.output()
+        )
+    }
+
+    fn unary_with_retry_async_opt(&self, method_name: &str) -> String {
+        format!(
+            "{}_with_retry_async_opt(&self, req: &{}, opt: {}, policy: &{}) -> {}<{}<{}>>",
+            method_name,
+            self.input(),
+            fq_grpc("CallOption"),
+            fq_grpc("RetryPolicy"),
+            fq_grpc("Result"),
+            fq_grpc("RetryUnaryReceiver"),
+            self.output()
+        )
+    }
+
+    fn unary_cached(&self, method_name: &str) -> String {
+        format!(
+            "{}_cached(&self, req: &{}) -> {}<{}>",
+            method_name,
+            self.input(),
+            fq_grpc("Result"),
+            self.output()
+        )
+    }
+
+    fn unary_cached_opt(&self, method_name: &str) -> String {
+        format!(
+            "{}_cached_opt(&self, req: &{}, opt: {}) -> {}<{}>",
+            method_name,
+            self.input(),
+            fq_grpc("CallOption"),
+            fq_grpc("Result"),
+            self.output()
+        )
+    }
+
     fn client_streaming(&self, method_name: &str) -> String {
         format!(
             "{}(&self) -> {}<({}<{}>, {}<{}>)>",
@@ -298,6 +531,61 @@ impl<'a> MethodGen<'a> {
                         fq_grpc("CallOption::default()")
                     ));
                 });
+
+                if self.is_idempotent() {
+                    w.write_line("");
+                    w.pub_fn(&self.unary_with_retry_opt(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.client.unary_call_with_retry(&{}, req, opt, policy)",
+                            self.const_method_name()
+                        ));
+                    });
+                    w.write_line("");
+
+                    w.pub_fn(&self.unary_with_retry(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.{}_with_retry_opt(req, {}, policy)",
+                            method_name,
+                            fq_grpc("CallOption::default()")
+                        ));
+                    });
+                    w.write_line("");
+
+                    w.pub_fn(&self.unary_with_retry_async_opt(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.client.unary_call_with_retry_async(&{}, req, opt, policy)",
+                            self.const_method_name()
+                        ));
+                    });
+                    w.write_line("");
+
+                    w.pub_fn(&self.unary_with_retry_async(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.{}_with_retry_async_opt(req, {}, policy)",
+                            method_name,
+                            fq_grpc("CallOption::default()")
+                        ));
+                    });
+                }
+
+                if self.is_cacheable() {
+                    w.write_line("");
+                    w.pub_fn(&self.unary_cached_opt(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.client.cacheable_unary_call(&{}, req, opt)",
+                            self.const_method_name()
+                        ));
+                    });
+                    w.write_line("");
+
+                    w.pub_fn(&self.unary_cached(&method_name), |w| {
+                        w.write_line(format!(
+                            "self.{}_cached_opt(req, {})",
+                            method_name,
+                            fq_grpc("CallOption::default()")
+                        ));
+                    });
+                }
             }
 
             // Client streaming
@@ -361,6 +649,25 @@ impl<'a> MethodGen<'a> {
 
     fn write_service(&self, w: &mut CodeWriter) {
         let req_stream_type = format!("{}<{}>", fq_grpc("RequestStream"), self.input());
+        if self.customize.future_handlers {
+            let (req, req_type, resp_type) = match self.method_type().0 {
+                MethodType::Unary => ("req", self.input(), "UnaryResponse"),
+                MethodType::ClientStreaming => ("stream", req_stream_type, "UnaryResponse"),
+                MethodType::ServerStreaming => ("req", self.input(), "StreamingResponse"),
+                MethodType::Duplex => ("stream", req_stream_type, "StreamingResponse"),
+            };
+            let sig = format!(
+                "{}(&mut self, ctx: &{}, {}: {}) -> {}<{}>",
+                self.name(),
+                fq_grpc("RpcContext"),
+                req,
+                req_type,
+                fq_grpc(resp_type),
+                self.output()
+            );
+            w.fn_def(&sig);
+            return;
+        }
         let (req, req_type, resp_type) = match self.method_type().0 {
             MethodType::Unary => ("req", self.input(), "UnarySink"),
             MethodType::ClientStreaming => ("stream", req_stream_type, "ClientStreamingSink"),
@@ -386,6 +693,21 @@ impl<'a> MethodGen<'a> {
             MethodType::ServerStreaming => "add_server_streaming_handler",
             MethodType::Duplex => "add_duplex_streaming_handler",
         };
+        if self.customize.future_handlers {
+            let add = add.replace("_handler", "_future_handler");
+            w.block(
+                &format!(
+                    "builder = builder.{}(&{}, move |ctx, req| {{",
+                    add,
+                    self.const_method_name()
+                ),
+                "});",
+                |w| {
+                    w.write_line(format!("instance.{}(ctx, req)", self.name()));
+                },
+            );
+            return;
+        }
         w.block(
             &format!(
                 "builder = builder.{}(&{}, move |ctx, req, resp| {{",
@@ -408,8 +730,9 @@ struct ServiceGen<'a> {
 impl<'a> ServiceGen<'a> {
     fn new(
         proto: &'a ServiceDescriptorProto,
-        file: &FileDescriptorProto,
+        file: &'a FileDescriptorProto,
         root_scope: &'a RootScope,
+        customize: &'a Customize,
     ) -> ServiceGen<'a> {
         let service_path = if file.get_package().is_empty() {
             format!("/{}", proto.get_name())
@@ -424,7 +747,9 @@ impl<'a> ServiceGen<'a> {
                     m,
                     util::to_camel_case(proto.get_name()),
                     service_path.clone(),
+                    file,
                     root_scope,
+                    customize,
                 )
             })
             .collect();
@@ -455,6 +780,45 @@ impl<'a> ServiceGen<'a> {
                 });
             });
 
+            w.write_line("");
+            w.pub_fn(
+                "set_default_timeout(&mut self, timeout: ::std::time::Duration)",
+                |w| {
+                    w.write_line("self.client.set_default_timeout(timeout)");
+                },
+            );
+
+            w.write_line("");
+            w.pub_fn(
+                "set_method_timeout(&mut self, path: &str, timeout: ::std::time::Duration)",
+                |w| {
+                    w.write_line("self.client.set_method_timeout(path, timeout)");
+                },
+            );
+
+            w.write_line("");
+            w.pub_fn(
+                &format!(
+                    "with_default_metadata(&self, headers: {}) -> Self",
+                    fq_grpc("Metadata")
+                ),
+                |w| {
+                    w.expr_block(&self.client_name(), |w| {
+                        w.field_entry("client", "self.client.with_default_metadata(headers)");
+                    });
+                },
+            );
+
+            w.write_line("");
+            w.pub_fn(
+                "with_default_timeout(&self, timeout: ::std::time::Duration) -> Self",
+                |w| {
+                    w.expr_block(&self.client_name(), |w| {
+                        w.field_entry("client", "self.client.with_default_timeout(timeout)");
+                    });
+                },
+            );
+
             for method in &self.methods {
                 w.write_line("");
                 method.write_client(w);
@@ -487,6 +851,9 @@ impl<'a> ServiceGen<'a> {
         );
         w.pub_fn(&s, |w| {
             w.write_line("let mut builder = ::grpcio::ServiceBuilder::new();");
+            w.block("for fd in FILE_DESCRIPTORS {", "}", |w| {
+                w.write_line("builder = builder.add_file_descriptor(fd);");
+            });
             for method in &self.methods[0..self.methods.len() - 1] {
                 w.write_line("let mut instance = s.clone();");
                 method.write_bind(w);
@@ -518,36 +885,141 @@ impl<'a> ServiceGen<'a> {
     }
 }
 
+/// Escape the bytes as the content of a byte string literal.
+fn escape_bytes(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        match *b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b' '..=b'~' => s.push(*b as char),
+            _ => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s
+}
+
+/// Write the serialized descriptors of the file and all its dependencies,
+/// dependencies first.
+fn write_file_descriptors(file: &FileDescriptorProto, root_scope: &RootScope, w: &mut CodeWriter) {
+    let files_map: HashMap<&str, &FileDescriptorProto> = root_scope
+        .file_descriptors
+        .iter()
+        .map(|f| (f.get_name(), f))
+        .collect();
+    let mut files = Vec::new();
+    let mut visit = vec![(file, false)];
+    while let Some((f, deps_visited)) = visit.pop() {
+        if files
+            .iter()
+            .any(|v: &&FileDescriptorProto| v.get_name() == f.get_name())
+        {
+            continue;
+        }
+        if deps_visited {
+            files.push(f);
+            continue;
+        }
+        visit.push((f, true));
+        for dep in f.get_dependency().iter().rev() {
+            if let Some(d) = files_map.get(dep.as_str()) {
+                visit.push((d, false));
+            }
+        }
+    }
+
+    w.block("const FILE_DESCRIPTORS: &[&[u8]] = &[", "];", |w| {
+        for f in &files {
+            let bytes = f.write_to_bytes().unwrap();
+            w.write_line(format!("b\"{}\",", escape_bytes(&bytes)));
+        }
+    });
+}
+
+/// Format the code with rustfmt.
+///
+/// # Panics
+///
+/// Panics if rustfmt can't be run or fails, there is no way to report errors
+/// to protoc.
+fn rustfmt(code: &[u8]) -> Vec<u8> {
+    let rustfmt = env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(&rustfmt)
+        .args(&["--edition", "2018"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("failed to run {:?}: {}", rustfmt, e));
+    // rustfmt reads all the input before writing anything.
+    child.stdin.take().unwrap().write_all(code).unwrap();
+    let output = child.wait_with_output().unwrap();
+    if !output.status.success() {
+        panic!(
+            "rustfmt failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    output.stdout
+}
+
 fn gen_file(
     file: &FileDescriptorProto,
     root_scope: &RootScope,
+    customize: &Customize,
 ) -> Option<compiler_plugin::GenResult> {
     if file.get_service().is_empty() {
         return None;
     }
 
-    let base = protobuf::descriptorx::proto_path_to_rust_mod(file.get_name());
+    let mut name = String::new();
+    if customize.package_modules {
+        for m in package_modules(file.get_package()) {
+            name.push_str(&m);
+            name.push('/');
+        }
+    }
+    name.push_str(&protobuf::descriptorx::proto_path_to_rust_mod(
+        file.get_name(),
+    ));
+    name.push_str("_grpc.rs");
 
     let mut v = Vec::new();
     {
         let mut w = CodeWriter::new(&mut v);
-        w.write_generated();
+        if customize.rustfmt {
+            w.write_line("// This file is generated. Do not edit");
+            w.write_line("// @generated");
+        } else {
+            w.write_generated();
+        }
+        w.write_line("");
+        write_file_descriptors(file, root_scope, &mut w);
 
         for service in file.get_service() {
             w.write_line("");
-            ServiceGen::new(service, file, root_scope).write(&mut w);
+            ServiceGen::new(service, file, root_scope, customize).write(&mut w);
         }
     }
 
-    Some(compiler_plugin::GenResult {
-        name: base + "_grpc.rs",
-        content: v,
-    })
+    if customize.rustfmt {
+        v = rustfmt(&v);
+    }
+    Some(compiler_plugin::GenResult { name, content: v })
 }
 
 pub fn gen(
     file_descriptors: &[FileDescriptorProto],
     files_to_generate: &[String],
+) -> Vec<compiler_plugin::GenResult> {
+    gen_with_customize(file_descriptors, files_to_generate, Customize::default())
+}
+
+/// Generate the sources with the given options, see [`Customize`].
+pub fn gen_with_customize(
+    file_descriptors: &[FileDescriptorProto],
+    files_to_generate: &[String],
+    customize: Customize,
 ) -> Vec<compiler_plugin::GenResult> {
     let files_map: HashMap<&str, &FileDescriptorProto> =
         file_descriptors.iter().map(|f| (f.get_name(), f)).collect();
@@ -563,12 +1035,292 @@ pub fn gen(
             continue;
         }
 
-        results.extend(gen_file(file, &root_scope).into_iter());
+        results.extend(gen_file(file, &root_scope, &customize));
     }
 
     results
 }
 
+/// Get the protoc to run, `PROTOC` takes precedence over the one in `PATH`.
+fn protoc() -> OsString {
+    env::var_os("PROTOC").unwrap_or_else(|| "protoc".into())
+}
+
+/// Run protoc to parse the files, the files they import are included if
+/// `include_imports` is true.
+fn descriptor_set<P: AsRef<Path>>(
+    protos: &[P],
+    includes: &[P],
+    include_imports: bool,
+) -> io::Result<FileDescriptorSet> {
+    let tmp = tempfile::Builder::new()
+        .prefix("grpcio-compiler")
+        .tempdir()?;
+    let path = tmp.path().join("descriptor-set");
+
+    let protoc = protoc();
+    let mut cmd = Command::new(&protoc);
+    cmd.arg("-o").arg(&path);
+    if include_imports {
+        cmd.arg("--include_imports");
+    }
+    for include in includes {
+        cmd.arg("-I").arg(include.as_ref());
+    }
+    if let Some(include) = env::var_os("PROTOC_INCLUDE") {
+        cmd.arg("-I").arg(include);
+    }
+    for proto in protos {
+        cmd.arg(proto.as_ref());
+    }
+
+    let output = cmd.output().map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "failed to run {:?}: {}, install protoc or set PROTOC to its path",
+                protoc, e
+            ),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "protoc failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let mut buf = Vec::new();
+    fs::File::open(path)?.read_to_end(&mut buf)?;
+    let mut set = FileDescriptorSet::new();
+    set.merge_from_bytes(&buf)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(set)
+}
+
+/// Generate the messages and services of the protos into `out_dir`, which
+/// is meant to be called in build scripts.
+///
+/// protoc is only used to parse the files, and the code is generated in
+/// process, so neither protoc-gen-rust nor grpc_rust_plugin is required.
+/// protoc is found by the `PROTOC` environment variable or in `PATH`, and
+/// `PROTOC_INCLUDE` can be set to the directory of the protos shipped with
+/// protoc if they are not installed with it.
+///
+/// For every proto, a module of the messages and a module of the services
+/// suffixed by `_grpc` are generated, the names of the modules are returned.
+/// They refer to each other by `super`, so they should be declared in the
+/// same module. Well-known types are not generated, the ones in the protobuf
+/// crate are used instead.
+///
+/// ```ignore
+/// // build.rs
+/// let out_dir = std::env::var("OUT_DIR").unwrap();
+/// grpcio_compiler::codegen::compile_protos(&["proto/svc.proto"], &["proto"], &out_dir)
+///     .unwrap();
+/// ```
+pub fn compile_protos<P>(protos: &[P], includes: &[P], out_dir: &str) -> io::Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    // The names of the files to generate are the ones recorded by protoc,
+    // which are relative to the include paths.
+    let files_to_generate: Vec<String> = descriptor_set(protos, includes, false)?
+        .take_file()
+        .into_iter()
+        .map(|mut f| f.take_name())
+        .collect();
+    let file_descriptors = descriptor_set(protos, includes, true)?.take_file();
+
+    let mut results = protobuf_codegen::gen(
+        &file_descriptors,
+        &files_to_generate,
+        &protobuf_codegen::Customize::default(),
+    );
+    results.extend(gen(&file_descriptors, &files_to_generate));
+
+    let mut modules = Vec::with_capacity(results.len());
+    for res in results {
+        fs::write(Path::new(out_dir).join(&res.name), &res.content)?;
+        modules.push(res.name.trim_end_matches(".rs").to_owned());
+    }
+    Ok(modules)
+}
+
+/// The entry of the protoc plugin.
+///
+/// The plugin declares the support of proto3 `optional` fields, which only
+/// affect messages, so protoc accepts the files that use them.
 pub fn protoc_gen_grpc_rust_main() {
-    compiler_plugin::plugin_main(gen);
+    compiler_plugin::plugin_main_2(|req| {
+        let customize = Customize::parse_from_parameter(req.parameter).unwrap();
+        gen_with_customize(req.file_descriptors, req.files_to_generate, customize)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, package: &str, messages: &[&str]) -> FileDescriptorProto {
+        let mut file = FileDescriptorProto::new();
+        file.set_name(name.to_owned());
+        file.set_package(package.to_owned());
+        for m in messages {
+            let mut message = DescriptorProto::new();
+            message.set_name(m.to_string());
+            let mut nested = DescriptorProto::new();
+            nested.set_name("Nested".to_owned());
+            message.mut_nested_type().push(nested);
+            file.mut_message_type().push(message);
+        }
+        file
+    }
+
+    #[test]
+    fn test_rust_type() {
+        let files = vec![
+            file("google/protobuf/empty.proto", "google.protobuf", &["Empty"]),
+            file(
+                "google/protobuf/timestamp.proto",
+                "google.protobuf",
+                &["Timestamp"],
+            ),
+            file(
+                "google/protobuf/descriptor.proto",
+                "google.protobuf",
+                &["FileDescriptorProto"],
+            ),
+            // Not a well-known type though it's in the same package.
+            file(
+                "google/protobuf/custom.proto",
+                "google.protobuf",
+                &["Custom"],
+            ),
+            file("svc/users.proto", "svc", &["User"]),
+        ];
+        let root_scope = RootScope {
+            file_descriptors: &files,
+        };
+        let cases = vec![
+            (
+                ".google.protobuf.Empty",
+                "::protobuf::well_known_types::Empty",
+            ),
+            (
+                ".google.protobuf.Timestamp",
+                "::protobuf::well_known_types::Timestamp",
+            ),
+            (
+                ".google.protobuf.FileDescriptorProto",
+                "::protobuf::descriptor::FileDescriptorProto",
+            ),
+            (".google.protobuf.Custom", "super::custom::Custom"),
+            (".svc.User", "super::users::User"),
+            (".svc.User.Nested", "super::users::User_Nested"),
+        ];
+        let customize = Customize::default();
+        for (proto_type, exp) in cases {
+            let res = rust_type(&root_scope, proto_type, &files[4], &customize);
+            assert_eq!(res, exp);
+        }
+    }
+
+    #[test]
+    fn test_customize() {
+        assert!(Customize::parse_from_parameter("unknown").is_err());
+        assert!(Customize::parse_from_parameter("messages_root=").is_err());
+        let customize =
+            Customize::parse_from_parameter("package_modules, messages_root=crate::protos::")
+                .unwrap();
+        assert!(customize.package_modules);
+        assert_eq!(customize.messages_root.as_deref(), Some("crate::protos"));
+        assert!(!customize.future_handlers && !customize.rustfmt);
+
+        let files = vec![
+            file("svc/v1/api.proto", "svc.v1", &["Req"]),
+            file("common/types.proto", "common", &["Page"]),
+            file("empty.proto", "", &["Empty"]),
+            file("google/protobuf/empty.proto", "google.protobuf", &["Empty"]),
+        ];
+        let root_scope = RootScope {
+            file_descriptors: &files,
+        };
+        let layouts = vec![
+            (
+                Customize::default(),
+                [
+                    "super::api::Req",
+                    "super::types::Page",
+                    "super::empty::Empty",
+                ],
+            ),
+            (
+                Customize {
+                    package_modules: true,
+                    ..Customize::default()
+                },
+                [
+                    "super::api::Req",
+                    "super::super::super::common::types::Page",
+                    "super::super::super::empty::Empty",
+                ],
+            ),
+            (
+                Customize {
+                    messages_root: Some("crate::protos".to_owned()),
+                    ..Customize::default()
+                },
+                [
+                    "crate::protos::api::Req",
+                    "crate::protos::types::Page",
+                    "crate::protos::empty::Empty",
+                ],
+            ),
+            (
+                customize,
+                [
+                    "crate::protos::svc::v1::api::Req",
+                    "crate::protos::common::types::Page",
+                    "crate::protos::empty::Empty",
+                ],
+            ),
+        ];
+        for (customize, exp) in layouts {
+            for (proto_type, exp) in [".svc.v1.Req", ".common.Page", ".Empty"].iter().zip(&exp) {
+                let res = rust_type(&root_scope, proto_type, &files[0], &customize);
+                assert_eq!(res, *exp, "{:?}", customize);
+            }
+            let res = rust_type(&root_scope, ".google.protobuf.Empty", &files[0], &customize);
+            assert_eq!(res, "::protobuf::well_known_types::Empty");
+        }
+
+        let mut service = ServiceDescriptorProto::new();
+        service.set_name("Api".to_owned());
+        let mut method = MethodDescriptorProto::new();
+        method.set_name("Get".to_owned());
+        method.set_input_type(".svc.v1.Req".to_owned());
+        method.set_output_type(".common.Page".to_owned());
+        service.mut_method().push(method);
+        let mut files = files;
+        files[0].mut_service().push(service);
+        let root_scope = RootScope {
+            file_descriptors: &files,
+        };
+        let res = gen_file(&files[0], &root_scope, &Customize::default()).unwrap();
+        assert_eq!(res.name, "api_grpc.rs");
+        let customize = Customize {
+            package_modules: true,
+            ..Customize::default()
+        };
+        let res = gen_file(&files[0], &root_scope, &customize).unwrap();
+        assert_eq!(res.name, "svc/v1/api_grpc.rs");
+        let content = String::from_utf8(res.content).unwrap();
+        assert!(content.contains("#![allow(clippy::all)]"), "{}", content);
+        assert!(
+            content.contains("super::super::super::common::types::Page"),
+            "{}",
+            content
+        );
+    }
 }
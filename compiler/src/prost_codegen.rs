@@ -15,6 +15,7 @@ use super::util::{fq_grpc, to_snake_case, MethodType};
 use derive_new::new;
 use prost::Message;
 use prost_build::{protoc, protoc_include, Config, Method, Service, ServiceGenerator};
+use prost_types::method_options::IdempotencyLevel;
 use prost_types::FileDescriptorSet;
 use std::io::{Error, ErrorKind, Read};
 use std::path::Path;
@@ -179,6 +180,8 @@ fn generate_client(service: &Service, buf: &mut String) {
     buf.push_str(&client_name);
     buf.push_str(" {\n");
     generate_ctor(&client_name, buf);
+    generate_timeout_setters(buf);
+    generate_derived_clients(&client_name, buf);
     generate_client_methods(service, buf);
     generate_spawn(buf);
     buf.push_str("}\n")
@@ -191,6 +194,33 @@ fn generate_ctor(client_name: &str, buf: &mut String) {
     buf.push_str("}\n");
 }
 
+fn generate_timeout_setters(buf: &mut String) {
+    buf.push_str(
+        "pub fn set_default_timeout(&mut self, timeout: ::std::time::Duration) {\
+         self.client.set_default_timeout(timeout)\
+         }\n",
+    );
+    buf.push_str(
+        "pub fn set_method_timeout(&mut self, path: &str, timeout: ::std::time::Duration) {\
+         self.client.set_method_timeout(path, timeout)\
+         }\n",
+    );
+}
+
+fn generate_derived_clients(client_name: &str, buf: &mut String) {
+    buf.push_str(&format!(
+        "pub fn with_default_metadata(&self, headers: {}) -> Self {{ \
+         {} {{ client: self.client.with_default_metadata(headers) }} }}\n",
+        fq_grpc("Metadata"),
+        client_name
+    ));
+    buf.push_str(&format!(
+        "pub fn with_default_timeout(&self, timeout: ::std::time::Duration) -> Self {{ \
+         {} {{ client: self.client.with_default_timeout(timeout) }} }}\n",
+        client_name
+    ));
+}
+
 fn generate_client_methods(service: &Service, buf: &mut String) {
     for method in &service.methods {
         generate_client_method(&service.name, method, buf);
@@ -253,6 +283,12 @@ fn generate_client_method(service_name: &str, method: &Method, buf: &mut String)
                 name,
             )
             .generate(buf);
+            if is_idempotent(method) {
+                generate_retry_methods(name, method, buf);
+            }
+            if method.options.idempotency_level() == IdempotencyLevel::NoSideEffects {
+                generate_cached_methods(name, method, buf);
+            }
         }
         MethodType::ClientStreaming => {
             ClientMethod::new(
@@ -445,6 +481,84 @@ impl<'a> ClientMethod<'a> {
     }
 }
 
+/// Whether the method is marked by `idempotency_level`, which makes it safe
+/// to be retried.
+fn is_idempotent(method: &Method) -> bool {
+    method.options.idempotency_level() != IdempotencyLevel::IdempotencyUnknown
+}
+
+fn generate_retry_methods(data_name: &str, method: &Method, buf: &mut String) {
+    let result = format!("{}<{}>", fq_grpc("Result"), method.output_type);
+    buf.push_str(&format!(
+        "pub fn {}_with_retry_opt(&self, req: &{}, opt: {}, policy: &{}) -> {} {{ \
+         self.client.unary_call_with_retry(&{}, req, opt, policy) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("CallOption"),
+        fq_grpc("RetryPolicy"),
+        result,
+        data_name,
+    ));
+    buf.push_str(&format!(
+        "pub fn {}_with_retry(&self, req: &{}, policy: &{}) -> {} {{ \
+         self.{}_with_retry_opt(req, {}, policy) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("RetryPolicy"),
+        result,
+        method.name,
+        fq_grpc("CallOption::default()"),
+    ));
+    let receiver = format!(
+        "{}<{}<{}>>",
+        fq_grpc("Result"),
+        fq_grpc("RetryUnaryReceiver"),
+        method.output_type
+    );
+    buf.push_str(&format!(
+        "pub fn {}_with_retry_async_opt(&self, req: &{}, opt: {}, policy: &{}) -> {} {{ \
+         self.client.unary_call_with_retry_async(&{}, req, opt, policy) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("CallOption"),
+        fq_grpc("RetryPolicy"),
+        receiver,
+        data_name,
+    ));
+    buf.push_str(&format!(
+        "pub fn {}_with_retry_async(&self, req: &{}, policy: &{}) -> {} {{ \
+         self.{}_with_retry_async_opt(req, {}, policy) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("RetryPolicy"),
+        receiver,
+        method.name,
+        fq_grpc("CallOption::default()"),
+    ));
+}
+
+fn generate_cached_methods(data_name: &str, method: &Method, buf: &mut String) {
+    let result = format!("{}<{}>", fq_grpc("Result"), method.output_type);
+    buf.push_str(&format!(
+        "pub fn {}_cached_opt(&self, req: &{}, opt: {}) -> {} {{ \
+         self.client.cacheable_unary_call(&{}, req, opt) }}\n",
+        method.name,
+        method.input_type,
+        fq_grpc("CallOption"),
+        result,
+        data_name,
+    ));
+    buf.push_str(&format!(
+        "pub fn {}_cached(&self, req: &{}) -> {} {{ \
+         self.{}_cached_opt(req, {}) }}\n",
+        method.name,
+        method.input_type,
+        result,
+        method.name,
+        fq_grpc("CallOption::default()"),
+    ));
+}
+
 fn generate_spawn(buf: &mut String) {
     buf.push_str(
         "pub fn spawn<F>(&self, f: F) \
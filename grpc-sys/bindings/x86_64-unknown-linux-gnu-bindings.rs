@@ -5946,9 +5946,101 @@ extern "C" {
         value: ::std::os::raw::c_int,
     );
 }
+extern "C" {
+    pub fn grpcwrap_channel_args_set_pointer_vtable(
+        args: *mut grpc_channel_args,
+        index: usize,
+        key: *const ::std::os::raw::c_char,
+        value: *mut ::std::os::raw::c_void,
+        vtable: *const grpc_arg_pointer_vtable,
+    );
+}
 extern "C" {
     pub fn grpcwrap_channel_args_destroy(args: *mut grpc_channel_args);
 }
+extern "C" {
+    pub fn grpcwrap_channel_args_merge(
+        base: *const grpc_channel_args,
+        overrides: *const grpc_channel_args,
+    ) -> *mut grpc_channel_args;
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct grpcwrap_socket_options {
+    pub tcp_user_timeout_ms: ::std::os::raw::c_int,
+    pub tcp_nodelay: ::std::os::raw::c_int,
+    pub recv_buffer_size: ::std::os::raw::c_int,
+    pub send_buffer_size: ::std::os::raw::c_int,
+}
+#[test]
+fn bindgen_test_layout_grpcwrap_socket_options() {
+    assert_eq!(
+        ::std::mem::size_of::<grpcwrap_socket_options>(),
+        16usize,
+        concat!("Size of: ", stringify!(grpcwrap_socket_options))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<grpcwrap_socket_options>(),
+        4usize,
+        concat!("Alignment of ", stringify!(grpcwrap_socket_options))
+    );
+    assert_eq!(
+        unsafe {
+            &(*(::std::ptr::null::<grpcwrap_socket_options>())).tcp_user_timeout_ms as *const _ as usize
+        },
+        0usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(grpcwrap_socket_options),
+            "::",
+            stringify!(tcp_user_timeout_ms)
+        )
+    );
+    assert_eq!(
+        unsafe {
+            &(*(::std::ptr::null::<grpcwrap_socket_options>())).tcp_nodelay as *const _ as usize
+        },
+        4usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(grpcwrap_socket_options),
+            "::",
+            stringify!(tcp_nodelay)
+        )
+    );
+    assert_eq!(
+        unsafe {
+            &(*(::std::ptr::null::<grpcwrap_socket_options>())).recv_buffer_size as *const _ as usize
+        },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(grpcwrap_socket_options),
+            "::",
+            stringify!(recv_buffer_size)
+        )
+    );
+    assert_eq!(
+        unsafe {
+            &(*(::std::ptr::null::<grpcwrap_socket_options>())).send_buffer_size as *const _ as usize
+        },
+        12usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(grpcwrap_socket_options),
+            "::",
+            stringify!(send_buffer_size)
+        )
+    );
+}
+extern "C" {
+    pub fn grpcwrap_channel_args_set_socket_options(
+        args: *mut grpc_channel_args,
+        index: usize,
+        key: *const ::std::os::raw::c_char,
+        options: *const grpcwrap_socket_options,
+    );
+}
 extern "C" {
     pub fn grpcwrap_call_start_unary(
         call: *mut grpc_call,
@@ -6062,6 +6154,18 @@ extern "C" {
         tag: *mut ::std::os::raw::c_void,
     ) -> grpc_call_error;
 }
+extern "C" {
+    #[doc = " Run the fork handlers of gRPC Core, they do nothing unless fork support is"]
+    #[doc = "enabled by the environment variable GRPC_ENABLE_FORK_SUPPORT. No thread"]
+    #[doc = "should be calling into gRPC when grpcwrap_prefork is called."]
+    pub fn grpcwrap_prefork();
+}
+extern "C" {
+    pub fn grpcwrap_postfork_parent();
+}
+extern "C" {
+    pub fn grpcwrap_postfork_child();
+}
 extern "C" {
     pub fn grpcwrap_server_request_call(
         server: *mut grpc_server,
@@ -6089,6 +6193,15 @@ extern "C" {
         force_client_auth: ::std::os::raw::c_int,
     ) -> *mut grpc_server_credentials;
 }
+extern "C" {
+    pub fn grpcwrap_ssl_server_credentials_create_ex(
+        pem_root_certs: *const ::std::os::raw::c_char,
+        key_cert_pair_cert_chain_array: *mut *const ::std::os::raw::c_char,
+        key_cert_pair_private_key_array: *mut *const ::std::os::raw::c_char,
+        num_key_cert_pairs: usize,
+        client_certificate_request: grpc_ssl_client_certificate_request_type,
+    ) -> *mut grpc_server_credentials;
+}
 extern "C" {
     pub fn grpcwrap_sanity_check_slice(size: usize, align: usize);
 }
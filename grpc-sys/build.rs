@@ -265,6 +265,7 @@ fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf) {
         .header("grpc_wrap.cc")
         .clang_arg("-xc++")
         .clang_arg("-I./grpc/include")
+        .clang_arg("-I./grpc")
         .clang_arg("-std=c++11")
         .whitelist_recursively(false)
         .whitelist_function(r"\bgrpc_.*")
@@ -276,6 +277,7 @@ fn bindgen_grpc(mut config: bindgen::Builder, file_path: &PathBuf) {
         .whitelist_type(r"\bgrpcwrap_.*")
         .whitelist_type(r"\bcensus_context.*")
         .whitelist_type(r"\bverify_peer_options.*")
+        .blacklist_function(r"\bgrpc_socket_mutator_.*")
         .blacklist_function(r"\bgpr_mu_.*")
         .blacklist_function(r"\bgpr_cv_.*")
         .blacklist_function(r"\bgpr_once_.*")
@@ -355,6 +357,9 @@ fn main() {
     if !cfg!(target_env = "msvc") {
         cc.flag("-std=c++11");
     }
+    // Private headers of gRPC Core, like the socket mutator, are included
+    // from the vendored source.
+    cc.include("grpc");
     cc.file("grpc_wrap.cc");
 
     cc.warnings_into_errors(true);
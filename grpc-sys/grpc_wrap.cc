@@ -55,14 +55,24 @@
 #include <grpc/support/log.h>
 #include <grpc/support/port_platform.h>
 #include <grpc/support/string_util.h>
+#include <grpc/support/sync.h>
 #include <grpc/support/thd_id.h>
 
+// Not a public header, it's found in the vendored source of gRPC Core.
+#include "src/core/lib/iomgr/socket_mutator.h"
+
 #ifdef GRPC_SYS_SECURE
 #include <grpc/grpc_security.h>
 #endif
 
 #include <string.h>
 
+#ifndef GPR_WINDOWS
+#include <netinet/in.h>
+#include <netinet/tcp.h>
+#include <sys/socket.h>
+#endif
+
 #ifdef GPR_WINDOWS
 #define GPR_EXPORT extern "C" __declspec(dllexport)
 #define GPR_CALLTYPE __cdecl
@@ -450,6 +460,17 @@ GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_integer(
   args->args[index].value.integer = value;
 }
 
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_pointer_vtable(
+    grpc_channel_args* args, size_t index, const char* key, void* value,
+    const grpc_arg_pointer_vtable* vtable) {
+  GPR_ASSERT(args);
+  GPR_ASSERT(index < args->num_args);
+  args->args[index].type = GRPC_ARG_POINTER;
+  args->args[index].key = gpr_strdup(key);
+  args->args[index].value.pointer.p = vtable->copy(value);
+  args->args[index].value.pointer.vtable = vtable;
+}
+
 GPR_EXPORT void GPR_CALLTYPE
 grpcwrap_channel_args_destroy(grpc_channel_args* args) {
   size_t i;
@@ -458,6 +479,9 @@ grpcwrap_channel_args_destroy(grpc_channel_args* args) {
       gpr_free(args->args[i].key);
       if (args->args[i].type == GRPC_ARG_STRING) {
         gpr_free(args->args[i].value.string);
+      } else if (args->args[i].type == GRPC_ARG_POINTER) {
+        args->args[i].value.pointer.vtable->destroy(
+            args->args[i].value.pointer.p);
       }
     }
     gpr_free(args->args);
@@ -465,6 +489,131 @@ grpcwrap_channel_args_destroy(grpc_channel_args* args) {
   }
 }
 
+static void grpcwrap_channel_arg_copy(grpc_arg* dst, const grpc_arg* src) {
+  dst->type = src->type;
+  dst->key = gpr_strdup(src->key);
+  switch (src->type) {
+    case GRPC_ARG_STRING:
+      dst->value.string = gpr_strdup(src->value.string);
+      break;
+    case GRPC_ARG_INTEGER:
+      dst->value.integer = src->value.integer;
+      break;
+    case GRPC_ARG_POINTER:
+      dst->value.pointer.p =
+          src->value.pointer.vtable->copy(src->value.pointer.p);
+      dst->value.pointer.vtable = src->value.pointer.vtable;
+      break;
+  }
+}
+
+/* Copy the arguments of base and overrides, the ones in overrides replace
+   the ones in base with the same keys. */
+GPR_EXPORT grpc_channel_args* GPR_CALLTYPE grpcwrap_channel_args_merge(
+    const grpc_channel_args* base, const grpc_channel_args* overrides) {
+  GPR_ASSERT(base);
+  GPR_ASSERT(overrides);
+  grpc_channel_args* args =
+      grpcwrap_channel_args_create(base->num_args + overrides->num_args);
+  size_t n = 0;
+  for (size_t i = 0; i < base->num_args; i++) {
+    bool replaced = false;
+    for (size_t j = 0; j < overrides->num_args && !replaced; j++) {
+      replaced = strcmp(base->args[i].key, overrides->args[j].key) == 0;
+    }
+    if (!replaced) {
+      grpcwrap_channel_arg_copy(&args->args[n++], &base->args[i]);
+    }
+  }
+  for (size_t j = 0; j < overrides->num_args; j++) {
+    grpcwrap_channel_arg_copy(&args->args[n++], &overrides->args[j]);
+  }
+  args->num_args = n;
+  return args;
+}
+
+/* Socket Mutator */
+
+// Negative values mean the option is left untouched.
+typedef struct {
+  int tcp_user_timeout_ms;
+  int tcp_nodelay;
+  int recv_buffer_size;
+  int send_buffer_size;
+} grpcwrap_socket_options;
+
+typedef struct {
+  grpc_socket_mutator base;
+  grpcwrap_socket_options options;
+} socket_options_mutator;
+
+static bool socket_options_mutate_fd(int fd, grpc_socket_mutator* mutator) {
+#ifndef GPR_WINDOWS
+  const grpcwrap_socket_options* opts =
+      &((socket_options_mutator*)mutator)->options;
+  if (opts->recv_buffer_size >= 0 &&
+      setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &opts->recv_buffer_size,
+                 sizeof(int)) != 0) {
+    return false;
+  }
+  if (opts->send_buffer_size >= 0 &&
+      setsockopt(fd, SOL_SOCKET, SO_SNDBUF, &opts->send_buffer_size,
+                 sizeof(int)) != 0) {
+    return false;
+  }
+  // TCP options don't apply to unix domain sockets.
+  struct sockaddr_storage addr;
+  socklen_t len = sizeof(addr);
+  if (getsockname(fd, (struct sockaddr*)&addr, &len) != 0) {
+    return false;
+  }
+  if (addr.ss_family != AF_INET && addr.ss_family != AF_INET6) {
+    return true;
+  }
+  if (opts->tcp_nodelay >= 0 &&
+      setsockopt(fd, IPPROTO_TCP, TCP_NODELAY, &opts->tcp_nodelay,
+                 sizeof(int)) != 0) {
+    return false;
+  }
+#ifdef TCP_USER_TIMEOUT
+  if (opts->tcp_user_timeout_ms >= 0 &&
+      setsockopt(fd, IPPROTO_TCP, TCP_USER_TIMEOUT, &opts->tcp_user_timeout_ms,
+                 sizeof(int)) != 0) {
+    return false;
+  }
+#endif
+#endif
+  return true;
+}
+
+static int socket_options_compare(grpc_socket_mutator* a,
+                                  grpc_socket_mutator* b) {
+  return memcmp(&((socket_options_mutator*)a)->options,
+                &((socket_options_mutator*)b)->options,
+                sizeof(grpcwrap_socket_options));
+}
+
+static void socket_options_destroy(grpc_socket_mutator* mutator) {
+  gpr_free(mutator);
+}
+
+static const grpc_socket_mutator_vtable socket_options_vtable = {
+    socket_options_mutate_fd, socket_options_compare, socket_options_destroy};
+
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_channel_args_set_socket_options(
+    grpc_channel_args* args, size_t index, const char* key,
+    const grpcwrap_socket_options* options) {
+  auto* mutator =
+      (socket_options_mutator*)gpr_malloc(sizeof(socket_options_mutator));
+  grpc_socket_mutator_init(&mutator->base, &socket_options_vtable);
+  mutator->options = *options;
+  grpc_arg arg = grpc_socket_mutator_to_arg(&mutator->base);
+  grpcwrap_channel_args_set_pointer_vtable(args, index, key,
+                                           arg.value.pointer.p,
+                                           arg.value.pointer.vtable);
+  grpc_socket_mutator_unref(&mutator->base);
+}
+
 /* Call */
 
 GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_start_unary(
@@ -770,6 +919,24 @@ GPR_EXPORT grpc_call_error GPR_CALLTYPE grpcwrap_call_kick_completion_queue(
   return grpc_call_start_batch(call, nullptr, 0, tag, nullptr);
 }
 
+/* Declared in grpc/fork.h, which is not included by grpc/grpc.h. */
+extern "C" void grpc_prefork(void);
+extern "C" void grpc_postfork_parent(void);
+extern "C" void grpc_postfork_child(void);
+
+/** Run the fork handlers of gRPC Core, they do nothing unless fork support is
+    enabled by the environment variable GRPC_ENABLE_FORK_SUPPORT. No thread
+    should be calling into gRPC when grpcwrap_prefork is called. */
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_prefork() { grpc_prefork(); }
+
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_postfork_parent() {
+  grpc_postfork_parent();
+}
+
+GPR_EXPORT void GPR_CALLTYPE grpcwrap_postfork_child() {
+  grpc_postfork_child();
+}
+
 /* Server */
 
 GPR_EXPORT grpc_call_error GPR_CALLTYPE
@@ -824,10 +991,10 @@ grpcwrap_ssl_credentials_create(const char* pem_root_certs,
 }
 
 GPR_EXPORT grpc_server_credentials* GPR_CALLTYPE
-grpcwrap_ssl_server_credentials_create(
+grpcwrap_ssl_server_credentials_create_ex(
     const char* pem_root_certs, const char** key_cert_pair_cert_chain_array,
     const char** key_cert_pair_private_key_array, size_t num_key_cert_pairs,
-    int force_client_auth) {
+    grpc_ssl_client_certificate_request_type client_certificate_request) {
   size_t i;
   grpc_server_credentials* creds;
   grpc_ssl_pem_key_cert_pair* key_cert_pairs =
@@ -845,14 +1012,24 @@ grpcwrap_ssl_server_credentials_create(
   }
   creds = grpc_ssl_server_credentials_create_ex(
       pem_root_certs, key_cert_pairs, num_key_cert_pairs,
-      force_client_auth
-          ? GRPC_SSL_REQUEST_AND_REQUIRE_CLIENT_CERTIFICATE_AND_VERIFY
-          : GRPC_SSL_DONT_REQUEST_CLIENT_CERTIFICATE,
-      NULL);
+      client_certificate_request, NULL);
   gpr_free(key_cert_pairs);
   return creds;
 }
 
+GPR_EXPORT grpc_server_credentials* GPR_CALLTYPE
+grpcwrap_ssl_server_credentials_create(
+    const char* pem_root_certs, const char** key_cert_pair_cert_chain_array,
+    const char** key_cert_pair_private_key_array, size_t num_key_cert_pairs,
+    int force_client_auth) {
+  return grpcwrap_ssl_server_credentials_create_ex(
+      pem_root_certs, key_cert_pair_cert_chain_array,
+      key_cert_pair_private_key_array, num_key_cert_pairs,
+      force_client_auth
+          ? GRPC_SSL_REQUEST_AND_REQUIRE_CLIENT_CERTIFICATE_AND_VERIFY
+          : GRPC_SSL_DONT_REQUEST_CLIENT_CERTIFICATE);
+}
+
 #endif
 
 /* Sanity check for complicated types */
@@ -0,0 +1,656 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admin services, the health checking service `grpc.health.v1.Health` and
+//! the reflection service `grpc.reflection.v1alpha.ServerReflection`.
+//!
+//! Channelz is not served as a gRPC service, as gRPC Core only exposes it in
+//! the JSON format, see [`channelz`](channelz/index.html) instead.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use futures::sync::mpsc::{self, UnboundedSender};
+use futures::Stream;
+use protobuf::descriptor::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto};
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, CodedOutputStream, Message, ProtobufResult};
+
+use crate::call::{Method, MethodType, RpcStatus, RpcStatusCode};
+use crate::codec::{raw_codec, Marshaller};
+use crate::server::{FileDescriptors, ServiceBuilder};
+
+const RAW: Marshaller<Vec<u8>> = Marshaller {
+    ser: raw_codec::ser,
+    de: raw_codec::de,
+};
+
+const HEALTH_CHECK: Method<Vec<u8>, Vec<u8>> = Method {
+    ty: MethodType::Unary,
+    name: "/grpc.health.v1.Health/Check",
+    req_mar: RAW,
+    resp_mar: RAW,
+};
+
+const HEALTH_WATCH: Method<Vec<u8>, Vec<u8>> = Method {
+    ty: MethodType::ServerStreaming,
+    name: "/grpc.health.v1.Health/Watch",
+    req_mar: RAW,
+    resp_mar: RAW,
+};
+
+const REFLECTION_INFO: Method<Vec<u8>, Vec<u8>> = Method {
+    ty: MethodType::Duplex,
+    name: "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+    req_mar: RAW,
+    resp_mar: RAW,
+};
+
+/// Encode a message, writing to a vector never fails.
+fn encode<F: FnOnce(&mut CodedOutputStream<'_>) -> ProtobufResult<()>>(f: F) -> Vec<u8> {
+    let mut buf = vec![];
+    {
+        let mut os = CodedOutputStream::vec(&mut buf);
+        f(&mut os).unwrap();
+        os.flush().unwrap();
+    }
+    buf
+}
+
+/// Decode a message, `f` is called with the number of every field and it
+/// should skip the field if it's not interested.
+fn decode<F>(data: &[u8], mut f: F) -> ProtobufResult<()>
+where
+    F: FnMut(u32, WireType, &mut CodedInputStream<'_>) -> ProtobufResult<()>,
+{
+    let mut is = CodedInputStream::from_bytes(data);
+    while !is.eof()? {
+        let (number, wire_type) = is.read_tag_unpack()?;
+        f(number, wire_type, &mut is)?;
+    }
+    Ok(())
+}
+
+fn invalid_argument(e: protobuf::ProtobufError) -> RpcStatus {
+    RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e.to_string()))
+}
+
+/// The serving status of a service, see [`HealthService`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    /// Only sent to watchers of services that are not set.
+    ServiceUnknown = 3,
+}
+
+impl ServingStatus {
+    fn encode(self) -> Vec<u8> {
+        encode(|os| {
+            if self != ServingStatus::Unknown {
+                os.write_enum(1, self as i32)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+struct HealthEntry {
+    status: ServingStatus,
+    watchers: Vec<UnboundedSender<ServingStatus>>,
+}
+
+/// The statuses served by the health checking service `grpc.health.v1.Health`.
+///
+/// Clones share the same statuses, so a service can be registered to a
+/// server while the statuses are updated by the application.
+#[derive(Clone)]
+pub struct HealthService {
+    entries: Arc<Mutex<HashMap<String, HealthEntry>>>,
+}
+
+impl HealthService {
+    /// Create a health service in which the server as a whole, whose service
+    /// name is empty, is serving.
+    pub fn new() -> HealthService {
+        let health = HealthService {
+            entries: Arc::default(),
+        };
+        health.set_serving_status("", ServingStatus::Serving);
+        health
+    }
+
+    /// Set the status of a service, like `helloworld.Greeter`, watchers are
+    /// notified if it's changed.
+    pub fn set_serving_status(&self, service: &str, status: ServingStatus) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry(service.to_owned())
+            .or_insert_with(|| HealthEntry {
+                status: ServingStatus::ServiceUnknown,
+                watchers: vec![],
+            });
+        if entry.status != status {
+            entry.status = status;
+            entry.watchers.retain(|w| w.unbounded_send(status).is_ok());
+        }
+    }
+
+    /// Get the status of a service, `None` is returned if it's not set.
+    pub fn serving_status(&self, service: &str) -> Option<ServingStatus> {
+        match self.entries.lock().unwrap().get(service) {
+            Some(e) if e.status != ServingStatus::ServiceUnknown => Some(e.status),
+            _ => None,
+        }
+    }
+
+    /// Set all the services to `NotServing`, which is usually called before
+    /// shutting down the server.
+    pub fn shutdown(&self) {
+        let services: Vec<_> = self.entries.lock().unwrap().keys().cloned().collect();
+        for s in services {
+            if self.serving_status(&s).is_some() {
+                self.set_serving_status(&s, ServingStatus::NotServing);
+            }
+        }
+    }
+
+    fn check(&self, req: &[u8]) -> Result<Vec<u8>, RpcStatus> {
+        let service = parse_health_request(req).map_err(invalid_argument)?;
+        match self.serving_status(&service) {
+            Some(status) => Ok(status.encode()),
+            None => Err(RpcStatus::new(
+                RpcStatusCode::NOT_FOUND,
+                Some(format!("unknown service {}", service)),
+            )),
+        }
+    }
+
+    fn watch(&self, req: &[u8]) -> Result<mpsc::UnboundedReceiver<ServingStatus>, RpcStatus> {
+        let service = parse_health_request(req).map_err(invalid_argument)?;
+        let (tx, rx) = mpsc::unbounded();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(service).or_insert_with(|| HealthEntry {
+            status: ServingStatus::ServiceUnknown,
+            watchers: vec![],
+        });
+        // The current status is always sent first.
+        tx.unbounded_send(entry.status).unwrap();
+        entry.watchers.push(tx);
+        Ok(rx)
+    }
+
+    pub(crate) fn add_handlers(&self, builder: ServiceBuilder) -> ServiceBuilder {
+        let (check, watch) = (self.clone(), self.clone());
+        builder
+            .add_unary_future_handler(&HEALTH_CHECK, move |_, req: Vec<u8>| check.check(&req))
+            .add_server_streaming_future_handler(&HEALTH_WATCH, move |_, req: Vec<u8>| {
+                let s: Box<dyn Stream<Item = Vec<u8>, Error = RpcStatus> + Send> =
+                    match watch.watch(&req) {
+                        Ok(rx) => Box::new(
+                            rx.map(ServingStatus::encode)
+                                .map_err(|()| RpcStatus::new(RpcStatusCode::CANCELLED, None)),
+                        ),
+                        Err(status) => Box::new(futures::stream::once(Err(status))),
+                    };
+                s
+            })
+    }
+}
+
+impl Default for HealthService {
+    fn default() -> HealthService {
+        HealthService::new()
+    }
+}
+
+/// Parse the service name of a `HealthCheckRequest`.
+fn parse_health_request(req: &[u8]) -> ProtobufResult<String> {
+    let mut service = String::new();
+    decode(req, |number, wire_type, is| match number {
+        1 => is.read_string_into(&mut service),
+        _ => is.skip_field(wire_type),
+    })?;
+    Ok(service)
+}
+
+/// The parsed registered files, which are indexed on every request as
+/// reflection is rarely used.
+struct Files {
+    raw: Vec<Vec<u8>>,
+    files: Vec<FileDescriptorProto>,
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn collect_message(scope: &str, msg: &DescriptorProto, symbols: &mut Vec<String>) {
+    let name = qualify(scope, msg.get_name());
+    for e in msg.get_enum_type() {
+        symbols.push(qualify(&name, e.get_name()));
+    }
+    for f in msg.get_extension() {
+        symbols.push(qualify(&name, f.get_name()));
+    }
+    for nested in msg.get_nested_type() {
+        collect_message(&name, nested, symbols);
+    }
+    symbols.push(name);
+}
+
+fn collect_extensions<'a>(msg: &'a DescriptorProto, exts: &mut Vec<&'a FieldDescriptorProto>) {
+    exts.extend(msg.get_extension());
+    for nested in msg.get_nested_type() {
+        collect_extensions(nested, exts);
+    }
+}
+
+impl Files {
+    fn new(raw: Vec<Vec<u8>>) -> Files {
+        // Descriptors are generated by protoc, invalid ones are ignored.
+        let (raw, files) = raw
+            .into_iter()
+            .filter_map(|r| {
+                let file = FileDescriptorProto::parse_from_bytes(&r).ok()?;
+                Some((r, file))
+            })
+            .unzip();
+        Files { raw, files }
+    }
+
+    fn symbols(file: &FileDescriptorProto) -> Vec<String> {
+        let package = file.get_package();
+        let mut symbols = vec![];
+        for msg in file.get_message_type() {
+            collect_message(package, msg, &mut symbols);
+        }
+        for e in file.get_enum_type() {
+            symbols.push(qualify(package, e.get_name()));
+        }
+        for f in file.get_extension() {
+            symbols.push(qualify(package, f.get_name()));
+        }
+        for s in file.get_service() {
+            let name = qualify(package, s.get_name());
+            for m in s.get_method() {
+                symbols.push(qualify(&name, m.get_name()));
+            }
+            symbols.push(name);
+        }
+        symbols
+    }
+
+    fn extensions(file: &FileDescriptorProto) -> Vec<&FieldDescriptorProto> {
+        let mut exts: Vec<_> = file.get_extension().iter().collect();
+        for msg in file.get_message_type() {
+            collect_extensions(msg, &mut exts);
+        }
+        exts
+    }
+
+    fn by_name(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|f| f.get_name() == name)
+    }
+
+    fn by_symbol(&self, symbol: &str) -> Option<usize> {
+        self.files
+            .iter()
+            .position(|f| Files::symbols(f).iter().any(|s| s == symbol))
+    }
+
+    fn by_extension(&self, extendee: &str, number: i32) -> Option<usize> {
+        self.files.iter().position(|f| {
+            Files::extensions(f).iter().any(|e| {
+                e.get_extendee().trim_start_matches('.') == extendee && e.get_number() == number
+            })
+        })
+    }
+
+    /// Get the file with all the files it depends on that are registered.
+    fn with_dependencies(&self, file: usize) -> Vec<&[u8]> {
+        let (mut res, mut visited, mut queue) = (vec![], HashSet::new(), VecDeque::new());
+        queue.push_back(file);
+        visited.insert(file);
+        while let Some(i) = queue.pop_front() {
+            res.push(self.raw[i].as_slice());
+            for dep in self.files[i].get_dependency() {
+                if let Some(d) = self.by_name(dep) {
+                    if visited.insert(d) {
+                        queue.push_back(d);
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+/// The reflection service `grpc.reflection.v1alpha.ServerReflection` that
+/// serves the file descriptors of the registered services.
+#[derive(Clone)]
+pub(crate) struct ReflectionService {
+    descriptors: FileDescriptors,
+}
+
+/// A request of `ServerReflectionRequest`.
+enum ReflectionRequest {
+    FileByFilename(String),
+    FileContainingSymbol(String),
+    FileContainingExtension(String, i32),
+    AllExtensionNumbersOfType(String),
+    ListServices,
+}
+
+fn parse_reflection_request(req: &[u8]) -> ProtobufResult<Option<ReflectionRequest>> {
+    let mut request = None;
+    decode(req, |number, wire_type, is| {
+        request = Some(match number {
+            3 => ReflectionRequest::FileByFilename(is.read_string()?),
+            4 => ReflectionRequest::FileContainingSymbol(is.read_string()?),
+            5 => {
+                let (mut extendee, mut ext_number) = (String::new(), 0);
+                decode(&is.read_bytes()?, |number, wire_type, is| match number {
+                    1 => is.read_string_into(&mut extendee),
+                    2 => {
+                        ext_number = is.read_int32()?;
+                        Ok(())
+                    }
+                    _ => is.skip_field(wire_type),
+                })?;
+                ReflectionRequest::FileContainingExtension(extendee, ext_number)
+            }
+            6 => ReflectionRequest::AllExtensionNumbersOfType(is.read_string()?),
+            7 => {
+                is.skip_field(wire_type)?;
+                ReflectionRequest::ListServices
+            }
+            _ => return is.skip_field(wire_type),
+        });
+        Ok(())
+    })?;
+    Ok(request)
+}
+
+impl ReflectionService {
+    pub fn new(descriptors: FileDescriptors) -> ReflectionService {
+        ReflectionService { descriptors }
+    }
+
+    /// Respond a `ServerReflectionRequest` with a `ServerReflectionResponse`.
+    fn respond(&self, req: &[u8]) -> Vec<u8> {
+        let files = Files::new(self.descriptors.read().unwrap().clone());
+        let not_found = |what: &str| (RpcStatusCode::NOT_FOUND, format!("{} not found", what));
+        let files_response = |file: Option<usize>, what: &str| match file {
+            // FileDescriptorResponse
+            Some(i) => Ok((
+                4,
+                encode(|os| {
+                    for f in files.with_dependencies(i) {
+                        os.write_bytes(1, f)?;
+                    }
+                    Ok(())
+                }),
+            )),
+            None => Err(not_found(what)),
+        };
+        let res = match parse_reflection_request(req) {
+            Ok(Some(ReflectionRequest::FileByFilename(name))) => {
+                files_response(files.by_name(&name), &name)
+            }
+            Ok(Some(ReflectionRequest::FileContainingSymbol(symbol))) => {
+                files_response(files.by_symbol(&symbol), &symbol)
+            }
+            Ok(Some(ReflectionRequest::FileContainingExtension(extendee, number))) => {
+                let what = format!("extension {} of {}", number, extendee);
+                files_response(files.by_extension(&extendee, number), &what)
+            }
+            Ok(Some(ReflectionRequest::AllExtensionNumbersOfType(name))) => {
+                if files.by_symbol(&name).is_none() {
+                    Err(not_found(&name))
+                } else {
+                    // ExtensionNumberResponse
+                    let numbers: Vec<_> = files
+                        .files
+                        .iter()
+                        .flat_map(Files::extensions)
+                        .filter(|e| e.get_extendee().trim_start_matches('.') == name)
+                        .map(FieldDescriptorProto::get_number)
+                        .collect();
+                    Ok((
+                        5,
+                        encode(|os| {
+                            os.write_string(1, &name)?;
+                            for n in numbers {
+                                os.write_int32(2, n)?;
+                            }
+                            Ok(())
+                        }),
+                    ))
+                }
+            }
+            Ok(Some(ReflectionRequest::ListServices)) => {
+                // ListServiceResponse
+                Ok((
+                    6,
+                    encode(|os| {
+                        for f in &files.files {
+                            for s in f.get_service() {
+                                let name = qualify(f.get_package(), s.get_name());
+                                os.write_bytes(1, &encode(|os| os.write_string(1, &name)))?;
+                            }
+                        }
+                        Ok(())
+                    }),
+                ))
+            }
+            Ok(None) => Err((
+                RpcStatusCode::INVALID_ARGUMENT,
+                "unknown reflection request".to_owned(),
+            )),
+            Err(e) => Err((RpcStatusCode::INVALID_ARGUMENT, e.to_string())),
+        };
+        let (number, message) = match res {
+            Ok(r) => r,
+            // ErrorResponse
+            Err((code, msg)) => (
+                7,
+                encode(|os| {
+                    os.write_int32(1, code.into())?;
+                    os.write_string(2, &msg)
+                }),
+            ),
+        };
+        encode(|os| {
+            os.write_bytes(2, req)?;
+            os.write_bytes(number, &message)
+        })
+    }
+
+    pub fn add_handlers(&self, builder: ServiceBuilder) -> ServiceBuilder {
+        let reflection = self.clone();
+        builder.add_duplex_streaming_future_handler(&REFLECTION_INFO, move |_, stream| {
+            let reflection = reflection.clone();
+            stream
+                .map(move |req: Vec<u8>| reflection.respond(&req))
+                .map_err(|e| RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e.to_string())))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::descriptor::{MethodDescriptorProto, ServiceDescriptorProto};
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_health() {
+        let health = HealthService::new();
+        assert_eq!(health.serving_status(""), Some(ServingStatus::Serving));
+        let req = encode(|os| os.write_string(1, "a.B"));
+        assert_eq!(parse_health_request(&req).unwrap(), "a.B");
+        match health.check(&req) {
+            Err(s) => assert_eq!(s.status, RpcStatusCode::NOT_FOUND),
+            r => panic!("expected not found, got {:?}", r),
+        }
+        assert!(health.check(&[0xff]).is_err());
+
+        let rx = health.watch(&req).unwrap();
+        health.set_serving_status("a.B", ServingStatus::Serving);
+        assert_eq!(health.check(&req).unwrap(), vec![0x08, 1]);
+        health.set_serving_status("a.B", ServingStatus::Serving);
+        health.shutdown();
+        assert_eq!(health.serving_status(""), Some(ServingStatus::NotServing));
+        drop(health);
+        let statuses: Vec<_> = rx.wait().map(|s| s.unwrap()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ServingStatus::ServiceUnknown,
+                ServingStatus::Serving,
+                ServingStatus::NotServing
+            ]
+        );
+        assert_eq!(ServingStatus::Unknown.encode(), vec![]);
+    }
+
+    fn file(name: &str, deps: &[&str]) -> Vec<u8> {
+        let mut file = FileDescriptorProto::new();
+        file.set_name(name.to_owned());
+        file.set_package("a".to_owned());
+        for d in deps {
+            file.mut_dependency().push(d.to_string());
+        }
+        let stem = name.trim_end_matches(".proto");
+        let mut msg = DescriptorProto::new();
+        msg.set_name(format!("{}Msg", stem));
+        let mut nested = DescriptorProto::new();
+        nested.set_name("Nested".to_owned());
+        msg.mut_nested_type().push(nested);
+        let mut ext = FieldDescriptorProto::new();
+        ext.set_name(format!("{}_ext", stem));
+        ext.set_extendee(".a.BaseMsg".to_owned());
+        ext.set_number(100 + deps.len() as i32);
+        msg.mut_extension().push(ext);
+        file.mut_message_type().push(msg);
+        let mut service = ServiceDescriptorProto::new();
+        service.set_name(format!("{}Service", stem));
+        let mut method = MethodDescriptorProto::new();
+        method.set_name("Get".to_owned());
+        service.mut_method().push(method);
+        file.mut_service().push(service);
+        file.write_to_bytes().unwrap()
+    }
+
+    /// Parse a `ServerReflectionResponse`, returns the number of the
+    /// response field and the repeated field 1 of it.
+    fn parse_response(req: &[u8], res: &[u8]) -> (u32, Vec<Vec<u8>>) {
+        let (mut number, mut message) = (0, vec![]);
+        decode(res, |n, _, is| {
+            let data = is.read_bytes()?;
+            if n == 2 {
+                assert_eq!(data, req);
+            } else {
+                number = n;
+                message = data;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let mut items = vec![];
+        decode(&message, |n, wire_type, is| {
+            if n == 1 && wire_type == WireType::WireTypeLengthDelimited {
+                items.push(is.read_bytes()?);
+                Ok(())
+            } else {
+                is.skip_field(wire_type)
+            }
+        })
+        .unwrap();
+        (number, items)
+    }
+
+    #[test]
+    fn test_reflection() {
+        let (base, derived) = (
+            file("Base.proto", &[]),
+            file("Derived.proto", &["Base.proto"]),
+        );
+        let descriptors = Arc::new(RwLock::new(vec![base.clone(), derived.clone()]));
+        let reflection = ReflectionService::new(descriptors);
+        let check = |req: Vec<u8>, number: u32, items: Vec<Vec<u8>>| {
+            let res = reflection.respond(&req);
+            assert_eq!(parse_response(&req, &res), (number, items));
+        };
+
+        let by_name = |name: &str| encode(|os| os.write_string(3, name));
+        check(by_name("Base.proto"), 4, vec![base.clone()]);
+        check(
+            by_name("Derived.proto"),
+            4,
+            vec![derived.clone(), base.clone()],
+        );
+        let by_symbol = |name: &str| encode(|os| os.write_string(4, name));
+        for symbol in &[
+            "a.DerivedMsg",
+            "a.DerivedMsg.Nested",
+            "a.DerivedMsg.Derived_ext",
+            "a.DerivedService",
+            "a.DerivedService.Get",
+        ] {
+            check(by_symbol(symbol), 4, vec![derived.clone(), base.clone()]);
+        }
+        let by_ext = encode(|os| {
+            os.write_bytes(
+                5,
+                &encode(|os| {
+                    os.write_string(1, "a.BaseMsg")?;
+                    os.write_int32(2, 101)
+                }),
+            )
+        });
+        check(by_ext, 4, vec![derived.clone(), base.clone()]);
+        check(
+            encode(|os| os.write_string(7, "")),
+            6,
+            vec![
+                encode(|os| os.write_string(1, "a.BaseService")),
+                encode(|os| os.write_string(1, "a.DerivedService")),
+            ],
+        );
+
+        // Extension numbers are in a packed or repeated field 2.
+        let req = encode(|os| os.write_string(6, "a.BaseMsg"));
+        let res = reflection.respond(&req);
+        let (number, items) = parse_response(&req, &res);
+        assert_eq!((number, items), (5, vec![b"a.BaseMsg".to_vec()]));
+
+        for req in &[
+            by_name("Unknown.proto"),
+            by_symbol("a.Unknown"),
+            encode(|os| os.write_string(6, "a.Unknown")),
+            encode(|os| os.write_string(1, "host")),
+            vec![0xff],
+        ] {
+            let res = reflection.respond(req);
+            let (number, _) = parse_response(req, &res);
+            assert_eq!(number, 7, "{:?}", req);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication information of the peer of a call.
+
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::{slice, str};
+
+use crate::grpc_sys::{self, grpc_auth_context, grpc_auth_property_iterator, grpc_call};
+
+const PROPERTY_TRANSPORT_SECURITY_TYPE: &str = "transport_security_type";
+const PROPERTY_X509_CN: &str = "x509_common_name";
+const PROPERTY_X509_SAN: &str = "x509_subject_alternative_name";
+const PROPERTY_X509_PEM_CERT: &str = "x509_pem_cert";
+const PROPERTY_SSL_SESSION_REUSED: &str = "ssl_session_reused";
+const PROPERTY_SECURITY_LEVEL: &str = "security_level";
+
+/// The protection provided by the transport of a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SecurityLevel {
+    None,
+    IntegrityOnly,
+    PrivacyAndIntegrity,
+}
+
+/// The authentication context of a call.
+///
+/// It holds the properties of the authenticated peer, like the common name
+/// and subject alternative names of the certificate presented by a TLS peer.
+pub struct AuthContext {
+    ctx: *mut grpc_auth_context,
+}
+
+unsafe impl Send for AuthContext {}
+unsafe impl Sync for AuthContext {}
+
+impl AuthContext {
+    /// Get the authentication context of the call, `None` is returned if the
+    /// call is not made on a secure transport.
+    pub(crate) unsafe fn from_call(call: *mut grpc_call) -> Option<AuthContext> {
+        let ctx = grpc_sys::grpc_call_auth_context(call);
+        if ctx.is_null() {
+            None
+        } else {
+            Some(AuthContext { ctx })
+        }
+    }
+
+    /// Check whether the peer is authenticated.
+    ///
+    /// For TLS, it means the peer has presented a certificate that is
+    /// verified against the root certificates.
+    pub fn peer_is_authenticated(&self) -> bool {
+        unsafe { grpc_sys::grpc_auth_context_peer_is_authenticated(self.ctx) != 0 }
+    }
+
+    /// Get the name of the property that identifies the peer, like
+    /// `x509_subject_alternative_name`.
+    pub fn peer_identity_property_name(&self) -> Option<&str> {
+        unsafe {
+            let name = grpc_sys::grpc_auth_context_peer_identity_property_name(self.ctx);
+            if name.is_null() {
+                None
+            } else {
+                CStr::from_ptr(name).to_str().ok()
+            }
+        }
+    }
+
+    /// Get the properties that identify the peer.
+    pub fn peer_identity(&self) -> AuthPropertyIter<'_> {
+        let it = unsafe { grpc_sys::grpc_auth_context_peer_identity(self.ctx) };
+        AuthPropertyIter::new(it, None)
+    }
+
+    /// Get all the properties.
+    pub fn properties(&self) -> AuthPropertyIter<'_> {
+        let it = unsafe { grpc_sys::grpc_auth_context_property_iterator(self.ctx) };
+        AuthPropertyIter::new(it, None)
+    }
+
+    /// Get the properties with the given name.
+    pub fn find_properties(&self, name: &str) -> AuthPropertyIter<'_> {
+        let name = CString::new(name).unwrap();
+        let it =
+            unsafe { grpc_sys::grpc_auth_context_find_properties_by_name(self.ctx, name.as_ptr()) };
+        // The iterator keeps a pointer to name.
+        AuthPropertyIter::new(it, Some(name))
+    }
+
+    fn find_str(&self, name: &str) -> Option<&str> {
+        self.find_properties(name)
+            .next()
+            .and_then(|p| p.value_str())
+    }
+
+    /// Get the type of the transport security, like `ssl`.
+    pub fn transport_security_type(&self) -> Option<&str> {
+        self.find_str(PROPERTY_TRANSPORT_SECURITY_TYPE)
+    }
+
+    /// Get the common name of the peer certificate.
+    pub fn x509_common_name(&self) -> Option<&str> {
+        self.find_str(PROPERTY_X509_CN)
+    }
+
+    /// Get the subject alternative names of the peer certificate.
+    pub fn x509_subject_alternative_names(&self) -> Vec<&str> {
+        self.find_properties(PROPERTY_X509_SAN)
+            .filter_map(|p| p.value_str())
+            .collect()
+    }
+
+    /// Get the protection provided by the transport.
+    ///
+    /// gRPC Core reports the level since 1.19, for the bundled one it's
+    /// derived from the transport security type instead, TLS provides both
+    /// privacy and integrity.
+    pub fn security_level(&self) -> SecurityLevel {
+        match self.find_str(PROPERTY_SECURITY_LEVEL) {
+            Some("TSI_SECURITY_NONE") => return SecurityLevel::None,
+            Some("TSI_INTEGRITY_ONLY") => return SecurityLevel::IntegrityOnly,
+            Some("TSI_PRIVACY_AND_INTEGRITY") => return SecurityLevel::PrivacyAndIntegrity,
+            _ => {}
+        }
+        match self.transport_security_type() {
+            Some("ssl") => SecurityLevel::PrivacyAndIntegrity,
+            _ => SecurityLevel::None,
+        }
+    }
+
+    /// Check whether the TLS session is resumed from a previous connection.
+    pub fn ssl_session_reused(&self) -> bool {
+        self.find_str(PROPERTY_SSL_SESSION_REUSED) == Some("true")
+    }
+
+    /// Get the PEM encoded peer certificate.
+    ///
+    /// Only the leaf certificate is exposed by gRPC Core, the intermediate
+    /// certificates of the verified chain are not available.
+    pub fn x509_pem_cert(&self) -> Option<&str> {
+        self.find_str(PROPERTY_X509_PEM_CERT)
+    }
+
+    /// Get a handle of the authenticated peer, `None` is returned if the
+    /// peer is not authenticated.
+    pub fn identity(&self) -> Option<PeerIdentity> {
+        if !self.peer_is_authenticated() {
+            return None;
+        }
+        Some(PeerIdentity {
+            security_type: self.transport_security_type().map(ToOwned::to_owned),
+            property_name: self.peer_identity_property_name().map(ToOwned::to_owned),
+            values: self.peer_identity().map(|p| p.value().to_vec()).collect(),
+            pem_cert: self.x509_pem_cert().map(ToOwned::to_owned),
+        })
+    }
+}
+
+impl Drop for AuthContext {
+    fn drop(&mut self) {
+        unsafe { grpc_sys::grpc_auth_context_release(self.ctx) }
+    }
+}
+
+/// A stable handle of an authenticated peer.
+///
+/// Unlike the peer address, it's derived from the credentials presented by
+/// the peer instead of the connection, so it stays the same across resumed
+/// TLS sessions, reconnections and connections reusing the credentials. It
+/// implements `Eq` and `Hash`, which makes it suitable as the key of a cache
+/// of authorization decisions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerIdentity {
+    security_type: Option<String>,
+    property_name: Option<String>,
+    values: Vec<Vec<u8>>,
+    pem_cert: Option<String>,
+}
+
+impl PeerIdentity {
+    /// Get the type of the transport security, like `ssl`.
+    pub fn transport_security_type(&self) -> Option<&str> {
+        self.security_type.as_deref()
+    }
+
+    /// Get the name of the property that identifies the peer.
+    pub fn property_name(&self) -> Option<&str> {
+        self.property_name.as_deref()
+    }
+
+    /// Get the values of the properties that identify the peer.
+    pub fn values(&self) -> impl Iterator<Item = &[u8]> {
+        self.values.iter().map(|v| v.as_slice())
+    }
+
+    /// Get the PEM encoded peer certificate.
+    pub fn x509_pem_cert(&self) -> Option<&str> {
+        self.pem_cert.as_deref()
+    }
+}
+
+/// A property of [`AuthContext`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthProperty<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+impl<'a> AuthProperty<'a> {
+    /// The name of the property.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The raw value of the property.
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// The value of the property if it's a valid UTF-8 string.
+    pub fn value_str(&self) -> Option<&'a str> {
+        str::from_utf8(self.value).ok()
+    }
+}
+
+/// An iterator over the properties of [`AuthContext`].
+pub struct AuthPropertyIter<'a> {
+    it: grpc_auth_property_iterator,
+    _name: Option<CString>,
+    _ctx: PhantomData<&'a AuthContext>,
+}
+
+impl<'a> AuthPropertyIter<'a> {
+    fn new(it: grpc_auth_property_iterator, name: Option<CString>) -> AuthPropertyIter<'a> {
+        AuthPropertyIter {
+            it,
+            _name: name,
+            _ctx: PhantomData,
+        }
+    }
+}
+
+impl<'a> Iterator for AuthPropertyIter<'a> {
+    type Item = AuthProperty<'a>;
+
+    fn next(&mut self) -> Option<AuthProperty<'a>> {
+        unsafe {
+            let prop = grpc_sys::grpc_auth_property_iterator_next(&mut self.it);
+            if prop.is_null() {
+                return None;
+            }
+            let prop = &*prop;
+            let name = CStr::from_ptr(prop.name).to_str().unwrap_or("");
+            let value = if prop.value.is_null() {
+                &[]
+            } else {
+                slice::from_raw_parts(prop.value as *const u8, prop.value_length)
+            };
+            Some(AuthProperty { name, value })
+        }
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Weighted budgeting of concurrent calls among classes of methods.
+
+use std::sync::{Arc, Mutex};
+
+use crate::call::server::RpcContext;
+use crate::call::{MessageReader, MethodType, RpcStatus, RpcStatusCode};
+use crate::server::{BoxHandler, CloneableHandler};
+
+type StarvationCallback = dyn Fn(&str, &BudgetClassStats) + Send + Sync;
+
+/// Statistics of a class of a [`CallBudget`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BudgetClassStats {
+    /// The count of calls the class is guaranteed to run concurrently.
+    pub share: usize,
+    /// The count of running calls.
+    pub in_flight: usize,
+    /// The count of admitted calls.
+    pub admitted: usize,
+    /// The count of calls rejected because the class used up its share and
+    /// there was no spare capacity.
+    pub throttled: usize,
+    /// The count of calls rejected while the class was below its share.
+    pub starved: usize,
+}
+
+struct Class {
+    name: String,
+    stats: BudgetClassStats,
+}
+
+enum Admission {
+    Admitted,
+    Throttled,
+    Starved(BudgetClassStats),
+}
+
+struct Inner {
+    capacity: usize,
+    classes: Mutex<Vec<Class>>,
+    on_starvation: Option<Box<StarvationCallback>>,
+}
+
+impl Inner {
+    fn acquire(&self, class: usize) -> Admission {
+        let mut classes = self.classes.lock().unwrap();
+        let total: usize = classes.iter().map(|c| c.stats.in_flight).sum();
+        let stats = classes[class].stats;
+        let admitted = if stats.in_flight < stats.share {
+            total < self.capacity
+        } else {
+            // Spare capacity can be borrowed, as long as the unused shares of
+            // other active classes are kept.
+            let reserved: usize = classes
+                .iter()
+                .enumerate()
+                .filter(|(i, c)| *i != class && c.stats.in_flight > 0)
+                .map(|(_, c)| c.stats.share.saturating_sub(c.stats.in_flight))
+                .sum();
+            total + reserved < self.capacity
+        };
+        let stats = &mut classes[class].stats;
+        if admitted {
+            stats.in_flight += 1;
+            stats.admitted += 1;
+            Admission::Admitted
+        } else if stats.in_flight < stats.share {
+            stats.starved += 1;
+            Admission::Starved(*stats)
+        } else {
+            stats.throttled += 1;
+            Admission::Throttled
+        }
+    }
+
+    fn release(&self, class: usize) {
+        let mut classes = self.classes.lock().unwrap();
+        classes[class].stats.in_flight -= 1;
+    }
+}
+
+/// A builder for [`CallBudget`].
+pub struct CallBudgetBuilder {
+    capacity: usize,
+    classes: Vec<(String, u32)>,
+    on_starvation: Option<Box<StarvationCallback>>,
+}
+
+impl CallBudgetBuilder {
+    /// Add a class with the relative weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the weight is 0 or the class has been added.
+    pub fn class<S: Into<String>>(mut self, name: S, weight: u32) -> CallBudgetBuilder {
+        let name = name.into();
+        assert!(weight > 0, "weight of class {} should be positive", name);
+        assert!(
+            self.classes.iter().all(|(n, _)| *n != name),
+            "class {} is added twice",
+            name
+        );
+        self.classes.push((name, weight));
+        self
+    }
+
+    /// Set the callback that is called when a call is rejected while its class
+    /// is below its share, which means other classes are using more than
+    /// their shares.
+    ///
+    /// It's called in the polling thread, so it should not block.
+    pub fn on_starvation<F>(mut self, f: F) -> CallBudgetBuilder
+    where
+        F: Fn(&str, &BudgetClassStats) + Send + Sync + 'static,
+    {
+        self.on_starvation = Some(Box::new(f));
+        self
+    }
+
+    /// Finalize the [`CallBudgetBuilder`] and build the [`CallBudget`].
+    pub fn build(self) -> CallBudget {
+        let total_weight: u64 = self.classes.iter().map(|(_, w)| u64::from(*w)).sum();
+        let capacity = self.capacity;
+        let classes = self
+            .classes
+            .into_iter()
+            .map(|(name, weight)| {
+                let share = capacity as u64 * u64::from(weight) / total_weight;
+                Class {
+                    name,
+                    stats: BudgetClassStats {
+                        share: share as usize,
+                        ..BudgetClassStats::default()
+                    },
+                }
+            })
+            .collect();
+        CallBudget {
+            inner: Arc::new(Inner {
+                capacity,
+                classes: Mutex::new(classes),
+                on_starvation: self.on_starvation,
+            }),
+        }
+    }
+}
+
+/// A budget of concurrent calls shared by classes of methods.
+///
+/// Each class is guaranteed a share of the capacity in proportion to its
+/// weight. When a class is idle, others can borrow its share, so the
+/// capacity is not wasted. Calls beyond the capacity are rejected with
+/// `RESOURCE_EXHAUSTED`. If a call is rejected while its class is below its
+/// share, which can happen when other classes have borrowed the capacity
+/// before it becomes active, the class is considered starved and the
+/// callback set by [`CallBudgetBuilder::on_starvation`] is called.
+///
+/// A call is counted until its sinks and request streams are dropped, which
+/// usually happens soon after the status is sent.
+///
+/// Methods are assigned to classes by `Service::budget` and
+/// `Service::method_budget`. A budget can be shared by multiple servers.
+#[derive(Clone)]
+pub struct CallBudget {
+    inner: Arc<Inner>,
+}
+
+impl CallBudget {
+    /// Create a builder for a budget that runs at most `capacity` calls
+    /// concurrently.
+    pub fn builder(capacity: usize) -> CallBudgetBuilder {
+        CallBudgetBuilder {
+            capacity,
+            classes: Vec::new(),
+            on_starvation: None,
+        }
+    }
+
+    /// Get the statistics of the class.
+    pub fn stats(&self, class: &str) -> Option<BudgetClassStats> {
+        let classes = self.inner.classes.lock().unwrap();
+        classes.iter().find(|c| c.name == class).map(|c| c.stats)
+    }
+
+    pub(crate) fn class_index(&self, class: &str) -> usize {
+        let classes = self.inner.classes.lock().unwrap();
+        match classes.iter().position(|c| c.name == class) {
+            Some(i) => i,
+            None => panic!("class {} is not found in the budget", class),
+        }
+    }
+}
+
+struct BudgetGuard {
+    inner: Arc<Inner>,
+    class: usize,
+}
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        self.inner.release(self.class);
+    }
+}
+
+/// A handler that only calls the wrapped handler if the budget allows.
+pub(crate) struct BudgetedHandler {
+    inner: BoxHandler,
+    budget: CallBudget,
+    class: usize,
+}
+
+impl BudgetedHandler {
+    pub fn new(inner: BoxHandler, budget: CallBudget, class: usize) -> BudgetedHandler {
+        BudgetedHandler {
+            inner,
+            budget,
+            class,
+        }
+    }
+}
+
+impl CloneableHandler for BudgetedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        let budget = &self.budget.inner;
+        match budget.acquire(self.class) {
+            Admission::Admitted => {
+                ctx.add_call_guard(Box::new(BudgetGuard {
+                    inner: budget.clone(),
+                    class: self.class,
+                }));
+                return self.inner.handle(ctx, reqs);
+            }
+            Admission::Throttled => {}
+            Admission::Starved(stats) => {
+                if let Some(ref f) = budget.on_starvation {
+                    let classes = budget.classes.lock().unwrap();
+                    let name = classes[self.class].name.clone();
+                    drop(classes);
+                    f(&name, &stats);
+                }
+            }
+        }
+        let status = RpcStatus::new(
+            RpcStatusCode::RESOURCE_EXHAUSTED,
+            Some("call budget is exhausted".to_owned()),
+        );
+        let mut call = ctx.call();
+        if call.start_server_side().is_ok() {
+            call.abort(&status);
+        }
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(BudgetedHandler {
+            inner: self.inner.box_clone(),
+            budget: self.budget.clone(),
+            class: self.class,
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn admitted(a: Admission) -> bool {
+        match a {
+            Admission::Admitted => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_call_budget() {
+        let starved = Arc::new(AtomicUsize::new(0));
+        let s = starved.clone();
+        let budget = CallBudget::builder(4)
+            .class("control", 1)
+            .class("data", 3)
+            .on_starvation(move |_, _| {
+                s.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+        let (control, data) = (budget.class_index("control"), budget.class_index("data"));
+        let inner = &budget.inner;
+        assert_eq!(budget.stats("control").unwrap().share, 1);
+        assert_eq!(budget.stats("data").unwrap().share, 3);
+        assert_eq!(budget.stats("unknown"), None);
+
+        // Data can borrow the share of the idle control class.
+        for _ in 0..4 {
+            assert!(admitted(inner.acquire(data)));
+        }
+        match inner.acquire(data) {
+            Admission::Throttled => {}
+            _ => panic!("data should be throttled"),
+        }
+        match inner.acquire(control) {
+            Admission::Starved(stats) => assert_eq!(stats.starved, 1),
+            _ => panic!("control should be starved"),
+        }
+
+        inner.release(data);
+        inner.release(data);
+        assert!(admitted(inner.acquire(control)));
+        // The unused share of the active data class is kept.
+        assert!(!admitted(inner.acquire(control)));
+        assert!(admitted(inner.acquire(data)));
+
+        let stats = budget.stats("data").unwrap();
+        assert_eq!(
+            (stats.in_flight, stats.admitted, stats.throttled),
+            (3, 5, 1)
+        );
+        let stats = budget.stats("control").unwrap();
+        assert_eq!(
+            (
+                stats.in_flight,
+                stats.admitted,
+                stats.throttled,
+                stats.starved
+            ),
+            (1, 1, 1, 1)
+        );
+        // The callback is only called by handlers.
+        assert_eq!(starved.load(Ordering::SeqCst), 0);
+    }
+}
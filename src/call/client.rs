@@ -11,20 +11,28 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ffi::CStr;
 use std::ptr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::grpc_sys;
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
 
-use super::{ShareCall, ShareCallHolder, SinkBase, WriteFlags};
-use crate::call::{check_run, Call, MessageReader, Method};
-use crate::channel::Channel;
+use super::{MessageSizeCheck, ShareCall, ShareCallHolder, SinkBase, WriteFlags};
+use crate::call::{check_run_with_metadata, Call, MessageReader, RpcStatus, RpcStatusCode};
+use crate::channel::{Channel, CompressionAlgorithms};
 use crate::codec::{DeserializeFn, SerializeFn};
+#[cfg(feature = "secure")]
+use crate::credentials::CallCredentials;
 use crate::error::{Error, Result};
-use crate::metadata::Metadata;
-use crate::task::{BatchFuture, BatchType, SpinLock};
+use crate::metadata::{Metadata, MetadataBuilder};
+use crate::task::{BatchFuture, BatchType, Delay, ResponseMetadata, SpinLock};
+use crate::trace::TraceContext;
+
+/// The internal header that overrides the compression algorithm of a call,
+/// it's consumed by gRPC Core and never sent to the peer.
+const COMPRESSION_REQUEST_KEY: &str = "grpc-internal-encoding-request";
 
 /// Update the flag bit in res.
 #[inline]
@@ -40,9 +48,18 @@ pub fn change_flag(res: &mut u32, flag: u32, set: bool) {
 #[derive(Clone, Default)]
 pub struct CallOption {
     timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    no_deadline: bool,
+    idle_timeout: Option<Duration>,
     write_flags: WriteFlags,
     call_flags: u32,
     headers: Option<Metadata>,
+    #[cfg(feature = "secure")]
+    credentials: Option<CallCredentials>,
+    size_check: Option<MessageSizeCheck>,
+    compression: Option<CompressionAlgorithms>,
+    trace: Option<TraceContext>,
+    watchdog: Option<Duration>,
 }
 
 impl CallOption {
@@ -83,8 +100,13 @@ impl CallOption {
     }
 
     /// Set a timeout.
+    ///
+    /// The deadline of the call is the timeout after the call is created. It
+    /// replaces the deadline set by `deadline`.
     pub fn timeout(mut self, timeout: Duration) -> CallOption {
         self.timeout = Some(timeout);
+        self.deadline = None;
+        self.no_deadline = false;
         self
     }
 
@@ -93,6 +115,95 @@ impl CallOption {
         self.timeout
     }
 
+    /// Set an absolute deadline.
+    ///
+    /// It's useful when several calls share the budget of a single
+    /// operation, for example a server passing the deadline of its own call
+    /// to the calls it makes. A call created after the deadline fails with
+    /// `DEADLINE_EXCEEDED` immediately. It replaces the timeout set by
+    /// `timeout`.
+    pub fn deadline(mut self, deadline: Instant) -> CallOption {
+        self.deadline = Some(deadline);
+        self.timeout = None;
+        self.no_deadline = false;
+        self
+    }
+
+    /// Get the deadline set by `deadline`.
+    pub fn get_deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the timeout or the deadline is set, or the deadline is
+    /// disabled explicitly.
+    pub(crate) fn has_deadline_option(&self) -> bool {
+        self.timeout.is_some() || self.deadline.is_some() || self.no_deadline
+    }
+
+    /// The time left for the call created now, `None` means no deadline.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        match self.deadline {
+            Some(deadline) => Some(deadline.saturating_duration_since(Instant::now())),
+            None => self.timeout,
+        }
+    }
+
+    /// Disable the deadline of the call explicitly.
+    ///
+    /// Not setting a timeout also means no deadline, but helper layers are
+    /// free to apply a default timeout in that case. With this option, the
+    /// call will never be limited by a deadline, which is what long-lived
+    /// watch streams usually need.
+    pub fn no_deadline(mut self) -> CallOption {
+        self.timeout = None;
+        self.deadline = None;
+        self.no_deadline = true;
+        self
+    }
+
+    /// Get whether the deadline is disabled explicitly.
+    pub fn get_no_deadline(&self) -> bool {
+        self.no_deadline
+    }
+
+    /// Set the idle timeout of the response stream.
+    ///
+    /// The call will be cancelled and fail with `DEADLINE_EXCEEDED` if no
+    /// message is received from the server within the duration. Unlike
+    /// `timeout`, it's reset every time a message arrives, so it can be
+    /// used along with `no_deadline`. The time a received message waits to
+    /// be taken doesn't count, so a slow consumer doesn't fail the call.
+    /// Only server streaming and duplex streaming calls respect this option.
+    pub fn stream_idle_timeout(mut self, timeout: Duration) -> CallOption {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Get the idle timeout of the response stream.
+    pub fn get_stream_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Abort the call if any of its batches, like sending or receiving a
+    /// message, is not completed within the bound.
+    ///
+    /// It's a last resort for calls that are wedged for some reason, for
+    /// example a message never arrives while the connection is alive. The
+    /// state of the call is logged and the call fails with `ABORTED`, so it
+    /// won't leak resources forever. Note that waiting for the response of a
+    /// unary call and waiting for the next message of a stream are batches,
+    /// so the bound should be longer than the time the server is expected to
+    /// take.
+    pub fn batch_watchdog(mut self, bound: Duration) -> CallOption {
+        self.watchdog = Some(bound);
+        self
+    }
+
+    /// Get the bound of the batch watchdog.
+    pub fn get_batch_watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
     /// Set the headers to be sent with the call.
     pub fn headers(mut self, meta: Metadata) -> CallOption {
         self.headers = Some(meta);
@@ -103,141 +214,381 @@ impl CallOption {
     pub fn get_headers(&self) -> Option<&Metadata> {
         self.headers.as_ref()
     }
+
+    /// Set a checker that is called with the serialized size of every
+    /// outgoing message before it's enqueued.
+    ///
+    /// It can be used for enforcing client side quotas or accounting egress
+    /// traffic. If the checker returns an error, the message is not sent and
+    /// the status is returned as `Error::RpcFailure`. Note that the size is
+    /// measured before compression, gRPC Core doesn't report the size of
+    /// compressed messages.
+    pub fn message_size_check<F>(mut self, check: F) -> CallOption
+    where
+        F: Fn(usize) -> std::result::Result<(), RpcStatus> + Send + Sync + 'static,
+    {
+        self.size_check = Some(Arc::new(check));
+        self
+    }
+
+    fn check_message_size(&self, size: usize) -> Result<()> {
+        match self.size_check {
+            Some(ref check) => check(size).map_err(Error::RpcFailure),
+            None => Ok(()),
+        }
+    }
+
+    /// Compress the messages of the call with the algorithm, overriding the
+    /// default compression algorithm of the channel.
+    ///
+    /// Messages can be sent uncompressed by `WriteFlags::force_no_compress`.
+    /// The peer fails the call with `UNIMPLEMENTED` if it has not enabled
+    /// the algorithm.
+    pub fn compress(mut self, algo: CompressionAlgorithms) -> CallOption {
+        self.compression = Some(algo);
+        self
+    }
+
+    /// Get the compression algorithm set by `compress` or
+    /// `stream_compression`.
+    pub fn get_compression(&self) -> Option<CompressionAlgorithms> {
+        self.compression
+    }
+
+    /// Compress the messages of the call as a whole stream instead of one by
+    /// one.
+    ///
+    /// The compression context is reused across messages, which improves
+    /// ratio and CPU usage when sending many small messages. Only peers built
+    /// on gRPC Core understand stream compression, so it should be left
+    /// disabled for other peers, in that case the default compression of the
+    /// channel applies.
+    pub fn stream_compression(mut self, enable: bool) -> CallOption {
+        self.compression = if enable {
+            Some(CompressionAlgorithms::GRPC_COMPRESS_STREAM_GZIP)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Get whether stream compression is enabled.
+    pub fn get_stream_compression(&self) -> bool {
+        self.compression == Some(CompressionAlgorithms::GRPC_COMPRESS_STREAM_GZIP)
+    }
+
+    /// Propagate the trace context to the server.
+    ///
+    /// The context is sent as both `traceparent` and `grpc-trace-bin`
+    /// headers, replacing the ones in the headers set by `headers`. A server
+    /// is supposed to pass `RpcContext::trace_context().child()` to the calls
+    /// it makes, so the trace continues across hops.
+    pub fn trace_context(mut self, ctx: TraceContext) -> CallOption {
+        self.trace = Some(ctx);
+        self
+    }
+
+    /// Get the trace context propagated to the server.
+    pub fn get_trace_context(&self) -> Option<&TraceContext> {
+        self.trace.as_ref()
+    }
+
+    /// Add the internal headers required by the options to the headers.
+    fn prepare_headers(&mut self) {
+        let compression = self.compression.and_then(|algo| {
+            let mut name = ptr::null();
+            unsafe {
+                if grpc_sys::grpc_compression_algorithm_name(algo, &mut name) == 0 {
+                    return None;
+                }
+                Some(CStr::from_ptr(name).to_bytes())
+            }
+        });
+        if compression.is_none() && self.trace.is_none() {
+            return;
+        }
+        let headers = self.headers.take();
+        let cap = headers.as_ref().map_or(0, Metadata::len) + 3;
+        let mut builder = MetadataBuilder::with_capacity(cap);
+        for (k, v) in headers.iter().flatten() {
+            if self.trace.is_some() && TraceContext::is_trace_key(k) {
+                continue;
+            }
+            builder.add_metadata(k, v).unwrap();
+        }
+        if let Some(name) = compression {
+            builder.add_metadata(COMPRESSION_REQUEST_KEY, name).unwrap();
+        }
+        if let Some(ref trace) = self.trace {
+            trace.add_to_metadata(&mut builder);
+        }
+        self.headers = Some(builder.build());
+    }
+
+    /// Set the credentials to be attached to the call.
+    #[cfg(feature = "secure")]
+    pub fn credentials(mut self, creds: CallCredentials) -> CallOption {
+        self.credentials = Some(creds);
+        self
+    }
+
+    /// Get the credentials to be attached to the call.
+    #[cfg(feature = "secure")]
+    pub fn get_credentials(&self) -> Option<&CallCredentials> {
+        self.credentials.as_ref()
+    }
 }
 
 impl Call {
-    pub fn unary_async<Req, Resp>(
+    pub fn unary_async<Req: ?Sized, Resp>(
         channel: &Channel,
-        method: &Method<Req, Resp>,
+        method: &str,
+        req_ser: SerializeFn<Req>,
+        resp_de: DeserializeFn<Resp>,
         req: &Req,
         mut opt: CallOption,
     ) -> Result<ClientUnaryReceiver<Resp>> {
-        let call = channel.create_call(method, &opt)?;
         let mut payload = vec![];
-        (method.req_ser())(req, &mut payload);
-        let cq_f = check_run(BatchType::CheckRead, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_start_unary(
-                call.call,
-                ctx,
-                payload.as_ptr() as *const _,
-                payload.len(),
-                opt.write_flags.flags,
-                opt.headers
-                    .as_mut()
-                    .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
-                opt.call_flags,
-                tag,
-            )
-        });
-        Ok(ClientUnaryReceiver::new(call, cq_f, method.resp_de()))
+        req_ser(req, &mut payload);
+        opt.check_message_size(payload.len())?;
+        opt.prepare_headers();
+        let call = channel.create_call(method, &opt)?;
+        call.check_send_size(payload.len())?;
+        call.on_sent(payload.len());
+        let metadata = Arc::new(SpinLock::new(ResponseMetadata::default()));
+        let cq_f = check_run_with_metadata(
+            BatchType::CheckRead,
+            Some(metadata.clone()),
+            call.watch("unary_call"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_start_unary(
+                    call.call,
+                    ctx,
+                    payload.as_ptr() as *const _,
+                    payload.len(),
+                    opt.write_flags.flags,
+                    opt.headers
+                        .as_mut()
+                        .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
+                    opt.call_flags,
+                    tag,
+                )
+            },
+        );
+        Ok(ClientUnaryReceiver::new(call, cq_f, metadata, resp_de))
     }
 
     pub fn client_streaming<Req, Resp>(
         channel: &Channel,
-        method: &Method<Req, Resp>,
+        method: &str,
+        req_ser: SerializeFn<Req>,
+        resp_de: DeserializeFn<Resp>,
         mut opt: CallOption,
     ) -> Result<(ClientCStreamSender<Req>, ClientCStreamReceiver<Resp>)> {
+        opt.prepare_headers();
         let call = channel.create_call(method, &opt)?;
-        let cq_f = check_run(BatchType::CheckRead, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_start_client_streaming(
-                call.call,
-                ctx,
-                opt.headers
-                    .as_mut()
-                    .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
-                opt.call_flags,
-                tag,
-            )
-        });
+        let metadata = Arc::new(SpinLock::new(ResponseMetadata::default()));
+        let cq_f = check_run_with_metadata(
+            BatchType::CheckRead,
+            Some(metadata.clone()),
+            call.trace("recv_status"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_start_client_streaming(
+                    call.call,
+                    ctx,
+                    opt.headers
+                        .as_mut()
+                        .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
+                    opt.call_flags,
+                    tag,
+                )
+            },
+        );
 
         let share_call = Arc::new(SpinLock::new(ShareCall::new(call, cq_f)));
-        let sink = ClientCStreamSender::new(share_call.clone(), method.req_ser());
+        let sink = ClientCStreamSender::new(share_call.clone(), req_ser, opt.size_check);
         let recv = ClientCStreamReceiver {
             call: share_call,
-            resp_de: method.resp_de(),
+            metadata,
+            resp_de,
             finished: false,
         };
         Ok((sink, recv))
     }
 
-    pub fn server_streaming<Req, Resp>(
+    pub fn server_streaming<Req: ?Sized, Resp>(
         channel: &Channel,
-        method: &Method<Req, Resp>,
+        method: &str,
+        req_ser: SerializeFn<Req>,
+        resp_de: DeserializeFn<Resp>,
         req: &Req,
         mut opt: CallOption,
     ) -> Result<ClientSStreamReceiver<Resp>> {
-        let call = channel.create_call(method, &opt)?;
         let mut payload = vec![];
-        (method.req_ser())(req, &mut payload);
-        let cq_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_start_server_streaming(
-                call.call,
-                ctx,
-                payload.as_ptr() as _,
-                payload.len(),
-                opt.write_flags.flags,
-                opt.headers
-                    .as_mut()
-                    .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
-                opt.call_flags,
-                tag,
-            )
-        });
+        req_ser(req, &mut payload);
+        opt.check_message_size(payload.len())?;
+        opt.prepare_headers();
+        let call = channel.create_call(method, &opt)?;
+        call.check_send_size(payload.len())?;
+        call.on_sent(payload.len());
+        let metadata = Arc::new(SpinLock::new(ResponseMetadata::default()));
+        let cq_f = check_run_with_metadata(
+            BatchType::Finish,
+            Some(metadata.clone()),
+            call.trace("recv_status"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_start_server_streaming(
+                    call.call,
+                    ctx,
+                    payload.as_ptr() as _,
+                    payload.len(),
+                    opt.write_flags.flags,
+                    opt.headers
+                        .as_mut()
+                        .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
+                    opt.call_flags,
+                    tag,
+                )
+            },
+        );
 
-        // TODO: handle header
-        check_run(BatchType::Finish, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
-        });
+        check_run_with_metadata(
+            BatchType::RecvHeaders,
+            Some(metadata.clone()),
+            call.trace("recv_initial_metadata"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
+            },
+        );
 
-        Ok(ClientSStreamReceiver::new(call, cq_f, method.resp_de()))
+        Ok(ClientSStreamReceiver::new(
+            call,
+            cq_f,
+            metadata,
+            opt.idle_timeout,
+            resp_de,
+        ))
     }
 
     pub fn duplex_streaming<Req, Resp>(
         channel: &Channel,
-        method: &Method<Req, Resp>,
+        method: &str,
+        req_ser: SerializeFn<Req>,
+        resp_de: DeserializeFn<Resp>,
         mut opt: CallOption,
     ) -> Result<(ClientDuplexSender<Req>, ClientDuplexReceiver<Resp>)> {
+        opt.prepare_headers();
         let call = channel.create_call(method, &opt)?;
-        let cq_f = check_run(BatchType::Finish, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_start_duplex_streaming(
-                call.call,
-                ctx,
-                opt.headers
-                    .as_mut()
-                    .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
-                opt.call_flags,
-                tag,
-            )
-        });
+        let metadata = Arc::new(SpinLock::new(ResponseMetadata::default()));
+        let cq_f = check_run_with_metadata(
+            BatchType::Finish,
+            Some(metadata.clone()),
+            call.trace("recv_status"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_start_duplex_streaming(
+                    call.call,
+                    ctx,
+                    opt.headers
+                        .as_mut()
+                        .map_or_else(ptr::null_mut, |c| c as *mut _ as _),
+                    opt.call_flags,
+                    tag,
+                )
+            },
+        );
 
-        // TODO: handle header.
-        check_run(BatchType::Finish, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
-        });
+        check_run_with_metadata(
+            BatchType::RecvHeaders,
+            Some(metadata.clone()),
+            call.trace("recv_initial_metadata"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_recv_initial_metadata(call.call, ctx, tag)
+            },
+        );
 
         let share_call = Arc::new(SpinLock::new(ShareCall::new(call, cq_f)));
-        let sink = ClientDuplexSender::new(share_call.clone(), method.req_ser());
-        let recv = ClientDuplexReceiver::new(share_call, method.resp_de());
+        let sink = ClientDuplexSender::new(share_call.clone(), req_ser, opt.size_check);
+        let recv = ClientDuplexReceiver::new(share_call, metadata, opt.idle_timeout, resp_de);
         Ok((sink, recv))
     }
 }
 
+/// A handle for cancelling a call from any thread.
+///
+/// It's obtained by `call_handle` of the receivers and sinks of a call, and
+/// stays valid after they are moved into a future or another thread, for
+/// example when the receiver is blocked in `wait`. Cancelling a call that
+/// has finished has no effect.
+///
+/// The server is notified by a `RST_STREAM` frame as soon as the call is
+/// cancelled, its `RpcContext::cancelled` resolves and its pending reads and
+/// writes fail. The reason is only reported on the client side, gRPC doesn't
+/// send it to the server.
+pub struct CallHandle {
+    call: Call,
+}
+
+// gRPC Core allows cancelling a call from any thread.
+unsafe impl Sync for CallHandle {}
+
+impl CallHandle {
+    pub(crate) fn new(call: &Call) -> CallHandle {
+        unsafe {
+            grpc_sys::grpc_call_ref(call.call);
+            CallHandle {
+                call: Call::from_raw(call.call, call.cq.clone()),
+            }
+        }
+    }
+
+    /// Cancel the call, it fails with `CANCELLED` on the client side.
+    pub fn cancel(&self) {
+        self.call.cancel()
+    }
+
+    /// Cancel the call, it fails with `CANCELLED` and the reason as the
+    /// details on the client side.
+    pub fn cancel_with_reason(&self, reason: &str) {
+        self.call.cancel_with_reason(reason)
+    }
+
+    pub(crate) fn cancel_with_status(&self, status: &RpcStatus) {
+        self.call.cancel_with_status(status)
+    }
+}
+
+impl Clone for CallHandle {
+    fn clone(&self) -> CallHandle {
+        CallHandle::new(&self.call)
+    }
+}
+
 /// A receiver for unary request.
 ///
-/// The future is resolved once response is received.
+/// The future is resolved once response is received. The call is cancelled
+/// if the receiver is dropped before that.
 #[must_use = "if unused the ClientUnaryReceiver may immediately cancel the RPC"]
 pub struct ClientUnaryReceiver<T> {
     call: Call,
     resp_f: BatchFuture,
+    metadata: Arc<SpinLock<ResponseMetadata>>,
     resp_de: DeserializeFn<T>,
+    finished: bool,
 }
 
 impl<T> ClientUnaryReceiver<T> {
-    fn new(call: Call, resp_f: BatchFuture, resp_de: DeserializeFn<T>) -> ClientUnaryReceiver<T> {
+    fn new(
+        call: Call,
+        resp_f: BatchFuture,
+        metadata: Arc<SpinLock<ResponseMetadata>>,
+        resp_de: DeserializeFn<T>,
+    ) -> ClientUnaryReceiver<T> {
         ClientUnaryReceiver {
             call,
             resp_f,
+            metadata,
             resp_de,
+            finished: false,
         }
     }
 
@@ -247,19 +598,67 @@ impl<T> ClientUnaryReceiver<T> {
         self.call.cancel()
     }
 
+    /// Cancel the call with the reason, see [`CallHandle::cancel_with_reason`].
+    pub fn cancel_with_reason(&mut self, reason: &str) {
+        self.call.cancel_with_reason(reason)
+    }
+
+    /// Get a handle for cancelling the call.
+    pub fn call_handle(&self) -> CallHandle {
+        CallHandle::new(&self.call)
+    }
+
+    /// Get the initial metadata sent by the server.
+    ///
+    /// Returns `None` if the headers have not been received yet.
+    pub fn headers(&self) -> Option<Metadata> {
+        self.metadata.lock().headers.clone()
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// Trailers are available once the call finishes, no matter it
+    /// succeeds or fails. Returns `None` if they have not been received yet.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.metadata.lock().trailers.clone()
+    }
+
     #[inline]
     pub fn resp_de(&self, reader: MessageReader) -> Result<T> {
         (self.resp_de)(reader)
     }
 }
 
+impl<T> Drop for ClientUnaryReceiver<T> {
+    /// The corresponding RPC will be canceled if the response is not
+    /// received before dropping.
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancel();
+        }
+    }
+}
+
 impl<T> Future for ClientUnaryReceiver<T> {
     type Item = T;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<T, Error> {
-        let data = try_ready!(self.resp_f.poll());
-        let t = self.resp_de(data.unwrap())?;
+        let data = match self.resp_f.poll() {
+            Ok(Async::Ready(data)) => data.unwrap(),
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                self.finished = true;
+                if let Error::RpcFailure(ref status) = e {
+                    self.call.on_status(status.status);
+                }
+                return Err(e);
+            }
+        };
+        self.finished = true;
+        self.call.on_received(&data);
+        self.call.on_status(RpcStatusCode::OK);
+        let t = self.resp_de(data)?;
         Ok(Async::Ready(t))
     }
 }
@@ -274,6 +673,7 @@ impl<T> Future for ClientUnaryReceiver<T> {
 #[must_use = "if unused the ClientCStreamReceiver may immediately cancel the RPC"]
 pub struct ClientCStreamReceiver<T> {
     call: Arc<SpinLock<ShareCall>>,
+    metadata: Arc<SpinLock<ResponseMetadata>>,
     resp_de: DeserializeFn<T>,
     finished: bool,
 }
@@ -285,6 +685,32 @@ impl<T> ClientCStreamReceiver<T> {
         lock.call.cancel()
     }
 
+    /// Cancel the call with the reason, see [`CallHandle::cancel_with_reason`].
+    pub fn cancel_with_reason(&mut self, reason: &str) {
+        let lock = self.call.lock();
+        lock.call.cancel_with_reason(reason)
+    }
+
+    /// Get a handle for cancelling the call.
+    pub fn call_handle(&self) -> CallHandle {
+        self.call.handle()
+    }
+
+    /// Get the initial metadata sent by the server.
+    ///
+    /// Returns `None` if the headers have not been received yet.
+    pub fn headers(&self) -> Option<Metadata> {
+        self.metadata.lock().headers.clone()
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// Trailers are available once the call finishes, no matter it
+    /// succeeds or fails. Returns `None` if they have not been received yet.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.metadata.lock().trailers.clone()
+    }
+
     #[inline]
     pub fn resp_de(&self, reader: MessageReader) -> Result<T> {
         (self.resp_de)(reader)
@@ -317,6 +743,12 @@ impl<T> Future for ClientCStreamReceiver<T> {
 }
 
 /// A sink for client streaming call and duplex streaming call.
+///
+/// It's a `Sink` of messages and their write flags. Only one message is
+/// written at a time, `start_send` returns `NotReady` until the previous
+/// write finishes, so combinators like `send_all` and `forward` are slowed
+/// down by a server that reads slowly.
+///
 /// To close the sink properly, you should call [`close`] before dropping.
 ///
 /// [`close`]: #method.close
@@ -329,19 +761,54 @@ pub struct StreamingCallSink<Req> {
 }
 
 impl<Req> StreamingCallSink<Req> {
-    fn new(call: Arc<SpinLock<ShareCall>>, req_ser: SerializeFn<Req>) -> StreamingCallSink<Req> {
+    fn new(
+        call: Arc<SpinLock<ShareCall>>,
+        req_ser: SerializeFn<Req>,
+        size_check: Option<MessageSizeCheck>,
+    ) -> StreamingCallSink<Req> {
+        let mut sink_base = SinkBase::new(None);
+        sink_base.size_check = size_check;
         StreamingCallSink {
             call,
-            sink_base: SinkBase::new(false),
+            sink_base,
             close_f: None,
             req_ser,
         }
     }
 
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         let call = self.call.lock();
         call.call.cancel()
     }
+
+    /// Cancel the call with the reason, see [`CallHandle::cancel_with_reason`].
+    pub fn cancel_with_reason(&mut self, reason: &str) {
+        let call = self.call.lock();
+        call.call.cancel_with_reason(reason)
+    }
+
+    /// Get a handle for cancelling the call.
+    pub fn call_handle(&self) -> CallHandle {
+        self.call.handle()
+    }
+
+    /// Buffer the following messages until the sink is uncorked and
+    /// flushed, so that many small messages can be coalesced into fewer
+    /// HTTP/2 frames.
+    ///
+    /// `poll_complete`, i.e. `flush`, sends out the buffered messages even if
+    /// the sink is still corked, and so does `close`.
+    pub fn cork(&mut self) {
+        self.sink_base.corked = true;
+    }
+
+    /// Stop buffering messages, see [`cork`](#method.cork).
+    ///
+    /// The buffered messages are sent along with the next message or flush.
+    pub fn uncork(&mut self) {
+        self.sink_base.corked = false;
+    }
 }
 
 impl<P> Drop for StreamingCallSink<P> {
@@ -381,14 +848,15 @@ impl<Req> Sink for StreamingCallSink<Req> {
             let mut call = self.call.lock();
             call.check_alive()?;
         }
-        self.sink_base.poll_complete()
+        self.sink_base.poll_flush(&mut self.call)
     }
 
     fn close(&mut self) -> Poll<(), Error> {
+        if self.close_f.is_none() {
+            try_ready!(self.sink_base.poll_flush(&mut self.call));
+        }
         let mut call = self.call.lock();
         if self.close_f.is_none() {
-            try_ready!(self.sink_base.poll_complete());
-
             let close_f = call.call.start_send_close_client()?;
             self.close_f = Some(close_f);
         }
@@ -415,21 +883,50 @@ pub type ClientCStreamSender<T> = StreamingCallSink<T>;
 /// [`close`]: #method.close
 pub type ClientDuplexSender<T> = StreamingCallSink<T>;
 
+/// Fails a response stream if no message is received in time.
+struct IdleTimer {
+    timeout: Duration,
+    delay: Delay,
+}
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> IdleTimer {
+        IdleTimer {
+            timeout,
+            delay: Delay::new(Instant::now() + timeout),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset(Instant::now() + self.timeout);
+    }
+
+    fn expired(&mut self) -> bool {
+        self.delay.poll() == Ok(Async::Ready(()))
+    }
+}
+
 struct ResponseStreamImpl<H, T> {
     call: H,
     msg_f: Option<BatchFuture>,
     read_done: bool,
     finished: bool,
+    idle_timer: Option<IdleTimer>,
     resp_de: DeserializeFn<T>,
 }
 
 impl<H: ShareCallHolder, T> ResponseStreamImpl<H, T> {
-    fn new(call: H, resp_de: DeserializeFn<T>) -> ResponseStreamImpl<H, T> {
+    fn new(
+        call: H,
+        idle_timeout: Option<Duration>,
+        resp_de: DeserializeFn<T>,
+    ) -> ResponseStreamImpl<H, T> {
         ResponseStreamImpl {
             call,
             msg_f: None,
             read_done: false,
             finished: false,
+            idle_timer: idle_timeout.map(IdleTimer::new),
             resp_de,
         }
     }
@@ -438,6 +935,31 @@ impl<H: ShareCallHolder, T> ResponseStreamImpl<H, T> {
         self.call.call(|c| c.call.cancel())
     }
 
+    fn cancel_with_reason(&mut self, reason: &str) {
+        self.call.call(|c| c.call.cancel_with_reason(reason))
+    }
+
+    fn check_idle(&mut self) -> Result<()> {
+        if self.read_done {
+            return Ok(());
+        }
+        let timeout = match self.idle_timer {
+            Some(ref mut timer) => {
+                if !timer.expired() {
+                    return Ok(());
+                }
+                timer.timeout
+            }
+            None => return Ok(()),
+        };
+        self.idle_timer.take();
+        self.cancel();
+        Err(Error::RpcFailure(RpcStatus::new(
+            RpcStatusCode::DEADLINE_EXCEEDED,
+            Some(format!("no message received in {:?}", timeout)),
+        )))
+    }
+
     fn poll(&mut self) -> Poll<Option<T>, Error> {
         if !self.finished {
             let finished = &mut self.finished;
@@ -452,7 +974,16 @@ impl<H: ShareCallHolder, T> ResponseStreamImpl<H, T> {
         loop {
             if !self.read_done {
                 if let Some(ref mut msg_f) = self.msg_f {
-                    bytes = try_ready!(msg_f.poll());
+                    match msg_f.poll()? {
+                        Async::Ready(b) => bytes = b,
+                        Async::NotReady => {
+                            // Only a read that is still pending can be idle, a
+                            // message that arrives in time is not failed no
+                            // matter how late it's taken.
+                            self.check_idle()?;
+                            return Ok(Async::NotReady);
+                        }
+                    }
                     if bytes.is_none() {
                         self.read_done = true;
                     }
@@ -470,7 +1001,12 @@ impl<H: ShareCallHolder, T> ResponseStreamImpl<H, T> {
             self.msg_f.take();
             let msg_f = self.call.call(|c| c.call.start_recv_message())?;
             self.msg_f = Some(msg_f);
+            // The timer runs from the time the read is issued.
+            if let Some(ref mut timer) = self.idle_timer {
+                timer.reset();
+            }
             if let Some(data) = bytes {
+                self.call.call(|c| c.call.on_received(&data));
                 let msg = (self.resp_de)(data)?;
                 return Ok(Async::Ready(Some(msg)));
             }
@@ -487,26 +1023,69 @@ impl<H: ShareCallHolder, T> ResponseStreamImpl<H, T> {
 }
 
 /// A receiver for server streaming call.
+///
+/// The next message is only read after the previous one is taken, so a
+/// consumer that is slow pushes back on the server through flow control.
+///
+/// The call is cancelled if the receiver is dropped before the stream ends.
 #[must_use = "if unused the ClientSStreamReceiver may immediately cancel the RPC"]
 pub struct ClientSStreamReceiver<Resp> {
     imp: ResponseStreamImpl<ShareCall, Resp>,
+    metadata: Arc<SpinLock<ResponseMetadata>>,
 }
 
 impl<Resp> ClientSStreamReceiver<Resp> {
     fn new(
         call: Call,
         finish_f: BatchFuture,
+        metadata: Arc<SpinLock<ResponseMetadata>>,
+        idle_timeout: Option<Duration>,
         de: DeserializeFn<Resp>,
     ) -> ClientSStreamReceiver<Resp> {
         let share_call = ShareCall::new(call, finish_f);
         ClientSStreamReceiver {
-            imp: ResponseStreamImpl::new(share_call, de),
+            imp: ResponseStreamImpl::new(share_call, idle_timeout, de),
+            metadata,
         }
     }
 
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         self.imp.cancel()
     }
+
+    /// Cancel the call with the reason, see [`CallHandle::cancel_with_reason`].
+    pub fn cancel_with_reason(&mut self, reason: &str) {
+        self.imp.cancel_with_reason(reason)
+    }
+
+    /// Get a handle for cancelling the call.
+    pub fn call_handle(&self) -> CallHandle {
+        self.imp.call.handle()
+    }
+
+    /// Get the initial metadata sent by the server.
+    ///
+    /// Returns `None` if the headers have not been received yet.
+    pub fn headers(&self) -> Option<Metadata> {
+        self.metadata.lock().headers.clone()
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// Trailers are available once the call finishes, no matter it
+    /// succeeds or fails. Returns `None` if they have not been received yet.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.metadata.lock().trailers.clone()
+    }
+}
+
+impl<Resp> Drop for ClientSStreamReceiver<Resp> {
+    /// The corresponding RPC will be canceled if the receiver did not
+    /// finish before dropping.
+    fn drop(&mut self) {
+        self.imp.on_drop()
+    }
 }
 
 impl<Resp> Stream for ClientSStreamReceiver<Resp> {
@@ -520,6 +1099,9 @@ impl<Resp> Stream for ClientSStreamReceiver<Resp> {
 
 /// A response receiver for duplex call.
 ///
+/// Like [`ClientSStreamReceiver`], the next message is only read after the
+/// previous one is taken.
+///
 /// If the corresponding sink has dropped or cancelled, this will poll a
 /// [`RpcFailure`] error with the [`Cancelled`] status.
 ///
@@ -528,18 +1110,51 @@ impl<Resp> Stream for ClientSStreamReceiver<Resp> {
 #[must_use = "if unused the ClientDuplexReceiver may immediately cancel the RPC"]
 pub struct ClientDuplexReceiver<Resp> {
     imp: ResponseStreamImpl<Arc<SpinLock<ShareCall>>, Resp>,
+    metadata: Arc<SpinLock<ResponseMetadata>>,
 }
 
 impl<Resp> ClientDuplexReceiver<Resp> {
-    fn new(call: Arc<SpinLock<ShareCall>>, de: DeserializeFn<Resp>) -> ClientDuplexReceiver<Resp> {
+    fn new(
+        call: Arc<SpinLock<ShareCall>>,
+        metadata: Arc<SpinLock<ResponseMetadata>>,
+        idle_timeout: Option<Duration>,
+        de: DeserializeFn<Resp>,
+    ) -> ClientDuplexReceiver<Resp> {
         ClientDuplexReceiver {
-            imp: ResponseStreamImpl::new(call, de),
+            imp: ResponseStreamImpl::new(call, idle_timeout, de),
+            metadata,
         }
     }
 
+    /// Cancel the call.
     pub fn cancel(&mut self) {
         self.imp.cancel()
     }
+
+    /// Cancel the call with the reason, see [`CallHandle::cancel_with_reason`].
+    pub fn cancel_with_reason(&mut self, reason: &str) {
+        self.imp.cancel_with_reason(reason)
+    }
+
+    /// Get a handle for cancelling the call.
+    pub fn call_handle(&self) -> CallHandle {
+        self.imp.call.handle()
+    }
+
+    /// Get the initial metadata sent by the server.
+    ///
+    /// Returns `None` if the headers have not been received yet.
+    pub fn headers(&self) -> Option<Metadata> {
+        self.metadata.lock().headers.clone()
+    }
+
+    /// Get the trailing metadata sent by the server.
+    ///
+    /// Trailers are available once the call finishes, no matter it
+    /// succeeds or fails. Returns `None` if they have not been received yet.
+    pub fn trailers(&self) -> Option<Metadata> {
+        self.metadata.lock().trailers.clone()
+    }
 }
 
 impl<Resp> Drop for ClientDuplexReceiver<Resp> {
@@ -14,9 +14,12 @@
 pub mod client;
 pub mod server;
 
+use std::collections::VecDeque;
+use std::ffi::CString;
 use std::io::{self, BufRead, ErrorKind, Read};
-use std::sync::Arc;
-use std::{cmp, mem, ptr, slice, usize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{cmp, mem, ptr, result, slice, usize};
 
 use crate::cq::CompletionQueue;
 use crate::grpc_sys::{
@@ -27,10 +30,20 @@ use bytes::Buf;
 use futures::{Async, Future, Poll};
 use libc::c_void;
 
+use self::client::CallHandle;
+use self::server::CallGuard;
+use crate::call_tracer::BatchTrace;
 use crate::codec::{DeserializeFn, Marshaller, SerializeFn};
 use crate::error::{Error, Result};
 use crate::grpc_sys::grpc_status_code::*;
-use crate::task::{self, BatchFuture, BatchType, CallTag, SpinLock};
+use crate::load_report::LOAD_REPORT_KEY;
+use crate::metadata::{Metadata, MetadataBuilder};
+use crate::stats::CallStats;
+use crate::task::{self, BatchFuture, BatchType, CallTag, CancelState, ResponseMetadata, SpinLock};
+use crate::watchdog::Watchdog;
+
+/// The trailer that carries the binary details of a status.
+const STATUS_DETAILS_KEY: &str = "grpc-status-details-bin";
 
 /// An gRPC status code structure.
 /// This type contains constants for all gRPC status codes.
@@ -202,6 +215,8 @@ pub struct RpcStatus {
 
     /// Optional detail string.
     pub details: Option<String>,
+
+    details_bin: Option<Vec<u8>>,
 }
 
 impl RpcStatus {
@@ -210,6 +225,7 @@ impl RpcStatus {
         RpcStatus {
             status: code.into(),
             details,
+            details_bin: None,
         }
     }
 
@@ -217,6 +233,24 @@ impl RpcStatus {
     pub fn ok() -> RpcStatus {
         RpcStatus::new(RpcStatusCode::OK, None)
     }
+
+    /// Attach binary details to the status, which are usually a serialized
+    /// `google.rpc.Status`.
+    ///
+    /// They are sent in the `grpc-status-details-bin` trailer by servers.
+    pub fn with_details_bin(mut self, details: Vec<u8>) -> RpcStatus {
+        self.details_bin = Some(details);
+        self
+    }
+
+    /// Get the binary details of the status, see
+    /// [`RpcStatus::with_details_bin`].
+    ///
+    /// For a status received by client, they come from the
+    /// `grpc-status-details-bin` trailer.
+    pub fn details_bin(&self) -> Option<&[u8]> {
+        self.details_bin.as_deref()
+    }
 }
 
 /// `MessageReader` is a zero-copy reader for the message payload.
@@ -232,11 +266,45 @@ pub struct MessageReader {
 }
 
 impl MessageReader {
+    /// Create a reader over a copy of the data.
+    pub(crate) fn from_bytes(data: &[u8]) -> MessageReader {
+        let mut slices = [grpc_slice::from(data)];
+        let mut buf = GrpcByteBuffer::from(&mut slices[..]);
+        let reader = grpc_byte_buffer_reader::from(&mut buf);
+        let length = reader.len();
+
+        MessageReader {
+            _buf: buf,
+            reader,
+            buffer_slice: Default::default(),
+            buffer_offset: 0,
+            length,
+        }
+    }
+
     /// Get the available bytes count of the reader.
     #[inline]
     pub fn pending_bytes_count(&self) -> usize {
         self.length
     }
+
+    /// Copy at most `n` bytes from the start of the message, no matter how
+    /// much has been consumed, without consuming them.
+    pub(crate) fn peek(&self, n: usize) -> Vec<u8> {
+        let mut buf = self._buf.clone();
+        let reader = grpc_byte_buffer_reader::from(&mut buf);
+        let length = reader.len();
+        let reader = MessageReader {
+            _buf: buf,
+            reader,
+            buffer_slice: Default::default(),
+            buffer_offset: 0,
+            length,
+        };
+        let mut prefix = Vec::with_capacity(cmp::min(n, length));
+        reader.take(n as u64).read_to_end(&mut prefix).unwrap();
+        prefix
+    }
 }
 
 unsafe impl Sync for MessageReader {}
@@ -381,6 +449,12 @@ impl BatchContext {
         }
     }
 
+    /// Check whether the server side call is cancelled, only meaningful for
+    /// the batch that receives the close of the call.
+    pub fn recv_close_on_server_cancelled(&self) -> bool {
+        unsafe { grpc_sys::grpcwrap_batch_context_recv_close_on_server_cancelled(self.ctx) != 0 }
+    }
+
     /// Get the status of the rpc call.
     pub fn rpc_status(&self) -> RpcStatus {
         let status = RpcStatusCode(unsafe {
@@ -401,7 +475,35 @@ impl BatchContext {
             }
         };
 
-        RpcStatus::new(status, details)
+        let mut status = RpcStatus::new(status, details);
+        if status.status != RpcStatusCode::OK {
+            status.details_bin = self
+                .recv_trailing_metadata()
+                .find(STATUS_DETAILS_KEY)
+                .map(<[u8]>::to_vec);
+        }
+        status
+    }
+
+    /// Get the initial metadata received from the remote side.
+    ///
+    /// The returned metadata is only valid during the lifetime of the context.
+    pub fn recv_initial_metadata(&self) -> &Metadata {
+        unsafe {
+            let ptr = grpc_sys::grpcwrap_batch_context_recv_initial_metadata(self.ctx);
+            &*(ptr as *const Metadata)
+        }
+    }
+
+    /// Get the trailing metadata received from the remote side.
+    ///
+    /// The returned metadata is only valid during the lifetime of the context.
+    pub fn recv_trailing_metadata(&self) -> &Metadata {
+        unsafe {
+            let ptr =
+                grpc_sys::grpcwrap_batch_context_recv_status_on_client_trailing_metadata(self.ctx);
+            &*(ptr as *const Metadata)
+        }
     }
 
     /// Fetch the response bytes of the rpc call.
@@ -427,20 +529,68 @@ impl Drop for BatchContext {
 }
 
 #[inline]
-fn box_batch_tag(tag: CallTag) -> (*mut grpcwrap_batch_context, *mut c_void) {
-    let tag_box = Box::new(tag);
+fn box_batch_tag(tag_box: Box<CallTag>) -> (*mut grpcwrap_batch_context, *mut c_void) {
     (
         tag_box.batch_ctx().unwrap().as_ptr(),
         Box::into_raw(tag_box) as _,
     )
 }
 
+/// The guards of a batch, which are notified once the batch is resolved.
+#[derive(Default)]
+pub struct BatchGuard {
+    watchdog: Option<Box<dyn Send>>,
+    trace: Option<BatchTrace>,
+}
+
+impl BatchGuard {
+    /// Report that the batch with the tag is going to be started.
+    fn start(&mut self, tag: usize) {
+        if let Some(ref mut trace) = self.trace {
+            trace.start(tag);
+        }
+    }
+
+    /// Report that the batch is resolved and release the guards.
+    pub fn resolve(&mut self, success: bool) {
+        self.watchdog.take();
+        if let Some(trace) = self.trace.take() {
+            trace.complete(success);
+        }
+    }
+}
+
 /// A helper function that runs the batch call and checks the result.
-fn check_run<F>(bt: BatchType, f: F) -> BatchFuture
+fn check_run<F>(bt: BatchType, guard: BatchGuard, f: F) -> BatchFuture
 where
     F: FnOnce(*mut grpcwrap_batch_context, *mut c_void) -> grpc_call_error,
 {
-    let (cq_f, tag) = CallTag::batch_pair(bt);
+    check_run_with_metadata(bt, None, guard, f)
+}
+
+/// Similar to `check_run`, but stores the received metadata into `metadata`.
+fn check_run_with_metadata<F>(
+    bt: BatchType,
+    metadata: Option<Arc<SpinLock<ResponseMetadata>>>,
+    guard: BatchGuard,
+    f: F,
+) -> BatchFuture
+where
+    F: FnOnce(*mut grpcwrap_batch_context, *mut c_void) -> grpc_call_error,
+{
+    let (cq_f, tag) = CallTag::batch_pair(bt, metadata, guard);
+    check_run_tag(cq_f, tag, f)
+}
+
+fn check_run_tag<F>(cq_f: BatchFuture, tag: CallTag, f: F) -> BatchFuture
+where
+    F: FnOnce(*mut grpcwrap_batch_context, *mut c_void) -> grpc_call_error,
+{
+    let mut tag = Box::new(tag);
+    let addr = &*tag as *const CallTag as usize;
+    if let CallTag::Batch(ref mut batch) = *tag {
+        batch.guard_mut().start(addr);
+    }
     let (batch_ptr, tag_ptr) = box_batch_tag(tag);
     let code = f(batch_ptr, tag_ptr);
     if code != grpc_call_error::GRPC_CALL_OK {
@@ -460,6 +610,11 @@ where
 pub struct Call {
     pub call: *mut grpc_call,
     pub cq: CompletionQueue,
+    pub stats: Option<Arc<CallStats>>,
+    pub watchdog: Option<Arc<Watchdog>>,
+    /// The limit of the size of sent messages, it's checked before messages
+    /// are handed to gRPC Core so that violations are reported clearly.
+    pub max_send_message_len: Option<usize>,
 }
 
 unsafe impl Send for Call {}
@@ -467,7 +622,67 @@ unsafe impl Send for Call {}
 impl Call {
     pub unsafe fn from_raw(call: *mut grpc_sys::grpc_call, cq: CompletionQueue) -> Call {
         assert!(!call.is_null());
-        Call { call, cq }
+        Call {
+            call,
+            cq,
+            stats: None,
+            watchdog: None,
+            max_send_message_len: None,
+        }
+    }
+
+    /// Watch the batch of the operation if the watchdog is enabled, it's
+    /// also traced if a call tracer is set.
+    pub(crate) fn watch(&self, op: &'static str) -> BatchGuard {
+        BatchGuard {
+            watchdog: self.watchdog.as_ref().map(|w| w.watch(self.call, op)),
+            trace: BatchTrace::new(self.call, op),
+        }
+    }
+
+    /// Similar to `watch`, but the batch is only traced, which is used by
+    /// the batches that last as long as the call.
+    pub(crate) fn trace(&self, op: &'static str) -> BatchGuard {
+        BatchGuard {
+            watchdog: None,
+            trace: BatchTrace::new(self.call, op),
+        }
+    }
+
+    /// Check the size of a message that is going to be sent against
+    /// `max_send_message_len`.
+    pub(crate) fn check_send_size(&self, size: usize) -> Result<()> {
+        match self.max_send_message_len {
+            Some(max) if size > max => Err(Error::RpcFailure(RpcStatus::new(
+                RpcStatusCode::RESOURCE_EXHAUSTED,
+                Some(format!(
+                    "Sent message larger than max ({} vs. {})",
+                    size, max
+                )),
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record a message that is going to be sent.
+    fn on_sent(&self, bytes: usize) {
+        if let Some(ref stats) = self.stats {
+            stats.sent(bytes);
+        }
+    }
+
+    /// Record a received message.
+    fn on_received(&self, msg: &MessageReader) {
+        if let Some(ref stats) = self.stats {
+            stats.received(msg.pending_bytes_count());
+        }
+    }
+
+    /// Record the status of the call.
+    fn on_status(&self, status: RpcStatusCode) {
+        if let Some(ref stats) = self.stats {
+            stats.finish(status);
+        }
     }
 
     /// Send a message asynchronously.
@@ -477,37 +692,47 @@ impl Call {
         write_flags: u32,
         initial_meta: bool,
     ) -> Result<BatchFuture> {
-        let _cq_ref = self.cq.borrow()?;
+        let _cq_ref = self.cq.try_ref()?;
+        self.check_send_size(msg.len())?;
+        self.on_sent(msg.len());
         let i = if initial_meta { 1 } else { 0 };
-        let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_send_message(
-                self.call,
-                ctx,
-                msg.as_ptr() as _,
-                msg.len(),
-                write_flags,
-                i,
-                tag,
-            )
-        });
+        let f = check_run(
+            BatchType::Finish,
+            self.watch("send_message"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_send_message(
+                    self.call,
+                    ctx,
+                    msg.as_ptr() as _,
+                    msg.len(),
+                    write_flags,
+                    i,
+                    tag,
+                )
+            },
+        );
         Ok(f)
     }
 
     /// Finish the rpc call from client.
     pub fn start_send_close_client(&mut self) -> Result<BatchFuture> {
-        let _cq_ref = self.cq.borrow()?;
-        let f = check_run(BatchType::Finish, |_, tag| unsafe {
-            grpc_sys::grpcwrap_call_send_close_from_client(self.call, tag)
-        });
+        let _cq_ref = self.cq.try_ref()?;
+        let f = check_run(
+            BatchType::Finish,
+            self.watch("send_close_from_client"),
+            |_, tag| unsafe { grpc_sys::grpcwrap_call_send_close_from_client(self.call, tag) },
+        );
         Ok(f)
     }
 
     /// Receive a message asynchronously.
     pub fn start_recv_message(&mut self) -> Result<BatchFuture> {
-        let _cq_ref = self.cq.borrow()?;
-        let f = check_run(BatchType::Read, |ctx, tag| unsafe {
-            grpc_sys::grpcwrap_call_recv_message(self.call, ctx, tag)
-        });
+        let _cq_ref = self.cq.try_ref()?;
+        let f = check_run(
+            BatchType::Read,
+            self.watch("recv_message"),
+            |ctx, tag| unsafe { grpc_sys::grpcwrap_call_recv_message(self.call, ctx, tag) },
+        );
         Ok(f)
     }
 
@@ -515,27 +740,102 @@ impl Call {
     ///
     /// Future will finish once close is received by the server.
     pub fn start_server_side(&mut self) -> Result<BatchFuture> {
-        let _cq_ref = self.cq.borrow()?;
-        let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
+        let _cq_ref = self.cq.try_ref()?;
+        // It's not finished until the call is finished, so it's not watched.
+        let guard = self.trace("recv_close_on_server");
+        let f = check_run(BatchType::Finish, guard, |ctx, tag| unsafe {
             grpc_sys::grpcwrap_call_start_serverside(self.call, ctx, tag)
         });
         Ok(f)
     }
 
+    /// Similar to `start_server_side`, but `cancel` is updated once the call
+    /// is finished.
+    pub(crate) fn start_server_side_with_cancel(
+        &mut self,
+        cancel: Arc<SpinLock<CancelState>>,
+    ) -> Result<BatchFuture> {
+        let _cq_ref = self.cq.try_ref()?;
+        let guard = self.trace("recv_close_on_server");
+        let (cq_f, tag) = CallTag::server_close_pair(cancel, guard);
+        let f = check_run_tag(cq_f, tag, |ctx, tag| unsafe {
+            grpc_sys::grpcwrap_call_start_serverside(self.call, ctx, tag)
+        });
+        Ok(f)
+    }
+
+    /// Send initial metadata from server.
+    pub fn start_send_initial_metadata(&mut self, metadata: &mut Metadata) -> Result<BatchFuture> {
+        let _cq_ref = self.cq.try_ref()?;
+        let f = check_run(
+            BatchType::Finish,
+            self.watch("send_initial_metadata"),
+            |ctx, tag| unsafe {
+                grpc_sys::grpcwrap_call_send_initial_metadata(
+                    self.call,
+                    ctx,
+                    metadata as *mut _ as _,
+                    tag,
+                )
+            },
+        );
+        Ok(f)
+    }
+
     /// Send a status from server.
     pub fn start_send_status_from_server(
         &mut self,
         status: &RpcStatus,
         send_empty_metadata: bool,
+        trailers: Option<&mut Metadata>,
+        load_report: Option<Vec<u8>>,
         payload: &Option<Vec<u8>>,
         write_flags: u32,
     ) -> Result<BatchFuture> {
-        let _cq_ref = self.cq.borrow()?;
+        let _cq_ref = self.cq.try_ref()?;
+        // Fail the call instead of sending a message that is too large, so
+        // the client receives the reason.
+        let (too_large, no_payload);
+        let (status, payload) = match payload.as_ref().map(|p| self.check_send_size(p.len())) {
+            Some(Err(Error::RpcFailure(s))) => {
+                warn!("failed to send response: {:?}", s.details);
+                too_large = s;
+                no_payload = None;
+                (&too_large, &no_payload)
+            }
+            _ => (status, payload),
+        };
         let send_empty_metadata = if send_empty_metadata { 1 } else { 0 };
         let (payload_ptr, payload_len) = payload
             .as_ref()
             .map_or((ptr::null(), 0), |b| (b.as_ptr(), b.len()));
-        let f = check_run(BatchType::Finish, |ctx, tag| unsafe {
+        if payload.is_some() {
+            self.on_sent(payload_len);
+        }
+        self.on_status(status.status);
+        // The binary details and the load report are sent as trailers along
+        // with the others.
+        let mut extra_trailers;
+        let trailers = if status.details_bin().is_some() || load_report.is_some() {
+            let mut builder = MetadataBuilder::new();
+            if let Some(trailers) = trailers {
+                for (key, value) in trailers.iter() {
+                    builder.add_metadata(key, value)?;
+                }
+            }
+            if let Some(details) = status.details_bin() {
+                builder.add_metadata(STATUS_DETAILS_KEY, details)?;
+            }
+            if let Some(report) = load_report {
+                builder.add_metadata(LOAD_REPORT_KEY, &report)?;
+            }
+            extra_trailers = builder.build();
+            Some(&mut extra_trailers)
+        } else {
+            trailers
+        };
+        let guard = self.watch("send_status_from_server");
+        let f = check_run(BatchType::Finish, guard, |ctx, tag| unsafe {
             let details_ptr = status
                 .details
                 .as_ref()
@@ -547,7 +847,7 @@ impl Call {
                 status.status.into(),
                 details_ptr,
                 details_len,
-                ptr::null_mut(),
+                trailers.map_or_else(ptr::null_mut, |m| m as *mut _ as _),
                 send_empty_metadata,
                 payload_ptr as _,
                 payload_len,
@@ -560,15 +860,16 @@ impl Call {
 
     /// Abort an rpc call before handler is called.
     pub fn abort(self, status: &RpcStatus) {
-        match self.cq.borrow() {
+        match self.cq.try_ref() {
             // Queue is shutdown, ignore.
             Err(Error::QueueShutdown) => return,
             Err(e) => panic!("unexpected error when aborting call: {:?}", e),
             _ => {}
         }
+        self.on_status(status.status);
         let call_ptr = self.call;
         let tag = CallTag::abort(self);
-        let (batch_ptr, tag_ptr) = box_batch_tag(tag);
+        let (batch_ptr, tag_ptr) = box_batch_tag(Box::new(tag));
 
         let code = unsafe {
             let details_ptr = status
@@ -600,7 +901,7 @@ impl Call {
 
     /// Cancel the rpc call by client.
     fn cancel(&self) {
-        match self.cq.borrow() {
+        match self.cq.try_ref() {
             // Queue is shutdown, ignore.
             Err(Error::QueueShutdown) => return,
             Err(e) => panic!("unexpected error when canceling call: {:?}", e),
@@ -610,6 +911,34 @@ impl Call {
             grpc_sys::grpc_call_cancel(self.call, ptr::null_mut());
         }
     }
+
+    /// Cancel the rpc call by client, the reason is used as the details of
+    /// the `CANCELLED` status reported locally.
+    fn cancel_with_reason(&self, reason: &str) {
+        let status = RpcStatus::new(RpcStatusCode::CANCELLED, Some(reason.to_owned()));
+        self.cancel_with_status(&status)
+    }
+
+    /// Cancel the rpc call with the status, which is sent to the client if
+    /// it's called by server.
+    fn cancel_with_status(&self, status: &RpcStatus) {
+        match self.cq.try_ref() {
+            // Queue is shutdown, ignore.
+            Err(Error::QueueShutdown) => return,
+            Err(e) => panic!("unexpected error when canceling call: {:?}", e),
+            _ => {}
+        }
+        let details = status.details.as_ref().map_or("", |d| d.as_str());
+        let details = CString::new(details.replace('\0', "")).unwrap();
+        unsafe {
+            grpc_sys::grpc_call_cancel_with_status(
+                self.call,
+                status.status.into(),
+                details.as_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
 }
 
 impl Drop for Call {
@@ -628,6 +957,8 @@ struct ShareCall {
     close_f: BatchFuture,
     finished: bool,
     status: Option<RpcStatus>,
+    // Dropped together with the call.
+    guard: Option<CallGuard>,
 }
 
 impl ShareCall {
@@ -637,6 +968,7 @@ impl ShareCall {
             close_f,
             finished: false,
             status: None,
+            guard: None,
         }
     }
 
@@ -646,11 +978,16 @@ impl ShareCall {
     fn poll_finish(&mut self) -> Poll<Option<MessageReader>, Error> {
         let res = match self.close_f.poll() {
             Err(Error::RpcFailure(status)) => {
+                self.call.on_status(status.status);
                 self.status = Some(status.clone());
                 Err(Error::RpcFailure(status))
             }
             Ok(Async::NotReady) => return Ok(Async::NotReady),
             Ok(Async::Ready(msg)) => {
+                if let Some(ref msg) = msg {
+                    self.call.on_received(msg);
+                }
+                self.call.on_status(RpcStatusCode::OK);
                 self.status = Some(RpcStatus::ok());
                 Ok(Async::Ready(msg))
             }
@@ -675,12 +1012,18 @@ impl ShareCall {
 /// A helper trait that allows executing function on the inernal `ShareCall` struct.
 trait ShareCallHolder {
     fn call<R, F: FnOnce(&mut ShareCall) -> R>(&mut self, f: F) -> R;
+
+    fn handle(&self) -> CallHandle;
 }
 
 impl ShareCallHolder for ShareCall {
     fn call<R, F: FnOnce(&mut ShareCall) -> R>(&mut self, f: F) -> R {
         f(self)
     }
+
+    fn handle(&self) -> CallHandle {
+        CallHandle::new(&self.call)
+    }
 }
 
 impl ShareCallHolder for Arc<SpinLock<ShareCall>> {
@@ -688,6 +1031,10 @@ impl ShareCallHolder for Arc<SpinLock<ShareCall>> {
         let mut call = self.lock();
         f(&mut call)
     }
+
+    fn handle(&self) -> CallHandle {
+        CallHandle::new(&self.lock().call)
+    }
 }
 
 /// A helper struct for constructing Stream object for batch requests.
@@ -749,10 +1096,12 @@ impl StreamingBase {
         self.msg_f.take();
         let msg_f = call.call(|c| c.call.start_recv_message())?;
         self.msg_f = Some(msg_f);
-        if bytes.is_none() {
-            self.poll(call, true)
-        } else {
-            Ok(Async::Ready(bytes))
+        match bytes {
+            None => self.poll(call, true),
+            Some(msg) => {
+                call.call(|c| c.call.on_received(&msg));
+                Ok(Async::Ready(Some(msg)))
+            }
         }
     }
 
@@ -806,29 +1155,187 @@ impl WriteFlags {
     }
 }
 
+/// A flag shared by all the senders of a server call, so that
+/// initial metadata is sent only once.
+///
+/// It also carries the load report set by the context, which is sent along
+/// with the status.
+#[derive(Clone, Default)]
+pub struct PendingHeaders {
+    sent: Arc<AtomicBool>,
+    load_report: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl PendingHeaders {
+    /// Mark the initial metadata as sent.
+    ///
+    /// Returns true if the caller is responsible for sending it.
+    fn take(&self) -> bool {
+        !self.sent.swap(true, Ordering::SeqCst)
+    }
+
+    fn set_load_report(&self, report: Vec<u8>) {
+        *self.load_report.lock().unwrap() = Some(report);
+    }
+
+    fn take_load_report(&self) -> Option<Vec<u8>> {
+        self.load_report.lock().unwrap().take()
+    }
+}
+
+/// A checker that is called with the serialized size of every outgoing message.
+pub(crate) type MessageSizeCheck =
+    Arc<dyn Fn(usize) -> result::Result<(), RpcStatus> + Send + Sync>;
+
 /// A helper struct for constructing Sink object for batch requests.
 struct SinkBase {
     batch_f: Option<BatchFuture>,
     buf: Vec<u8>,
-    send_metadata: bool,
+    headers: Option<PendingHeaders>,
+    size_check: Option<MessageSizeCheck>,
+    corked: bool,
+    /// The last message written while corked. It's held back so that it can
+    /// be sent without buffer hint to flush the buffered ones.
+    held: Option<(Vec<u8>, WriteFlags)>,
+    /// The serialized messages waiting for the previous write to finish.
+    queue: Option<SendQueue>,
+}
+
+/// What a bounded send queue does when a message is sent while it's full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// The sink is not ready until a queued message is written.
+    Block,
+    /// Fail the send with `RESOURCE_EXHAUSTED`, the sink can still be used.
+    Error,
+    /// Drop the oldest queued message to make room for the new one.
+    DropOldest,
+}
+
+struct SendQueue {
+    capacity: usize,
+    policy: OverflowPolicy,
+    msgs: VecDeque<(Vec<u8>, WriteFlags)>,
 }
 
 impl SinkBase {
-    fn new(send_metadata: bool) -> SinkBase {
+    fn new(headers: Option<PendingHeaders>) -> SinkBase {
         SinkBase {
             batch_f: None,
             buf: Vec::new(),
-            send_metadata,
+            headers,
+            size_check: None,
+            corked: false,
+            held: None,
+            queue: None,
         }
     }
 
+    /// Queue up to `capacity` messages while the previous write is not
+    /// finished.
+    fn set_queue(&mut self, capacity: usize, policy: OverflowPolicy) {
+        assert!(capacity > 0);
+        let mut msgs = self.queue.take().map_or_else(VecDeque::new, |q| q.msgs);
+        while msgs.len() > capacity {
+            msgs.pop_front();
+        }
+        self.queue = Some(SendQueue {
+            capacity,
+            policy,
+            msgs,
+        });
+    }
+
+    fn queued_len(&self) -> usize {
+        self.queue.as_ref().map_or(0, |q| q.msgs.len())
+    }
+
+    /// Check whether empty initial metadata should be sent with next batch.
+    fn take_send_metadata(&self) -> bool {
+        match self.headers {
+            Some(ref headers) => headers.take(),
+            None => false,
+        }
+    }
+
+    fn take_load_report(&self) -> Option<Vec<u8>> {
+        self.headers
+            .as_ref()
+            .and_then(PendingHeaders::take_load_report)
+    }
+
     fn start_send<T, C: ShareCallHolder>(
         &mut self,
         call: &mut C,
         t: &T,
-        mut flags: WriteFlags,
+        flags: WriteFlags,
         ser: SerializeFn<T>,
     ) -> Result<bool> {
+        if self.queue.is_some() {
+            return self.start_send_queued(call, t, flags, ser);
+        }
+        if !self.poll_ready(call)? {
+            return Ok(false);
+        }
+
+        self.buf.clear();
+        ser(t, &mut self.buf);
+        if let Some(ref check) = self.size_check {
+            check(self.buf.len()).map_err(Error::RpcFailure)?;
+        }
+        self.write_buf(call, flags)?;
+        Ok(true)
+    }
+
+    fn start_send_queued<T, C: ShareCallHolder>(
+        &mut self,
+        call: &mut C,
+        t: &T,
+        flags: WriteFlags,
+        ser: SerializeFn<T>,
+    ) -> Result<bool> {
+        self.drain_queue(call)?;
+        let queue = self.queue.as_mut().unwrap();
+        if queue.msgs.len() >= queue.capacity {
+            match queue.policy {
+                OverflowPolicy::Block => return Ok(false),
+                OverflowPolicy::Error => {
+                    return Err(Error::RpcFailure(RpcStatus::new(
+                        RpcStatusCode::RESOURCE_EXHAUSTED,
+                        Some(format!("send queue is full ({})", queue.capacity)),
+                    )));
+                }
+                OverflowPolicy::DropOldest => {
+                    queue.msgs.pop_front();
+                }
+            }
+        }
+        let mut buf = Vec::new();
+        ser(t, &mut buf);
+        if let Some(ref check) = self.size_check {
+            check(buf.len()).map_err(Error::RpcFailure)?;
+        }
+        self.queue.as_mut().unwrap().msgs.push_back((buf, flags));
+        self.drain_queue(call)?;
+        Ok(true)
+    }
+
+    /// Write the queued messages until a write is not finished.
+    fn drain_queue<C: ShareCallHolder>(&mut self, call: &mut C) -> Result<()> {
+        while self.queued_len() > 0 {
+            if !self.poll_ready(call)? {
+                return Ok(());
+            }
+            let (buf, flags) = self.queue.as_mut().unwrap().msgs.pop_front().unwrap();
+            self.buf = buf;
+            self.write_buf(call, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the previous write is finished, so that a new message
+    /// can be written.
+    fn poll_ready<C: ShareCallHolder>(&mut self, call: &mut C) -> Result<bool> {
         if self.batch_f.is_some() {
             // try its best not to return false.
             self.poll_complete()?;
@@ -837,19 +1344,65 @@ impl SinkBase {
             }
         }
 
-        self.buf.clear();
-        ser(t, &mut self.buf);
-        if flags.get_buffer_hint() && self.send_metadata {
+        if !self.corked {
+            if let Some((buf, held_flags)) = self.held.take() {
+                // The message held back before uncorking is sent first, it can
+                // still be buffered as another message follows.
+                self.buf = buf;
+                self.send_buf(call, held_flags.buffer_hint(true))?;
+                self.poll_complete()?;
+                if self.batch_f.is_some() {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write the serialized message in `buf`, it's held back if the sink is
+    /// corked.
+    fn write_buf<C: ShareCallHolder>(&mut self, call: &mut C, mut flags: WriteFlags) -> Result<()> {
+        if self.corked {
+            let msg = mem::take(&mut self.buf);
+            match self.held.replace((msg, flags)) {
+                Some((buf, held_flags)) => {
+                    self.buf = buf;
+                    flags = held_flags.buffer_hint(true);
+                }
+                None => return Ok(()),
+            }
+        }
+        self.send_buf(call, flags)
+    }
+
+    fn send_buf<C: ShareCallHolder>(&mut self, call: &mut C, mut flags: WriteFlags) -> Result<()> {
+        let send_metadata = self.take_send_metadata();
+        if flags.get_buffer_hint() && send_metadata {
             // temporary fix: buffer hint with send meta will not send out any metadata.
             flags = flags.buffer_hint(false);
         }
         let write_f = call.call(|c| {
             c.call
-                .start_send_message(&self.buf, flags.flags, self.send_metadata)
+                .start_send_message(&self.buf, flags.flags, send_metadata)
         })?;
         self.batch_f = Some(write_f);
-        self.send_metadata = false;
-        Ok(true)
+        Ok(())
+    }
+
+    /// Send the held message without buffer hint, so that all the buffered
+    /// messages go out on the wire, and wait for the write to finish.
+    fn poll_flush<C: ShareCallHolder>(&mut self, call: &mut C) -> Poll<(), Error> {
+        self.drain_queue(call)?;
+        if self.queued_len() > 0 {
+            return Ok(Async::NotReady);
+        }
+        if self.held.is_some() {
+            try_ready!(self.poll_complete());
+            let (buf, flags) = self.held.take().unwrap();
+            self.buf = buf;
+            self.send_buf(call, flags.buffer_hint(false))?;
+        }
+        self.poll_complete()
     }
 
     fn poll_complete(&mut self) -> Poll<(), Error> {
@@ -881,6 +1434,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_message_reader_peek() {
+        let data: Vec<u8> = (0..10).collect();
+        let mut reader = make_message_reader(&data, 2);
+        assert_eq!(reader.peek(3), vec![0, 1, 2]);
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        // Peeking doesn't depend on or affect the consumed bytes.
+        assert_eq!(reader.peek(12), [&data[..], &data[..2]].concat());
+        assert_eq!(reader.peek(100).len(), 20);
+        assert_eq!(reader.pending_bytes_count(), 16);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [&data[4..], &data[..]].concat());
+    }
+
     #[test]
     // Old code crashes under a very weird circumstance, due to a typo in `MessageReader::consume`
     fn test_typo_len_offset() {
@@ -12,24 +12,63 @@
 // limitations under the License.
 
 use std::ffi::CStr;
+use std::mem;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::{result, slice};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{result, slice, str};
 
 use crate::grpc_sys::{
     self, gpr_clock_type, gpr_timespec, grpc_call_error, grpcwrap_request_call_context,
 };
+use futures::sink::Send as SendFuture;
 use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
 
-use super::{RpcStatus, ShareCall, ShareCallHolder, WriteFlags};
+use super::{CallHandle, RpcStatus, ShareCall, ShareCallHolder, WriteFlags};
+#[cfg(feature = "secure")]
+use crate::auth_context::{AuthContext, PeerIdentity};
 use crate::call::{
-    BatchContext, Call, MessageReader, MethodType, RpcStatusCode, SinkBase, StreamingBase,
+    BatchContext, Call, MessageReader, MethodType, OverflowPolicy, PendingHeaders, RpcStatusCode,
+    SinkBase, StreamingBase,
 };
-use crate::codec::{DeserializeFn, SerializeFn};
+use crate::codec::{raw_codec, DeserializeFn, SerializeFn};
 use crate::cq::CompletionQueue;
-use crate::error::Error;
+use crate::error::{Error, Result};
+use crate::load_report::LoadReport;
 use crate::metadata::Metadata;
 use crate::server::{BoxHandler, RequestCallContext};
-use crate::task::{BatchFuture, CallTag, Executor, Kicker, SpinLock};
+use crate::stats::CallStats;
+use crate::task::{
+    BatchFuture, CallTag, CancelState, Delay, Executor, Kicker, SpinLock, TaskGroup,
+};
+use crate::trace::TraceContext;
+use crate::watchdog::Watchdog;
+
+/// A callback that receives the serialized response of a successful unary call.
+pub(crate) type ResponseHook = Box<dyn FnOnce(&[u8]) + Send>;
+/// An object that is kept until the sinks and streams of the call are dropped.
+pub(crate) type CallGuard = Box<dyn Send>;
+type PrefixValidator = dyn Fn(&[u8]) -> result::Result<(), RpcStatus> + Send + Sync;
+type RequestDeserializer<T> = Box<dyn Fn(MessageReader) -> Result<T> + Send + Sync>;
+
+const USER_AGENT_KEY: &str = "user-agent";
+
+/// A validator of the first bytes of request messages.
+#[derive(Clone)]
+pub(crate) struct PrefixCheck {
+    len: usize,
+    validator: Arc<PrefixValidator>,
+}
+
+impl PrefixCheck {
+    pub fn new(len: usize, validator: Arc<PrefixValidator>) -> PrefixCheck {
+        PrefixCheck { len, validator }
+    }
+
+    pub fn check(&self, msg: &MessageReader) -> result::Result<(), RpcStatus> {
+        (self.validator)(&msg.peek(self.len))
+    }
+}
 
 pub struct Deadline {
     spec: gpr_timespec,
@@ -51,6 +90,54 @@ impl Deadline {
             grpc_sys::gpr_time_cmp(now, self.spec) >= 0
         }
     }
+
+    /// Get the point in time of the deadline, `None` if the call has no
+    /// deadline.
+    pub fn system_time(&self) -> Option<SystemTime> {
+        if self.spec.tv_sec == gpr_timespec::inf_future().tv_sec {
+            return None;
+        }
+        let since_epoch = Duration::new(self.spec.tv_sec as u64, self.spec.tv_nsec as u32);
+        Some(UNIX_EPOCH + since_epoch)
+    }
+
+    /// Get the time left before the deadline, which is zero if it's
+    /// exceeded. `None` is returned if the call has no deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.system_time().map(|t| {
+            t.duration_since(SystemTime::now())
+                .unwrap_or_else(|_| Duration::from_secs(0))
+        })
+    }
+}
+
+/// A future that resolves once the call is cancelled by the client or
+/// exceeds its deadline, see [`RpcContext::cancelled`].
+///
+/// It fails with `Error::RpcFinished` if the call finishes without being
+/// cancelled.
+#[must_use = "futures do nothing unless polled"]
+pub struct Cancelled {
+    state: Arc<SpinLock<CancelState>>,
+}
+
+impl Future for Cancelled {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        self.state.lock().poll_cancelled()
+    }
+}
+
+/// Parse the peer reported by gRPC Core, like `ipv6:[::1]:50051`.
+fn parse_peer_addr(peer: &str) -> Option<SocketAddr> {
+    let addr = if peer.starts_with("ipv4:") || peer.starts_with("ipv6:") {
+        &peer[5..]
+    } else {
+        return None;
+    };
+    addr.parse().ok()
 }
 
 /// Context for accepting a request.
@@ -77,12 +164,13 @@ impl RequestContext {
         cq: &CompletionQueue,
         rc: &mut RequestCallContext,
     ) -> result::Result<(), Self> {
+        let tasks = rc.task_group();
         let handler = unsafe { rc.get_handler(self.method()) };
         match handler {
             Some(handler) => match handler.method_type() {
                 MethodType::Unary | MethodType::ServerStreaming => Err(self),
                 _ => {
-                    execute(self, cq, None, handler);
+                    execute(self, cq, tasks, None, handler);
                     Ok(())
                 }
             },
@@ -220,9 +308,14 @@ impl UnaryRequestContext {
         cq: &CompletionQueue,
         reader: Option<MessageReader>,
     ) {
-        let handler = unsafe { rc.get_handler(self.request.method()).unwrap() };
+        let tasks = rc.task_group();
+        let handler = match unsafe { rc.get_handler(self.request.method()) } {
+            Some(handler) => handler,
+            // The service is removed while receiving the request.
+            None => return execute_unimplemented(self.request, cq.clone()),
+        };
         if reader.is_some() {
-            return execute(self.request, cq, reader, handler);
+            return execute(self.request, cq, tasks, reader, handler);
         }
 
         let status = RpcStatus::new(RpcStatusCode::INTERNAL, Some("No payload".to_owned()));
@@ -232,21 +325,31 @@ impl UnaryRequestContext {
 
 /// A stream for client a streaming call and a duplex streaming call.
 ///
+/// The next message is only read after the previous one is taken, so a
+/// handler that consumes slowly pushes back on the client through flow
+/// control.
+///
 /// The corresponding RPC will be canceled if the stream did not
 /// finish before dropping.
 #[must_use = "if unused the RequestStream may immediately cancel the RPC"]
 pub struct RequestStream<T> {
     call: Arc<SpinLock<ShareCall>>,
     base: StreamingBase,
-    de: DeserializeFn<T>,
+    de: RequestDeserializer<T>,
+    check: Option<PrefixCheck>,
 }
 
 impl<T> RequestStream<T> {
-    fn new(call: Arc<SpinLock<ShareCall>>, de: DeserializeFn<T>) -> RequestStream<T> {
+    fn new(
+        call: Arc<SpinLock<ShareCall>>,
+        de: RequestDeserializer<T>,
+        check: Option<PrefixCheck>,
+    ) -> RequestStream<T> {
         RequestStream {
             call,
             base: StreamingBase::new(None),
             de,
+            check,
         }
     }
 }
@@ -261,11 +364,14 @@ impl<T> Stream for RequestStream<T> {
             call.check_alive()?;
         }
 
-        match try_ready!(self.base.poll(&mut self.call, false)).map(self.de) {
-            None => Ok(Async::Ready(None)),
-            Some(Ok(data)) => Ok(Async::Ready(Some(data))),
-            Some(Err(err)) => Err(err),
+        let msg = match try_ready!(self.base.poll(&mut self.call, false)) {
+            Some(msg) => msg,
+            None => return Ok(Async::Ready(None)),
+        };
+        if let Some(ref check) = self.check {
+            check.check(&msg).map_err(Error::RpcFailure)?;
         }
+        (self.de)(msg).map(|data| Async::Ready(Some(data)))
     }
 }
 
@@ -277,6 +383,242 @@ impl<T> Drop for RequestStream<T> {
     }
 }
 
+/// A request message that is deserialized on demand, see
+/// `ServiceBuilder::add_unary_raw_handler`.
+///
+/// Services that only forward or persist the payloads can take the bytes
+/// without deserializing them at all.
+pub struct LazyMessage<T> {
+    reader: MessageReader,
+    de: DeserializeFn<T>,
+}
+
+impl<T> LazyMessage<T> {
+    pub(crate) fn new(reader: MessageReader, de: DeserializeFn<T>) -> LazyMessage<T> {
+        LazyMessage { reader, de }
+    }
+
+    /// Get the size of the serialized message.
+    pub fn len(&self) -> usize {
+        self.reader.pending_bytes_count()
+    }
+
+    /// Whether the serialized message is empty, which is a valid message
+    /// with default values for most codecs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Deserialize the message with the marshaller of the method.
+    pub fn parse(self) -> Result<T> {
+        (self.de)(self.reader)
+    }
+
+    /// Get the reader of the serialized message.
+    pub fn into_reader(self) -> MessageReader {
+        self.reader
+    }
+
+    /// Copy the serialized message.
+    pub fn into_bytes(self) -> Result<Vec<u8>> {
+        raw_codec::de(self.reader)
+    }
+}
+
+/// A future that resolves to the response of a unary or client streaming
+/// call, see `ServiceBuilder::add_unary_future_handler`.
+pub type UnaryResponse<T> = Box<dyn Future<Item = T, Error = RpcStatus> + Send>;
+/// A stream of the responses of a server streaming or duplex streaming call,
+/// see `ServiceBuilder::add_server_streaming_future_handler`.
+pub type StreamingResponse<T> = Box<dyn Stream<Item = T, Error = RpcStatus> + Send>;
+
+/// Check whether the call is finished before the response is sent, which
+/// means it's cancelled by the client or exceeds its deadline.
+///
+/// The current task is notified when the call is finished.
+fn poll_cancelled<C: ShareCallHolder>(call: &mut C) -> bool {
+    call.call(ShareCall::poll_finish)
+        .map(|r| r.is_ready())
+        .unwrap_or(true)
+}
+
+/// A sink that is completed by the result of a future.
+pub(crate) trait Respond<T> {
+    type Completion: Future<Item = (), Error = Error>;
+
+    fn poll_cancelled(&mut self) -> bool;
+    fn respond(self, res: result::Result<T, RpcStatus>) -> Self::Completion;
+}
+
+/// A sink that is fed by a stream.
+pub(crate) trait RespondStream<T>:
+    Sink<SinkItem = (T, WriteFlags), SinkError = Error>
+{
+    type Failure: Future<Item = (), Error = Error>;
+
+    fn poll_cancelled(&mut self) -> bool;
+    fn fail(self, status: RpcStatus) -> Self::Failure;
+}
+
+enum ResponderState<F: Future<Error = RpcStatus>, S: Respond<F::Item>> {
+    Handling(F, S),
+    Responding(S::Completion),
+    Done,
+}
+
+/// Completes the sink with the result of the handler future.
+///
+/// The handler future is dropped without being polled again once the call is
+/// cancelled.
+pub(crate) struct Responder<F: Future<Error = RpcStatus>, S: Respond<F::Item>> {
+    state: ResponderState<F, S>,
+}
+
+impl<F: Future<Error = RpcStatus>, S: Respond<F::Item>> Responder<F, S> {
+    pub fn new(f: F, sink: S) -> Responder<F, S> {
+        Responder {
+            state: ResponderState::Handling(f, sink),
+        }
+    }
+}
+
+impl<F: Future<Error = RpcStatus>, S: Respond<F::Item>> Future for Responder<F, S> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            self.state = match mem::replace(&mut self.state, ResponderState::Done) {
+                ResponderState::Handling(mut f, mut sink) => {
+                    if sink.poll_cancelled() {
+                        return Ok(Async::Ready(()));
+                    }
+                    let res = match f.poll() {
+                        Ok(Async::NotReady) => {
+                            self.state = ResponderState::Handling(f, sink);
+                            return Ok(Async::NotReady);
+                        }
+                        Ok(Async::Ready(resp)) => Ok(resp),
+                        Err(status) => Err(status),
+                    };
+                    ResponderState::Responding(sink.respond(res))
+                }
+                ResponderState::Responding(mut f) => match f.poll() {
+                    Ok(Async::NotReady) => {
+                        self.state = ResponderState::Responding(f);
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => return Ok(Async::Ready(())),
+                    Err(e) => {
+                        debug!("failed to send response: {:?}", e);
+                        return Err(());
+                    }
+                },
+                ResponderState::Done => panic!("cannot poll Responder twice"),
+            };
+        }
+    }
+}
+
+enum StreamResponderState<S: Stream<Error = RpcStatus>, K: RespondStream<S::Item>> {
+    Streaming(S, K, Option<S::Item>),
+    Closing(K),
+    Failing(K::Failure),
+    Done,
+}
+
+/// Feeds the sink with the responses yielded by the handler stream.
+///
+/// The handler stream is dropped without being polled again once the call is
+/// cancelled.
+pub(crate) struct StreamResponder<S: Stream<Error = RpcStatus>, K: RespondStream<S::Item>> {
+    state: StreamResponderState<S, K>,
+}
+
+impl<S: Stream<Error = RpcStatus>, K: RespondStream<S::Item>> StreamResponder<S, K> {
+    pub fn new(s: S, sink: K) -> StreamResponder<S, K> {
+        StreamResponder {
+            state: StreamResponderState::Streaming(s, sink, None),
+        }
+    }
+}
+
+impl<S: Stream<Error = RpcStatus>, K: RespondStream<S::Item>> StreamResponder<S, K> {
+    /// Send the pending response and the following ones until the handler
+    /// stream or the sink is not ready. `Some(status)` is returned if the
+    /// handler stream fails, and `None` if it's exhausted.
+    fn poll_stream(
+        s: &mut S,
+        sink: &mut K,
+        pending: &mut Option<S::Item>,
+    ) -> Poll<Option<RpcStatus>, Error> {
+        loop {
+            if let Some(item) = pending.take() {
+                if let AsyncSink::NotReady((item, _)) =
+                    sink.start_send((item, WriteFlags::default()))?
+                {
+                    *pending = Some(item);
+                    sink.poll_complete()?;
+                    return Ok(Async::NotReady);
+                }
+            }
+            match s.poll() {
+                Ok(Async::Ready(Some(item))) => *pending = Some(item),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => {
+                    sink.poll_complete()?;
+                    return Ok(Async::NotReady);
+                }
+                Err(status) => return Ok(Async::Ready(Some(status))),
+            }
+        }
+    }
+}
+
+impl<S: Stream<Error = RpcStatus>, K: RespondStream<S::Item>> Future for StreamResponder<S, K> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let res = loop {
+            self.state = match mem::replace(&mut self.state, StreamResponderState::Done) {
+                StreamResponderState::Streaming(mut s, mut sink, mut pending) => {
+                    if sink.poll_cancelled() {
+                        return Ok(Async::Ready(()));
+                    }
+                    match Self::poll_stream(&mut s, &mut sink, &mut pending) {
+                        Ok(Async::NotReady) => {
+                            self.state = StreamResponderState::Streaming(s, sink, pending);
+                            return Ok(Async::NotReady);
+                        }
+                        Ok(Async::Ready(None)) => StreamResponderState::Closing(sink),
+                        Ok(Async::Ready(Some(status))) => {
+                            StreamResponderState::Failing(RespondStream::fail(sink, status))
+                        }
+                        Err(e) => break Err(e),
+                    }
+                }
+                StreamResponderState::Closing(mut sink) => match sink.close() {
+                    Ok(Async::NotReady) => {
+                        self.state = StreamResponderState::Closing(sink);
+                        return Ok(Async::NotReady);
+                    }
+                    res => break res,
+                },
+                StreamResponderState::Failing(mut f) => match f.poll() {
+                    Ok(Async::NotReady) => {
+                        self.state = StreamResponderState::Failing(f);
+                        return Ok(Async::NotReady);
+                    }
+                    res => break res,
+                },
+                StreamResponderState::Done => panic!("cannot poll StreamResponder twice"),
+            };
+        };
+        res.map_err(|e| debug!("failed to send responses: {:?}", e))
+    }
+}
+
 /// A helper macro used to implement server side unary sink.
 /// Not using generic here because we don't need to expose
 /// `CallHolder` or `Call` to caller.
@@ -310,19 +652,35 @@ macro_rules! impl_unary_sink {
         $(#[$attr])*
         pub struct $t<T> {
             call: Option<$holder>,
+            headers: PendingHeaders,
+            trailers: Option<Metadata>,
             write_flags: u32,
             ser: SerializeFn<T>,
+            on_success: Option<ResponseHook>,
         }
 
         impl<T> $t<T> {
-            fn new(call: $holder, ser: SerializeFn<T>) -> $t<T> {
+            fn new(
+                call: $holder,
+                headers: PendingHeaders,
+                ser: SerializeFn<T>,
+                on_success: Option<ResponseHook>,
+            ) -> $t<T> {
                 $t {
                     call: Some(call),
+                    headers,
+                    trailers: None,
                     write_flags: 0,
                     ser: ser,
+                    on_success,
                 }
             }
 
+            /// Set the trailing metadata that will be sent along with the status.
+            pub fn set_trailers(&mut self, trailers: Metadata) {
+                self.trailers = Some(trailers);
+            }
+
             pub fn success(self, t: T) -> $rt {
                 self.complete(RpcStatus::ok(), Some(t))
             }
@@ -338,10 +696,25 @@ macro_rules! impl_unary_sink {
                     buf
                 });
 
+                if let (Some(hook), Some(data)) = (self.on_success.take(), data.as_ref()) {
+                    if status.status == RpcStatusCode::OK {
+                        hook(data);
+                    }
+                }
+
                 let write_flags = self.write_flags;
+                let send_metadata = self.headers.take();
+                let load_report = self.headers.take_load_report();
+                let trailers = self.trailers.as_mut();
                 let res = self.call.as_mut().unwrap().call(|c| {
-                    c.call
-                        .start_send_status_from_server(&status, true, &data, write_flags)
+                    c.call.start_send_status_from_server(
+                        &status,
+                        send_metadata,
+                        trailers,
+                        load_report,
+                        &data,
+                        write_flags,
+                    )
                 });
 
                 let (cq_f, err) = match res {
@@ -357,6 +730,21 @@ macro_rules! impl_unary_sink {
             }
         }
 
+        impl<T> Respond<T> for $t<T> {
+            type Completion = $rt;
+
+            fn poll_cancelled(&mut self) -> bool {
+                poll_cancelled(self.call.as_mut().unwrap())
+            }
+
+            fn respond(self, res: result::Result<T, RpcStatus>) -> $rt {
+                match res {
+                    Ok(t) => self.success(t),
+                    Err(status) => self.fail(status),
+                }
+            }
+        }
+
         impl<T> Drop for $t<T> {
             /// The corresponding RPC will be canceled if the sink did not
             /// send a response before dropping.
@@ -403,18 +791,20 @@ macro_rules! impl_stream_sink {
             base: SinkBase,
             flush_f: Option<BatchFuture>,
             status: RpcStatus,
+            trailers: Option<Metadata>,
             flushed: bool,
             closed: bool,
             ser: SerializeFn<T>,
         }
 
         impl<T> $t<T> {
-            fn new(call: $holder, ser: SerializeFn<T>) -> $t<T> {
+            fn new(call: $holder, headers: PendingHeaders, ser: SerializeFn<T>) -> $t<T> {
                 $t {
                     call: Some(call),
-                    base: SinkBase::new(true),
+                    base: SinkBase::new(Some(headers)),
                     flush_f: None,
                     status: RpcStatus::ok(),
+                    trailers: None,
                     flushed: false,
                     closed: false,
                     ser: ser,
@@ -426,12 +816,65 @@ macro_rules! impl_stream_sink {
                 self.status = status;
             }
 
+            /// Set the trailing metadata that will be sent along with the status.
+            pub fn set_trailers(&mut self, trailers: Metadata) {
+                assert!(self.flush_f.is_none());
+                self.trailers = Some(trailers);
+            }
+
+            /// Buffer the following messages until the sink is uncorked and
+            /// flushed, so that many small messages can be coalesced into
+            /// fewer HTTP/2 frames.
+            ///
+            /// `poll_complete`, i.e. `flush`, sends out the buffered messages
+            /// even if the sink is still corked, and so does `close`. Messages
+            /// are discarded if the sink fails instead.
+            pub fn cork(&mut self) {
+                self.base.corked = true;
+            }
+
+            /// Stop buffering messages, see [`cork`](#method.cork).
+            ///
+            /// The buffered messages are sent along with the next message or
+            /// flush.
+            pub fn uncork(&mut self) {
+                self.base.corked = false;
+            }
+
+            /// Send a message and flush the sink, the call fails with
+            /// `DEADLINE_EXCEEDED` if it's not written in `timeout`.
+            ///
+            /// A write can't finish if the client stops reading and the flow
+            /// control window is exhausted, the timeout makes sure such a
+            /// client doesn't hold the memory and the handler forever.
+            pub fn send_with_timeout(
+                self,
+                item: (T, WriteFlags),
+                timeout: Duration,
+            ) -> SendWithTimeout<$t<T>> {
+                let handle = self.call.as_ref().unwrap().handle();
+                SendWithTimeout {
+                    send: self.send(item),
+                    handle,
+                    delay: Delay::new(Instant::now() + timeout),
+                    timeout,
+                }
+            }
+
             pub fn fail(mut self, status: RpcStatus) -> $ft {
                 assert!(self.flush_f.is_none());
-                let send_metadata = self.base.send_metadata;
+                let send_metadata = self.base.take_send_metadata();
+                let load_report = self.base.take_load_report();
+                let trailers = self.trailers.as_mut();
                 let res = self.call.as_mut().unwrap().call(|c| {
-                    c.call
-                        .start_send_status_from_server(&status, send_metadata, &None, 0)
+                    c.call.start_send_status_from_server(
+                        &status,
+                        send_metadata,
+                        trailers,
+                        load_report,
+                        &None,
+                        0,
+                    )
                 });
 
                 let (fail_f, err) = match res {
@@ -447,6 +890,18 @@ macro_rules! impl_stream_sink {
             }
         }
 
+        impl<T> RespondStream<T> for $t<T> {
+            type Failure = $ft;
+
+            fn poll_cancelled(&mut self) -> bool {
+                poll_cancelled(self.call.as_mut().unwrap())
+            }
+
+            fn fail(self, status: RpcStatus) -> $ft {
+                $t::fail(self, status)
+            }
+        }
+
         impl<T> Drop for $t<T> {
             /// The corresponding RPC will be canceled if the sink did not call
             /// [`close`] or [`fail`] before dropping.
@@ -485,18 +940,26 @@ macro_rules! impl_stream_sink {
                 if let Async::Ready(_) = self.call.as_mut().unwrap().call(ShareCall::poll_finish)? {
                     return Err(Error::RemoteStopped);
                 }
-                self.base.poll_complete()
+                self.base.poll_flush(self.call.as_mut().unwrap())
             }
 
             fn close(&mut self) -> Poll<(), Error> {
                 if self.flush_f.is_none() {
-                    try_ready!(self.base.poll_complete());
+                    try_ready!(self.base.poll_flush(self.call.as_mut().unwrap()));
 
-                    let send_metadata = self.base.send_metadata;
+                    let send_metadata = self.base.take_send_metadata();
+                    let load_report = self.base.take_load_report();
                     let status = &self.status;
+                    let trailers = self.trailers.as_mut();
                     let flush_f = self.call.as_mut().unwrap().call(|c| {
-                        c.call
-                            .start_send_status_from_server(status, send_metadata, &None, 0)
+                        c.call.start_send_status_from_server(
+                            status,
+                            send_metadata,
+                            trailers,
+                            load_report,
+                            &None,
+                            0,
+                        )
                     })?;
                     self.flush_f = Some(flush_f);
                 }
@@ -550,8 +1013,15 @@ macro_rules! impl_stream_sink {
 impl_stream_sink!(
     /// A sink for server streaming call.
     ///
+    /// It's a `Sink` of messages and their write flags. Only one message is
+    /// written at a time, `start_send` returns `NotReady` until the previous
+    /// write finishes, so combinators like `send_all` and `forward` are
+    /// slowed down by a client that reads slowly. Use [`bounded_queue`] to
+    /// queue the messages instead.
+    ///
     /// To close the sink properly, you should call [`close`] or [`fail`] before dropping.
     ///
+    /// [`bounded_queue`]: #method.bounded_queue
     /// [`close`]: #method.close
     /// [`fail`]: #method.fail
     #[must_use = "if unused the sink may immediately cancel the RPC"]
@@ -559,9 +1029,37 @@ impl_stream_sink!(
     ServerStreamingSinkFailure,
     ShareCall
 );
+
+impl<T> ServerStreamingSink<T> {
+    /// Queue up to `capacity` serialized messages while the previous write is
+    /// not finished, instead of returning `NotReady` from `start_send`.
+    ///
+    /// Once the queue is full, `start_send` behaves as `policy` says. The
+    /// queued messages are written as the writes finish when the sink is
+    /// polled, and `poll_complete` and `close` wait for all of them. If it's
+    /// called again, the oldest queued messages beyond the new capacity are
+    /// dropped.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `capacity` is 0.
+    pub fn bounded_queue(&mut self, capacity: usize, policy: OverflowPolicy) {
+        self.base.set_queue(capacity, policy);
+    }
+
+    /// Get the number of the queued messages that are not written yet.
+    pub fn queued_len(&self) -> usize {
+        self.base.queued_len()
+    }
+}
 impl_stream_sink!(
     /// A sink for duplex streaming call.
     ///
+    /// It's a `Sink` of messages and their write flags. Only one message is
+    /// written at a time, `start_send` returns `NotReady` until the previous
+    /// write finishes, so combinators like `send_all` and `forward` are
+    /// slowed down by a client that reads slowly.
+    ///
     /// To close the sink properly, you should call [`close`] or [`fail`] before dropping.
     ///
     /// [`close`]: #method.close
@@ -572,29 +1070,114 @@ impl_stream_sink!(
     Arc<SpinLock<ShareCall>>
 );
 
+/// A future that resolves with the sink once the message is written, see
+/// `ServerStreamingSink::send_with_timeout`.
+#[must_use = "futures do nothing unless polled"]
+pub struct SendWithTimeout<S: Sink> {
+    send: SendFuture<S>,
+    handle: CallHandle,
+    delay: Delay,
+    timeout: Duration,
+}
+
+impl<S: Sink<SinkError = Error>> Future for SendWithTimeout<S> {
+    type Item = S;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<S, Error> {
+        if let Async::Ready(sink) = self.send.poll()? {
+            return Ok(Async::Ready(sink));
+        }
+        if let Ok(Async::NotReady) = self.delay.poll() {
+            return Ok(Async::NotReady);
+        }
+        let status = RpcStatus::new(
+            RpcStatusCode::DEADLINE_EXCEEDED,
+            Some(format!("message is not sent in {:?}", self.timeout)),
+        );
+        self.handle.cancel_with_status(&status);
+        Err(Error::RpcFailure(status))
+    }
+}
+
 /// A context for rpc handling.
 pub struct RpcContext<'a> {
     ctx: RequestContext,
     executor: Executor<'a>,
     deadline: Deadline,
+    headers: PendingHeaders,
+    tasks: TaskGroup,
+    response_hook: Option<ResponseHook>,
+    call_guard: Option<CallGuard>,
+    stats: Option<Arc<CallStats>>,
+    prefix_check: Option<PrefixCheck>,
+    watchdog: Option<Arc<Watchdog>>,
+    max_send_message_len: Option<usize>,
+    cancel: Arc<SpinLock<CancelState>>,
 }
 
 impl<'a> RpcContext<'a> {
-    fn new(ctx: RequestContext, cq: &CompletionQueue) -> RpcContext<'_> {
+    fn new(ctx: RequestContext, cq: &CompletionQueue, tasks: TaskGroup) -> RpcContext<'_> {
         RpcContext {
             deadline: ctx.deadline(),
             ctx,
             executor: Executor::new(cq),
+            headers: PendingHeaders::default(),
+            tasks,
+            response_hook: None,
+            call_guard: None,
+            stats: None,
+            prefix_check: None,
+            watchdog: None,
+            max_send_message_len: None,
+            cancel: Arc::new(SpinLock::new(CancelState::default())),
         }
     }
 
+    /// Set a hook that is called with the response of the unary call.
+    pub(crate) fn set_response_hook(&mut self, hook: ResponseHook) {
+        self.response_hook = Some(hook);
+    }
+
+    /// Add a guard that is dropped once the call is finished.
+    pub(crate) fn add_call_guard(&mut self, guard: CallGuard) {
+        self.call_guard = Some(match self.call_guard.take() {
+            Some(prev) => Box::new((prev, guard)),
+            None => guard,
+        });
+    }
+
+    /// Set the recorder of the statistics of the call.
+    pub(crate) fn set_call_stats(&mut self, stats: Arc<CallStats>) {
+        self.stats = Some(stats);
+    }
+
+    /// Set the watchdog of the batches of the call.
+    pub(crate) fn set_watchdog(&mut self, watchdog: Arc<Watchdog>) {
+        self.watchdog = Some(watchdog);
+    }
+
+    /// Set the limit of the size of the messages sent by the call.
+    pub(crate) fn set_max_send_message_len(&mut self, len: usize) {
+        self.max_send_message_len = Some(len);
+    }
+
+    /// Set the validator of the messages of the request stream.
+    pub(crate) fn set_prefix_check(&mut self, check: PrefixCheck) {
+        self.prefix_check = Some(check);
+    }
+
     fn kicker(&self) -> Kicker {
-        let call = self.call();
+        let call = self.ctx.call(self.executor.cq().clone());
         Kicker::from_call(call)
     }
 
     pub(crate) fn call(&self) -> Call {
-        self.ctx.call(self.executor.cq().clone())
+        let mut call = self.ctx.call(self.executor.cq().clone());
+        call.stats = self.stats.clone();
+        call.watchdog = self.watchdog.clone();
+        call.max_send_message_len = self.max_send_message_len;
+        call
     }
 
     pub fn method(&self) -> &[u8] {
@@ -605,28 +1188,149 @@ impl<'a> RpcContext<'a> {
         self.ctx.host()
     }
 
+    /// Get the deadline of the call, see [`Deadline::system_time`] for the
+    /// point in time of it.
     pub fn deadline(&self) -> &Deadline {
         &self.deadline
     }
 
+    /// Get the time left before the deadline of the call, `None` if the call
+    /// has no deadline.
+    ///
+    /// It can be used to budget the calls made to other services.
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.deadline.remaining()
+    }
+
+    /// Get a future that resolves once the call is cancelled by the client
+    /// or exceeds its deadline.
+    ///
+    /// Long running handlers can use it to stop the work early, instead of
+    /// finding out the cancellation when the response fails to be sent.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            state: self.cancel.clone(),
+        }
+    }
+
+    /// Check whether the call is cancelled by the client or exceeds its
+    /// deadline.
+    ///
+    /// The cancellation is noticed asynchronously, so it may still return
+    /// false for a short while after the call is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.lock().is_cancelled()
+    }
+
     /// Get the initial metadata sent by client.
     pub fn request_headers(&self) -> &Metadata {
         self.ctx.metadata()
     }
 
+    /// Get the user-agent of the client, which is set by
+    /// `ChannelBuilder::primary_user_agent` and
+    /// `ChannelBuilder::secondary_user_agent` for clients of this crate.
+    ///
+    /// `None` is returned if it's absent or not valid UTF-8.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.request_headers()
+            .find(USER_AGENT_KEY)
+            .and_then(|v| str::from_utf8(v).ok())
+    }
+
+    /// Get the address of the client, like `ipv4:127.0.0.1:50051`.
     pub fn peer(&self) -> String {
         self.ctx.peer()
     }
 
+    /// Get the socket address of the client, `None` is returned if the
+    /// client is not connected via TCP, for example by a unix socket.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        parse_peer_addr(&self.peer())
+    }
+
+    /// Get the trace context propagated by client.
+    ///
+    /// Both `traceparent` and `grpc-trace-bin` headers are recognized, the
+    /// former is preferred if both are present. `None` is returned if neither
+    /// is present or valid.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        TraceContext::from_metadata(self.request_headers())
+    }
+
+    /// Get the authentication context of the call.
+    ///
+    /// `None` is returned if the call is not made on a secure transport.
+    #[cfg(feature = "secure")]
+    pub fn auth_context(&self) -> Option<AuthContext> {
+        unsafe {
+            // RequestContext always holds a reference of the call.
+            let call = grpc_sys::grpcwrap_request_call_context_get_call(self.ctx.as_ptr());
+            AuthContext::from_call(call)
+        }
+    }
+
+    /// Get the identity of the authenticated peer of the call.
+    ///
+    /// `None` is returned if the call is not made on a secure transport or
+    /// the peer is not authenticated. See [`PeerIdentity`] for details.
+    #[cfg(feature = "secure")]
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        self.auth_context().and_then(|ctx| ctx.identity())
+    }
+
+    /// Send the initial metadata to client before any response.
+    ///
+    /// If it's not called, empty initial metadata will be sent along with the
+    /// first response or the status. It should be called at most once, and
+    /// before anything is sent through the sink, otherwise
+    /// `Error::CallFailure(GRPC_CALL_ERROR_TOO_MANY_OPERATIONS)` is returned.
+    pub fn send_initial_metadata(&self, mut metadata: Metadata) -> Result<()> {
+        if !self.headers.take() {
+            return Err(Error::CallFailure(
+                grpc_call_error::GRPC_CALL_ERROR_TOO_MANY_OPERATIONS,
+            ));
+        }
+        // The batch owns the metadata after started, so the future can be dropped.
+        self.call().start_send_initial_metadata(&mut metadata)?;
+        Ok(())
+    }
+
+    /// Attach the load report to the trailers of the call, so that clients
+    /// can balance calls by the load of this server.
+    ///
+    /// It should be called before the status is sent through the sink, the
+    /// last report set wins.
+    pub fn set_load_report(&self, report: &LoadReport) {
+        self.headers.set_load_report(report.encode());
+    }
+
     /// Spawn the future into current gRPC poll thread.
     ///
     /// This can reduce a lot of context switching, but please make
     /// sure there is no heavy work in the future.
+    ///
+    /// The future is tracked by the server until it's finished, see
+    /// [`ShutdownFuture::join_tasks`] for more details.
+    ///
+    /// [`ShutdownFuture::join_tasks`]: struct.ShutdownFuture.html#method.join_tasks
     pub fn spawn<F>(&self, f: F)
     where
         F: Future<Item = (), Error = ()> + Send + 'static,
     {
-        self.executor.spawn(f, self.kicker())
+        self.executor.spawn(self.tasks.track(f), self.kicker())
+    }
+
+    /// Like [`spawn`](RpcContext::spawn), but fails with `Error::Overloaded`
+    /// instead if there are too many unfinished futures spawned into current
+    /// gRPC poll thread, see [`EnvBuilder::spawn_limit`].
+    ///
+    /// [`EnvBuilder::spawn_limit`]: struct.EnvBuilder.html#method.spawn_limit
+    pub fn try_spawn<F>(&self, f: F) -> Result<()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.executor.try_spawn(self.tasks.track(f), self.kicker())
     }
 }
 
@@ -640,11 +1344,18 @@ macro_rules! accept_call {
             Ok(f) => f,
         }
     };
+    ($call:expr, $cancel:expr) => {
+        match $call.start_server_side_with_cancel($cancel) {
+            Err(Error::QueueShutdown) => return,
+            Err(e) => panic!("unexpected error when trying to accept request: {:?}", e),
+            Ok(f) => f,
+        }
+    };
 }
 
 // Helper function to call a unary handler.
 pub fn execute_unary<P, Q, F>(
-    ctx: RpcContext<'_>,
+    mut ctx: RpcContext<'_>,
     ser: SerializeFn<Q>,
     de: DeserializeFn<P>,
     payload: MessageReader,
@@ -653,7 +1364,7 @@ pub fn execute_unary<P, Q, F>(
     F: FnMut(RpcContext<'_>, P, UnarySink<Q>),
 {
     let mut call = ctx.call();
-    let close_f = accept_call!(call);
+    let close_f = accept_call!(call, ctx.cancel.clone());
     let request = match de(payload) {
         Ok(f) => f,
         Err(e) => {
@@ -665,31 +1376,36 @@ pub fn execute_unary<P, Q, F>(
             return;
         }
     };
-    let sink = UnarySink::new(ShareCall::new(call, close_f), ser);
+    let mut call = ShareCall::new(call, close_f);
+    call.guard = ctx.call_guard.take();
+    let sink = UnarySink::new(call, ctx.headers.clone(), ser, ctx.response_hook.take());
     f(ctx, request, sink)
 }
 
 // Helper function to call client streaming handler.
-pub fn execute_client_streaming<P, Q, F>(
-    ctx: RpcContext<'_>,
+pub fn execute_client_streaming<P, Q, D, F>(
+    mut ctx: RpcContext<'_>,
     ser: SerializeFn<Q>,
-    de: DeserializeFn<P>,
+    de: D,
     f: &mut F,
 ) where
+    D: Fn(MessageReader) -> Result<P> + Send + Sync + 'static,
     F: FnMut(RpcContext<'_>, RequestStream<P>, ClientStreamingSink<Q>),
 {
     let mut call = ctx.call();
-    let close_f = accept_call!(call);
-    let call = Arc::new(SpinLock::new(ShareCall::new(call, close_f)));
+    let close_f = accept_call!(call, ctx.cancel.clone());
+    let mut call = ShareCall::new(call, close_f);
+    call.guard = ctx.call_guard.take();
+    let call = Arc::new(SpinLock::new(call));
 
-    let req_s = RequestStream::new(call.clone(), de);
-    let sink = ClientStreamingSink::new(call, ser);
+    let req_s = RequestStream::new(call.clone(), Box::new(de), ctx.prefix_check.take());
+    let sink = ClientStreamingSink::new(call, ctx.headers.clone(), ser, None);
     f(ctx, req_s, sink)
 }
 
 // Helper function to call server streaming handler.
 pub fn execute_server_streaming<P, Q, F>(
-    ctx: RpcContext<'_>,
+    mut ctx: RpcContext<'_>,
     ser: SerializeFn<Q>,
     de: DeserializeFn<P>,
     payload: MessageReader,
@@ -698,7 +1414,7 @@ pub fn execute_server_streaming<P, Q, F>(
     F: FnMut(RpcContext<'_>, P, ServerStreamingSink<Q>),
 {
     let mut call = ctx.call();
-    let close_f = accept_call!(call);
+    let close_f = accept_call!(call, ctx.cancel.clone());
 
     let request = match de(payload) {
         Ok(t) => t,
@@ -712,25 +1428,30 @@ pub fn execute_server_streaming<P, Q, F>(
         }
     };
 
-    let sink = ServerStreamingSink::new(ShareCall::new(call, close_f), ser);
+    let mut call = ShareCall::new(call, close_f);
+    call.guard = ctx.call_guard.take();
+    let sink = ServerStreamingSink::new(call, ctx.headers.clone(), ser);
     f(ctx, request, sink)
 }
 
 // Helper function to call duplex streaming handler.
-pub fn execute_duplex_streaming<P, Q, F>(
-    ctx: RpcContext<'_>,
+pub fn execute_duplex_streaming<P, Q, D, F>(
+    mut ctx: RpcContext<'_>,
     ser: SerializeFn<Q>,
-    de: DeserializeFn<P>,
+    de: D,
     f: &mut F,
 ) where
+    D: Fn(MessageReader) -> Result<P> + Send + Sync + 'static,
     F: FnMut(RpcContext<'_>, RequestStream<P>, DuplexSink<Q>),
 {
     let mut call = ctx.call();
-    let close_f = accept_call!(call);
-    let call = Arc::new(SpinLock::new(ShareCall::new(call, close_f)));
+    let close_f = accept_call!(call, ctx.cancel.clone());
+    let mut call = ShareCall::new(call, close_f);
+    call.guard = ctx.call_guard.take();
+    let call = Arc::new(SpinLock::new(call));
 
-    let req_s = RequestStream::new(call.clone(), de);
-    let sink = DuplexSink::new(call, ser);
+    let req_s = RequestStream::new(call.clone(), Box::new(de), ctx.prefix_check.take());
+    let sink = DuplexSink::new(call, ctx.headers.clone(), ser);
     f(ctx, req_s, sink)
 }
 
@@ -749,9 +1470,30 @@ pub fn execute_unimplemented(ctx: RequestContext, cq: CompletionQueue) {
 fn execute(
     ctx: RequestContext,
     cq: &CompletionQueue,
+    tasks: TaskGroup,
     payload: Option<MessageReader>,
     f: &mut BoxHandler,
 ) {
-    let rpc_ctx = RpcContext::new(ctx, cq);
+    let rpc_ctx = RpcContext::new(ctx, cq, tasks);
     f.handle(rpc_ctx, payload)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer_addr() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("ipv4:127.0.0.1:50051", Some("127.0.0.1:50051")),
+            ("ipv6:[::1]:443", Some("[::1]:443")),
+            ("unix:/tmp/grpc.sock", None),
+            ("ipv4:127.0.0.1", None),
+            ("", None),
+        ];
+        for (peer, expected) in cases {
+            let expected = expected.map(|s| s.parse().unwrap());
+            assert_eq!(parse_peer_addr(peer), expected, "{}", peer);
+        }
+    }
+}
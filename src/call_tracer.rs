@@ -0,0 +1,170 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hooks that receive the low-level batch events of calls.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::grpc_sys::grpc_call;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACER: RwLock<Option<Arc<dyn CallTracer>>> = RwLock::new(None);
+
+/// A batch of operations started on a call.
+#[derive(Clone, Debug)]
+pub struct BatchEvent {
+    op: &'static str,
+    call: usize,
+    tag: usize,
+    started_at: Instant,
+}
+
+impl BatchEvent {
+    /// Get the name of the batch, like `send_initial_metadata`,
+    /// `send_message`, `recv_message`, `recv_initial_metadata`,
+    /// `recv_status`, `send_status_from_server` or `unary_call`.
+    pub fn op(&self) -> &'static str {
+        self.op
+    }
+
+    /// Get the address of the underlying call of gRPC Core, which identifies
+    /// the batches of the same call.
+    pub fn call_id(&self) -> usize {
+        self.call
+    }
+
+    /// Get the address of the tag of the batch, which is the tag of the
+    /// event polled from the completion queue once the batch completes.
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    /// Get the time the batch is started.
+    pub fn started_at(&self) -> Instant {
+        self.started_at
+    }
+}
+
+/// A tracer that receives an event for each batch of the calls, which helps
+/// to diagnose stuck calls.
+///
+/// It's called on the thread that starts or completes the batch, usually
+/// the polling thread of the completion queue, so it should be cheap and
+/// must not block.
+pub trait CallTracer: Send + Sync {
+    /// Called before the batch is started.
+    fn batch_started(&self, event: &BatchEvent);
+
+    /// Called once the batch is completed, either successfully or not.
+    fn batch_completed(&self, event: &BatchEvent, success: bool, elapsed: Duration);
+}
+
+/// Set the tracer of the batches of all calls, or remove it with `None`.
+///
+/// It's meant for debugging: all the batches that are started afterwards
+/// are traced, which adds overhead to every operation of the calls.
+pub fn set_call_tracer(tracer: Option<Arc<dyn CallTracer>>) {
+    let mut t = TRACER.write().unwrap();
+    ENABLED.store(tracer.is_some(), Ordering::SeqCst);
+    *t = tracer;
+}
+
+/// The trace of a batch, which is reported once the batch is resolved.
+pub(crate) struct BatchTrace {
+    tracer: Arc<dyn CallTracer>,
+    event: BatchEvent,
+}
+
+impl BatchTrace {
+    /// Create a trace for the batch if a tracer is set.
+    pub fn new(call: *mut grpc_call, op: &'static str) -> Option<BatchTrace> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        let tracer = TRACER.read().unwrap().clone()?;
+        Some(BatchTrace {
+            tracer,
+            event: BatchEvent {
+                op,
+                call: call as usize,
+                tag: 0,
+                started_at: Instant::now(),
+            },
+        })
+    }
+
+    /// Report that the batch with the tag is going to be started.
+    pub fn start(&mut self, tag: usize) {
+        self.event.tag = tag;
+        self.event.started_at = Instant::now();
+        self.tracer.batch_started(&self.event);
+    }
+
+    /// Report that the batch is completed.
+    pub fn complete(self, success: bool) {
+        let elapsed = self.event.started_at.elapsed();
+        self.tracer.batch_completed(&self.event, success, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(&'static str, usize, Option<bool>)>>);
+
+    impl CallTracer for Recorder {
+        fn batch_started(&self, event: &BatchEvent) {
+            self.0.lock().unwrap().push((event.op(), event.tag(), None));
+        }
+
+        fn batch_completed(&self, event: &BatchEvent, success: bool, _: Duration) {
+            let record = (event.op(), event.tag(), Some(success));
+            self.0.lock().unwrap().push(record);
+        }
+    }
+
+    #[test]
+    fn test_batch_trace() {
+        let call = 0x10 as *mut grpc_call;
+        let recorder = Arc::new(Recorder::default());
+        set_call_tracer(Some(recorder.clone()));
+        let mut trace = BatchTrace::new(call, "send_message").unwrap();
+        assert_eq!(trace.event.call_id(), 0x10);
+        trace.start(0x20);
+        trace.complete(false);
+        set_call_tracer(None);
+        assert!(BatchTrace::new(call, "recv_message").is_none());
+        // Batches of the other tests may be traced too.
+        let records: Vec<_> = recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.1 == 0x20)
+            .cloned()
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ("send_message", 0x20, None),
+                ("send_message", 0x20, Some(false))
+            ]
+        );
+    }
+}
@@ -15,18 +15,33 @@ use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
-use std::sync::Arc;
-use std::time::Duration;
+use std::marker::PhantomData;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
 use std::{cmp, i32, ptr};
 
-use crate::grpc_sys::{self, gpr_timespec, grpc_channel, grpc_channel_args};
-use libc::{self, c_char, c_int};
+use crate::grpc_sys::{
+    self, gpr_timespec, grpc_arg_pointer_vtable, grpc_channel, grpc_channel_args,
+};
+use libc::{self, c_char, c_int, c_void};
 
-use crate::call::{Call, Method};
+use crate::call::Call;
+use crate::channelz::{self, Kind};
 use crate::cq::CompletionQueue;
 use crate::env::Environment;
+#[cfg(feature = "secure")]
+use crate::error::Error;
 use crate::error::Result;
-use crate::task::Kicker;
+use crate::proxy::{self, HttpProxy};
+use crate::quota::ResourceQuota;
+use crate::resolver::{self, FixedTtlResolverCache};
+use crate::stats::{CallSide, CallStats, StatsHandler};
+use crate::task::{CallTag, CqFuture, Executor, Kicker};
+use crate::watchdog::Watchdog;
 use crate::CallOption;
 
 pub use crate::grpc_sys::{
@@ -36,34 +51,47 @@ pub use crate::grpc_sys::{
 
 // hack: add a '\0' to be compatible with c string without extra allocation.
 const OPT_DEFAULT_AUTHORITY: &[u8] = b"grpc.default_authority\0";
+const OPT_ENABLE_HTTP_PROXY: &[u8] = b"grpc.enable_http_proxy\0";
+const OPT_HTTP_CONNECT_SERVER: &[u8] = b"grpc.http_connect_server\0";
+const OPT_HTTP_CONNECT_HEADERS: &[u8] = b"grpc.http_connect_headers\0";
 const OPT_MAX_CONCURRENT_STREAMS: &[u8] = b"grpc.max_concurrent_streams\0";
-const OPT_MAX_RECEIVE_MESSAGE_LENGTH: &[u8] = b"grpc.max_receive_message_length\0";
-const OPT_MAX_SEND_MESSAGE_LENGTH: &[u8] = b"grpc.max_send_message_length\0";
+pub(crate) const OPT_MAX_RECEIVE_MESSAGE_LENGTH: &[u8] = b"grpc.max_receive_message_length\0";
+pub(crate) const OPT_MAX_SEND_MESSAGE_LENGTH: &[u8] = b"grpc.max_send_message_length\0";
 const OPT_MAX_RECONNECT_BACKOFF_MS: &[u8] = b"grpc.max_reconnect_backoff_ms\0";
 const OPT_INITIAL_RECONNECT_BACKOFF_MS: &[u8] = b"grpc.initial_reconnect_backoff_ms\0";
 const OPT_HTTP2_INITIAL_SEQUENCE_NUMBER: &[u8] = b"grpc.http2.initial_sequence_number\0";
-const OPT_SO_REUSE_PORT: &[u8] = b"grpc.so_reuseport\0";
-const OPT_STREAM_INITIAL_WINDOW_SIZE: &[u8] = b"grpc.http2.lookahead_bytes\0";
+pub(crate) const OPT_SO_REUSE_PORT: &[u8] = b"grpc.so_reuseport\0";
+const OPT_SOCKET_MUTATOR: &[u8] = b"grpc.socket_mutator\0";
+pub(crate) const OPT_STREAM_INITIAL_WINDOW_SIZE: &[u8] = b"grpc.http2.lookahead_bytes\0";
 const OPT_TCP_READ_CHUNK_SIZE: &[u8] = b"grpc.experimental.tcp_read_chunk_size\0";
 const OPT_TCP_MIN_READ_CHUNK_SIZE: &[u8] = b"grpc.experimental.tcp_min_read_chunk_size\0";
 const OPT_TCP_MAX_READ_CHUNK_SIZE: &[u8] = b"grpc.experimental.tcp_max_read_chunk_size\0";
 const OPT_HTTP2_WRITE_BUFFER_SIZE: &[u8] = b"grpc.http2.write_buffer_size\0";
-const OPT_HTTP2_MAX_FRAME_SIZE: &[u8] = b"grpc.http2.max_frame_size\0";
-const OPT_HTTP2_BDP_PROBE: &[u8] = b"grpc.http2.bdp_probe\0";
-const OPT_HTTP2_MIN_SENT_PING_INTERVAL_WITHOUT_DATA_MS: &[u8] =
+pub(crate) const OPT_HTTP2_MAX_FRAME_SIZE: &[u8] = b"grpc.http2.max_frame_size\0";
+pub(crate) const OPT_HTTP2_BDP_PROBE: &[u8] = b"grpc.http2.bdp_probe\0";
+pub(crate) const OPT_HTTP2_MIN_SENT_PING_INTERVAL_WITHOUT_DATA_MS: &[u8] =
     b"grpc.http2.min_time_between_pings_ms\0";
-const OPT_HTTP2_MIN_RECV_PING_INTERVAL_WITHOUT_DATA_MS: &[u8] =
+pub(crate) const OPT_HTTP2_MIN_RECV_PING_INTERVAL_WITHOUT_DATA_MS: &[u8] =
     b"grpc.http2.min_ping_interval_without_data_ms\0";
-const OPT_HTTP2_MAX_PINGS_WITHOUT_DATA: &[u8] = b"grpc.http2.max_pings_without_data\0";
-const OPT_HTTP2_MAX_PING_STRIKES: &[u8] = b"grpc.http2.max_ping_strikes\0";
+pub(crate) const OPT_HTTP2_MAX_PINGS_WITHOUT_DATA: &[u8] = b"grpc.http2.max_pings_without_data\0";
+pub(crate) const OPT_HTTP2_MAX_PING_STRIKES: &[u8] = b"grpc.http2.max_ping_strikes\0";
 const OPT_DEFALUT_COMPRESSION_ALGORITHM: &[u8] = b"grpc.default_compression_algorithm\0";
 const OPT_DEFAULT_COMPRESSION_LEVEL: &[u8] = b"grpc.default_compression_level\0";
-const OPT_KEEPALIVE_TIME_MS: &[u8] = b"grpc.keepalive_time_ms\0";
-const OPT_KEEPALIVE_TIMEOUT_MS: &[u8] = b"grpc.keepalive_timeout_ms\0";
-const OPT_KEEPALIVE_PERMIT_WITHOUT_CALLS: &[u8] = b"grpc.keepalive_permit_without_calls\0";
+pub(crate) const OPT_COMPRESSION_ENABLED_ALGORITHMS_BITSET: &[u8] =
+    b"grpc.compression_enabled_algorithms_bitset\0";
+pub(crate) const OPT_KEEPALIVE_TIME_MS: &[u8] = b"grpc.keepalive_time_ms\0";
+pub(crate) const OPT_KEEPALIVE_TIMEOUT_MS: &[u8] = b"grpc.keepalive_timeout_ms\0";
+pub(crate) const OPT_KEEPALIVE_PERMIT_WITHOUT_CALLS: &[u8] =
+    b"grpc.keepalive_permit_without_calls\0";
+pub(crate) const OPT_RESOURCE_QUOTA: &[u8] = b"grpc.resource_quota\0";
+const OPT_CLIENT_IDLE_TIMEOUT_MS: &[u8] = b"grpc.client_idle_timeout_ms\0";
 const OPT_OPTIMIZATION_TARGET: &[u8] = b"grpc.optimization_target\0";
 const PRIMARY_USER_AGENT_STRING: &[u8] = b"grpc.primary_user_agent\0";
+const SECONDARY_USER_AGENT_STRING: &[u8] = b"grpc.secondary_user_agent\0";
+const OPT_CHANNEL_POOL_INDEX: &[u8] = b"grpcio.channel_pool_index\0";
 const OPT_GRPC_ARG_LB_POLICY_NAME: &[u8] = b"grpc.lb_policy_name\0";
+const OPT_SERVICE_CONFIG: &[u8] = b"grpc.service_config\0";
+const OPT_MAX_PAYLOAD_SIZE_FOR_GET: &[u8] = b"grpc.max_payload_size_for_get\0";
 
 /// Ref: http://www.grpc.io/docs/guides/wire.html#user-agents
 fn format_user_agent_string(agent: &str) -> CString {
@@ -77,14 +105,262 @@ fn format_user_agent_string(agent: &str) -> CString {
     CString::new(val).unwrap()
 }
 
-fn dur_to_ms(dur: Duration) -> i32 {
+/// A transport for IPC between processes on the same host, which works with
+/// [`ChannelBuilder::connect_local`] and `ServerBuilder::bind_local`.
+///
+/// Only Unix domain sockets are supported. gRPC Core can neither serve nor
+/// connect to Windows named pipes, so there is no local transport on
+/// Windows, use a loopback address instead.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocalTransport {
+    /// A Unix domain socket at the path, which is only available on Unix.
+    UnixSocket(PathBuf),
+}
+
+impl LocalTransport {
+    /// Get the target of channels connecting to the transport.
+    pub(crate) fn target(&self) -> Result<String> {
+        match *self {
+            #[cfg(unix)]
+            LocalTransport::UnixSocket(ref path) => Ok(format!("unix:{}", path.display())),
+            #[cfg(not(unix))]
+            LocalTransport::UnixSocket(_) => Err(unsupported_transport(self)),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported_transport(transport: &LocalTransport) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{:?} is not supported on this platform", transport),
+    ))
+}
+
+/// Convert the enabled compression algorithms to the bitset of gRPC Core,
+/// `GRPC_COMPRESS_NONE` is always included.
+pub(crate) fn compression_bitset(algos: &[CompressionAlgorithms]) -> i32 {
+    algos.iter().fold(1, |set, algo| set | 1 << *algo as i32)
+}
+
+pub(crate) fn dur_to_ms(dur: Duration) -> i32 {
     let millis = dur.as_secs() * 1000 + dur.subsec_nanos() as u64 / 1_000_000;
     cmp::min(i32::MAX as u64, millis) as i32
 }
 
-enum Options {
+/// Convert a keepalive duration to milliseconds, gRPC Core treats zero as
+/// invalid and falls back to the default silently.
+pub(crate) fn keepalive_ms(dur: Duration, name: &str) -> i32 {
+    let ms = dur_to_ms(dur);
+    assert!(ms > 0, "{} should be at least 1ms, got {:?}", name, dur);
+    ms
+}
+
+/// Convert a count of pings to a channel arg, counts that don't fit are
+/// saturated as they are practically unlimited.
+pub(crate) fn ping_count(num: u32) -> i32 {
+    cmp::min(num, i32::MAX as u32) as i32
+}
+
+pub(crate) enum Options {
     Integer(i32),
     String(CString),
+    ResourceQuota(ResourceQuota),
+    Socket(grpc_sys::grpcwrap_socket_options),
+    Pointer(RawPointer),
+}
+
+/// A pointer argument owned by a builder, the value is released by the
+/// vtable when the builder is dropped.
+pub(crate) struct RawPointer {
+    p: *mut c_void,
+    vtable: &'static grpc_arg_pointer_vtable,
+}
+
+// Only `Arc<T>` with `T: Send + Sync` can be turned into a `RawPointer`.
+unsafe impl Send for RawPointer {}
+unsafe impl Sync for RawPointer {}
+
+impl Drop for RawPointer {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.destroy.unwrap())(self.p) }
+    }
+}
+
+unsafe extern "C" fn arc_copy<T>(p: *mut c_void) -> *mut c_void {
+    let arc = Arc::from_raw(p as *const T);
+    let copy = arc.clone();
+    mem::forget(arc);
+    Arc::into_raw(copy) as *mut c_void
+}
+
+unsafe extern "C" fn arc_destroy<T>(p: *mut c_void) {
+    drop(Arc::from_raw(p as *const T));
+}
+
+unsafe extern "C" fn arc_cmp(p: *mut c_void, q: *mut c_void) -> c_int {
+    (p as usize).cmp(&(q as usize)) as c_int
+}
+
+struct ArcVtable<T>(PhantomData<T>);
+
+impl<T> ArcVtable<T> {
+    const VTABLE: &'static grpc_arg_pointer_vtable = &grpc_arg_pointer_vtable {
+        copy: Some(arc_copy::<T>),
+        destroy: Some(arc_destroy::<T>),
+        cmp: Some(arc_cmp),
+    };
+}
+
+/// Channel arguments of gRPC Core that are not wrapped by the builders yet.
+///
+/// The arguments can be attached to both `ChannelBuilder::raw_args` and
+/// `ServerBuilder::raw_args`. Keys and values are copied when channels or
+/// servers are built, pointers are reference counted, so nothing needs to
+/// be kept alive by users.
+///
+/// ```
+/// use grpcio::RawChannelArgs;
+///
+/// let args = RawChannelArgs::new()
+///     .set_int("grpc.max_connection_idle_ms", 60_000)
+///     .set_string("grpc.lb_policy_name", "round_robin");
+/// ```
+#[derive(Default)]
+pub struct RawChannelArgs {
+    args: Vec<(Vec<u8>, Options)>,
+}
+
+impl RawChannelArgs {
+    pub fn new() -> RawChannelArgs {
+        RawChannelArgs::default()
+    }
+
+    fn set(mut self, key: &str, val: Options) -> RawChannelArgs {
+        let key = CString::new(key).unwrap().into_bytes_with_nul();
+        self.args.retain(|(k, _)| *k != key);
+        self.args.push((key, val));
+        self
+    }
+
+    /// Set an integer argument.
+    ///
+    /// # Panics
+    ///
+    /// If `key` contains a nul byte.
+    pub fn set_int(self, key: &str, val: i32) -> RawChannelArgs {
+        self.set(key, Options::Integer(val))
+    }
+
+    /// Set a string argument.
+    ///
+    /// # Panics
+    ///
+    /// If `key` or `val` contains a nul byte.
+    pub fn set_string(self, key: &str, val: &str) -> RawChannelArgs {
+        self.set(key, Options::String(CString::new(val).unwrap()))
+    }
+
+    /// Set a pointer argument, gRPC Core holds a reference of `val` for as
+    /// long as it uses the argument.
+    ///
+    /// # Safety
+    ///
+    /// gRPC Core casts the pointer to the type it expects for `key`, so `T`
+    /// must have the same layout as that type. Pointers of a key that gRPC
+    /// Core doesn't know are ignored, which is always safe.
+    ///
+    /// # Panics
+    ///
+    /// If `key` contains a nul byte.
+    pub unsafe fn set_pointer<T: Send + Sync + 'static>(
+        self,
+        key: &str,
+        val: Arc<T>,
+    ) -> RawChannelArgs {
+        let p = RawPointer {
+            p: Arc::into_raw(val) as *mut c_void,
+            vtable: ArcVtable::<T>::VTABLE,
+        };
+        self.set(key, Options::Pointer(p))
+    }
+
+    /// Merge the arguments into the options of a builder.
+    pub(crate) fn merge_into(self, options: &mut HashMap<Cow<'static, [u8]>, Options>) {
+        for (k, v) in self.args {
+            options.insert(Cow::Owned(k), v);
+        }
+    }
+
+    /// Build `ChannelArgs`, which can be passed to
+    /// `ServerBuilder::channel_args` directly.
+    pub fn build(self) -> ChannelArgs {
+        let mut options = HashMap::with_capacity(self.args.len());
+        self.merge_into(&mut options);
+        build_channel_args(&options)
+    }
+}
+
+/// Get the socket options of the builder, they are applied together by a
+/// socket mutator after gRPC Core sets up the socket.
+pub(crate) fn socket_options<'a>(
+    options: &'a mut HashMap<Cow<'static, [u8]>, Options>,
+) -> &'a mut grpc_sys::grpcwrap_socket_options {
+    let opt = options
+        .entry(Cow::Borrowed(OPT_SOCKET_MUTATOR))
+        .or_insert_with(|| {
+            Options::Socket(grpc_sys::grpcwrap_socket_options {
+                tcp_user_timeout_ms: -1,
+                tcp_nodelay: -1,
+                recv_buffer_size: -1,
+                send_buffer_size: -1,
+            })
+        });
+    match opt {
+        Options::Socket(opts) => opts,
+        _ => unreachable!(),
+    }
+}
+
+/// Convert options to `ChannelArgs`.
+#[allow(clippy::identity_conversion)]
+pub(crate) fn build_channel_args(options: &HashMap<Cow<'static, [u8]>, Options>) -> ChannelArgs {
+    let args = unsafe { grpc_sys::grpcwrap_channel_args_create(options.len()) };
+    for (i, (k, v)) in options.iter().enumerate() {
+        let key = k.as_ptr() as *const c_char;
+        match *v {
+            Options::Integer(val) => unsafe {
+                // On most modern compiler and architect, c_int is the same as i32,
+                // panic directly to simplify signature.
+                assert!(
+                    val <= i32::from(libc::INT_MAX) && val >= i32::from(libc::INT_MIN),
+                    "{} is out of range for {:?}",
+                    val,
+                    CStr::from_bytes_with_nul(k).unwrap()
+                );
+                grpc_sys::grpcwrap_channel_args_set_integer(args, i, key, val as c_int)
+            },
+            Options::String(ref val) => unsafe {
+                grpc_sys::grpcwrap_channel_args_set_string(args, i, key, val.as_ptr())
+            },
+            Options::ResourceQuota(ref quota) => unsafe {
+                grpc_sys::grpcwrap_channel_args_set_pointer_vtable(
+                    args,
+                    i,
+                    key,
+                    quota.as_ptr() as _,
+                    ResourceQuota::arg_vtable(),
+                )
+            },
+            Options::Socket(ref opts) => unsafe {
+                grpc_sys::grpcwrap_channel_args_set_socket_options(args, i, key, opts)
+            },
+            Options::Pointer(ref ptr) => unsafe {
+                grpc_sys::grpcwrap_channel_args_set_pointer_vtable(args, i, key, ptr.p, ptr.vtable)
+            },
+        }
+    }
+    ChannelArgs { args }
 }
 
 /// The optimization target for a [`Channel`].
@@ -98,16 +374,51 @@ pub enum OptTarget {
     Throughput,
 }
 
-#[derive(Clone, Copy)]
+/// Load balancing policies of client channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LbPolicy {
+    /// Connect to the first address that works and send all calls to it,
+    /// which is the default.
     PickFirst,
+    /// Connect to all the addresses and spread calls among ready ones in
+    /// turn.
     RoundRobin,
 }
 
+/// The state of a subchannel of a [`Channel`].
+///
+/// Every subchannel is a connection to an address resolved from the target of
+/// the channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubchannelState {
+    /// The channelz id of the subchannel.
+    pub id: u64,
+    /// The address the subchannel connects to.
+    pub target: String,
+    pub state: ConnectivityState,
+}
+
+fn parse_connectivity_state(s: &str) -> Option<ConnectivityState> {
+    let state = match s {
+        "IDLE" => ConnectivityState::GRPC_CHANNEL_IDLE,
+        "CONNECTING" => ConnectivityState::GRPC_CHANNEL_CONNECTING,
+        "READY" => ConnectivityState::GRPC_CHANNEL_READY,
+        "TRANSIENT_FAILURE" => ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE,
+        "SHUTDOWN" => ConnectivityState::GRPC_CHANNEL_SHUTDOWN,
+        _ => return None,
+    };
+    Some(state)
+}
+
 /// [`Channel`] factory in order to configure the properties.
 pub struct ChannelBuilder {
     env: Arc<Environment>,
     options: HashMap<Cow<'static, [u8]>, Options>,
+    resolver: Option<FixedTtlResolverCache>,
+    http_proxy: Option<HttpProxy>,
+    no_proxy: Vec<String>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    connectivity_callback: Option<Arc<ConnectivityCallback>>,
 }
 
 impl ChannelBuilder {
@@ -116,6 +427,11 @@ impl ChannelBuilder {
         ChannelBuilder {
             env,
             options: HashMap::new(),
+            resolver: None,
+            http_proxy: None,
+            no_proxy: vec![],
+            stats_handler: None,
+            connectivity_callback: None,
         }
     }
 
@@ -138,7 +454,11 @@ impl ChannelBuilder {
         self
     }
 
-    /// Set maximum message length that the channel can receive. `usize::MAX` means unlimited.
+    /// Set maximum message length that the channel can receive. `-1` means unlimited,
+    /// the default is 4MiB.
+    ///
+    /// Calls receiving larger messages fail with `RESOURCE_EXHAUSTED` and
+    /// details containing both the limit and the actual size.
     pub fn max_receive_message_len(mut self, len: i32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_MAX_RECEIVE_MESSAGE_LENGTH),
@@ -147,7 +467,25 @@ impl ChannelBuilder {
         self
     }
 
-    /// Set maximum message length that the channel can send. `-1` means unlimited.
+    /// Set the max size of messages of cacheable calls that can be sent as
+    /// HTTP/2 GET requests, larger ones are sent by POST as usual.
+    ///
+    /// Only calls marked by `CallOption::cacheable` are affected, like the
+    /// ones made by `Client::cacheable_unary_call`. The default is 2KiB.
+    pub fn max_get_payload_size(mut self, bytes: usize) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_PAYLOAD_SIZE_FOR_GET),
+            Options::Integer(cmp::min(bytes, i32::MAX as usize) as i32),
+        );
+        self
+    }
+
+    /// Set maximum message length that the channel can send. `-1` means unlimited,
+    /// which is the default.
+    ///
+    /// Sending a larger message fails with `Error::RpcFailure` of
+    /// `RESOURCE_EXHAUSTED` and details containing both the limit and the
+    /// actual size, the message is not sent.
     pub fn max_send_message_len(mut self, len: i32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_MAX_SEND_MESSAGE_LENGTH),
@@ -204,6 +542,20 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set secondary user agent, which goes at the end of the user-agent metadata sent on
+    /// each request.
+    ///
+    /// The full user-agent is like `{primary} grpc-rust/{version} grpc-c/{core version}
+    /// ({platform}; chttp2; {code name}) {secondary}`, servers can get it by
+    /// `RpcContext::user_agent`.
+    pub fn secondary_user_agent(mut self, agent: &str) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(SECONDARY_USER_AGENT_STRING),
+            Options::String(CString::new(agent.trim()).unwrap()),
+        );
+        self
+    }
+
     /// Set whether to allow the use of `SO_REUSEPORT` if available. Defaults to `true`.
     pub fn reuse_port(mut self, reuse: bool) -> ChannelBuilder {
         let opt = if reuse { 1 } else { 0 };
@@ -212,6 +564,34 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set `TCP_USER_TIMEOUT` of the connections, which is how long
+    /// transmitted data may remain unacknowledged before the connection is
+    /// closed.
+    ///
+    /// It's ignored on platforms that don't support the option.
+    pub fn tcp_user_timeout(mut self, timeout: Duration) -> ChannelBuilder {
+        socket_options(&mut self.options).tcp_user_timeout_ms = dur_to_ms(timeout);
+        self
+    }
+
+    /// Set whether to enable `TCP_NODELAY`. gRPC Core enables it by default.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> ChannelBuilder {
+        socket_options(&mut self.options).tcp_nodelay = if nodelay { 1 } else { 0 };
+        self
+    }
+
+    /// Set `SO_RCVBUF` of the connections.
+    pub fn recv_buffer_size(mut self, bytes: i32) -> ChannelBuilder {
+        socket_options(&mut self.options).recv_buffer_size = bytes;
+        self
+    }
+
+    /// Set `SO_SNDBUF` of the connections.
+    pub fn send_buffer_size(mut self, bytes: i32) -> ChannelBuilder {
+        socket_options(&mut self.options).send_buffer_size = bytes;
+        self
+    }
+
     /// Set the size of slice to try and read from the wire each time.
     pub fn tcp_read_chunk_size(mut self, bytes: i32) -> ChannelBuilder {
         self.options.insert(
@@ -261,7 +641,11 @@ impl ChannelBuilder {
         self
     }
 
-    /// Set whether to enable BDP probing.
+    /// Set whether to enable BDP probing. Defaults to `true`.
+    ///
+    /// gRPC Core grows the connection and stream windows to the estimated
+    /// bandwidth-delay product of the link, the connection window has no
+    /// fixed size to set otherwise.
     pub fn http2_bdp_probe(mut self, enable: bool) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_HTTP2_BDP_PROBE),
@@ -299,10 +683,10 @@ impl ChannelBuilder {
     /// How many pings can we send before needing to send a data frame or header
     /// frame? (0 indicates that an infinite number of pings can be sent without
     /// sending a data frame or header frame)
-    pub fn http2_max_pings_without_data(mut self, num: i32) -> ChannelBuilder {
+    pub fn http2_max_pings_without_data(mut self, num: u32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_HTTP2_MAX_PINGS_WITHOUT_DATA),
-            Options::Integer(num),
+            Options::Integer(ping_count(num)),
         );
         self
     }
@@ -310,10 +694,10 @@ impl ChannelBuilder {
     /// How many misbehaving pings the server can bear before sending goaway and
     /// closing the transport? (0 indicates that the server can bear an infinite
     /// number of misbehaving pings)
-    pub fn http2_max_ping_strikes(mut self, num: i32) -> ChannelBuilder {
+    pub fn http2_max_ping_strikes(mut self, num: u32) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_HTTP2_MAX_PING_STRIKES),
-            Options::Integer(num),
+            Options::Integer(ping_count(num)),
         );
         self
     }
@@ -327,6 +711,25 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the compression algorithms that are enabled, all algorithms are
+    /// enabled by default.
+    ///
+    /// Only enabled algorithms are advertised to the peer in
+    /// `grpc-accept-encoding`, and calls that receive messages compressed by
+    /// other algorithms fail with `UNIMPLEMENTED`. `GRPC_COMPRESS_NONE` is
+    /// always enabled. See `ServerBuilder::enabled_compression_algorithms`
+    /// for servers.
+    pub fn enabled_compression_algorithms(
+        mut self,
+        algos: &[CompressionAlgorithms],
+    ) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_COMPRESSION_ENABLED_ALGORITHMS_BITSET),
+            Options::Integer(compression_bitset(algos)),
+        );
+        self
+    }
+
     /// Set default compression level for the channel.
     pub fn default_compression_level(mut self, level: CompressionLevel) -> ChannelBuilder {
         self.options.insert(
@@ -338,20 +741,32 @@ impl ChannelBuilder {
 
     /// After a duration of this time the client/server pings its peer to see
     /// if the transport is still alive.
+    ///
+    /// Servers reject pings that are more frequent than their
+    /// `http2_min_recv_ping_interval_without_data`, which is 5 minutes by
+    /// default, and close the connection after too many strikes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is less than 1ms.
     pub fn keepalive_time(mut self, timeout: Duration) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_KEEPALIVE_TIME_MS),
-            Options::Integer(dur_to_ms(timeout)),
+            Options::Integer(keepalive_ms(timeout, "keepalive time")),
         );
         self
     }
 
     /// After waiting for a duration of this time, if the keepalive ping sender does
     /// not receive the ping ack, it will close the transport.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is less than 1ms.
     pub fn keepalive_timeout(mut self, timeout: Duration) -> ChannelBuilder {
         self.options.insert(
             Cow::Borrowed(OPT_KEEPALIVE_TIMEOUT_MS),
-            Options::Integer(dur_to_ms(timeout)),
+            Options::Integer(keepalive_ms(timeout, "keepalive timeout")),
         );
         self
     }
@@ -365,6 +780,56 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set the memory quota of the channel, it can be shared with other
+    /// channels and servers.
+    pub fn resource_quota(mut self, quota: ResourceQuota) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_RESOURCE_QUOTA),
+            Options::ResourceQuota(quota),
+        );
+        self
+    }
+
+    /// Set the handler that is notified of the events of calls made by the
+    /// channel, see [`StatsHandler`] for details.
+    pub fn stats_handler(mut self, handler: Arc<dyn StatsHandler>) -> ChannelBuilder {
+        self.stats_handler = Some(handler);
+        self
+    }
+
+    /// Set the callback that is invoked whenever a subchannel of the channel
+    /// transitions to another connectivity state.
+    ///
+    /// The states are watched in the completion queue of the channel, so the
+    /// callback should return quickly. They are sampled via channelz when
+    /// the state of the channel changes, and every second otherwise, so a
+    /// transition that is reverted in between may be missed. Subchannels
+    /// that appear are reported as moving from `GRPC_CHANNEL_IDLE`, and the
+    /// ones that are not used anymore as moving to `GRPC_CHANNEL_SHUTDOWN`.
+    /// Nothing is reported if channelz is disabled.
+    pub fn on_connectivity_change<F>(mut self, callback: F) -> ChannelBuilder
+    where
+        F: Fn(&ConnectivityEvent) + Send + Sync + 'static,
+    {
+        self.connectivity_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Move the channel to idle state and close its connections after having
+    /// no outstanding calls for the duration. A new connection is established
+    /// on the next call.
+    ///
+    /// It's only supported by gRPC Core 1.20 and later, so it's ignored by the
+    /// bundled version for now, and only takes effect when linking to a newer
+    /// system library.
+    pub fn idle_timeout(mut self, timeout: Duration) -> ChannelBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_CLIENT_IDLE_TIMEOUT_MS),
+            Options::Integer(dur_to_ms(timeout)),
+        );
+        self
+    }
+
     /// Set optimization target for the channel. See [`OptTarget`] for all available
     /// optimization targets. Defaults to `OptTarget::Blend`.
     pub fn optimize_for(mut self, target: OptTarget) -> ChannelBuilder {
@@ -415,33 +880,38 @@ impl ChannelBuilder {
         self
     }
 
+    /// Set arguments of gRPC Core that are not wrapped by the builder.
+    ///
+    /// Like other methods, an argument replaces the option set before with
+    /// the same key.
+    pub fn raw_args(mut self, args: RawChannelArgs) -> ChannelBuilder {
+        args.merge_into(&mut self.options);
+        self
+    }
+
+    /// Make the builder build the `index`th channel of a pool, the stats
+    /// handler is replaced by the one returned by `wrap`.
+    ///
+    /// gRPC Core shares connections among channels with the same arguments,
+    /// so the index is set as an argument to keep the connections separate.
+    pub(crate) fn pool_member<F>(mut self, index: usize, wrap: F) -> ChannelBuilder
+    where
+        F: FnOnce(Option<Arc<dyn StatsHandler>>) -> Arc<dyn StatsHandler>,
+    {
+        self.options.insert(
+            Cow::Borrowed(OPT_CHANNEL_POOL_INDEX),
+            Options::Integer(index as i32),
+        );
+        self.stats_handler = Some(wrap(self.stats_handler.take()));
+        self
+    }
+
     /// Build `ChannelArgs` from the current configuration.
     ///
     /// This method is only for bench usage, users should use the encapsulated API instead.
     #[doc(hidden)]
-    #[allow(clippy::identity_conversion)]
     pub fn build_args(&self) -> ChannelArgs {
-        let args = unsafe { grpc_sys::grpcwrap_channel_args_create(self.options.len()) };
-        for (i, (k, v)) in self.options.iter().enumerate() {
-            let key = k.as_ptr() as *const c_char;
-            match *v {
-                Options::Integer(val) => unsafe {
-                    // On most modern compiler and architect, c_int is the same as i32,
-                    // panic directly to simplify signature.
-                    assert!(
-                        val <= i32::from(libc::INT_MAX) && val >= i32::from(libc::INT_MIN),
-                        "{} is out of range for {:?}",
-                        val,
-                        CStr::from_bytes_with_nul(k).unwrap()
-                    );
-                    grpc_sys::grpcwrap_channel_args_set_integer(args, i, key, val as c_int)
-                },
-                Options::String(ref val) => unsafe {
-                    grpc_sys::grpcwrap_channel_args_set_string(args, i, key, val.as_ptr())
-                },
-            }
-        }
-        ChannelArgs { args }
+        build_channel_args(&self.options)
     }
 
     fn prepare_connect_args(&mut self) -> ChannelArgs {
@@ -451,15 +921,163 @@ impl ChannelBuilder {
         self.build_args()
     }
 
+    /// Connect to the addresses cached by the given cache instead of
+    /// resolving the address with gRPC Core.
+    ///
+    /// Connecting never waits for resolution. If the address is cached, the
+    /// channel connects to the cached addresses directly, and the host is
+    /// used as default authority unless it's set explicitly. Such a channel
+    /// keeps using these addresses, it doesn't see address changes, so it
+    /// needs to be recreated after the TTL of the cache to pick them up.
+    /// Otherwise the address is passed to gRPC Core as is, which resolves it
+    /// and follows the changes, and it's resolved in background for the
+    /// channels created later.
+    pub fn resolver_cache(mut self, cache: FixedTtlResolverCache) -> ChannelBuilder {
+        self.resolver = Some(cache);
+        self
+    }
+
+    /// Tunnel connections through the HTTP CONNECT proxy at `url`, which is
+    /// in the form of `http://[user:password@]host[:port]`.
+    ///
+    /// Basic authentication is used if credentials are given. Proxy settings
+    /// from environment variables are ignored once it's set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the url is invalid.
+    pub fn http_proxy(mut self, url: &str) -> ChannelBuilder {
+        let proxy = HttpProxy::parse(url).unwrap_or_else(|e| panic!("{}", e));
+        self.http_proxy = Some(proxy);
+        self.options
+            .insert(Cow::Borrowed(OPT_ENABLE_HTTP_PROXY), Options::Integer(0));
+        self
+    }
+
+    /// Set the hosts that should be connected directly instead of through
+    /// the proxy set by `http_proxy`.
+    ///
+    /// A host matches all its subdomains, and `*` matches all hosts.
+    pub fn no_proxy<I, S>(mut self, hosts: I) -> ChannelBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.no_proxy = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Enable or disable using proxies for the channel.
+    ///
+    /// gRPC Core reads proxy settings from environment variables by default,
+    /// disabling it also removes the proxy set by `http_proxy`.
+    pub fn enable_http_proxy(mut self, enable: bool) -> ChannelBuilder {
+        if !enable {
+            self.http_proxy = None;
+        }
+        self.options.insert(
+            Cow::Borrowed(OPT_ENABLE_HTTP_PROXY),
+            Options::Integer(enable as i32),
+        );
+        self
+    }
+
+    fn prepare_target(&mut self, addr: &str) -> CString {
+        let proxy = match self.http_proxy.take() {
+            Some(proxy) => proxy,
+            None => return self.resolve_target(addr),
+        };
+        match resolver::split_host_port(addr) {
+            Some((host, _)) if !proxy::is_excluded(&self.no_proxy, host) => {}
+            _ => return self.resolve_target(addr),
+        }
+        // The proxy connects to the original target on behalf of the channel.
+        let authority = resolver::strip_dns_scheme(addr);
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP_CONNECT_SERVER),
+            Options::String(CString::new(authority).unwrap()),
+        );
+        if let Some(auth) = proxy.authorization {
+            let header = format!("Proxy-Authorization:{}", auth);
+            self.options.insert(
+                Cow::Borrowed(OPT_HTTP_CONNECT_HEADERS),
+                Options::String(CString::new(header).unwrap()),
+            );
+        }
+        if let Entry::Vacant(e) = self.options.entry(Cow::Borrowed(OPT_DEFAULT_AUTHORITY)) {
+            e.insert(Options::String(CString::new(authority).unwrap()));
+        }
+        CString::new(proxy.addr).unwrap()
+    }
+
+    fn resolve_target(&mut self, addr: &str) -> CString {
+        if let Some((name, res)) = resolver::resolve_with_registered(addr) {
+            match res {
+                Ok(res) => {
+                    if let Entry::Vacant(e) =
+                        self.options.entry(Cow::Borrowed(OPT_DEFAULT_AUTHORITY))
+                    {
+                        e.insert(Options::String(CString::new(name).unwrap()));
+                    }
+                    if let Some(config) = res.service_config {
+                        self.options.insert(
+                            Cow::Borrowed(OPT_SERVICE_CONFIG),
+                            Options::String(CString::new(config).unwrap()),
+                        );
+                    }
+                    return CString::new(resolver::to_target(&res.addrs)).unwrap();
+                }
+                Err(e) => {
+                    warn!("failed to resolve {}: {:?}", addr, e);
+                    return CString::new(addr).unwrap();
+                }
+            }
+        }
+        let cache = match self.resolver {
+            Some(ref cache) => cache,
+            None => return CString::new(addr).unwrap(),
+        };
+        match cache.cached_target(addr) {
+            Some(target) => {
+                if let Entry::Vacant(e) = self.options.entry(Cow::Borrowed(OPT_DEFAULT_AUTHORITY)) {
+                    let authority = resolver::strip_dns_scheme(addr);
+                    e.insert(Options::String(CString::new(authority).unwrap()));
+                }
+                CString::new(target).unwrap()
+            }
+            None => CString::new(addr).unwrap(),
+        }
+    }
+
+    /// Build an insecure [`Channel`] that connects to a local transport.
+    ///
+    /// An error is returned if the transport is not supported on the
+    /// platform, see [`LocalTransport`].
+    pub fn connect_local(self, transport: &LocalTransport) -> Result<Channel> {
+        Ok(self.connect(&transport.target()?))
+    }
+
     /// Build an insecure [`Channel`] that connects to a specific address.
     pub fn connect(mut self, addr: &str) -> Channel {
+        let addr = self.prepare_target(addr);
         let args = self.prepare_connect_args();
-        let addr = CString::new(addr).unwrap();
         let addr_ptr = addr.as_ptr();
-        let channel =
-            unsafe { grpc_sys::grpc_insecure_channel_create(addr_ptr, args.args, ptr::null_mut()) };
-
-        Channel::new(self.env.pick_cq(), self.env, channel)
+        let (channel, channelz_id) = channelz::create_and_find_id(Kind::Channel, || unsafe {
+            grpc_sys::grpc_insecure_channel_create(addr_ptr, args.args, ptr::null_mut())
+        });
+
+        let ch = Channel::new(
+            self.env.pick_cq(),
+            self.env,
+            channel,
+            channelz_id,
+            send_limit(&self.options),
+            self.stats_handler,
+        );
+        if let Some(callback) = self.connectivity_callback {
+            ch.monitor_connectivity(callback);
+        }
+        ch
     }
 }
 
@@ -471,9 +1089,11 @@ mod secure_channel {
 
     use crate::grpc_sys;
 
+    use crate::channelz::{self, Kind};
     use crate::credentials::ChannelCredentials;
+    use crate::error::Result;
 
-    use super::{Channel, ChannelBuilder, Options};
+    use super::{Channel, ChannelBuilder, LocalTransport, Options};
 
     const OPT_SSL_TARGET_NAME_OVERRIDE: &[u8] = b"grpc.ssl_target_name_override\0";
 
@@ -492,25 +1112,57 @@ mod secure_channel {
             self
         }
 
+        /// Build a secure [`Channel`] that connects to a local transport, see
+        /// [`ChannelBuilder::connect_local`].
+        ///
+        /// It's usually used with `ChannelCredentials::local`, which lets
+        /// servers authenticate peers by the transport.
+        pub fn secure_connect_local(
+            self,
+            transport: &LocalTransport,
+            creds: ChannelCredentials,
+        ) -> Result<Channel> {
+            Ok(self.secure_connect(&transport.target()?, creds))
+        }
+
         /// Build a secure [`Channel`] that connects to a specific address.
         pub fn secure_connect(mut self, addr: &str, mut creds: ChannelCredentials) -> Channel {
+            let addr = self.prepare_target(addr);
             let args = self.prepare_connect_args();
-            let addr = CString::new(addr).unwrap();
             let addr_ptr = addr.as_ptr();
-            let channel = unsafe {
+            let (channel, channelz_id) = channelz::create_and_find_id(Kind::Channel, || unsafe {
                 grpc_sys::grpc_secure_channel_create(
                     creds.as_mut_ptr(),
                     addr_ptr,
                     args.args,
                     ptr::null_mut(),
                 )
-            };
-
-            Channel::new(self.env.pick_cq(), self.env, channel)
+            });
+
+            let ch = Channel::new(
+                self.env.pick_cq(),
+                self.env,
+                channel,
+                channelz_id,
+                super::send_limit(&self.options),
+                self.stats_handler,
+            );
+            if let Some(callback) = self.connectivity_callback {
+                ch.monitor_connectivity(callback);
+            }
+            ch
         }
     }
 }
 
+/// Get the limit of the size of sent messages set in the options.
+pub(crate) fn send_limit(options: &HashMap<Cow<'static, [u8]>, Options>) -> Option<usize> {
+    match options.get(OPT_MAX_SEND_MESSAGE_LENGTH) {
+        Some(Options::Integer(len)) if *len >= 0 => Some(*len as usize),
+        _ => None,
+    }
+}
+
 pub struct ChannelArgs {
     args: *mut grpc_channel_args,
 }
@@ -519,6 +1171,13 @@ impl ChannelArgs {
     pub fn as_ptr(&self) -> *const grpc_channel_args {
         self.args
     }
+
+    /// Copy the arguments along with `overrides`, which replace the
+    /// arguments with the same keys.
+    pub(crate) fn merge(&self, overrides: &ChannelArgs) -> ChannelArgs {
+        let args = unsafe { grpc_sys::grpcwrap_channel_args_merge(self.args, overrides.args) };
+        ChannelArgs { args }
+    }
 }
 
 impl Drop for ChannelArgs {
@@ -527,9 +1186,146 @@ impl Drop for ChannelArgs {
     }
 }
 
+/// A future that resolves with whether the channel is connected before the
+/// deadline, see [`Channel::wait_for_connected`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WaitForConnected {
+    channel: Channel,
+    deadline: Instant,
+    watch: Option<CqFuture<bool>>,
+}
+
+impl Future for WaitForConnected {
+    type Item = bool;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<bool, Error> {
+        loop {
+            if let Some(ref mut watch) = self.watch {
+                let changed = try_ready!(watch.poll());
+                self.watch = None;
+                if !changed {
+                    let state = self.channel.check_connectivity_state(false);
+                    return Ok(Async::Ready(state == ConnectivityState::GRPC_CHANNEL_READY));
+                }
+            }
+            // Trying to connect again is necessary when the channel falls
+            // back to idle after a failure.
+            let state = self.channel.check_connectivity_state(true);
+            match state {
+                ConnectivityState::GRPC_CHANNEL_READY => return Ok(Async::Ready(true)),
+                ConnectivityState::GRPC_CHANNEL_SHUTDOWN => return Ok(Async::Ready(false)),
+                _ if Instant::now() >= self.deadline => return Ok(Async::Ready(false)),
+                _ => {}
+            }
+            self.watch = Some(
+                self.channel
+                    .watch_connectivity_state(state, self.deadline)?,
+            );
+        }
+    }
+}
+
+/// A transition of the connectivity state of a subchannel, see
+/// [`ChannelBuilder::on_connectivity_change`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectivityEvent {
+    /// The channelz id of the subchannel.
+    pub subchannel_id: u64,
+    /// The address the subchannel connects to.
+    pub address: String,
+    pub old_state: ConnectivityState,
+    pub new_state: ConnectivityState,
+}
+
+type ConnectivityCallback = dyn Fn(&ConnectivityEvent) + Send + Sync;
+
+/// How often the states of the subchannels are sampled if the state of the
+/// channel doesn't change.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A future that reports the transitions of the subchannels of a channel.
+///
+/// It only holds a weak reference to the channel so that it doesn't keep the
+/// channel alive, and it stops once the channel is dropped.
+struct ConnectivityMonitor {
+    channel: Weak<ChannelInner>,
+    cq: CompletionQueue,
+    callback: Arc<ConnectivityCallback>,
+    states: HashMap<u64, SubchannelState>,
+    watch: Option<CqFuture<bool>>,
+}
+
+impl ConnectivityMonitor {
+    fn report(&mut self, states: Vec<SubchannelState>) {
+        let mut old_states = mem::take(&mut self.states);
+        for state in states {
+            let old_state = old_states
+                .remove(&state.id)
+                .map(|s| s.state)
+                .unwrap_or(ConnectivityState::GRPC_CHANNEL_IDLE);
+            if old_state != state.state {
+                (self.callback)(&ConnectivityEvent {
+                    subchannel_id: state.id,
+                    address: state.target.clone(),
+                    old_state,
+                    new_state: state.state,
+                });
+            }
+            self.states.insert(state.id, state);
+        }
+        for (id, state) in old_states {
+            (self.callback)(&ConnectivityEvent {
+                subchannel_id: id,
+                address: state.target,
+                old_state: state.state,
+                new_state: ConnectivityState::GRPC_CHANNEL_SHUTDOWN,
+            });
+        }
+    }
+}
+
+impl Future for ConnectivityMonitor {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Some(ref mut watch) = self.watch {
+                match watch.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) => {}
+                    Err(_) => return Ok(Async::Ready(())),
+                }
+                self.watch = None;
+            }
+            let channel = match self.channel.upgrade() {
+                Some(inner) => Channel {
+                    inner,
+                    cq: self.cq.clone(),
+                },
+                None => return Ok(Async::Ready(())),
+            };
+            self.report(channel.subchannel_states());
+            let state = channel.check_connectivity_state(false);
+            if state == ConnectivityState::GRPC_CHANNEL_SHUTDOWN {
+                return Ok(Async::Ready(()));
+            }
+            let deadline = Instant::now() + MONITOR_INTERVAL;
+            match channel.watch_connectivity_state(state, deadline) {
+                Ok(watch) => self.watch = Some(watch),
+                Err(_) => return Ok(Async::Ready(())),
+            }
+        }
+    }
+}
+
 struct ChannelInner {
     _env: Arc<Environment>,
     channel: *mut grpc_channel,
+    channelz_id: Option<u64>,
+    max_send_message_len: Option<usize>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
 }
 
 impl ChannelInner {
@@ -541,6 +1337,10 @@ impl ChannelInner {
     }
 }
 
+// The channel of gRPC Core is thread safe.
+unsafe impl Send for ChannelInner {}
+unsafe impl Sync for ChannelInner {}
+
 impl Drop for ChannelInner {
     fn drop(&mut self) {
         unsafe {
@@ -565,22 +1365,151 @@ unsafe impl Send for Channel {}
 unsafe impl Sync for Channel {}
 
 impl Channel {
-    fn new(cq: CompletionQueue, env: Arc<Environment>, channel: *mut grpc_channel) -> Channel {
+    fn new(
+        cq: CompletionQueue,
+        env: Arc<Environment>,
+        channel: *mut grpc_channel,
+        channelz_id: Option<u64>,
+        max_send_message_len: Option<usize>,
+        stats_handler: Option<Arc<dyn StatsHandler>>,
+    ) -> Channel {
         Channel {
-            inner: Arc::new(ChannelInner { _env: env, channel }),
+            inner: Arc::new(ChannelInner {
+                _env: env,
+                channel,
+                channelz_id,
+                max_send_message_len,
+                stats_handler,
+            }),
             cq,
         }
     }
 
+    /// Get the channelz id of the channel.
+    ///
+    /// `None` is returned if channelz is disabled.
+    pub fn channelz_id(&self) -> Option<u64> {
+        self.inner.channelz_id
+    }
+
+    /// Get the channelz state of the channel, including its connectivity
+    /// state, call counts, traces and references to subchannels.
+    ///
+    /// See [`channelz`](channelz/index.html) for the format.
+    pub fn channelz_state(&self) -> Option<String> {
+        self.inner.channelz_id.and_then(channelz::get_channel)
+    }
+
     // If try_to_connect is true, the channel will try to establish a connection, potentially
     // changing the state.
     pub fn check_connectivity_state(&self, try_to_connect: bool) -> ConnectivityState {
         self.inner.check_connectivity_state(try_to_connect)
     }
 
+    /// Start connecting if the channel is idle.
+    ///
+    /// A channel doesn't connect until its first call by default, calling
+    /// this at startup moves the cost of resolving and connecting out of
+    /// the first call. It returns immediately, see
+    /// [`Channel::wait_for_connected`] for waiting until it's ready.
+    pub fn connect(&self) {
+        self.inner.check_connectivity_state(true);
+    }
+
+    /// Get a future that connects the channel and resolves with whether it
+    /// becomes ready before the deadline.
+    ///
+    /// The channel keeps reconnecting after failures until the deadline, so
+    /// a backend that comes up in time is still reported as connected. It's
+    /// useful for readiness checks.
+    pub fn wait_for_connected(&self, deadline: Instant) -> WaitForConnected {
+        WaitForConnected {
+            channel: self.clone(),
+            deadline,
+            watch: None,
+        }
+    }
+
+    /// Watch the connectivity state, the future resolves with `true` once the
+    /// state is different from `last_observed`, or `false` if the deadline
+    /// is reached first.
+    fn watch_connectivity_state(
+        &self,
+        last_observed: ConnectivityState,
+        deadline: Instant,
+    ) -> Result<CqFuture<bool>> {
+        let cq_ref = self.cq.try_ref()?;
+        let (f, tag) = CallTag::watch_state_pair();
+        let tag = Box::into_raw(Box::new(tag));
+        let timeout = gpr_timespec::from(deadline.saturating_duration_since(Instant::now()));
+        unsafe {
+            grpc_sys::grpc_channel_watch_connectivity_state(
+                self.inner.channel,
+                last_observed,
+                timeout,
+                cq_ref.as_ptr(),
+                tag as *mut _,
+            )
+        }
+        Ok(f)
+    }
+
+    /// Get the states of the subchannels that are used by the load balancing
+    /// policy currently.
+    ///
+    /// With `LbPolicy::RoundRobin`, there is a subchannel for every resolved
+    /// address, and calls are only sent to the ready ones. The states are
+    /// fetched via channelz, so an empty list is returned if channelz is
+    /// disabled.
+    pub fn subchannel_states(&self) -> Vec<SubchannelState> {
+        let json = match self.channelz_state() {
+            Some(json) => json,
+            None => return vec![],
+        };
+        // Trace events may also refer to subchannels that are not used anymore.
+        let refs = match json.find("\"subchannelRef\":[") {
+            Some(pos) => {
+                let refs = &json[pos..];
+                &refs[..refs.find(']').unwrap_or(refs.len())]
+            }
+            None => return vec![],
+        };
+        channelz::find_ids(refs, "subchannelId")
+            .into_iter()
+            .filter_map(|id| {
+                let json = channelz::get_subchannel(id)?;
+                let state =
+                    channelz::find_str(&json, "state").and_then(parse_connectivity_state)?;
+                let target = channelz::find_str(&json, "target").unwrap_or_default();
+                Some(SubchannelState {
+                    id,
+                    target: target.to_owned(),
+                    state,
+                })
+            })
+            .collect()
+    }
+
+    /// Report the transitions of the subchannels to the callback until the
+    /// channel is dropped.
+    fn monitor_connectivity(&self, callback: Arc<ConnectivityCallback>) {
+        let kicker = match self.create_kicker() {
+            Ok(kicker) => kicker,
+            Err(_) => return,
+        };
+        let monitor = ConnectivityMonitor {
+            channel: Arc::downgrade(&self.inner),
+            cq: self.cq.clone(),
+            callback,
+            states: HashMap::new(),
+            watch: None,
+        };
+        Executor::new(&self.cq).spawn(monitor, kicker);
+    }
+
     /// Create a Kicker.
     pub(crate) fn create_kicker(&self) -> Result<Kicker> {
-        let cq_ref = self.cq.borrow()?;
+        let cq_ref = self.cq.try_ref()?;
         let raw_call = unsafe {
             let ch = self.inner.channel;
             let cq = cq_ref.as_ptr();
@@ -603,19 +1532,15 @@ impl Channel {
     }
 
     /// Create a call using the method and option.
-    pub(crate) fn create_call<Req, Resp>(
-        &self,
-        method: &Method<Req, Resp>,
-        opt: &CallOption,
-    ) -> Result<Call> {
-        let cq_ref = self.cq.borrow()?;
+    pub(crate) fn create_call(&self, method: &str, opt: &CallOption) -> Result<Call> {
+        let cq_ref = self.cq.try_ref()?;
         let raw_call = unsafe {
             let ch = self.inner.channel;
             let cq = cq_ref.as_ptr();
-            let method_ptr = method.name.as_ptr();
-            let method_len = method.name.len();
+            let method_ptr = method.as_ptr();
+            let method_len = method.len();
             let timeout = opt
-                .get_timeout()
+                .remaining()
                 .map_or_else(gpr_timespec::inf_future, gpr_timespec::from);
             grpc_sys::grpcwrap_channel_create_call(
                 ch,
@@ -629,8 +1554,28 @@ impl Channel {
                 timeout,
             )
         };
+        let mut call = unsafe { Call::from_raw(raw_call, self.cq.clone()) };
+        call.max_send_message_len = self.inner.max_send_message_len;
+        if let Some(ref handler) = self.inner.stats_handler {
+            let stats = CallStats::new(handler.clone(), method.as_bytes(), CallSide::Client);
+            call.stats = Some(stats);
+        }
+        if let Some(bound) = opt.get_batch_watchdog() {
+            call.watchdog = Some(Watchdog::new(bound, method.as_bytes()));
+        }
+
+        #[cfg(feature = "secure")]
+        {
+            if let Some(creds) = opt.get_credentials() {
+                let code =
+                    unsafe { grpc_sys::grpc_call_set_credentials(call.call, creds.as_mut_ptr()) };
+                if code != grpc_sys::grpc_call_error::GRPC_CALL_OK {
+                    return Err(Error::CallFailure(code));
+                }
+            }
+        }
 
-        unsafe { Ok(Call::from_raw(raw_call, self.cq.clone())) }
+        Ok(call)
     }
 
     pub(crate) fn cq(&self) -> &CompletionQueue {
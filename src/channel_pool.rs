@@ -0,0 +1,211 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pools of channels connecting to the same target.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::channel::{Channel, ChannelBuilder};
+#[cfg(feature = "secure")]
+use crate::credentials::ChannelCredentials;
+use crate::stats::{CallEnd, CallInfo, StatsHandler};
+
+/// How a [`ChannelPool`] distributes calls among its channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolPolicy {
+    /// Pick channels in turn, which is the default.
+    RoundRobin,
+    /// Pick the channel with the fewest calls in flight, ties are broken in
+    /// turn.
+    LeastLoaded,
+}
+
+/// Counts the calls in flight of a channel, and forwards the events to the
+/// handler set by users.
+struct LoadTracker {
+    in_flight: AtomicUsize,
+    handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl StatsHandler for LoadTracker {
+    fn call_start(&self, call: &CallInfo) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if let Some(ref h) = self.handler {
+            h.call_start(call);
+        }
+    }
+
+    fn message_sent(&self, call: &CallInfo, bytes: usize) {
+        if let Some(ref h) = self.handler {
+            h.message_sent(call, bytes);
+        }
+    }
+
+    fn message_received(&self, call: &CallInfo, bytes: usize) {
+        if let Some(ref h) = self.handler {
+            h.message_received(call, bytes);
+        }
+    }
+
+    fn call_end(&self, call: &CallInfo, end: &CallEnd) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if let Some(ref h) = self.handler {
+            h.call_end(call, end);
+        }
+    }
+
+    fn host_picked(&self, host: &str, in_flight: usize, queue_wait: Duration) {
+        if let Some(ref h) = self.handler {
+            h.host_picked(host, in_flight, queue_wait);
+        }
+    }
+}
+
+/// A builder for [`ChannelPool`].
+pub struct ChannelPoolBuilder {
+    size: usize,
+    policy: PoolPolicy,
+}
+
+impl ChannelPoolBuilder {
+    /// Create a builder of a pool with `size` channels.
+    pub fn new(size: usize) -> ChannelPoolBuilder {
+        ChannelPoolBuilder {
+            size,
+            policy: PoolPolicy::RoundRobin,
+        }
+    }
+
+    /// Set how calls are distributed among the channels.
+    pub fn policy(mut self, policy: PoolPolicy) -> ChannelPoolBuilder {
+        self.policy = policy;
+        self
+    }
+
+    /// Build a pool of insecure channels connecting to `addr`, `builder` is
+    /// called to configure every channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is 0.
+    pub fn connect<B>(self, addr: &str, builder: B) -> ChannelPool
+    where
+        B: FnMut() -> ChannelBuilder,
+    {
+        self.build(builder, |b| b.connect(addr))
+    }
+
+    /// Build a pool of secure channels connecting to `addr`, `builder` and
+    /// `creds` are called to configure every channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is 0.
+    #[cfg(feature = "secure")]
+    pub fn secure_connect<B, C>(self, addr: &str, builder: B, mut creds: C) -> ChannelPool
+    where
+        B: FnMut() -> ChannelBuilder,
+        C: FnMut() -> ChannelCredentials,
+    {
+        self.build(builder, |b| b.secure_connect(addr, creds()))
+    }
+
+    fn build<B, C>(self, mut builder: B, mut connect: C) -> ChannelPool
+    where
+        B: FnMut() -> ChannelBuilder,
+        C: FnMut(ChannelBuilder) -> Channel,
+    {
+        assert!(self.size > 0, "channel pool should not be empty");
+        let mut members = Vec::with_capacity(self.size);
+        for i in 0..self.size {
+            let mut tracker = None;
+            let b = builder().pool_member(i, |handler| {
+                let t = Arc::new(LoadTracker {
+                    in_flight: AtomicUsize::new(0),
+                    handler,
+                });
+                tracker = Some(t.clone());
+                t
+            });
+            members.push(Member {
+                channel: connect(b),
+                tracker: tracker.unwrap(),
+            });
+        }
+        ChannelPool {
+            inner: Arc::new(Inner {
+                members,
+                policy: self.policy,
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+struct Member {
+    channel: Channel,
+    tracker: Arc<LoadTracker>,
+}
+
+struct Inner {
+    members: Vec<Member>,
+    policy: PoolPolicy,
+    next: AtomicUsize,
+}
+
+/// A pool of channels connecting to the same target.
+///
+/// A connection can only carry a limited number of concurrent streams, which
+/// is negotiated by HTTP/2 settings, and its throughput is bounded by the
+/// single polling thread that drives it. A pool keeps a separate connection
+/// for every channel, so a high QPS client can spread its calls among them.
+///
+/// Calls are tracked by their channels, so the channels returned by
+/// [`ChannelPool::channel`] can be used like any other channel, for example
+/// to create generated clients.
+#[derive(Clone)]
+pub struct ChannelPool {
+    inner: Arc<Inner>,
+}
+
+impl ChannelPool {
+    /// Pick a channel for the next calls according to the policy.
+    pub fn channel(&self) -> Channel {
+        let members = &self.inner.members;
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let picked = match self.inner.policy {
+            PoolPolicy::RoundRobin => start % members.len(),
+            PoolPolicy::LeastLoaded => (0..members.len())
+                .map(|i| (start + i) % members.len())
+                .min_by_key(|i| members[*i].tracker.in_flight.load(Ordering::SeqCst))
+                .unwrap(),
+        };
+        members[picked].channel.clone()
+    }
+
+    /// The count of the channels.
+    pub fn size(&self) -> usize {
+        self.inner.members.len()
+    }
+
+    /// Get the count of calls in flight of every channel.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.inner
+            .members
+            .iter()
+            .map(|m| m.tracker.in_flight.load(Ordering::SeqCst))
+            .collect()
+    }
+}
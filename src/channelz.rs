@@ -0,0 +1,219 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Channelz introspection of channels, subchannels, servers and sockets.
+//!
+//! All the functions return the objects of `grpc.channelz.v1` in the proto3
+//! JSON format, which can be parsed by any JSON library or converted to the
+//! messages defined in [channelz.proto]. Objects are identified by the ids
+//! found in the references of other objects, and `None` is returned if the
+//! object doesn't exist (anymore).
+//!
+//! Channelz is enabled by default, it can be disabled by setting the channel
+//! argument `grpc.enable_channelz` to 0.
+//!
+//! [channelz.proto]: https://github.com/grpc/grpc/blob/master/src/proto/grpc/channelz/channelz.proto
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use crate::grpc_sys;
+
+/// Copy and free the JSON string returned by gRPC Core.
+unsafe fn take_json(ptr: *mut c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let json = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    grpc_sys::gpr_free(ptr as _);
+    Some(json)
+}
+
+/// Get the top level channels whose ids are not less than `start_channel_id`.
+///
+/// At most 100 channels are returned, `end` is set when there are no more.
+pub fn get_top_channels(start_channel_id: u64) -> String {
+    unsafe {
+        take_json(grpc_sys::grpc_channelz_get_top_channels(
+            start_channel_id as _,
+        ))
+    }
+    .unwrap_or_default()
+}
+
+/// Get the servers whose ids are not less than `start_server_id`.
+pub fn get_servers(start_server_id: u64) -> String {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_servers(start_server_id as _)) }
+        .unwrap_or_default()
+}
+
+/// Get the server.
+pub fn get_server(server_id: u64) -> Option<String> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_server(server_id as _)) }
+}
+
+/// Get the sockets of the server whose ids are not less than
+/// `start_socket_id`.
+pub fn get_server_sockets(server_id: u64, start_socket_id: u64) -> Option<String> {
+    unsafe {
+        take_json(grpc_sys::grpc_channelz_get_server_sockets(
+            server_id as _,
+            start_socket_id as _,
+        ))
+    }
+}
+
+/// Get the channel.
+pub fn get_channel(channel_id: u64) -> Option<String> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_channel(channel_id as _)) }
+}
+
+/// Get the subchannel.
+pub fn get_subchannel(subchannel_id: u64) -> Option<String> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_subchannel(subchannel_id as _)) }
+}
+
+/// Get the socket.
+pub fn get_socket(socket_id: u64) -> Option<String> {
+    unsafe { take_json(grpc_sys::grpc_channelz_get_socket(socket_id as _)) }
+}
+
+/// Find all the ids of `"key":"id"` in the JSON.
+pub(crate) fn find_ids(json: &str, key: &str) -> Vec<u64> {
+    let pattern = format!("\"{}\":", key);
+    let mut ids = vec![];
+    let mut rest = json;
+    while let Some(pos) = rest.find(&pattern) {
+        rest = &rest[pos + pattern.len()..];
+        let digits = rest.trim_start_matches('"');
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        if let Ok(id) = digits[..end].parse::<u64>() {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+/// Find the max id of `"key":"id"` in the JSON.
+fn max_id(json: &str, key: &str) -> Option<u64> {
+    find_ids(json, key).into_iter().max()
+}
+
+/// Find the first string value of `"key":"value"` in the JSON.
+///
+/// Escaped characters are kept as is.
+pub(crate) fn find_str<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("\"{}\":\"", key);
+    let start = json.find(&pattern)? + pattern.len();
+    let value = &json[start..];
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' if !escaped => return Some(&value[..i]),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+// Ids are allocated from a global counter in ascending order.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static CREATING: AtomicBool = AtomicBool::new(false);
+
+/// The kinds of objects that are registered when created.
+#[derive(Clone, Copy)]
+pub(crate) enum Kind {
+    Channel,
+    Server,
+}
+
+/// Create an object and find its channelz id.
+///
+/// gRPC Core doesn't expose the ids of objects, so creation is serialized
+/// and the id is found by listing the objects that are newer than the known
+/// ids. It only works if all the objects are created via this function.
+pub(crate) fn create_and_find_id<T, F: FnOnce() -> T>(kind: Kind, create: F) -> (T, Option<u64>) {
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            CREATING.store(false, Ordering::Release);
+        }
+    }
+
+    while CREATING
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        thread::yield_now();
+    }
+    let _guard = Guard;
+    let obj = create();
+    let (list, key): (fn(u64) -> String, _) = match kind {
+        Kind::Channel => (get_top_channels, "channelId"),
+        Kind::Server => (get_servers, "serverId"),
+    };
+    let mut start = NEXT_ID.load(Ordering::Relaxed) as u64;
+    let mut id = None;
+    loop {
+        let json = list(start);
+        match max_id(&json, key) {
+            Some(max) => {
+                id = Some(max);
+                start = max + 1;
+            }
+            None => break,
+        }
+        if json.contains("\"end\":true") {
+            break;
+        }
+    }
+    if let Some(id) = id {
+        NEXT_ID.store(id as usize + 1, Ordering::Relaxed);
+    }
+    (obj, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_str, max_id};
+
+    #[test]
+    fn test_max_id() {
+        let json = r#"{"channel":[{"ref":{"channelId":"4"},"data":{"target":"a"}},
+            {"ref":{"channelId":"12"},"subchannelRef":[{"subchannelId":"13"}]}],"end":true}"#;
+        assert_eq!(max_id(json, "channelId"), Some(12));
+        assert_eq!(max_id(json, "subchannelId"), Some(13));
+        assert_eq!(max_id(json, "serverId"), None);
+        assert_eq!(
+            max_id(r#"{"server":[{"ref":{"serverId":7}}]}"#, "serverId"),
+            Some(7)
+        );
+        assert_eq!(max_id("", "serverId"), None);
+    }
+
+    #[test]
+    fn test_find_str() {
+        let json = r#"{"data":{"state":{"state":"READY"},"target":"ipv4:127.0.0.1:80",
+            "trace":{"events":[{"description":"say \"hi\""}]}}}"#;
+        assert_eq!(find_str(json, "state"), Some("READY"));
+        assert_eq!(find_str(json, "target"), Some("ipv4:127.0.0.1:80"));
+        assert_eq!(find_str(json, "description"), Some(r#"say \"hi\""#));
+        assert_eq!(find_str(json, "data"), None);
+        assert_eq!(find_str(r#"{"target":"abc"#, "target"), None);
+    }
+}
@@ -11,18 +11,200 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use futures::Future;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
 
 use crate::call::client::{
     CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
     ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver,
 };
-use crate::call::{Call, Method};
+use crate::call::{Call, MessageReader, Method, RpcStatusCode};
 use crate::channel::Channel;
+use crate::codec::{raw_codec, DeserializeFn};
+use crate::metadata::{Metadata, MetadataBuilder};
+use crate::response_cache::ClientCache;
 use crate::task::Executor;
 use crate::task::Kicker;
+use crate::task::{self, Delay};
+
+use crate::error::{Error, Result};
+
+/// How [`Client::unary_call_with_retry`] and
+/// [`Client::unary_call_with_retry_async`] re-issue failed calls.
+///
+/// Only calls failed with `UNAVAILABLE` or `DEADLINE_EXCEEDED` are retried,
+/// and the delay between attempts grows exponentially from the initial
+/// backoff until the max backoff.
+///
+/// It's up to the caller to make sure the method is safe to call more than
+/// once, the generated clients only expose the helpers for methods marked
+/// with `option idempotency_level = IDEMPOTENT` or `NO_SIDE_EFFECTS`. Unlike
+/// the transparent retries of gRPC Core, which only re-send calls that never
+/// reach the server, a retried call may have been processed by the server.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+        }
+    }
+}
 
-use crate::error::Result;
+impl RetryPolicy {
+    /// Create a policy that makes at most `max_attempts` attempts, including
+    /// the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_attempts` is 0.
+    pub fn new(max_attempts: usize) -> RetryPolicy {
+        assert!(max_attempts > 0, "max_attempts should be at least 1");
+        RetryPolicy {
+            max_attempts,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Set the delay before the first retry and the upper bound of the
+    /// delays.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial_backoff = initial;
+        self.max_backoff = cmp::max(initial, max);
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after every retry.
+    pub fn multiplier(mut self, multiplier: f64) -> RetryPolicy {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        cmp::min(backoff.mul_f64(self.multiplier), self.max_backoff)
+    }
+
+    fn is_retryable(res: &Result<impl Sized>) -> bool {
+        match *res {
+            Err(Error::RpcFailure(ref s)) => {
+                s.status == RpcStatusCode::UNAVAILABLE
+                    || s.status == RpcStatusCode::DEADLINE_EXCEEDED
+            }
+            _ => false,
+        }
+    }
+}
+
+enum RetryState {
+    Calling(ClientUnaryReceiver<Vec<u8>>),
+    Backoff(Delay),
+}
+
+/// A receiver for unary request that is re-issued according to a
+/// [`RetryPolicy`], see [`Client::unary_call_with_retry_async`].
+///
+/// The future is resolved with the result of the last attempt. The pending
+/// attempt is cancelled if the receiver is dropped before that.
+#[must_use = "if unused the RetryUnaryReceiver may immediately cancel the RPC"]
+pub struct RetryUnaryReceiver<Resp> {
+    client: Client,
+    path: &'static str,
+    req: Vec<u8>,
+    opt: CallOption,
+    policy: RetryPolicy,
+    resp_de: DeserializeFn<Resp>,
+    backoff: Duration,
+    attempts: usize,
+    state: RetryState,
+}
+
+impl<Resp> RetryUnaryReceiver<Resp> {
+    fn start_call(&self) -> Result<ClientUnaryReceiver<Vec<u8>>> {
+        Call::unary_async(
+            &self.client.channel,
+            self.path,
+            raw_codec::ser_slice,
+            raw_codec::de,
+            &self.req,
+            self.client.call_option(self.path, self.opt.clone()),
+        )
+    }
+
+    fn should_retry(&self, res: &Result<Vec<u8>>) -> bool {
+        if self.attempts >= self.policy.max_attempts || !RetryPolicy::is_retryable(res) {
+            return false;
+        }
+        match self.opt.get_deadline() {
+            Some(deadline) => Instant::now() + self.backoff < deadline,
+            None => true,
+        }
+    }
+}
+
+impl<Resp> Future for RetryUnaryReceiver<Resp> {
+    type Item = Resp;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Resp, Error> {
+        loop {
+            let res = match self.state {
+                RetryState::Calling(ref mut f) => match f.poll() {
+                    Ok(Async::Ready(resp)) => Ok(resp),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => Err(e),
+                },
+                RetryState::Backoff(ref mut delay) => {
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                    self.attempts += 1;
+                    match self.start_call() {
+                        Ok(f) => {
+                            self.state = RetryState::Calling(f);
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            if !self.should_retry(&res) {
+                let resp = res?;
+                return (self.resp_de)(MessageReader::from_bytes(&resp)).map(Async::Ready);
+            }
+            self.state = RetryState::Backoff(task::sleep(self.backoff));
+            self.backoff = self.policy.next_backoff(self.backoff);
+        }
+    }
+}
+
+/// Merge the headers, the ones in `defaults` are skipped if `headers`
+/// contains the same keys.
+fn merge_headers(defaults: &Metadata, headers: &Metadata) -> Metadata {
+    let mut builder = MetadataBuilder::with_capacity(defaults.len() + headers.len());
+    for (k, v) in defaults.iter() {
+        if headers.iter().all(|(key, _)| key != k) {
+            builder.add_metadata(k, v).unwrap();
+        }
+    }
+    for (k, v) in headers.iter() {
+        builder.add_metadata(k, v).unwrap();
+    }
+    builder.build()
+}
 
 /// A generic client for making RPC calls.
 #[derive(Clone)]
@@ -30,13 +212,99 @@ pub struct Client {
     channel: Channel,
     // Used to kick its completion queue.
     kicker: Kicker,
+    default_timeout: Option<Duration>,
+    method_timeouts: Arc<HashMap<String, Duration>>,
+    default_headers: Option<Arc<Metadata>>,
+    cache: Option<Arc<dyn ClientCache>>,
 }
 
 impl Client {
     /// Initialize a new [`Client`].
     pub fn new(channel: Channel) -> Client {
         let kicker = channel.create_kicker().unwrap();
-        Client { channel, kicker }
+        Client {
+            channel,
+            kicker,
+            default_timeout: None,
+            method_timeouts: Arc::default(),
+            default_headers: None,
+            cache: None,
+        }
+    }
+
+    /// Create a client that shares the channel and the settings of this one,
+    /// and sends the headers with every call.
+    ///
+    /// It's cheap, so it's the way to scope headers like tenant IDs or auth
+    /// tokens to a subsystem. The headers are added to the default headers of
+    /// this client, and the headers set by `CallOption::headers` take
+    /// precedence over the default ones with the same keys.
+    pub fn with_default_metadata(&self, headers: Metadata) -> Client {
+        let headers = match self.default_headers {
+            Some(ref defaults) => merge_headers(defaults, &headers),
+            None => headers,
+        };
+        Client {
+            default_headers: Some(Arc::new(headers)),
+            ..self.clone()
+        }
+    }
+
+    /// Create a client that shares the channel and the settings of this one,
+    /// and uses the timeout by default, see [`Client::set_default_timeout`].
+    pub fn with_default_timeout(&self, timeout: Duration) -> Client {
+        let mut client = self.clone();
+        client.set_default_timeout(timeout);
+        client
+    }
+
+    /// Create a client that shares the channel and the settings of this one,
+    /// and caches the responses of [`Client::cacheable_unary_call`] in
+    /// `cache`.
+    pub fn with_cache<C: ClientCache + 'static>(&self, cache: C) -> Client {
+        Client {
+            cache: Some(Arc::new(cache)),
+            ..self.clone()
+        }
+    }
+
+    /// Set the timeout of calls that don't set a timeout or a deadline in
+    /// their options.
+    ///
+    /// Calls that disable the deadline by `CallOption::no_deadline` are not
+    /// affected. The timeouts set by `set_method_timeout` take precedence.
+    pub fn set_default_timeout(&mut self, timeout: Duration) {
+        self.default_timeout = Some(timeout);
+    }
+
+    /// Set the default timeout of calls to the method at `path`, like
+    /// `/helloworld.Greeter/SayHello`, see [`Client::set_default_timeout`].
+    pub fn set_method_timeout(&mut self, path: &str, timeout: Duration) {
+        Arc::make_mut(&mut self.method_timeouts).insert(path.to_owned(), timeout);
+    }
+
+    /// Apply the default timeouts and headers to the options of a call to
+    /// `path`.
+    fn call_option(&self, path: &str, mut opt: CallOption) -> CallOption {
+        if let Some(ref defaults) = self.default_headers {
+            let headers = match opt.get_headers() {
+                Some(headers) => merge_headers(defaults, headers),
+                None => (**defaults).clone(),
+            };
+            opt = opt.headers(headers);
+        }
+        if opt.has_deadline_option() {
+            return opt;
+        }
+        match self
+            .method_timeouts
+            .get(path)
+            .cloned()
+            .or(self.default_timeout)
+        {
+            Some(timeout) => opt.timeout(timeout),
+            None => opt,
+        }
     }
 
     /// Create a synchronized unary RPC call.
@@ -50,6 +318,114 @@ impl Client {
         f.wait()
     }
 
+    /// Create a synchronized unary RPC call, and re-issue it according to
+    /// the policy if it fails with `UNAVAILABLE` or `DEADLINE_EXCEEDED`.
+    ///
+    /// The timeout of the options applies to every attempt, while the
+    /// deadline applies to all of them, no attempt is made after that. The
+    /// result of the last attempt is returned.
+    pub fn unary_call_with_retry<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        req: &Req,
+        opt: CallOption,
+        policy: &RetryPolicy,
+    ) -> Result<Resp> {
+        let mut backoff = policy.initial_backoff;
+        let mut attempts = 1;
+        loop {
+            let res = self.unary_call(method, req, opt.clone());
+            if attempts >= policy.max_attempts || !RetryPolicy::is_retryable(&res) {
+                return res;
+            }
+            if let Some(deadline) = opt.get_deadline() {
+                if Instant::now() + backoff >= deadline {
+                    return res;
+                }
+            }
+            thread::sleep(backoff);
+            backoff = policy.next_backoff(backoff);
+            attempts += 1;
+        }
+    }
+
+    /// Create an asynchronized unary RPC call, and re-issue it according to
+    /// the policy, see [`Client::unary_call_with_retry`].
+    ///
+    /// The delays between attempts are waited by timers instead of blocking
+    /// the thread, so the receiver can be polled by futures running on the
+    /// gRPC poll threads.
+    pub fn unary_call_with_retry_async<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        req: &Req,
+        opt: CallOption,
+        policy: &RetryPolicy,
+    ) -> Result<RetryUnaryReceiver<Resp>> {
+        let mut buf = vec![];
+        (method.req_ser())(req, &mut buf);
+        let f = Call::unary_async(
+            &self.channel,
+            method.name,
+            raw_codec::ser_slice,
+            raw_codec::de,
+            &buf,
+            self.call_option(method.name, opt.clone()),
+        )?;
+        Ok(RetryUnaryReceiver {
+            client: self.clone(),
+            path: method.name,
+            req: buf,
+            opt,
+            policy: policy.clone(),
+            resp_de: method.resp_de(),
+            backoff: policy.initial_backoff,
+            attempts: 1,
+            state: RetryState::Calling(f),
+        })
+    }
+
+    /// Create a synchronized unary RPC call to a method without side
+    /// effects, which is marked with `option idempotency_level =
+    /// NO_SIDE_EFFECTS`.
+    ///
+    /// The call is marked as cacheable, so gRPC Core is free to send it as
+    /// an HTTP/2 GET request with the message in the query string when it's
+    /// small enough, see `ChannelBuilder::max_get_payload_size`. If the
+    /// client has a cache set by [`Client::with_cache`], it's keyed by the
+    /// method, the headers including the default ones and the serialized
+    /// request, and successful responses are served from it without calling
+    /// the server.
+    pub fn cacheable_unary_call<Req, Resp>(
+        &self,
+        method: &Method<Req, Resp>,
+        req: &Req,
+        opt: CallOption,
+    ) -> Result<Resp> {
+        let mut buf = vec![];
+        (method.req_ser())(req, &mut buf);
+        let opt = self.call_option(method.name, opt.cacheable(true));
+        let cache = self.cache.as_ref();
+        let headers = opt.get_headers();
+        if let Some(resp) = cache.and_then(|c| c.get(method.name, headers, &buf)) {
+            return (method.resp_de())(MessageReader::from_bytes(&resp));
+        }
+        let resp = Call::unary_async(
+            &self.channel,
+            method.name,
+            raw_codec::ser_slice,
+            raw_codec::de,
+            &buf,
+            opt.clone(),
+        )?
+        .wait()?;
+        let res = (method.resp_de())(MessageReader::from_bytes(&resp));
+        if let (Ok(_), Some(c)) = (&res, cache) {
+            c.insert(method.name, opt.get_headers(), &buf, resp);
+        }
+        res
+    }
+
     /// Create an asynchronized unary RPC call.
     pub fn unary_call_async<Req, Resp>(
         &self,
@@ -57,7 +433,14 @@ impl Client {
         req: &Req,
         opt: CallOption,
     ) -> Result<ClientUnaryReceiver<Resp>> {
-        Call::unary_async(&self.channel, method, req, opt)
+        Call::unary_async(
+            &self.channel,
+            method.name,
+            method.req_ser(),
+            method.resp_de(),
+            req,
+            self.call_option(method.name, opt),
+        )
     }
 
     /// Create an asynchronized client streaming call.
@@ -68,7 +451,13 @@ impl Client {
         method: &Method<Req, Resp>,
         opt: CallOption,
     ) -> Result<(ClientCStreamSender<Req>, ClientCStreamReceiver<Resp>)> {
-        Call::client_streaming(&self.channel, method, opt)
+        Call::client_streaming(
+            &self.channel,
+            method.name,
+            method.req_ser(),
+            method.resp_de(),
+            self.call_option(method.name, opt),
+        )
     }
 
     /// Create an asynchronized server streaming call.
@@ -80,7 +469,14 @@ impl Client {
         req: &Req,
         opt: CallOption,
     ) -> Result<ClientSStreamReceiver<Resp>> {
-        Call::server_streaming(&self.channel, method, req, opt)
+        Call::server_streaming(
+            &self.channel,
+            method.name,
+            method.req_ser(),
+            method.resp_de(),
+            req,
+            self.call_option(method.name, opt),
+        )
     }
 
     /// Create an asynchronized duplex streaming call.
@@ -93,7 +489,93 @@ impl Client {
         method: &Method<Req, Resp>,
         opt: CallOption,
     ) -> Result<(ClientDuplexSender<Req>, ClientDuplexReceiver<Resp>)> {
-        Call::duplex_streaming(&self.channel, method, opt)
+        Call::duplex_streaming(
+            &self.channel,
+            method.name,
+            method.req_ser(),
+            method.resp_de(),
+            self.call_option(method.name, opt),
+        )
+    }
+
+    /// Create a synchronized unary RPC call to the method at `path`, like
+    /// `/helloworld.Greeter/SayHello`.
+    ///
+    /// Messages are sent and received as is, so calls can be made without
+    /// the definitions of the service, for example by proxies.
+    pub fn generic_unary_call(&self, path: &str, req: &[u8], opt: CallOption) -> Result<Vec<u8>> {
+        let f = self.generic_unary_call_async(path, req, opt)?;
+        f.wait()
+    }
+
+    /// Create an asynchronized unary RPC call to the method at `path`, see
+    /// [`Client::generic_unary_call`].
+    pub fn generic_unary_call_async(
+        &self,
+        path: &str,
+        req: &[u8],
+        opt: CallOption,
+    ) -> Result<ClientUnaryReceiver<Vec<u8>>> {
+        Call::unary_async(
+            &self.channel,
+            path,
+            raw_codec::ser_slice,
+            raw_codec::de,
+            req,
+            self.call_option(path, opt),
+        )
+    }
+
+    /// Create an asynchronized client streaming call to the method at
+    /// `path`, see [`Client::generic_unary_call`].
+    #[allow(clippy::type_complexity)]
+    pub fn generic_client_streaming(
+        &self,
+        path: &str,
+        opt: CallOption,
+    ) -> Result<(ClientCStreamSender<Vec<u8>>, ClientCStreamReceiver<Vec<u8>>)> {
+        Call::client_streaming(
+            &self.channel,
+            path,
+            raw_codec::ser,
+            raw_codec::de,
+            self.call_option(path, opt),
+        )
+    }
+
+    /// Create an asynchronized server streaming call to the method at
+    /// `path`, see [`Client::generic_unary_call`].
+    pub fn generic_server_streaming(
+        &self,
+        path: &str,
+        req: &[u8],
+        opt: CallOption,
+    ) -> Result<ClientSStreamReceiver<Vec<u8>>> {
+        Call::server_streaming(
+            &self.channel,
+            path,
+            raw_codec::ser_slice,
+            raw_codec::de,
+            req,
+            self.call_option(path, opt),
+        )
+    }
+
+    /// Create an asynchronized duplex streaming call to the method at
+    /// `path`, see [`Client::generic_unary_call`].
+    #[allow(clippy::type_complexity)]
+    pub fn generic_duplex_streaming(
+        &self,
+        path: &str,
+        opt: CallOption,
+    ) -> Result<(ClientDuplexSender<Vec<u8>>, ClientDuplexReceiver<Vec<u8>>)> {
+        Call::duplex_streaming(
+            &self.channel,
+            path,
+            raw_codec::ser,
+            raw_codec::de,
+            self.call_option(path, opt),
+        )
     }
 
     /// Spawn the future into current gRPC poll thread.
@@ -107,4 +589,48 @@ impl Client {
         let kicker = self.kicker.clone();
         Executor::new(self.channel.cq()).spawn(f, kicker)
     }
+
+    /// Like [`spawn`](Client::spawn), but fails with `Error::Overloaded`
+    /// instead if there are too many unfinished futures spawned into current
+    /// gRPC poll thread, see [`EnvBuilder::spawn_limit`].
+    ///
+    /// [`EnvBuilder::spawn_limit`]: struct.EnvBuilder.html#method.spawn_limit
+    pub fn try_spawn<F>(&self, f: F) -> Result<()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let kicker = self.kicker.clone();
+        Executor::new(self.channel.cq()).try_spawn(f, kicker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::RpcStatus;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new(5)
+            .backoff(Duration::from_millis(10), Duration::from_millis(25))
+            .multiplier(2.0);
+        let backoff = policy.next_backoff(policy.initial_backoff);
+        assert_eq!(backoff, Duration::from_millis(20));
+        assert_eq!(policy.next_backoff(backoff), Duration::from_millis(25));
+
+        let failure = |code| Err::<(), _>(Error::RpcFailure(RpcStatus::new(code, None)));
+        assert!(RetryPolicy::is_retryable(&failure(
+            RpcStatusCode::UNAVAILABLE
+        )));
+        assert!(RetryPolicy::is_retryable(&failure(
+            RpcStatusCode::DEADLINE_EXCEEDED
+        )));
+        assert!(!RetryPolicy::is_retryable(&failure(
+            RpcStatusCode::INTERNAL
+        )));
+        assert!(!RetryPolicy::is_retryable(&Err::<(), _>(
+            Error::QueueShutdown
+        )));
+        assert!(!RetryPolicy::is_retryable(&Ok(())));
+    }
 }
@@ -35,6 +35,33 @@ pub struct Marshaller<T> {
     pub de: DeserializeFn<T>,
 }
 
+/// Codec of raw messages, which are passed through without being parsed.
+pub mod raw_codec {
+    use std::io::Read;
+
+    use super::MessageReader;
+    use crate::error::{Error, Result};
+
+    // The signature is required by `SerializeFn`.
+    #[allow(clippy::ptr_arg)]
+    #[inline]
+    pub fn ser(t: &Vec<u8>, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(t)
+    }
+
+    #[inline]
+    pub fn de(mut reader: MessageReader) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(reader.pending_bytes_count());
+        reader.read_to_end(&mut buf).map_err(Error::Io)?;
+        Ok(buf)
+    }
+
+    #[inline]
+    pub(crate) fn ser_slice(t: &[u8], buf: &mut Vec<u8>) {
+        buf.extend_from_slice(t)
+    }
+}
+
 #[cfg(feature = "protobuf-codec")]
 pub mod pb_codec {
     use protobuf::{CodedInputStream, Message};
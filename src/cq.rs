@@ -12,8 +12,8 @@
 // limitations under the License.
 
 use std::ptr;
-use std::sync::atomic::{AtomicIsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::thread::ThreadId;
 
 use crate::grpc_sys::{self, gpr_clock_type, grpc_completion_queue};
@@ -31,16 +31,25 @@ pub struct CompletionQueueHandle {
     // be shutdown; When `ref_cnt` > 0, completion queue can accept requests
     // and should not be shutdown.
     ref_cnt: AtomicIsize,
+    // The count of spawned futures that are not finished yet.
+    spawned: AtomicUsize,
+    spawn_limit: usize,
+    // The thread polling the queue, which changes when the polling thread is
+    // restarted after fork.
+    worker_id: RwLock<Option<ThreadId>>,
 }
 
 unsafe impl Sync for CompletionQueueHandle {}
 unsafe impl Send for CompletionQueueHandle {}
 
 impl CompletionQueueHandle {
-    pub fn new() -> CompletionQueueHandle {
+    pub fn new(spawn_limit: usize) -> CompletionQueueHandle {
         CompletionQueueHandle {
             cq: unsafe { grpc_sys::grpc_completion_queue_create_for_next(ptr::null_mut()) },
             ref_cnt: AtomicIsize::new(1),
+            spawned: AtomicUsize::new(0),
+            spawn_limit,
+            worker_id: RwLock::new(None),
         }
     }
 
@@ -83,6 +92,10 @@ impl CompletionQueueHandle {
         }
     }
 
+    fn is_shutdown(&self) -> bool {
+        self.ref_cnt.load(Ordering::SeqCst) <= 0
+    }
+
     fn shutdown(&self) {
         let shutdown = loop {
             let cnt = self.ref_cnt.load(Ordering::SeqCst);
@@ -116,6 +129,8 @@ impl Drop for CompletionQueueHandle {
     }
 }
 
+/// A reference that keeps the completion queue from being shutdown until
+/// it's dropped, so that the queue is safe to be used by it.
 pub struct CompletionQueueRef<'a> {
     queue: &'a CompletionQueue,
 }
@@ -132,15 +147,47 @@ impl<'a> Drop for CompletionQueueRef<'a> {
     }
 }
 
+/// Like [`CompletionQueueRef`], but holds the queue by itself, so that it can
+/// be stored by objects that use the queue during their whole lifetime.
+pub(crate) struct CompletionQueueGuard {
+    handle: Arc<CompletionQueueHandle>,
+}
+
+impl CompletionQueueGuard {
+    pub fn as_ptr(&self) -> *mut grpc_completion_queue {
+        self.handle.cq
+    }
+}
+
+impl Drop for CompletionQueueGuard {
+    fn drop(&mut self) {
+        self.handle.unref();
+    }
+}
+
+/// A slot of a spawned future, which is released when it's dropped.
+pub(crate) struct SpawnSlot {
+    handle: Arc<CompletionQueueHandle>,
+}
+
+impl Drop for SpawnSlot {
+    fn drop(&mut self) {
+        self.handle.spawned.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 #[derive(Clone)]
 pub struct CompletionQueue {
     handle: Arc<CompletionQueueHandle>,
-    id: ThreadId,
 }
 
 impl CompletionQueue {
+    /// Create a queue that is polled by the thread `id`, the clones of the
+    /// queue share the same worker id.
     pub fn new(handle: Arc<CompletionQueueHandle>, id: ThreadId) -> CompletionQueue {
-        CompletionQueue { handle, id }
+        let cq = CompletionQueue { handle };
+        cq.set_worker_id(id);
+        cq
     }
 
     /// Blocks until an event is available, the completion queue is being shut down.
@@ -151,20 +198,65 @@ impl CompletionQueue {
         }
     }
 
-    pub fn borrow(&self) -> Result<CompletionQueueRef<'_>> {
+    /// Get a reference to the queue, which should be held while submitting
+    /// operations to the queue.
+    ///
+    /// `Error::QueueShutdown` is returned once `shutdown` is called, the queue
+    /// is only shutdown actually after all the references are dropped.
+    pub fn try_ref(&self) -> Result<CompletionQueueRef<'_>> {
         self.handle.add_ref()?;
         Ok(CompletionQueueRef { queue: self })
     }
 
+    pub(crate) fn guard(&self) -> Result<CompletionQueueGuard> {
+        self.handle.add_ref()?;
+        Ok(CompletionQueueGuard {
+            handle: self.handle.clone(),
+        })
+    }
+
     /// Begin destruction of a completion queue.
     ///
     /// Once all possible events are drained then `next()` will start to produce
-    /// `Event::QueueShutdown` events only.
+    /// `Event::QueueShutdown` events only. It's safe to be called more than once.
     pub fn shutdown(&self) {
         self.handle.shutdown()
     }
 
+    /// Check if `shutdown` has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.handle.is_shutdown()
+    }
+
+    /// Get the count of futures spawned into the queue that are not finished
+    /// yet.
+    pub fn spawned(&self) -> usize {
+        self.handle.spawned.load(Ordering::SeqCst)
+    }
+
+    /// Take a slot for a spawned future, `Error::Overloaded` is returned if
+    /// `bounded` is true and the spawn limit is reached.
+    pub(crate) fn spawn_slot(&self, bounded: bool) -> Result<SpawnSlot> {
+        let spawned = self.handle.spawned.fetch_add(1, Ordering::SeqCst);
+        let slot = SpawnSlot {
+            handle: self.handle.clone(),
+        };
+        if bounded && spawned >= self.handle.spawn_limit {
+            return Err(Error::Overloaded);
+        }
+        Ok(slot)
+    }
+
     pub fn worker_id(&self) -> ThreadId {
-        self.id
+        self.handle.worker_id.read().unwrap().unwrap()
+    }
+
+    /// Change the thread polling the queue, for all the clones of the queue.
+    pub(crate) fn set_worker_id(&self, id: ThreadId) {
+        *self.handle.worker_id.write().unwrap() = Some(id);
+    }
+
+    pub(crate) fn handle(&self) -> &Arc<CompletionQueueHandle> {
+        &self.handle
     }
 }
@@ -11,12 +11,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::Path;
 use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+use crate::call::{RpcStatus, RpcStatusCode};
 use crate::error::{Error, Result};
-use crate::grpc_sys::{self, grpc_channel_credentials, grpc_server_credentials};
-use libc::c_char;
+use crate::grpc_sys::{
+    self, grpc_auth_metadata_context, grpc_call_credentials, grpc_channel_credentials,
+    grpc_credentials_plugin_metadata_cb, grpc_local_connect_type, grpc_metadata,
+    grpc_metadata_array, grpc_metadata_credentials_plugin, grpc_server_credentials,
+    grpc_ssl_certificate_config_reload_status, grpc_ssl_pem_key_cert_pair,
+    grpc_ssl_server_certificate_config, grpc_status_code, verify_peer_options,
+};
+use crate::metadata::Metadata;
+use libc::{c_char, c_int, c_void};
+
+pub use crate::grpc_sys::grpc_ssl_client_certificate_request_type as CertificateRequestType;
+
+/// Initialize the runtime. Because credentials can be built before construction
+/// of an `Environment`, it needs to be called for those that depend on the runtime.
+fn init_grpc() {
+    unsafe {
+        grpc_sys::grpc_init();
+    }
+}
 
 fn clear_key_securely(key: &mut [u8]) {
     unsafe {
@@ -26,12 +49,123 @@ fn clear_key_securely(key: &mut [u8]) {
     }
 }
 
+/// Convert the private key to a `CString` without leaving a copy of it in memory.
+fn key_to_cstring(mut private_key: Vec<u8>) -> CString {
+    if private_key.capacity() == private_key.len() {
+        let mut nil_key = Vec::with_capacity(private_key.len() + 1);
+        nil_key.extend_from_slice(&private_key);
+        clear_key_securely(&mut private_key);
+        private_key = nil_key;
+    }
+    CString::new(private_key).unwrap()
+}
+
+/// A set of certificates and keys that is used by a server.
+///
+/// It's returned by a [`CertificateProvider`] to update the certificates of
+/// [`ServerCredentials`] on the fly.
+pub struct CertificateConfig {
+    root: Option<CString>,
+    cert_key_pairs: Vec<(CString, CString)>,
+}
+
+impl CertificateConfig {
+    /// Initialize an empty [`CertificateConfig`].
+    pub fn new() -> CertificateConfig {
+        CertificateConfig {
+            root: None,
+            cert_key_pairs: vec![],
+        }
+    }
+
+    /// Set the PEM encoded client root certificate to verify client's identity.
+    pub fn root_cert<S: Into<Vec<u8>>>(mut self, cert: S) -> CertificateConfig {
+        self.root = Some(CString::new(cert).unwrap());
+        self
+    }
+
+    /// Add a PEM encoded server side certificate and key.
+    pub fn add_cert(mut self, cert: Vec<u8>, private_key: Vec<u8>) -> CertificateConfig {
+        self.cert_key_pairs
+            .push((CString::new(cert).unwrap(), key_to_cstring(private_key)));
+        self
+    }
+
+    fn to_raw(&self) -> *mut grpc_ssl_server_certificate_config {
+        let pairs: Vec<_> = self
+            .cert_key_pairs
+            .iter()
+            .map(|(cert, key)| grpc_ssl_pem_key_cert_pair {
+                private_key: key.as_ptr(),
+                cert_chain: cert.as_ptr(),
+            })
+            .collect();
+        let root = self.root.as_ref().map_or_else(ptr::null, |r| r.as_ptr());
+        // All the strings are copied.
+        unsafe {
+            grpc_sys::grpc_ssl_server_certificate_config_create(root, pairs.as_ptr(), pairs.len())
+        }
+    }
+}
+
+impl Default for CertificateConfig {
+    fn default() -> CertificateConfig {
+        CertificateConfig::new()
+    }
+}
+
+impl Drop for CertificateConfig {
+    fn drop(&mut self) {
+        for (_, key) in self.cert_key_pairs.drain(..) {
+            clear_key_securely(&mut key.into_bytes_with_nul());
+        }
+    }
+}
+
+/// A source of server certificates that may change over time.
+///
+/// It's consulted by gRPC Core before every new handshake, so rotated
+/// certificates can be picked up without restarting the server. Existing
+/// connections are not affected by the change.
+pub trait CertificateProvider: Send + Sync + 'static {
+    /// Fetch the latest certificates.
+    ///
+    /// Returns `Ok(None)` if the certificates are not changed since last fetch.
+    /// If an error is returned, the previous certificates are still used. The
+    /// first fetch happens when the server binds to the port and must return
+    /// a config, otherwise binding fails.
+    fn fetch(&self) -> Result<Option<CertificateConfig>>;
+}
+
+type BoxProvider = Box<dyn CertificateProvider>;
+
+extern "C" fn fetch_certificate_config(
+    user_data: *mut c_void,
+    config: *mut *mut grpc_ssl_server_certificate_config,
+) -> grpc_ssl_certificate_config_reload_status {
+    let provider = unsafe { &*(user_data as *const BoxProvider) };
+    match provider.fetch() {
+        Ok(None) => {
+            grpc_ssl_certificate_config_reload_status::GRPC_SSL_CERTIFICATE_CONFIG_RELOAD_UNCHANGED
+        }
+        Ok(Some(c)) => {
+            unsafe { *config = c.to_raw() };
+            grpc_ssl_certificate_config_reload_status::GRPC_SSL_CERTIFICATE_CONFIG_RELOAD_NEW
+        }
+        Err(e) => {
+            error!("failed to fetch certificates: {:?}", e);
+            grpc_ssl_certificate_config_reload_status::GRPC_SSL_CERTIFICATE_CONFIG_RELOAD_FAIL
+        }
+    }
+}
+
 /// [`ServerCredentials`] factory in order to configure the properties.
 pub struct ServerCredentialsBuilder {
     root: Option<CString>,
     cert_chains: Vec<*mut c_char>,
     private_keys: Vec<*mut c_char>,
-    force_client_auth: bool,
+    cert_request_type: CertificateRequestType,
+    provider: Option<BoxProvider>,
 }
 
 impl ServerCredentialsBuilder {
@@ -41,7 +175,8 @@ impl ServerCredentialsBuilder {
             root: None,
             cert_chains: vec![],
             private_keys: vec![],
-            force_client_auth: false,
+            cert_request_type: CertificateRequestType::GRPC_SSL_DONT_REQUEST_CLIENT_CERTIFICATE,
+            provider: None,
         }
     }
 
@@ -53,42 +188,84 @@ impl ServerCredentialsBuilder {
         force_client_auth: bool,
     ) -> ServerCredentialsBuilder {
         self.root = Some(CString::new(cert).unwrap());
-        self.force_client_auth = force_client_auth;
+        self.cert_request_type = if force_client_auth {
+            CertificateRequestType::GRPC_SSL_REQUEST_AND_REQUIRE_CLIENT_CERTIFICATE_AND_VERIFY
+        } else {
+            CertificateRequestType::GRPC_SSL_DONT_REQUEST_CLIENT_CERTIFICATE
+        };
+        self
+    }
+
+    /// Set how the client certificate is requested and verified.
+    ///
+    /// It overrides the mode set by `root_cert`. Modes that verify the
+    /// certificate require a root certificate to be set. The identity of a
+    /// client can be read from `RpcContext::auth_context` in handlers.
+    pub fn client_certificate_request_type(
+        mut self,
+        ty: CertificateRequestType,
+    ) -> ServerCredentialsBuilder {
+        self.cert_request_type = ty;
         self
     }
 
     /// Add a PEM encoded server side certificate and key.
-    pub fn add_cert(mut self, cert: Vec<u8>, mut private_key: Vec<u8>) -> ServerCredentialsBuilder {
-        if private_key.capacity() == private_key.len() {
-            let mut nil_key = Vec::with_capacity(private_key.len() + 1);
-            nil_key.extend_from_slice(&private_key);
-            clear_key_securely(&mut private_key);
-            private_key = nil_key;
-        }
+    pub fn add_cert(mut self, cert: Vec<u8>, private_key: Vec<u8>) -> ServerCredentialsBuilder {
         self.cert_chains
             .push(CString::new(cert).unwrap().into_raw());
         self.private_keys
-            .push(CString::new(private_key).unwrap().into_raw());
+            .push(key_to_cstring(private_key).into_raw());
+        self
+    }
+
+    /// Load the certificates from the provider instead, so they can be
+    /// reloaded without restarting the server.
+    ///
+    /// Certificates and keys set by `root_cert` and `add_cert` are ignored,
+    /// while the client certificate request type still applies.
+    pub fn with_provider<P: CertificateProvider>(
+        mut self,
+        provider: P,
+    ) -> ServerCredentialsBuilder {
+        self.provider = Some(Box::new(provider));
         self
     }
 
     /// Finalize the [`ServerCredentialsBuilder`] and build the [`ServerCredentials`].
     pub fn build(mut self) -> ServerCredentials {
+        if let Some(provider) = self.provider.take() {
+            // Core doesn't notify when user data is no longer used, so the
+            // provider is owned by the credentials.
+            let provider = Box::new(provider);
+            let credentials = unsafe {
+                let opts =
+                    grpc_sys::grpc_ssl_server_credentials_create_options_using_config_fetcher(
+                        self.cert_request_type,
+                        Some(fetch_certificate_config),
+                        &*provider as *const BoxProvider as *mut c_void,
+                    );
+                grpc_sys::grpc_ssl_server_credentials_create_with_options(opts)
+            };
+            return ServerCredentials {
+                creds: credentials,
+                _provider: Some(provider),
+            };
+        }
+
         let root_cert = self
             .root
             .take()
             .map_or_else(ptr::null_mut, CString::into_raw);
         let cert_chains = self.cert_chains.as_mut_ptr();
         let private_keys = self.private_keys.as_mut_ptr();
-        let force_auth = if self.force_client_auth { 1 } else { 0 };
 
         let credentials = unsafe {
-            grpc_sys::grpcwrap_ssl_server_credentials_create(
+            grpc_sys::grpcwrap_ssl_server_credentials_create_ex(
                 root_cert,
                 cert_chains as _,
                 private_keys as _,
                 self.cert_chains.len(),
-                force_auth,
+                self.cert_request_type,
             )
         };
 
@@ -98,7 +275,10 @@ impl ServerCredentialsBuilder {
             }
         }
 
-        ServerCredentials { creds: credentials }
+        ServerCredentials {
+            creds: credentials,
+            _provider: None,
+        }
     }
 }
 
@@ -121,12 +301,28 @@ impl Drop for ServerCredentialsBuilder {
 /// Use [`ServerCredentialsBuilder`] to build a [`ServerCredentials`].
 pub struct ServerCredentials {
     creds: *mut grpc_server_credentials,
+    _provider: Option<Box<BoxProvider>>,
 }
 
 impl ServerCredentials {
     pub fn as_mut_ptr(&mut self) -> *mut grpc_server_credentials {
         self.creds
     }
+
+    /// Build credentials of local transports, which only accept connections
+    /// from Unix domain sockets, see `ServerBuilder::bind_local_secure`.
+    ///
+    /// Connections are not encrypted, the auth context of calls contains the
+    /// `security_level` of the transport.
+    pub fn local() -> ServerCredentials {
+        init_grpc();
+        let creds =
+            unsafe { grpc_sys::grpc_local_server_credentials_create(grpc_local_connect_type::UDS) };
+        ServerCredentials {
+            creds,
+            _provider: None,
+        }
+    }
 }
 
 impl Drop for ServerCredentials {
@@ -135,10 +331,38 @@ impl Drop for ServerCredentials {
     }
 }
 
+type BoxVerifier = Box<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+extern "C" fn verify_peer(
+    target_name: *const c_char,
+    peer_pem: *const c_char,
+    userdata: *mut c_void,
+) -> c_int {
+    let verifier = unsafe { &*(userdata as *const BoxVerifier) };
+    if target_name.is_null() || peer_pem.is_null() {
+        return 1;
+    }
+    let (target_name, peer_pem) = unsafe {
+        (
+            CStr::from_ptr(target_name).to_str(),
+            CStr::from_ptr(peer_pem).to_str(),
+        )
+    };
+    match (target_name, peer_pem) {
+        (Ok(name), Ok(pem)) if verifier(name, pem) => 0,
+        _ => 1,
+    }
+}
+
+extern "C" fn destroy_verifier(userdata: *mut c_void) {
+    unsafe { drop(Box::from_raw(userdata as *mut BoxVerifier)) }
+}
+
 /// [`ChannelCredentials`] factory in order to configure the properties.
 pub struct ChannelCredentialsBuilder {
     root: Option<CString>,
     cert_key_pair: Option<(CString, CString)>,
+    verifier: Option<BoxVerifier>,
 }
 
 impl ChannelCredentialsBuilder {
@@ -147,6 +371,7 @@ impl ChannelCredentialsBuilder {
         ChannelCredentialsBuilder {
             root: None,
             cert_key_pair: None,
+            verifier: None,
         }
     }
 
@@ -171,8 +396,31 @@ impl ChannelCredentialsBuilder {
         self
     }
 
+    /// Set a callback to do additional verification of the server certificate.
+    ///
+    /// The callback is invoked with the target name and the PEM encoded
+    /// certificate of the server after the certificate chain and host name
+    /// are verified, and the handshake fails if it returns `false`. It can
+    /// be used to check identities that are not covered by host name
+    /// verification, like SPIFFE IDs in URI SANs. Note that host name
+    /// verification can't be skipped, use `ChannelBuilder::override_ssl_target`
+    /// to match a DNS name in the certificate instead.
+    ///
+    /// The callback is blocking the handshake, so it should be light-weight.
+    pub fn verify_peer<F>(mut self, f: F) -> ChannelCredentialsBuilder
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.verifier = Some(Box::new(f));
+        self
+    }
+
     /// Finalize the [`ChannelCredentialsBuilder`] and build the [`ChannelCredentials`].
     pub fn build(mut self) -> ChannelCredentials {
+        if let Some(verifier) = self.verifier.take() {
+            return self.build_with_verifier(verifier);
+        }
+
         let root_ptr = self
             .root
             .take()
@@ -201,6 +449,31 @@ impl ChannelCredentialsBuilder {
 
         ChannelCredentials { creds }
     }
+
+    fn build_with_verifier(self, verifier: BoxVerifier) -> ChannelCredentials {
+        let root_ptr = self.root.as_ref().map_or_else(ptr::null, |r| r.as_ptr());
+        let mut pair = self
+            .cert_key_pair
+            .as_ref()
+            .map(|(cert, key)| grpc_ssl_pem_key_cert_pair {
+                private_key: key.as_ptr(),
+                cert_chain: cert.as_ptr(),
+            });
+        let pair_ptr = pair
+            .as_mut()
+            .map_or_else(ptr::null_mut, |p| p as *mut grpc_ssl_pem_key_cert_pair);
+        // The verifier is released by `destroy_verifier` when the
+        // credentials are destroyed.
+        let opts = verify_peer_options {
+            verify_peer_callback: Some(verify_peer),
+            verify_peer_callback_userdata: Box::into_raw(Box::new(verifier)) as *mut c_void,
+            verify_peer_destruct: Some(destroy_verifier),
+        };
+        let creds = unsafe {
+            grpc_sys::grpc_ssl_credentials_create(root_ptr, pair_ptr, &opts, ptr::null_mut())
+        };
+        ChannelCredentials { creds }
+    }
 }
 
 impl Drop for ChannelCredentialsBuilder {
@@ -213,7 +486,7 @@ impl Drop for ChannelCredentialsBuilder {
 
 /// Client-side SSL credentials.
 ///
-/// Use [`ChannelCredentialsBuilder`] or [`ChannelCredentials::google_default_credentials`] to
+/// Use [`ChannelCredentialsBuilder`] or [`ChannelCredentials::google_default`] to
 /// build a [`ChannelCredentials`].
 pub struct ChannelCredentials {
     creds: *mut grpc_channel_credentials,
@@ -225,13 +498,12 @@ impl ChannelCredentials {
     }
 
     /// Try to build a [`ChannelCredentials`] to authenticate with Google OAuth credentials.
-    pub fn google_default_credentials() -> Result<ChannelCredentials> {
-        // Initialize the runtime here. Because this is an associated method
-        // that can be called before construction of an `Environment`, we
-        // need to call this here too.
-        unsafe {
-            grpc_sys::grpc_init();
-        }
+    ///
+    /// Credentials are looked up in the same order as other gRPC implementations:
+    /// the JSON key file pointed by `GOOGLE_APPLICATION_CREDENTIALS`, the well-known
+    /// file created by gcloud, and finally the GCE metadata server.
+    pub fn google_default() -> Result<ChannelCredentials> {
+        init_grpc();
         let creds = unsafe { grpc_sys::grpc_google_default_credentials_create() };
         if creds.is_null() {
             Err(Error::GoogleAuthenticationFailed)
@@ -239,6 +511,39 @@ impl ChannelCredentials {
             Ok(ChannelCredentials { creds })
         }
     }
+
+    /// Try to build a [`ChannelCredentials`] to authenticate with Google OAuth credentials.
+    #[deprecated(since = "0.5.0", note = "use `google_default` instead")]
+    pub fn google_default_credentials() -> Result<ChannelCredentials> {
+        ChannelCredentials::google_default()
+    }
+
+    /// Build credentials of local transports, which only connect to Unix
+    /// domain sockets, see `ChannelBuilder::secure_connect_local`.
+    ///
+    /// Call credentials composed with them are sent as the connection is
+    /// considered secure.
+    pub fn local() -> ChannelCredentials {
+        init_grpc();
+        let creds =
+            unsafe { grpc_sys::grpc_local_credentials_create(grpc_local_connect_type::UDS) };
+        ChannelCredentials { creds }
+    }
+
+    /// Attach the call credentials to every call made on channels that are
+    /// built with the credentials.
+    pub fn compose(self, creds: &CallCredentials) -> ChannelCredentials {
+        let composed = unsafe {
+            grpc_sys::grpc_composite_channel_credentials_create(
+                self.creds,
+                creds.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+        assert!(!composed.is_null(), "failed to compose credentials");
+        // `self` is released here, the composite credentials hold their own references.
+        ChannelCredentials { creds: composed }
+    }
 }
 
 impl Drop for ChannelCredentials {
@@ -246,3 +551,345 @@ impl Drop for ChannelCredentials {
         unsafe { grpc_sys::grpc_channel_credentials_release(self.creds) }
     }
 }
+
+struct RawCallCredentials(*mut grpc_call_credentials);
+
+unsafe impl Send for RawCallCredentials {}
+unsafe impl Sync for RawCallCredentials {}
+
+impl Drop for RawCallCredentials {
+    fn drop(&mut self) {
+        unsafe { grpc_sys::grpc_call_credentials_release(self.0) }
+    }
+}
+
+/// Credentials that are attached to calls instead of channels.
+///
+/// Unlike [`ChannelCredentials`], call credentials are usually used to carry
+/// per-user tokens like OAuth2 access tokens or JWTs. They can be set on a
+/// call by `CallOption::credentials`. Note that call credentials only take
+/// effect on secure channels.
+#[derive(Clone)]
+pub struct CallCredentials {
+    creds: Arc<RawCallCredentials>,
+}
+
+impl CallCredentials {
+    fn new(creds: *mut grpc_call_credentials) -> CallCredentials {
+        assert!(!creds.is_null(), "failed to create call credentials");
+        CallCredentials {
+            creds: Arc::new(RawCallCredentials(creds)),
+        }
+    }
+
+    /// Build a [`CallCredentials`] that sends the OAuth2 access token in the
+    /// `authorization` header.
+    pub fn access_token(token: &str) -> CallCredentials {
+        let token = CString::new(token).unwrap();
+        let creds = unsafe {
+            grpc_sys::grpc_access_token_credentials_create(token.as_ptr(), ptr::null_mut())
+        };
+        CallCredentials::new(creds)
+    }
+
+    fn try_new(creds: *mut grpc_call_credentials) -> Result<CallCredentials> {
+        if creds.is_null() {
+            Err(Error::GoogleAuthenticationFailed)
+        } else {
+            Ok(CallCredentials::new(creds))
+        }
+    }
+
+    /// Build a [`CallCredentials`] that signs JWTs with a service account JSON key
+    /// and sends them in the `authorization` header.
+    ///
+    /// `token_lifetime` is capped to one hour by gRPC Core.
+    pub fn service_account_jwt_access(
+        json_key: &str,
+        token_lifetime: Duration,
+    ) -> Result<CallCredentials> {
+        init_grpc();
+        let json_key = CString::new(json_key).map_err(|_| Error::GoogleAuthenticationFailed)?;
+        let creds = unsafe {
+            grpc_sys::grpc_service_account_jwt_access_credentials_create(
+                json_key.as_ptr(),
+                token_lifetime.into(),
+                ptr::null_mut(),
+            )
+        };
+        CallCredentials::try_new(creds)
+    }
+
+    /// Same as `service_account_jwt_access`, but reads the JSON key from a file.
+    pub fn service_account_jwt_access_from_file<P: AsRef<Path>>(
+        path: P,
+        token_lifetime: Duration,
+    ) -> Result<CallCredentials> {
+        let json_key = fs::read_to_string(path).map_err(Error::Io)?;
+        CallCredentials::service_account_jwt_access(&json_key, token_lifetime)
+    }
+
+    /// Build a [`CallCredentials`] that exchanges a Google refresh token, in the
+    /// JSON format generated by gcloud, for access tokens.
+    pub fn google_refresh_token(json_refresh_token: &str) -> Result<CallCredentials> {
+        init_grpc();
+        let token =
+            CString::new(json_refresh_token).map_err(|_| Error::GoogleAuthenticationFailed)?;
+        let creds = unsafe {
+            grpc_sys::grpc_google_refresh_token_credentials_create(token.as_ptr(), ptr::null_mut())
+        };
+        CallCredentials::try_new(creds)
+    }
+
+    /// Build a [`CallCredentials`] that fetches access tokens from the GCE
+    /// metadata server.
+    ///
+    /// It should only be used when running on Google Compute Engine and talking
+    /// to Google services.
+    pub fn google_compute_engine() -> Result<CallCredentials> {
+        init_grpc();
+        let creds =
+            unsafe { grpc_sys::grpc_google_compute_engine_credentials_create(ptr::null_mut()) };
+        CallCredentials::try_new(creds)
+    }
+
+    /// Build a [`CallCredentials`] that fetches metadata from the plugin for
+    /// every call.
+    pub fn from_plugin<P: MetadataCredentialsPlugin>(plugin: P) -> CallCredentials {
+        let plugin = grpc_metadata_credentials_plugin {
+            get_metadata: Some(plugin_get_metadata::<P>),
+            destroy: Some(plugin_destroy::<P>),
+            state: Box::into_raw(Box::new(plugin)) as *mut c_void,
+            type_: b"grpcio.plugin\0".as_ptr() as *const c_char,
+        };
+        let creds = unsafe {
+            grpc_sys::grpc_metadata_credentials_create_from_plugin(plugin, ptr::null_mut())
+        };
+        CallCredentials::new(creds)
+    }
+
+    /// Combine two call credentials, metadata from both of them will be
+    /// attached to the call.
+    pub fn compose(&self, other: &CallCredentials) -> CallCredentials {
+        let creds = unsafe {
+            grpc_sys::grpc_composite_call_credentials_create(
+                self.as_mut_ptr(),
+                other.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+        CallCredentials::new(creds)
+    }
+
+    pub(crate) fn as_mut_ptr(&self) -> *mut grpc_call_credentials {
+        (self.creds).0
+    }
+}
+
+/// The information that can be used by a [`MetadataCredentialsPlugin`] to
+/// create auth metadata.
+#[derive(Debug, Clone)]
+pub struct AuthMetadataContext {
+    service_url: String,
+    method_name: String,
+}
+
+impl AuthMetadataContext {
+    unsafe fn from_raw(ctx: &grpc_auth_metadata_context) -> AuthMetadataContext {
+        let to_string = |s: *const c_char| {
+            if s.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(s).to_string_lossy().into_owned()
+            }
+        };
+        AuthMetadataContext {
+            service_url: to_string(ctx.service_url),
+            method_name: to_string(ctx.method_name),
+        }
+    }
+
+    /// The URL of the service that is being called, e.g.
+    /// `https://example.com/helloworld.Greeter`.
+    pub fn service_url(&self) -> &str {
+        &self.service_url
+    }
+
+    /// The name of the method that is being called, e.g. `SayHello`.
+    pub fn method_name(&self) -> &str {
+        &self.method_name
+    }
+}
+
+/// A plugin that mints auth metadata for calls.
+///
+/// It's used to build [`CallCredentials`] by [`CallCredentials::from_plugin`].
+pub trait MetadataCredentialsPlugin: Send + Sync + 'static {
+    /// Fetch the auth metadata for a call.
+    ///
+    /// The method is called in gRPC threads, so it must not block. Tokens
+    /// can be minted asynchronously and `callback` can be invoked later from
+    /// any thread. The call will not be sent until `callback` is invoked; if
+    /// it's dropped without being invoked, the call fails with `INTERNAL`.
+    fn get_metadata(&self, ctx: AuthMetadataContext, callback: AuthMetadataCallback);
+}
+
+type AuthMetadataResult = std::result::Result<Metadata, RpcStatus>;
+
+struct PendingMetadata {
+    cb: grpc_credentials_plugin_metadata_cb,
+    user_data: *mut c_void,
+    /// Whether `get_metadata` of the plugin is still running.
+    in_plugin: bool,
+    result: Option<AuthMetadataResult>,
+}
+
+unsafe impl Send for PendingMetadata {}
+
+unsafe fn notify(
+    cb: grpc_credentials_plugin_metadata_cb,
+    user_data: *mut c_void,
+    res: AuthMetadataResult,
+) {
+    let cb = cb.unwrap();
+    match res {
+        Ok(md) => {
+            let arr = &*(&md as *const Metadata as *const grpc_metadata_array);
+            cb(
+                user_data,
+                arr.metadata,
+                arr.count,
+                grpc_status_code::GRPC_STATUS_OK,
+                ptr::null(),
+            );
+        }
+        Err(status) => {
+            let details = status
+                .details
+                .and_then(|d| CString::new(d).ok())
+                .unwrap_or_default();
+            cb(
+                user_data,
+                ptr::null(),
+                0,
+                status.status.into(),
+                details.as_ptr(),
+            );
+        }
+    }
+}
+
+/// The callback that should be invoked once the auth metadata of a call is
+/// ready.
+pub struct AuthMetadataCallback {
+    pending: Option<Arc<Mutex<PendingMetadata>>>,
+}
+
+impl AuthMetadataCallback {
+    /// Attach the metadata to the call.
+    pub fn success(mut self, metadata: Metadata) {
+        self.done(Ok(metadata));
+    }
+
+    /// Fail the call with the status.
+    pub fn fail(mut self, status: RpcStatus) {
+        self.done(Err(status));
+    }
+
+    fn done(&mut self, res: AuthMetadataResult) {
+        let pending = self.pending.take().unwrap();
+        let (cb, user_data) = {
+            let mut p = pending.lock().unwrap();
+            if p.in_plugin {
+                // The callback must not be invoked in the thread in which
+                // `get_metadata` is called, return the result synchronously instead.
+                p.result = Some(res);
+                return;
+            }
+            (p.cb, p.user_data)
+        };
+        unsafe { notify(cb, user_data, res) }
+    }
+}
+
+impl Drop for AuthMetadataCallback {
+    fn drop(&mut self) {
+        if self.pending.is_some() {
+            self.done(Err(RpcStatus::new(
+                RpcStatusCode::INTERNAL,
+                Some("credentials plugin dropped the callback".to_owned()),
+            )));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "C" fn plugin_get_metadata<P: MetadataCredentialsPlugin>(
+    state: *mut c_void,
+    context: grpc_auth_metadata_context,
+    cb: grpc_credentials_plugin_metadata_cb,
+    user_data: *mut c_void,
+    creds_md: *mut grpc_metadata,
+    num_creds_md: *mut usize,
+    status: *mut grpc_status_code::Type,
+    error_details: *mut *const c_char,
+) -> c_int {
+    let plugin = &*(state as *const P);
+    let pending = Arc::new(Mutex::new(PendingMetadata {
+        cb,
+        user_data,
+        in_plugin: true,
+        result: None,
+    }));
+    let callback = AuthMetadataCallback {
+        pending: Some(pending.clone()),
+    };
+    plugin.get_metadata(AuthMetadataContext::from_raw(&context), callback);
+
+    let mut p = pending.lock().unwrap();
+    p.in_plugin = false;
+    match p.result.take() {
+        None => 0,
+        Some(Ok(md)) => {
+            if md.len() > grpc_sys::GRPC_METADATA_CREDENTIALS_PLUGIN_SYNC_MAX as usize {
+                // Too many entries to be returned synchronously.
+                let user_data = user_data as usize;
+                thread::spawn(move || unsafe { notify(cb, user_data as *mut c_void, Ok(md)) });
+                return 0;
+            }
+            // The caller takes ownership of the entries.
+            let arr = &*(&md as *const Metadata as *const grpc_metadata_array);
+            for i in 0..arr.count {
+                let src = &*arr.metadata.add(i);
+                // The entries are uninitialized, so they must not be dropped.
+                let dst = &mut *creds_md.add(i);
+                ptr::write(&mut dst.key, src.key.clone());
+                ptr::write(&mut dst.value, src.value.clone());
+            }
+            *num_creds_md = arr.count;
+            *status = grpc_status_code::GRPC_STATUS_OK;
+            *error_details = ptr::null();
+            1
+        }
+        Some(Err(s)) => {
+            *num_creds_md = 0;
+            *status = s.status.into();
+            *error_details = match s.details {
+                Some(d) => {
+                    // The caller takes ownership of the details and frees it
+                    // with `gpr_free`.
+                    let buf = grpc_sys::gpr_malloc(d.len() + 1) as *mut u8;
+                    ptr::copy_nonoverlapping(d.as_ptr(), buf, d.len());
+                    *buf.add(d.len()) = 0;
+                    buf as *const c_char
+                }
+                None => ptr::null(),
+            };
+            1
+        }
+    }
+}
+
+unsafe extern "C" fn plugin_destroy<P: MetadataCredentialsPlugin>(state: *mut c_void) {
+    drop(Box::from_raw(state as *mut P));
+}
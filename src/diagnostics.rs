@@ -0,0 +1,374 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per method histograms of calls handled by a server, and logs of the calls
+//! that are too slow or carry too large messages.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::call::server::RpcContext;
+use crate::stats::{CallEnd, CallInfo, StatsHandler};
+
+/// Upper bounds in microseconds of the buckets of latency histograms.
+const LATENCY_BOUNDS: &[u64] = &[
+    1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000,
+    5_000_000, 10_000_000,
+];
+
+/// Upper bounds in bytes of the buckets of message size histograms.
+const SIZE_BOUNDS: &[u64] = &[
+    64,
+    256,
+    1 << 10,
+    4 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+];
+
+/// A histogram with fixed buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    counts: Vec<u64>,
+    sum: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0,
+        }
+    }
+
+    fn observe(&mut self, value: u64) {
+        let i = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[i] += 1;
+        self.sum += value;
+    }
+
+    /// The count of observed values.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The sum of observed values.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Get the inclusive upper bounds of the buckets with the counts of
+    /// values in them, the bound of the last bucket is `None`.
+    pub fn buckets(&self) -> Vec<(Option<u64>, u64)> {
+        self.bounds
+            .iter()
+            .map(|b| Some(*b))
+            .chain(Some(None))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+}
+
+/// Statistics of the calls to a method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodDiagnostics {
+    /// The count of finished calls.
+    pub calls: u64,
+    /// The count of calls that take longer than the slow call threshold.
+    pub slow_calls: u64,
+    /// Latencies in microseconds.
+    pub latency: Histogram,
+    /// Sizes of received messages in bytes.
+    pub request_size: Histogram,
+    /// Sizes of sent messages in bytes.
+    pub response_size: Histogram,
+}
+
+impl MethodDiagnostics {
+    fn new() -> MethodDiagnostics {
+        MethodDiagnostics {
+            calls: 0,
+            slow_calls: 0,
+            latency: Histogram::new(LATENCY_BOUNDS),
+            request_size: Histogram::new(SIZE_BOUNDS),
+            response_size: Histogram::new(SIZE_BOUNDS),
+        }
+    }
+}
+
+/// Configuration of call diagnostics, see
+/// `ServerBuilder::enable_call_diagnostics`.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsConfig {
+    slow_call_threshold: Option<Duration>,
+    large_message_threshold: Option<usize>,
+    log_headers: bool,
+}
+
+impl DiagnosticsConfig {
+    /// Create a configuration that only records histograms.
+    pub fn new() -> DiagnosticsConfig {
+        DiagnosticsConfig::default()
+    }
+
+    /// Log the calls that take longer than `threshold` with their peers.
+    pub fn slow_call_threshold(mut self, threshold: Duration) -> DiagnosticsConfig {
+        self.slow_call_threshold = Some(threshold);
+        self
+    }
+
+    /// Log the messages that are larger than `bytes` with the peers of their
+    /// calls.
+    pub fn large_message_threshold(mut self, bytes: usize) -> DiagnosticsConfig {
+        self.large_message_threshold = Some(bytes);
+        self
+    }
+
+    /// Whether the request headers are included in the logs. Values of
+    /// binary headers are never logged.
+    pub fn log_headers(mut self, enable: bool) -> DiagnosticsConfig {
+        self.log_headers = enable;
+        self
+    }
+}
+
+/// Diagnostics of the calls handled by a server, which can be got by
+/// `Server::call_diagnostics`.
+///
+/// Calls to unimplemented methods are not recorded.
+pub struct CallDiagnostics {
+    config: DiagnosticsConfig,
+    methods: Mutex<BTreeMap<String, MethodDiagnostics>>,
+}
+
+impl CallDiagnostics {
+    pub(crate) fn new(config: DiagnosticsConfig) -> CallDiagnostics {
+        CallDiagnostics {
+            config,
+            methods: Mutex::default(),
+        }
+    }
+
+    /// Get the statistics of a method, like `/helloworld.Greeter/SayHello`,
+    /// `None` is returned if it's never called.
+    pub fn method(&self, method: &str) -> Option<MethodDiagnostics> {
+        self.methods.lock().unwrap().get(method).cloned()
+    }
+
+    /// Get the statistics of all the called methods.
+    pub fn methods(&self) -> BTreeMap<String, MethodDiagnostics> {
+        self.methods.lock().unwrap().clone()
+    }
+
+    fn update<F: FnOnce(&mut MethodDiagnostics)>(&self, method: &str, f: F) {
+        let mut methods = self.methods.lock().unwrap();
+        if let Some(m) = methods.get_mut(method) {
+            return f(m);
+        }
+        f(methods
+            .entry(method.to_owned())
+            .or_insert_with(MethodDiagnostics::new))
+    }
+
+    /// Create a recorder for a call, events are also forwarded to `handler`.
+    pub(crate) fn recorder(
+        self: &Arc<CallDiagnostics>,
+        ctx: &RpcContext<'_>,
+        handler: Option<Arc<dyn StatsHandler>>,
+    ) -> Arc<dyn StatsHandler> {
+        let mut headers = String::new();
+        if self.config.log_headers {
+            for (i, (key, value)) in ctx.request_headers().iter().enumerate() {
+                if i > 0 {
+                    headers.push_str(", ");
+                }
+                if key.ends_with("-bin") {
+                    write!(headers, "{}: [{} bytes]", key, value.len()).unwrap();
+                } else {
+                    write!(headers, "{}: {}", key, String::from_utf8_lossy(value)).unwrap();
+                }
+            }
+        }
+        Arc::new(CallRecorder {
+            diagnostics: self.clone(),
+            peer: ctx.peer(),
+            headers,
+            handler,
+        })
+    }
+}
+
+/// Records the events of a call into [`CallDiagnostics`].
+struct CallRecorder {
+    diagnostics: Arc<CallDiagnostics>,
+    peer: String,
+    headers: String,
+    handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl CallRecorder {
+    fn check_size(&self, call: &CallInfo, what: &str, bytes: usize) {
+        match self.diagnostics.config.large_message_threshold {
+            Some(threshold) if bytes > threshold => warn!(
+                "large {} of {}: {} bytes, peer: {}, headers: [{}]",
+                what,
+                call.method(),
+                bytes,
+                self.peer,
+                self.headers
+            ),
+            _ => {}
+        }
+    }
+}
+
+impl StatsHandler for CallRecorder {
+    fn call_start(&self, call: &CallInfo) {
+        if let Some(ref h) = self.handler {
+            h.call_start(call);
+        }
+    }
+
+    fn message_sent(&self, call: &CallInfo, bytes: usize) {
+        self.check_size(call, "response", bytes);
+        self.diagnostics
+            .update(call.method(), |m| m.response_size.observe(bytes as u64));
+        if let Some(ref h) = self.handler {
+            h.message_sent(call, bytes);
+        }
+    }
+
+    fn message_received(&self, call: &CallInfo, bytes: usize) {
+        self.check_size(call, "request", bytes);
+        self.diagnostics
+            .update(call.method(), |m| m.request_size.observe(bytes as u64));
+        if let Some(ref h) = self.handler {
+            h.message_received(call, bytes);
+        }
+    }
+
+    fn call_end(&self, call: &CallInfo, end: &CallEnd) {
+        let slow = match self.diagnostics.config.slow_call_threshold {
+            Some(threshold) => end.elapsed > threshold,
+            None => false,
+        };
+        if slow {
+            warn!(
+                "slow call {} took {:?}, status: {:?}, sent: {} messages of {} bytes, \
+                 received: {} messages of {} bytes, peer: {}, headers: [{}]",
+                call.method(),
+                end.elapsed,
+                end.status,
+                end.sent_messages,
+                end.sent_bytes,
+                end.received_messages,
+                end.received_bytes,
+                self.peer,
+                self.headers
+            );
+        }
+        let micros = end.elapsed.as_secs() * 1_000_000 + u64::from(end.elapsed.subsec_micros());
+        self.diagnostics.update(call.method(), |m| {
+            m.calls += 1;
+            m.latency.observe(micros);
+            if slow {
+                m.slow_calls += 1;
+            }
+        });
+        if let Some(ref h) = self.handler {
+            h.call_end(call, end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::RpcStatusCode;
+    use crate::stats::{CallSide, CallStats, NoopStatsHandler};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::new(&[10, 100]);
+        for v in &[1, 10, 11, 1000] {
+            h.observe(*v);
+        }
+        assert_eq!(h.count(), 4);
+        assert_eq!(h.sum(), 1022);
+        assert_eq!(h.buckets(), vec![(Some(10), 2), (Some(100), 1), (None, 1)]);
+    }
+
+    #[test]
+    fn test_call_recorder() {
+        #[derive(Default)]
+        struct Counter(AtomicUsize);
+
+        impl StatsHandler for Counter {
+            fn call_end(&self, _: &CallInfo, _: &CallEnd) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let config = DiagnosticsConfig::new().slow_call_threshold(Duration::from_secs(0));
+        let diagnostics = Arc::new(CallDiagnostics::new(config));
+        let counter = Arc::new(Counter::default());
+        for handler in vec![Some(counter.clone() as Arc<dyn StatsHandler>), None] {
+            let recorder = Arc::new(CallRecorder {
+                diagnostics: diagnostics.clone(),
+                peer: "ipv4:127.0.0.1:1".to_owned(),
+                headers: String::new(),
+                handler,
+            });
+            let stats = CallStats::new(recorder, b"/a/b", CallSide::Server);
+            stats.received(100);
+            stats.sent(10);
+            stats.sent(1000);
+            stats.finish(RpcStatusCode::OK);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        assert_eq!(diagnostics.method("/a/c"), None);
+        let m = diagnostics.method("/a/b").unwrap();
+        assert_eq!(m.calls, 2);
+        assert_eq!(m.slow_calls, 2);
+        assert_eq!(m.latency.count(), 2);
+        assert_eq!(m.request_size.count(), 2);
+        assert_eq!(m.request_size.sum(), 200);
+        assert_eq!(m.response_size.count(), 4);
+        assert_eq!(m.response_size.sum(), 2020);
+        assert_eq!(diagnostics.methods().len(), 1);
+
+        let diagnostics = Arc::new(CallDiagnostics::new(DiagnosticsConfig::new()));
+        let recorder = Arc::new(CallRecorder {
+            diagnostics: diagnostics.clone(),
+            peer: String::new(),
+            headers: String::new(),
+            handler: Some(Arc::new(NoopStatsHandler)),
+        });
+        CallStats::new(recorder, b"/a/b", CallSide::Server).finish(RpcStatusCode::OK);
+        assert_eq!(diagnostics.method("/a/b").unwrap().slow_calls, 0);
+    }
+}
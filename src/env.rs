@@ -11,17 +11,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, Builder as ThreadBuilder, JoinHandle};
 
-use crate::grpc_sys;
+use crate::grpc_sys::{self, gpr_timespec, grpc_status_code};
 
+use crate::call::Call;
 use crate::cq::{CompletionQueue, CompletionQueueHandle, EventType};
-use crate::task::CallTag;
+use crate::error::{Error, Result};
+use crate::low_level;
+use crate::task::{self, CallTag, Kicker};
 
 // event loop
-fn poll_queue(cq: Arc<CompletionQueueHandle>) {
+fn poll_queue(cq: Arc<CompletionQueueHandle>, paused: Arc<AtomicBool>) {
     let id = thread::current().id();
     let cq = CompletionQueue::new(cq, id);
     loop {
@@ -33,9 +37,53 @@ fn poll_queue(cq: Arc<CompletionQueueHandle>) {
             EventType::GRPC_OP_COMPLETE => {}
         }
 
-        let tag: Box<CallTag> = unsafe { Box::from_raw(e.tag as _) };
+        unsafe { low_level::resolve_event(&cq, e) };
+        // Left events are polled by the thread spawned after fork.
+        if paused.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+}
 
-        tag.resolve(&cq, e.success != 0);
+fn spawn_poller(
+    name_prefix: &Option<String>,
+    i: usize,
+    cq: Arc<CompletionQueueHandle>,
+    paused: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    let mut builder = ThreadBuilder::new();
+    if let Some(ref prefix) = name_prefix {
+        builder = builder.name(format!("{}-{}", prefix, i));
+    }
+    builder.spawn(move || poll_queue(cq, paused)).unwrap()
+}
+
+/// Wake up the thread polling the queue by an empty batch of a call on a
+/// lame channel, which completes immediately.
+fn kick(cq: &CompletionQueue) -> Result<()> {
+    let (_, tag) = CallTag::watch_state_pair();
+    unsafe {
+        let ch = grpc_sys::grpc_lame_client_channel_create(
+            b"fork.kicker\0".as_ptr() as _,
+            grpc_status_code::GRPC_STATUS_UNAVAILABLE,
+            b"kicker\0".as_ptr() as _,
+        );
+        let res = cq.try_ref().and_then(|cq_ref| {
+            let call = grpc_sys::grpcwrap_channel_create_call(
+                ch,
+                ptr::null_mut(),
+                0,
+                cq_ref.as_ptr(),
+                ptr::null(),
+                0,
+                ptr::null(),
+                0,
+                gpr_timespec::inf_future(),
+            );
+            Kicker::from_call(Call::from_raw(call, cq.clone())).kick(Box::new(tag))
+        });
+        grpc_sys::grpc_channel_destroy(ch);
+        res
     }
 }
 
@@ -43,6 +91,7 @@ fn poll_queue(cq: Arc<CompletionQueueHandle>) {
 pub struct EnvBuilder {
     cq_count: usize,
     name_prefix: Option<String>,
+    spawn_limit: usize,
 }
 
 impl EnvBuilder {
@@ -51,6 +100,7 @@ impl EnvBuilder {
         EnvBuilder {
             cq_count: unsafe { grpc_sys::gpr_cpu_num_cores() as usize },
             name_prefix: None,
+            spawn_limit: usize::MAX,
         }
     }
 
@@ -72,6 +122,19 @@ impl EnvBuilder {
         self
     }
 
+    /// Set the max count of unfinished futures spawned into each completion
+    /// queue, beyond which `try_spawn` of [`Client`] and [`RpcContext`] fails
+    /// with `Error::Overloaded`. Unlimited by default.
+    ///
+    /// Futures spawned by `spawn` are counted but never rejected.
+    ///
+    /// [`Client`]: struct.Client.html
+    /// [`RpcContext`]: struct.RpcContext.html
+    pub fn spawn_limit(mut self, limit: usize) -> EnvBuilder {
+        self.spawn_limit = limit;
+        self
+    }
+
     /// Finalize the [`EnvBuilder`], build the [`Environment`] and initialize the gRPC library.
     pub fn build(self) -> Environment {
         unsafe {
@@ -79,14 +142,10 @@ impl EnvBuilder {
         }
         let mut cqs = Vec::with_capacity(self.cq_count);
         let mut handles = Vec::with_capacity(self.cq_count);
+        let paused = Arc::new(AtomicBool::new(false));
         for i in 0..self.cq_count {
-            let cq = Arc::new(CompletionQueueHandle::new());
-            let cq_ = cq.clone();
-            let mut builder = ThreadBuilder::new();
-            if let Some(ref prefix) = self.name_prefix {
-                builder = builder.name(format!("{}-{}", prefix, i));
-            }
-            let handle = builder.spawn(move || poll_queue(cq_)).unwrap();
+            let cq = Arc::new(CompletionQueueHandle::new(self.spawn_limit));
+            let handle = spawn_poller(&self.name_prefix, i, cq.clone(), paused.clone());
             cqs.push(CompletionQueue::new(cq, handle.thread().id()));
             handles.push(handle);
         }
@@ -94,7 +153,9 @@ impl EnvBuilder {
         Environment {
             cqs,
             idx: AtomicUsize::new(0),
-            _handles: handles,
+            name_prefix: self.name_prefix,
+            paused,
+            handles: Mutex::new(handles),
         }
     }
 }
@@ -103,7 +164,10 @@ impl EnvBuilder {
 pub struct Environment {
     cqs: Vec<CompletionQueue>,
     idx: AtomicUsize,
-    _handles: Vec<JoinHandle<()>>,
+    name_prefix: Option<String>,
+    // Whether the polling threads are stopped for fork.
+    paused: Arc<AtomicBool>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl Environment {
@@ -132,20 +196,109 @@ impl Environment {
         let idx = self.idx.fetch_add(1, Ordering::Relaxed);
         self.cqs[idx % self.cqs.len()].clone()
     }
-}
 
-impl Drop for Environment {
-    fn drop(&mut self) {
+    /// Shutdown all the completion queues, which is also done when the
+    /// environment is dropped.
+    ///
+    /// New calls are rejected with `Error::QueueShutdown` afterwards, while
+    /// the calls in flight are still polled until they finish. Queues used by
+    /// servers are shutdown only after the servers are dropped. It's safe to
+    /// be called more than once.
+    pub fn shutdown(&self) {
         for cq in self.completion_queues() {
-            // it's safe to shutdown more than once.
             cq.shutdown()
         }
     }
+
+    /// Check if [`shutdown`](Environment::shutdown) has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.cqs.iter().all(CompletionQueue::is_shutdown)
+    }
+
+    /// Prepare for `fork`, which stops the polling threads and the timer
+    /// thread, and runs the fork handler of gRPC Core.
+    ///
+    /// gRPC Core can only handle fork when no thread is calling into it, so
+    /// the application should not make calls or spawn futures until
+    /// [`post_fork_parent`](Environment::post_fork_parent) or
+    /// [`post_fork_child`](Environment::post_fork_child) is called after
+    /// `fork`. The handlers of gRPC Core do nothing unless fork support is
+    /// enabled by setting the environment variable
+    /// `GRPC_ENABLE_FORK_SUPPORT=1` before gRPC is initialized, without it
+    /// the child should not use gRPC at all. Only one environment should be
+    /// prepared at a time.
+    ///
+    /// `Error::QueueShutdown` is returned if the environment has been
+    /// shutdown. It does nothing if it has been prepared.
+    pub fn prepare_fork(&self) -> Result<()> {
+        let mut handles = self.handles.lock().unwrap();
+        if self.paused.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if self.cqs.iter().any(CompletionQueue::is_shutdown) {
+            return Err(Error::QueueShutdown);
+        }
+        self.paused.store(true, Ordering::SeqCst);
+        for cq in &self.cqs {
+            kick(cq)?;
+        }
+        for handle in handles.drain(..) {
+            handle.join().unwrap();
+        }
+        // Delays are driven by the timer thread, which doesn't exist in the
+        // child and may hold the lock of the timer queue when forking.
+        task::pause_timer();
+        unsafe { grpc_sys::grpcwrap_prefork() };
+        Ok(())
+    }
+
+    /// Restart the polling threads and the timer thread in the parent process
+    /// after `fork`, see
+    /// [`prepare_fork`](Environment::prepare_fork).
+    pub fn post_fork_parent(&self) {
+        self.resume(|| unsafe { grpc_sys::grpcwrap_postfork_parent() })
+    }
+
+    /// Restart the polling threads and the timer thread in the child process
+    /// after `fork`, see
+    /// [`prepare_fork`](Environment::prepare_fork).
+    ///
+    /// Connections of channels and servers created before `fork` are not
+    /// usable in the child, gRPC Core re-establishes the ones of channels
+    /// when fork support is enabled.
+    pub fn post_fork_child(&self) {
+        self.resume(|| unsafe { grpc_sys::grpcwrap_postfork_child() })
+    }
+
+    fn resume<F: FnOnce()>(&self, post_fork: F) {
+        let mut handles = self.handles.lock().unwrap();
+        if !self.paused.load(Ordering::SeqCst) {
+            return;
+        }
+        post_fork();
+        task::resume_timer();
+        self.paused.store(false, Ordering::SeqCst);
+        for (i, cq) in self.cqs.iter().enumerate() {
+            let handle = cq.handle().clone();
+            let handle = spawn_poller(&self.name_prefix, i, handle, self.paused.clone());
+            // Futures are polled inline only when they are notified on the
+            // polling thread.
+            cq.set_worker_id(handle.thread().id());
+            handles.push(handle);
+        }
+    }
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        self.shutdown()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::Error;
 
     #[test]
     fn test_basic_loop() {
@@ -156,8 +309,8 @@ mod tests {
         let q3 = env.pick_cq();
         let cases = vec![(&q1, &q3, true), (&q1, &q2, false)];
         for (lq, rq, is_eq) in cases {
-            let lq_ref = lq.borrow().unwrap();
-            let rq_ref = rq.borrow().unwrap();
+            let lq_ref = lq.try_ref().unwrap();
+            let rq_ref = rq.try_ref().unwrap();
             if is_eq {
                 assert_eq!(lq_ref.as_ptr(), rq_ref.as_ptr());
             } else {
@@ -166,12 +319,39 @@ mod tests {
         }
 
         assert_eq!(env.completion_queues().len(), 2);
+        assert!(!env.is_shutdown());
+        env.shutdown();
+        env.shutdown();
+        assert!(env.is_shutdown());
         for cq in env.completion_queues() {
-            cq.shutdown();
+            assert!(matches!(cq.try_ref(), Err(Error::QueueShutdown)));
         }
 
-        for handle in env._handles.drain(..) {
+        for handle in env.handles.get_mut().unwrap().drain(..) {
             handle.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_fork_handlers() {
+        let env = EnvBuilder::new().cq_count(2).build();
+        // Nothing happens before preparing.
+        env.post_fork_parent();
+        assert_eq!(env.handles.lock().unwrap().len(), 2);
+
+        for _ in 0..2 {
+            env.prepare_fork().unwrap();
+            env.prepare_fork().unwrap();
+            assert!(env.handles.lock().unwrap().is_empty());
+            env.post_fork_parent();
+            let handles = env.handles.lock().unwrap();
+            assert_eq!(handles.len(), 2);
+            for (cq, handle) in env.cqs.iter().zip(handles.iter()) {
+                assert_eq!(cq.worker_id(), handle.thread().id());
+            }
+        }
+
+        env.shutdown();
+        assert!(matches!(env.prepare_fork(), Err(Error::QueueShutdown)));
+    }
 }
@@ -12,9 +12,9 @@
 // limitations under the License.
 
 use std::fmt::{self, Display, Formatter};
-use std::{error, result};
+use std::{error, io, result};
 
-use crate::call::RpcStatus;
+use crate::call::{RpcStatus, RpcStatusCode};
 use crate::grpc_sys::grpc_call_error;
 
 #[cfg(feature = "prost-codec")]
@@ -41,10 +41,14 @@ pub enum Error {
     BindFail(String, u16),
     /// gRPC completion queue is shutdown.
     QueueShutdown,
-    /// Failed to create Google default credentials.
+    /// Failed to create Google credentials.
     GoogleAuthenticationFailed,
     /// Invalid format of metadata.
     InvalidMetadata(String),
+    /// Failed to read from or write to an io object.
+    Io(io::Error),
+    /// Too many futures are spawned into a completion queue.
+    Overloaded,
 }
 
 impl Display for Error {
@@ -64,14 +68,17 @@ impl error::Error for Error {
             Error::ShutdownFailed => "Failed to shutdown.",
             Error::BindFail(_, _) => "gRPC Bind Error",
             Error::QueueShutdown => "gRPC completion queue shutdown",
-            Error::GoogleAuthenticationFailed => "Could not create google default credentials.",
+            Error::GoogleAuthenticationFailed => "Could not create google credentials.",
             Error::InvalidMetadata(_) => "invalid format of metadata",
+            Error::Io(_) => "io error",
+            Error::Overloaded => "too many spawned futures",
         }
     }
 
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             Error::Codec(ref e) => Some(e.as_ref()),
+            Error::Io(ref e) => Some(e),
             _ => None,
         }
     }
@@ -97,6 +104,93 @@ impl From<DecodeError> for Error {
 /// Type alias to use this library's [`Error`] type in a `Result`.
 pub type Result<T> = result::Result<T, Error>;
 
+/// The class of the failure of a call, which can be matched on instead of
+/// parsing the messages of [`Error`].
+///
+/// It's converted from an [`Error`] returned by a call with `From`. The
+/// status codes that gRPC Core reports locally can't be told from the ones
+/// sent by servers, so a status is classified by its code only.
+#[derive(Debug)]
+pub enum CallError {
+    /// The server can't be reached or the connection is broken, which is
+    /// reported as `UNAVAILABLE`.
+    Transport(RpcStatus),
+    /// The deadline expires before the call finishes.
+    DeadlineExceeded(RpcStatus),
+    /// The call is cancelled by either side.
+    Cancelled(RpcStatus),
+    /// Failed to serialize a request or deserialize a response.
+    Serialization(Box<dyn error::Error + Send + Sync>),
+    /// The server fails the call with the status.
+    Remote(RpcStatus),
+    /// The call can't be started or continued locally, for example the
+    /// completion queue is shut down.
+    Local(Error),
+}
+
+impl CallError {
+    /// Get the status of the call, `None` is returned if the call fails
+    /// without a status.
+    pub fn status(&self) -> Option<&RpcStatus> {
+        match *self {
+            CallError::Transport(ref s)
+            | CallError::DeadlineExceeded(ref s)
+            | CallError::Cancelled(ref s)
+            | CallError::Remote(ref s) => Some(s),
+            CallError::Serialization(_) | CallError::Local(_) => None,
+        }
+    }
+
+    fn from_status(status: RpcStatus) -> CallError {
+        match status.status {
+            RpcStatusCode::UNAVAILABLE => CallError::Transport(status),
+            RpcStatusCode::DEADLINE_EXCEEDED => CallError::DeadlineExceeded(status),
+            RpcStatusCode::CANCELLED => CallError::Cancelled(status),
+            _ => CallError::Remote(status),
+        }
+    }
+}
+
+impl From<Error> for CallError {
+    fn from(e: Error) -> CallError {
+        match e {
+            Error::RpcFailure(status) | Error::RpcFinished(Some(status)) => {
+                CallError::from_status(status)
+            }
+            Error::Codec(e) => CallError::Serialization(e),
+            e => CallError::Local(e),
+        }
+    }
+}
+
+impl Display for CallError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let (class, status) = match *self {
+            CallError::Transport(ref s) => ("transport failure", s),
+            CallError::DeadlineExceeded(ref s) => ("deadline exceeded", s),
+            CallError::Cancelled(ref s) => ("cancelled", s),
+            CallError::Remote(ref s) => ("remote failure", s),
+            CallError::Serialization(ref e) => return write!(fmt, "serialization failure: {}", e),
+            CallError::Local(ref e) => return write!(fmt, "local failure: {}", e),
+        };
+        write!(fmt, "{}: {:?}", class, status.status)?;
+        if let Some(ref details) = status.details {
+            write!(fmt, " {}", details)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for CallError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            CallError::Serialization(ref e) => Some(e.as_ref()),
+            CallError::Local(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(all(test, feature = "protobuf-codec"))]
 mod tests {
     use std::error::Error as StdError;
@@ -104,7 +198,8 @@ mod tests {
     use protobuf::error::WireError;
     use protobuf::ProtobufError;
 
-    use super::Error;
+    use super::{CallError, Error};
+    use crate::call::{RpcStatus, RpcStatusCode};
 
     #[allow(deprecated)]
     #[test]
@@ -114,4 +209,28 @@ mod tests {
         assert_eq!(e.description(), "gRPC Codec Error");
         assert!(e.cause().is_some());
     }
+
+    #[test]
+    fn test_call_error() {
+        let failure = |code| Error::RpcFailure(RpcStatus::new(code, Some("msg".to_owned())));
+        let e = CallError::from(failure(RpcStatusCode::UNAVAILABLE));
+        assert!(matches!(e, CallError::Transport(_)));
+        assert_eq!(e.to_string(), "transport failure: RpcStatusCode(14) msg");
+        let e = CallError::from(failure(RpcStatusCode::DEADLINE_EXCEEDED));
+        assert!(matches!(e, CallError::DeadlineExceeded(_)));
+        let e = CallError::from(failure(RpcStatusCode::CANCELLED));
+        assert!(matches!(e, CallError::Cancelled(_)));
+        let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, None).with_details_bin(vec![1]);
+        let e = CallError::from(Error::RpcFinished(Some(status)));
+        assert_eq!(e.status().unwrap().details_bin(), Some(&[1][..]));
+        assert!(matches!(e, CallError::Remote(_)));
+
+        let error = ProtobufError::WireError(WireError::UnexpectedEof);
+        let e = CallError::from(Error::from(error));
+        assert!(matches!(e, CallError::Serialization(_)));
+        assert!(e.source().is_some());
+        let e = CallError::from(Error::QueueShutdown);
+        assert!(matches!(e, CallError::Local(Error::QueueShutdown)));
+        assert!(e.status().is_none());
+    }
 }
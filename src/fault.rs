@@ -0,0 +1,404 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transport level fault injection for chaos testing.
+//!
+//! gRPC Core doesn't provide channel arguments for injecting faults into
+//! transports, so a proxy is put between clients and the server instead. The
+//! proxy understands HTTP/2 framing, which makes it possible to inject faults
+//! that are hard to simulate by interceptors, like dropping connections in the
+//! middle of a frame exchange or corrupting trailers. Only plaintext
+//! connections are supported.
+//!
+//! Every proxied connection is served by its own threads, so the proxy is only
+//! built with the `fault-injection` feature.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_TYPE_HEADERS: u8 = 0x1;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+
+/// A xorshift generator, it's good enough for deciding when to inject faults.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at 0.
+        Rng(if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        })
+    }
+
+    /// Return true with the probability of `p`.
+    fn hit(&mut self, p: f64) -> bool {
+        if p <= 0.0 {
+            return false;
+        }
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ((self.0 >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    close_probability: f64,
+    settings_delay: Option<Duration>,
+    corrupt_trailers_probability: f64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// [`FaultInjector`] factory in order to configure the faults.
+pub struct FaultInjectorBuilder {
+    cfg: Config,
+    seed: Option<u64>,
+}
+
+impl Default for FaultInjectorBuilder {
+    fn default() -> FaultInjectorBuilder {
+        FaultInjectorBuilder::new()
+    }
+}
+
+impl FaultInjectorBuilder {
+    /// Initialize a new [`FaultInjectorBuilder`] that injects no faults.
+    pub fn new() -> FaultInjectorBuilder {
+        FaultInjectorBuilder {
+            cfg: Config {
+                close_probability: 0.0,
+                settings_delay: None,
+                corrupt_trailers_probability: 0.0,
+            },
+            seed: None,
+        }
+    }
+
+    /// Set the probability of closing the connection before forwarding a frame.
+    pub fn close_probability(mut self, p: f64) -> FaultInjectorBuilder {
+        self.cfg.close_probability = p;
+        self
+    }
+
+    /// Delay the initial SETTINGS frame sent by the server.
+    ///
+    /// Clients can't start any call before the frame arrives, so it can be
+    /// used to simulate slow connection establishment.
+    pub fn settings_delay(mut self, delay: Duration) -> FaultInjectorBuilder {
+        self.cfg.settings_delay = Some(delay);
+        self
+    }
+
+    /// Set the probability of corrupting the trailers sent by the server.
+    ///
+    /// The header block is garbled, which is a connection error to clients.
+    pub fn corrupt_trailers_probability(mut self, p: f64) -> FaultInjectorBuilder {
+        self.cfg.corrupt_trailers_probability = p;
+        self
+    }
+
+    /// Set the seed of the random generator, so faults can be reproduced.
+    pub fn seed(mut self, seed: u64) -> FaultInjectorBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Start a proxy on a random local port that forwards connections to `target`.
+    pub fn build<A: ToSocketAddrs>(self, target: A) -> Result<FaultInjector> {
+        let target = target
+            .to_socket_addrs()
+            .map_err(Error::Io)?
+            .next()
+            .ok_or_else(|| Error::Io(io::Error::new(ErrorKind::InvalidInput, "no address")))?;
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(Error::Io)?;
+        let addr = listener.local_addr().map_err(Error::Io)?;
+        listener.set_nonblocking(true).map_err(Error::Io)?;
+        let seed = self.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            now.as_secs() ^ u64::from(now.subsec_nanos())
+        });
+        let rng = Arc::new(Mutex::new(Rng::new(seed)));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped2 = stopped.clone();
+        let cfg = self.cfg;
+        let handle = thread::Builder::new()
+            .name("grpc-fault-injector".to_owned())
+            .spawn(move || accept_loop(listener, target, cfg, rng, stopped2))
+            .map_err(Error::Io)?;
+        Ok(FaultInjector {
+            addr,
+            stopped,
+            handle: Some(handle),
+        })
+    }
+}
+
+/// A proxy that injects faults into the connections it forwards.
+///
+/// Clients should connect to [`addr`] instead of the server. Dropping the
+/// injector stops accepting new connections, established connections are
+/// still forwarded until either side closes them.
+///
+/// [`addr`]: #method.addr
+pub struct FaultInjector {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FaultInjector {
+    /// Get the address that the proxy listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for FaultInjector {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+fn accept_loop(
+    listener: TcpListener,
+    target: SocketAddr,
+    cfg: Config,
+    rng: Arc<Mutex<Rng>>,
+    stopped: Arc<AtomicBool>,
+) {
+    while !stopped.load(Ordering::SeqCst) {
+        let client = match listener.accept() {
+            Ok((s, _)) => s,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            Err(e) => {
+                error!("fault injector failed to accept: {:?}", e);
+                return;
+            }
+        };
+        let server = match TcpStream::connect(target) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("fault injector failed to connect {}: {:?}", target, e);
+                continue;
+            }
+        };
+        let streams = client
+            .set_nonblocking(false)
+            .and_then(|_| Ok((client.try_clone()?, client, server.try_clone()?, server)));
+        let (c1, c2, s1, s2) = match streams {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("fault injector failed to setup connection: {:?}", e);
+                continue;
+            }
+        };
+        let (cfg1, rng1) = (cfg.clone(), rng.clone());
+        thread::spawn(move || forward(c1, s1, Direction::ToServer, &cfg1, &rng1));
+        let (cfg2, rng2) = (cfg.clone(), rng.clone());
+        thread::spawn(move || forward(s2, c2, Direction::ToClient, &cfg2, &rng2));
+    }
+}
+
+/// Read a whole frame, including the header. `None` is returned at EOF.
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut frame = vec![0; FRAME_HEADER_LEN];
+    let mut read = 0;
+    while read < FRAME_HEADER_LEN {
+        match r.read(&mut frame[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let len = (usize::from(frame[0]) << 16) | (usize::from(frame[1]) << 8) | usize::from(frame[2]);
+    frame.resize(FRAME_HEADER_LEN + len, 0);
+    r.read_exact(&mut frame[FRAME_HEADER_LEN..])?;
+    Ok(Some(frame))
+}
+
+/// Inject faults into the frame. Returns false if the connection should be closed.
+fn inject(frame: &mut [u8], dir: Direction, cfg: &Config, rng: &Mutex<Rng>, first: bool) -> bool {
+    let mut rng = rng.lock().unwrap();
+    if rng.hit(cfg.close_probability) {
+        return false;
+    }
+    if dir == Direction::ToServer {
+        return true;
+    }
+    let (ty, flags) = (frame[3], frame[4]);
+    if ty == FRAME_TYPE_SETTINGS && flags & FLAG_ACK == 0 && first {
+        if let Some(d) = cfg.settings_delay {
+            drop(rng);
+            thread::sleep(d);
+            return true;
+        }
+    }
+    if ty == FRAME_TYPE_HEADERS
+        && flags & FLAG_END_STREAM != 0
+        && rng.hit(cfg.corrupt_trailers_probability)
+    {
+        // 0xff is never a valid start of an HPACK representation in the
+        // middle of a block, and it also makes the padding length invalid.
+        for b in &mut frame[FRAME_HEADER_LEN..] {
+            *b = 0xff;
+        }
+    }
+    true
+}
+
+fn forward(mut from: TcpStream, mut to: TcpStream, dir: Direction, cfg: &Config, rng: &Mutex<Rng>) {
+    let res = (|| -> io::Result<()> {
+        if dir == Direction::ToServer {
+            let mut preface = [0; 24];
+            from.read_exact(&mut preface)?;
+            if preface != PREFACE {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "not a HTTP/2 connection",
+                ));
+            }
+            to.write_all(&preface)?;
+        }
+        let mut first = true;
+        while let Some(mut frame) = read_frame(&mut from)? {
+            if !inject(&mut frame, dir, cfg, rng, first) {
+                return Ok(());
+            }
+            first = false;
+            to.write_all(&frame)?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = res {
+        debug!("fault injector stops forwarding: {:?}", e);
+    }
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(ty: u8, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let len = payload.len();
+        let mut f = vec![
+            (len >> 16) as u8,
+            (len >> 8) as u8,
+            len as u8,
+            ty,
+            flags,
+            0,
+            0,
+            0,
+            1,
+        ];
+        f.extend_from_slice(payload);
+        f
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut data = frame(FRAME_TYPE_HEADERS, FLAG_END_STREAM, b"abc");
+        data.extend(frame(FRAME_TYPE_SETTINGS, 0, b""));
+        let mut r = data.as_slice();
+        assert_eq!(
+            read_frame(&mut r).unwrap(),
+            Some(frame(FRAME_TYPE_HEADERS, FLAG_END_STREAM, b"abc"))
+        );
+        assert_eq!(
+            read_frame(&mut r).unwrap(),
+            Some(frame(FRAME_TYPE_SETTINGS, 0, b""))
+        );
+        assert_eq!(read_frame(&mut r).unwrap(), None);
+
+        let data = frame(FRAME_TYPE_HEADERS, 0, b"abc");
+        let mut r = &data[..data.len() - 1];
+        assert!(read_frame(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_inject() {
+        let rng = Mutex::new(Rng::new(1));
+        let mut cfg = FaultInjectorBuilder::new().cfg;
+        let mut trailers = frame(FRAME_TYPE_HEADERS, FLAG_END_STREAM, b"abc");
+        assert!(inject(
+            &mut trailers,
+            Direction::ToClient,
+            &cfg,
+            &rng,
+            false
+        ));
+        assert_eq!(trailers, frame(FRAME_TYPE_HEADERS, FLAG_END_STREAM, b"abc"));
+
+        cfg.corrupt_trailers_probability = 1.0;
+        let mut headers = frame(FRAME_TYPE_HEADERS, 0, b"abc");
+        assert!(inject(&mut headers, Direction::ToClient, &cfg, &rng, false));
+        assert_eq!(headers, frame(FRAME_TYPE_HEADERS, 0, b"abc"));
+        assert!(inject(
+            &mut trailers,
+            Direction::ToClient,
+            &cfg,
+            &rng,
+            false
+        ));
+        assert_eq!(
+            trailers,
+            frame(FRAME_TYPE_HEADERS, FLAG_END_STREAM, b"\xff\xff\xff")
+        );
+
+        cfg.close_probability = 1.0;
+        assert!(!inject(
+            &mut headers,
+            Direction::ToServer,
+            &cfg,
+            &rng,
+            false
+        ));
+    }
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(0);
+        assert!(!rng.hit(0.0));
+        assert!(rng.hit(1.0));
+        let hits = (0..10000).filter(|_| rng.hit(0.3)).count();
+        assert!(hits > 2500 && hits < 3500, "{}", hits);
+    }
+}
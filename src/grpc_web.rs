@@ -0,0 +1,405 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A gateway serving grpc-web clients.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::call::{RpcStatus, RpcStatusCode};
+use crate::channel::Channel;
+use crate::client::Client;
+use crate::http::{self, relay_call, write_empty, HttpListener, ListenerConfig, Request};
+use crate::metadata::{self, Metadata};
+
+const FRAME_HEADER_LEN: usize = 5;
+const FLAG_COMPRESSED: u8 = 0x01;
+const FLAG_TRAILER: u8 = 0x80;
+const CONTENT_TYPE_TEXT: &str = "application/grpc-web-text";
+const CONTENT_TYPE_BINARY: &str = "application/grpc-web";
+
+/// Percent encode a status message as the value of `grpc-message`.
+fn encode_message(msg: &str) -> String {
+    let mut res = String::with_capacity(msg.len());
+    for b in msg.bytes() {
+        if (b' '..=b'~').contains(&b) && b != b'%' {
+            res.push(char::from(b));
+        } else {
+            write!(res, "%{:02X}", b).unwrap();
+        }
+    }
+    res
+}
+
+/// Decode the body of a grpc-web-text request.
+///
+/// Clients may send the body in several base64 encoded segments, so every
+/// segment ending with padding is decoded separately.
+fn decode_text(body: &[u8]) -> Option<Vec<u8>> {
+    let text: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let text = str::from_utf8(&text).ok()?;
+    let mut res = Vec::with_capacity(text.len() / 4 * 3);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = match rest.find('=') {
+            Some(pos) => pos + rest[pos..].bytes().take_while(|b| *b == b'=').count(),
+            None => rest.len(),
+        };
+        res.extend(metadata::decode_base64(&rest[..end]).ok()?);
+        rest = &rest[end..];
+    }
+    Some(res)
+}
+
+/// Split the body of a request into messages, trailer frames are ignored.
+fn parse_frames(mut body: &[u8]) -> Result<Vec<Vec<u8>>, RpcStatus> {
+    let mut messages = vec![];
+    while !body.is_empty() {
+        if body.len() < FRAME_HEADER_LEN {
+            return Err(RpcStatus::new(
+                RpcStatusCode::INVALID_ARGUMENT,
+                Some("truncated grpc-web frame".to_owned()),
+            ));
+        }
+        let flag = body[0];
+        let mut len = [0; 4];
+        len.copy_from_slice(&body[1..FRAME_HEADER_LEN]);
+        let len = u32::from_be_bytes(len) as usize;
+        let frame = &body[FRAME_HEADER_LEN..];
+        if frame.len() < len {
+            return Err(RpcStatus::new(
+                RpcStatusCode::INVALID_ARGUMENT,
+                Some("truncated grpc-web frame".to_owned()),
+            ));
+        }
+        if flag & FLAG_COMPRESSED != 0 {
+            return Err(RpcStatus::new(
+                RpcStatusCode::UNIMPLEMENTED,
+                Some("compressed grpc-web messages are not supported".to_owned()),
+            ));
+        }
+        if flag & FLAG_TRAILER == 0 {
+            messages.push(frame[..len].to_vec());
+        }
+        body = &frame[len..];
+    }
+    Ok(messages)
+}
+
+fn encode_frame(flag: u8, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + data.len());
+    frame.push(flag);
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Format the entries of metadata as header lines.
+fn write_metadata(meta: &Metadata, out: &mut String) {
+    for (key, value) in meta.ascii_entries() {
+        write!(out, "{}: {}\r\n", key, value).unwrap();
+    }
+    for (key, value) in meta.binary_entries() {
+        write!(out, "{}: {}\r\n", key, metadata::encode_base64(value)).unwrap();
+    }
+}
+
+/// The response of a grpc-web call, which is sent in chunked encoding so
+/// that streamed messages are passed along as they arrive.
+struct Response<'a> {
+    stream: &'a mut TcpStream,
+    text: bool,
+    /// The header lines to send before the first frame.
+    head: Option<String>,
+}
+
+impl<'a> Response<'a> {
+    fn write_head(&mut self, headers: Option<&Metadata>) -> io::Result<()> {
+        let mut head = match self.head.take() {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        let mut exposed = "grpc-status, grpc-message".to_owned();
+        if let Some(headers) = headers {
+            for (key, _) in headers {
+                write!(exposed, ", {}", key).unwrap();
+            }
+            write_metadata(headers, &mut head);
+        }
+        write!(
+            self.stream,
+            "{}access-control-expose-headers: {}\r\n\r\n",
+            head, exposed
+        )
+    }
+
+    fn write_frame(&mut self, flag: u8, data: &[u8]) -> io::Result<()> {
+        self.write_head(None)?;
+        let mut frame = encode_frame(flag, data);
+        if self.text {
+            frame = metadata::encode_base64(&frame).into_bytes();
+        }
+        http::write_chunk(self.stream, &frame)
+    }
+
+    fn finish(mut self, status: &RpcStatus, trailers: Option<&Metadata>) -> io::Result<()> {
+        let mut block = format!("grpc-status: {}\r\n", Into::<i32>::into(status.status));
+        if let Some(ref details) = status.details {
+            write!(block, "grpc-message: {}\r\n", encode_message(details)).unwrap();
+        }
+        if let Some(trailers) = trailers {
+            write_metadata(trailers, &mut block);
+        }
+        self.write_frame(FLAG_TRAILER, block.as_bytes())?;
+        http::write_last_chunk(self.stream)
+    }
+}
+
+struct Gateway {
+    client: Client,
+    allowed_origins: Option<Vec<String>>,
+}
+
+impl Gateway {
+    /// Get the CORS headers of the response, `None` is returned if the
+    /// origin is not allowed.
+    fn cors_headers(&self, req: &Request) -> Option<String> {
+        let origin = match req.header("origin") {
+            Some(origin) => origin,
+            None => return Some(String::new()),
+        };
+        if let Some(ref allowed) = self.allowed_origins {
+            if !allowed.iter().any(|o| o == origin) {
+                return None;
+            }
+        }
+        Some(format!(
+            "access-control-allow-origin: {}\r\nvary: origin\r\n",
+            origin
+        ))
+    }
+
+    fn handle(&self, mut req: Request, stream: &mut TcpStream) -> io::Result<()> {
+        let cors = match self.cors_headers(&req) {
+            Some(cors) => cors,
+            None => return write_empty(stream, "403 Forbidden", ""),
+        };
+        if req.method == "OPTIONS" {
+            let allowed = req
+                .header("access-control-request-headers")
+                .unwrap_or("content-type, x-grpc-web, x-user-agent, grpc-timeout");
+            let headers = format!(
+                "{}access-control-allow-methods: POST, OPTIONS\r\n\
+                 access-control-allow-headers: {}\r\n\
+                 access-control-max-age: 86400\r\n",
+                cors, allowed
+            );
+            return write_empty(stream, "204 No Content", &headers);
+        }
+        if req.method != "POST" {
+            return write_empty(stream, "405 Method Not Allowed", "allow: POST, OPTIONS\r\n");
+        }
+        let content_type = req.header("content-type").unwrap_or("").to_owned();
+        let text = if content_type.starts_with(CONTENT_TYPE_TEXT) {
+            true
+        } else if content_type.starts_with(CONTENT_TYPE_BINARY) {
+            false
+        } else {
+            return write_empty(stream, "415 Unsupported Media Type", &cors);
+        };
+        let mut body = mem::take(&mut req.body);
+        if text {
+            body = match decode_text(&body) {
+                Some(body) => body,
+                None => return write_empty(stream, "400 Bad Request", &cors),
+            };
+        }
+
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\n{}",
+            content_type, cors
+        );
+        let mut resp = Response {
+            stream,
+            text,
+            head: Some(head),
+        };
+        let messages = match parse_frames(&body) {
+            Ok(messages) => messages,
+            Err(status) => return resp.finish(&status, None),
+        };
+        let opt = req.call_option();
+        let (status, rx) = relay_call(&self.client, &req.path, opt, messages, |rx, msg| {
+            resp.write_head(rx.headers().as_ref())?;
+            resp.write_frame(0, &msg)
+        })?;
+        let (headers, trailers) = match rx {
+            Some(rx) => (rx.headers(), rx.trailers()),
+            None => (None, None),
+        };
+        resp.write_head(headers.as_ref())?;
+        resp.finish(&status, trailers.as_ref())
+    }
+}
+
+/// A builder for [`GrpcWebServer`].
+pub struct GrpcWebServerBuilder {
+    channel: Channel,
+    allowed_origins: Option<Vec<String>>,
+    config: ListenerConfig,
+}
+
+impl GrpcWebServerBuilder {
+    /// Create a builder forwarding requests through the channel.
+    pub fn new(channel: Channel) -> GrpcWebServerBuilder {
+        GrpcWebServerBuilder {
+            channel,
+            allowed_origins: None,
+            config: ListenerConfig::default(),
+        }
+    }
+
+    /// Only accept cross origin requests from the given origins. Requests
+    /// from all origins are accepted by default.
+    pub fn allowed_origins<I, S>(mut self, origins: I) -> GrpcWebServerBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_origins = Some(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Set the max size of a request body, 4 MiB by default. Larger requests
+    /// are rejected with `413 Payload Too Large`.
+    pub fn max_body_len(mut self, len: usize) -> GrpcWebServerBuilder {
+        self.config.max_body_len = len;
+        self
+    }
+
+    /// Set the max number of connections that are served at the same time,
+    /// 1024 by default. Other connections are answered with
+    /// `503 Service Unavailable` and closed.
+    pub fn max_connections(mut self, count: usize) -> GrpcWebServerBuilder {
+        self.config.max_connections = count;
+        self
+    }
+
+    /// Set how long a request can take to be received, 30 seconds by
+    /// default. Connections are closed if the next request is not received
+    /// in time, or if a write to them blocks longer than that.
+    pub fn io_timeout(mut self, timeout: Duration) -> GrpcWebServerBuilder {
+        self.config.io_timeout = timeout;
+        self
+    }
+
+    /// Listen on the address and start serving requests.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<GrpcWebServer> {
+        let gateway = Gateway {
+            client: Client::new(self.channel),
+            allowed_origins: self.allowed_origins,
+        };
+        let handler = Arc::new(move |req, stream: &mut TcpStream| gateway.handle(req, stream));
+        let listener = HttpListener::bind(addr, "grpc-web", self.config, handler)?;
+        Ok(GrpcWebServer { listener })
+    }
+}
+
+/// A server accepting grpc-web requests and forwarding them to a gRPC
+/// server.
+///
+/// gRPC Core only accepts HTTP/2, which browsers can't speak to it directly,
+/// so grpc-web requests can't be served on the port of a [`Server`]. Instead
+/// the gateway listens on its own port, accepts grpc-web requests over
+/// HTTP/1.1, in both the binary `application/grpc-web` and the base64 encoded
+/// `application/grpc-web-text` formats, and forwards them to the server
+/// through a [`Channel`]. Trailers are sent back in the last frame of the
+/// response body as grpc-web specifies, and CORS preflight requests are
+/// answered so that pages from other origins can make calls.
+///
+/// Every connection is served in its own thread, up to the limit set by
+/// `max_connections`, and connections that don't send a request within
+/// `io_timeout` are closed. The server stops
+/// accepting connections when it's shut down or dropped, connections that
+/// are already accepted are served until they are closed.
+pub struct GrpcWebServer {
+    listener: HttpListener,
+}
+
+impl GrpcWebServer {
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    /// Stop accepting connections and wait for the listening thread to exit.
+    pub fn shutdown(&mut self) {
+        self.listener.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let mut body = encode_frame(0, b"hello");
+        body.extend(encode_frame(0, b""));
+        body.extend(encode_frame(FLAG_TRAILER, b"grpc-status: 0\r\n"));
+        assert_eq!(
+            parse_frames(&body).unwrap(),
+            vec![b"hello".to_vec(), vec![]]
+        );
+
+        let status = parse_frames(&body[..3]).unwrap_err();
+        assert_eq!(status.status, RpcStatusCode::INVALID_ARGUMENT);
+        let status = parse_frames(&body[..8]).unwrap_err();
+        assert_eq!(status.status, RpcStatusCode::INVALID_ARGUMENT);
+        let status = parse_frames(&encode_frame(FLAG_COMPRESSED, b"a")).unwrap_err();
+        assert_eq!(status.status, RpcStatusCode::UNIMPLEMENTED);
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let frame = encode_frame(0, b"hello");
+        let mut text = metadata::encode_base64(&frame);
+        assert_eq!(decode_text(text.as_bytes()).unwrap(), frame);
+
+        // Padded segments can be concatenated.
+        text.push_str(&metadata::encode_base64(b"a"));
+        text.push_str("\r\n");
+        text.push_str(&metadata::encode_base64(b"bc"));
+        let mut expected = frame.clone();
+        expected.extend_from_slice(b"abc");
+        assert_eq!(decode_text(text.as_bytes()).unwrap(), expected);
+
+        assert_eq!(decode_text(b"a!bc"), None);
+    }
+
+    #[test]
+    fn test_encode_message() {
+        assert_eq!(encode_message("not found"), "not found");
+        assert_eq!(encode_message("50%\r\n"), "50%25%0D%0A");
+        assert_eq!(encode_message("é"), "%C3%A9");
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client side balancing of calls among channels to different backends.
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+
+use crate::channel::Channel;
+use crate::error::Error;
+use crate::stats::StatsHandler;
+
+/// Statistics of a host of a [`HostPool`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostStats {
+    pub host: String,
+    /// The count of picked channels that are not dropped yet.
+    pub in_flight: usize,
+    /// The count of picks that are assigned to the host.
+    pub picked: usize,
+    /// The count of picks that are moved to another host because the host
+    /// was at its concurrency limit.
+    pub shed: usize,
+    /// The total time that the picks assigned to the host have been queued.
+    pub queue_wait: Duration,
+    /// The longest time that a pick assigned to the host has been queued.
+    pub max_queue_wait: Duration,
+}
+
+struct Host {
+    channel: Channel,
+    stats: HostStats,
+}
+
+struct State {
+    hosts: Vec<Host>,
+    next: usize,
+    waiters: Vec<Task>,
+}
+
+struct Inner {
+    max_concurrency: usize,
+    shed: bool,
+    state: Mutex<State>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl Inner {
+    fn has_capacity(&self, host: &Host) -> bool {
+        self.max_concurrency == 0 || host.stats.in_flight < self.max_concurrency
+    }
+
+    fn try_acquire(&self, state: &mut State) -> Option<usize> {
+        let len = state.hosts.len();
+        let preferred = state.next % len;
+        let picked = if self.has_capacity(&state.hosts[preferred]) {
+            preferred
+        } else if self.shed {
+            let (i, host) = state
+                .hosts
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, h)| h.stats.in_flight)
+                .unwrap();
+            if !self.has_capacity(host) {
+                return None;
+            }
+            state.hosts[preferred].stats.shed += 1;
+            i
+        } else {
+            return None;
+        };
+        state.next = preferred + 1;
+        state.hosts[picked].stats.in_flight += 1;
+        state.hosts[picked].stats.picked += 1;
+        Some(picked)
+    }
+
+    fn release(&self, host: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.hosts[host].stats.in_flight -= 1;
+        for t in state.waiters.drain(..) {
+            t.notify();
+        }
+    }
+}
+
+/// A builder for [`HostPool`].
+pub struct HostPoolBuilder {
+    hosts: Vec<Host>,
+    max_concurrency: usize,
+    shed: bool,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl HostPoolBuilder {
+    pub fn new() -> HostPoolBuilder {
+        HostPoolBuilder {
+            hosts: vec![],
+            max_concurrency: 0,
+            shed: false,
+            stats_handler: None,
+        }
+    }
+
+    /// Add a host with the channel connecting to it.
+    pub fn host<S: Into<String>>(mut self, host: S, channel: Channel) -> HostPoolBuilder {
+        let stats = HostStats {
+            host: host.into(),
+            in_flight: 0,
+            picked: 0,
+            shed: 0,
+            queue_wait: Duration::from_secs(0),
+            max_queue_wait: Duration::from_secs(0),
+        };
+        self.hosts.push(Host { channel, stats });
+        self
+    }
+
+    /// Limit the count of in flight picks of every host, 0 means unlimited,
+    /// which is the default.
+    ///
+    /// Picks are queued until the picked host is below the limit.
+    pub fn max_concurrency(mut self, max: usize) -> HostPoolBuilder {
+        self.max_concurrency = max;
+        self
+    }
+
+    /// Move picks to the least loaded host if the host picked in turn is at
+    /// its concurrency limit, instead of queuing them. Picks are still queued
+    /// if all hosts are at the limit.
+    pub fn shed_to_least_loaded(mut self, shed: bool) -> HostPoolBuilder {
+        self.shed = shed;
+        self
+    }
+
+    /// Set the handler that is notified with the load of a host every time
+    /// it's picked, see [`StatsHandler::host_picked`].
+    pub fn stats_handler(mut self, handler: Arc<dyn StatsHandler>) -> HostPoolBuilder {
+        self.stats_handler = Some(handler);
+        self
+    }
+
+    /// Finalize the [`HostPoolBuilder`] and build the [`HostPool`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no host is added.
+    pub fn build(self) -> HostPool {
+        assert!(!self.hosts.is_empty(), "host pool should not be empty");
+        HostPool {
+            inner: Arc::new(Inner {
+                max_concurrency: self.max_concurrency,
+                shed: self.shed,
+                state: Mutex::new(State {
+                    hosts: self.hosts,
+                    next: 0,
+                    waiters: vec![],
+                }),
+                stats_handler: self.stats_handler,
+            }),
+        }
+    }
+}
+
+impl Default for HostPoolBuilder {
+    fn default() -> HostPoolBuilder {
+        HostPoolBuilder::new()
+    }
+}
+
+/// A pool of channels connecting to different backends of a service.
+///
+/// Hosts are picked in turn, and the count of picks that are in flight is
+/// tracked per host. A pick stays in flight until the returned
+/// [`PooledChannel`] is dropped, so it should be held until the calls made on
+/// it finish.
+#[derive(Clone)]
+pub struct HostPool {
+    inner: Arc<Inner>,
+}
+
+impl HostPool {
+    /// Pick a host for the next calls.
+    ///
+    /// The future is resolved once a host is below its concurrency limit.
+    pub fn pick(&self) -> HostPick {
+        HostPick {
+            inner: Some(self.inner.clone()),
+            start: Instant::now(),
+        }
+    }
+
+    /// Get the statistics of all the hosts.
+    pub fn stats(&self) -> Vec<HostStats> {
+        let state = self.inner.state.lock().unwrap();
+        state.hosts.iter().map(|h| h.stats.clone()).collect()
+    }
+}
+
+/// A future that resolves into a [`PooledChannel`], see [`HostPool::pick`].
+#[must_use = "futures do nothing unless polled"]
+pub struct HostPick {
+    inner: Option<Arc<Inner>>,
+    start: Instant,
+}
+
+impl Future for HostPick {
+    type Item = PooledChannel;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<PooledChannel, Error> {
+        let inner = self.inner.take().expect("cannot poll HostPick twice");
+        let mut state = inner.state.lock().unwrap();
+        let host = match inner.try_acquire(&mut state) {
+            Some(host) => host,
+            None => {
+                state.waiters.push(task::current());
+                drop(state);
+                self.inner = Some(inner);
+                return Ok(Async::NotReady);
+            }
+        };
+        let wait = self.start.elapsed();
+        let stats = &mut state.hosts[host].stats;
+        stats.queue_wait += wait;
+        if wait > stats.max_queue_wait {
+            stats.max_queue_wait = wait;
+        }
+        let in_flight = stats.in_flight;
+        let name = stats.host.clone();
+        let channel = state.hosts[host].channel.clone();
+        drop(state);
+        if let Some(ref handler) = inner.stats_handler {
+            handler.host_picked(&name, in_flight, wait);
+        }
+        Ok(Async::Ready(PooledChannel {
+            channel,
+            host,
+            name,
+            inner,
+        }))
+    }
+}
+
+/// A channel picked from a [`HostPool`].
+///
+/// The pick is in flight until it's dropped.
+pub struct PooledChannel {
+    channel: Channel,
+    host: usize,
+    name: String,
+    inner: Arc<Inner>,
+}
+
+impl PooledChannel {
+    /// The host the channel connects to.
+    pub fn host(&self) -> &str {
+        &self.name
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl Deref for PooledChannel {
+    type Target = Channel;
+
+    fn deref(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl Drop for PooledChannel {
+    fn drop(&mut self) {
+        self.inner.release(self.host);
+    }
+}
@@ -0,0 +1,566 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal HTTP/1.1 server shared by the gateways in front of gRPC servers.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future, Sink, Stream};
+
+use crate::call::client::{CallOption, ClientDuplexReceiver};
+use crate::call::{RpcStatus, RpcStatusCode, WriteFlags};
+use crate::client::Client;
+use crate::error::Error;
+use crate::metadata::MetadataBuilder;
+
+const MAX_HEADER_LEN: u64 = 64 * 1024;
+
+/// Limits of the connections served by a [`HttpListener`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ListenerConfig {
+    /// The max size of a request body.
+    pub max_body_len: usize,
+    /// The max number of connections that are served at the same time.
+    pub max_connections: usize,
+    /// How long a request can take to be received, or a write can block.
+    pub io_timeout: Duration,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            max_body_len: 4 * 1024 * 1024,
+            max_connections: 1024,
+            io_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Headers that are handled by the gateways or only make sense to HTTP/1.1,
+/// all other headers are forwarded as metadata.
+const SKIPPED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "accept-language",
+    "cache-control",
+    "connection",
+    "content-length",
+    "content-type",
+    "host",
+    "keep-alive",
+    "origin",
+    "pragma",
+    "referer",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+    "user-agent",
+    "x-grpc-web",
+    "x-user-agent",
+];
+const SKIPPED_HEADER_PREFIXES: &[&str] = &["access-control-", "grpc-", "proxy-", "sec-"];
+
+fn is_forwarded_header(name: &str) -> bool {
+    !SKIPPED_HEADERS.contains(&name) && !SKIPPED_HEADER_PREFIXES.iter().any(|p| name.starts_with(p))
+}
+
+/// Parse the value of a `grpc-timeout` header.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(n * 3600),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+pub(crate) enum HttpError {
+    BadRequest,
+    TooLarge,
+    Io(io::Error),
+}
+
+impl From<io::Error> for HttpError {
+    fn from(e: io::Error) -> HttpError {
+        HttpError::Io(e)
+    }
+}
+
+pub(crate) struct Request {
+    pub method: String,
+    /// The request target, including the query.
+    pub path: String,
+    /// Headers with lower case names.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Build the option of the forwarded call. Headers are forwarded as
+    /// metadata, and `grpc-timeout` is used as the timeout.
+    pub fn call_option(&self) -> CallOption {
+        let mut builder = MetadataBuilder::new();
+        for (name, value) in &self.headers {
+            if !is_forwarded_header(name) {
+                continue;
+            }
+            // Headers that are not valid metadata are dropped.
+            let _ = if name.ends_with("-bin") {
+                builder.add_base64(name, value)
+            } else {
+                builder.add_str(name, value)
+            };
+        }
+        let mut opt = CallOption::default().headers(builder.build());
+        if let Some(timeout) = self.header("grpc-timeout").and_then(parse_timeout) {
+            opt = opt.timeout(timeout);
+        }
+        opt
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut String) -> Result<usize, HttpError> {
+    buf.clear();
+    let n = reader.by_ref().take(MAX_HEADER_LEN).read_line(buf)?;
+    if n as u64 == MAX_HEADER_LEN {
+        return Err(HttpError::TooLarge);
+    }
+    let len = buf.trim_end_matches(&['\r', '\n'][..]).len();
+    buf.truncate(len);
+    Ok(n)
+}
+
+/// Read a request, `None` is returned if the connection is closed before
+/// a new request starts.
+pub(crate) fn read_request<R: BufRead>(
+    reader: &mut R,
+    max_body_len: usize,
+) -> Result<Option<Request>, HttpError> {
+    let mut line = String::new();
+    if read_line(reader, &mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_owned(), path.to_owned())
+        }
+        _ => return Err(HttpError::BadRequest),
+    };
+
+    let mut headers = vec![];
+    loop {
+        if read_line(reader, &mut line)? == 0 {
+            return Err(HttpError::BadRequest);
+        }
+        if line.is_empty() {
+            break;
+        }
+        let pos = line.find(':').ok_or(HttpError::BadRequest)?;
+        let name = line[..pos].trim().to_ascii_lowercase();
+        headers.push((name, line[pos + 1..].trim().to_owned()));
+    }
+    let mut req = Request {
+        method,
+        path,
+        headers,
+        body: vec![],
+    };
+
+    let chunked = req
+        .header("transfer-encoding")
+        .map(|te| te.to_ascii_lowercase().contains("chunked"))
+        .unwrap_or(false);
+    if chunked {
+        loop {
+            read_line(reader, &mut line)?;
+            let size = line.split(';').next().unwrap().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| HttpError::BadRequest)?;
+            if size == 0 {
+                // Skip the trailer section.
+                while read_line(reader, &mut line)? != 0 && !line.is_empty() {}
+                break;
+            }
+            if req.body.len() + size > max_body_len {
+                return Err(HttpError::TooLarge);
+            }
+            let start = req.body.len();
+            req.body.resize(start + size, 0);
+            reader.read_exact(&mut req.body[start..])?;
+            read_line(reader, &mut line)?;
+        }
+    } else if let Some(len) = req.header("content-length") {
+        let len: usize = len.parse().map_err(|_| HttpError::BadRequest)?;
+        if len > max_body_len {
+            return Err(HttpError::TooLarge);
+        }
+        req.body.resize(len, 0);
+        reader.read_exact(&mut req.body)?;
+    }
+    Ok(Some(req))
+}
+
+/// Write a response without body, `headers` are formatted header lines.
+pub(crate) fn write_empty(stream: &mut TcpStream, status: &str, headers: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n{}content-length: 0\r\n\r\n",
+        status, headers
+    )?;
+    stream.flush()
+}
+
+/// Write a chunk of a response in chunked encoding.
+pub(crate) fn write_chunk(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    write!(stream, "{:x}\r\n", data.len())?;
+    stream.write_all(data)?;
+    stream.write_all(b"\r\n")
+}
+
+/// Write the last chunk of a response in chunked encoding.
+pub(crate) fn write_last_chunk(stream: &mut TcpStream) -> io::Result<()> {
+    stream.write_all(b"0\r\n\r\n")?;
+    stream.flush()
+}
+
+/// Make a call with the messages and pass the responses to `on_message` as
+/// they arrive. The final status is returned with the receiver, which is
+/// `None` if the call can't be started, so that the headers and trailers
+/// can be read.
+pub(crate) fn relay_call<F>(
+    client: &Client,
+    path: &str,
+    opt: CallOption,
+    messages: Vec<Vec<u8>>,
+    mut on_message: F,
+) -> io::Result<(RpcStatus, Option<ClientDuplexReceiver<Vec<u8>>>)>
+where
+    F: FnMut(&ClientDuplexReceiver<Vec<u8>>, Vec<u8>) -> io::Result<()>,
+{
+    let (tx, mut rx) = match client.generic_duplex_streaming(path, opt) {
+        Ok(call) => call,
+        Err(e) => {
+            let status = RpcStatus::new(RpcStatusCode::INTERNAL, Some(format!("{}", e)));
+            return Ok((status, None));
+        }
+    };
+    let sent = messages
+        .into_iter()
+        .try_fold(tx, |tx, msg| tx.send((msg, WriteFlags::default())).wait());
+    // If sending fails, the call has failed and the status is polled
+    // from the receiver.
+    let _tx = sent.and_then(|mut tx| future::poll_fn(|| tx.close()).wait().map(|_| tx));
+
+    let status = loop {
+        match future::poll_fn(|| rx.poll()).wait() {
+            Ok(Some(msg)) => on_message(&rx, msg)?,
+            Ok(None) => break RpcStatus::ok(),
+            Err(Error::RpcFailure(status)) => break status,
+            Err(e) => break RpcStatus::new(RpcStatusCode::UNKNOWN, Some(format!("{}", e))),
+        }
+    };
+    Ok((status, Some(rx)))
+}
+
+pub(crate) type Handler = dyn Fn(Request, &mut TcpStream) -> io::Result<()> + Send + Sync;
+
+/// A reader failing with `TimedOut` once the deadline is passed, so that
+/// neither idle clients nor clients sending requests slowly can keep a
+/// connection forever.
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request is not received in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(self.deadline - now))?;
+        self.stream.read(buf)
+    }
+}
+
+fn serve(stream: TcpStream, config: &ListenerConfig, handler: &Handler) -> io::Result<()> {
+    stream.set_write_timeout(Some(config.io_timeout))?;
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now(),
+    });
+    let mut stream = stream;
+    loop {
+        // The next request, including its body, must be received in time.
+        reader.get_mut().deadline = Instant::now() + config.io_timeout;
+        let req = match read_request(&mut reader, config.max_body_len) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(HttpError::BadRequest) => {
+                return write_empty(&mut stream, "400 Bad Request", "connection: close\r\n");
+            }
+            Err(HttpError::TooLarge) => {
+                return write_empty(
+                    &mut stream,
+                    "413 Payload Too Large",
+                    "connection: close\r\n",
+                );
+            }
+            Err(HttpError::Io(e)) => return Err(e),
+        };
+        let close = req
+            .header("connection")
+            .map(|c| c.eq_ignore_ascii_case("close"))
+            .unwrap_or(false);
+        handler(req, &mut stream)?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Decreases the number of active connections when it's dropped.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A listener serving every connection in its own thread.
+///
+/// At most `max_connections` connections are served at the same time, other
+/// connections are answered with `503 Service Unavailable` and closed. A
+/// connection is closed if the next request is not received within
+/// `io_timeout`, or a write to it blocks longer than that.
+///
+/// It stops accepting connections when it's shut down or dropped,
+/// connections that are already accepted are served until they are closed.
+pub(crate) struct HttpListener {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HttpListener {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        name: &'static str,
+        config: ListenerConfig,
+        handler: Arc<Handler>,
+    ) -> io::Result<HttpListener> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        let active = Arc::new(AtomicUsize::new(0));
+        let handle = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("failed to accept {} connection: {}", name, e);
+                            continue;
+                        }
+                    };
+                    // Only this thread adds connections, so the limit can't be
+                    // exceeded between the check and the increment.
+                    if active.load(Ordering::SeqCst) >= config.max_connections {
+                        let _ = stream.set_write_timeout(Some(config.io_timeout));
+                        let _ = write_empty(
+                            &mut stream,
+                            "503 Service Unavailable",
+                            "connection: close\r\n",
+                        );
+                        continue;
+                    }
+                    active.fetch_add(1, Ordering::SeqCst);
+                    let guard = ConnectionGuard(active.clone());
+                    let handler = handler.clone();
+                    let res =
+                        thread::Builder::new()
+                            .name(format!("{}-conn", name))
+                            .spawn(move || {
+                                let _guard = guard;
+                                if let Err(e) = serve(stream, &config, &*handler) {
+                                    debug!("{} connection closed: {}", name, e);
+                                }
+                            });
+                    if let Err(e) = res {
+                        warn!("failed to spawn {} connection thread: {}", name, e);
+                    }
+                }
+            })?;
+        Ok(HttpListener {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting connections and wait for the listening thread to exit.
+    pub fn shutdown(&mut self) {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the listening thread.
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            let ip = match addr.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            };
+            addr.set_ip(ip);
+        }
+        if TcpStream::connect(addr).is_ok() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for HttpListener {
+    fn drop(&mut self) {
+        self.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_timeout("3S"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_timeout("5u"), Some(Duration::from_micros(5)));
+        assert_eq!(parse_timeout("7n"), Some(Duration::from_nanos(7)));
+        for s in &["", "S", "1", "1s", "-1S", "123456789S"] {
+            assert_eq!(parse_timeout(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /a/B HTTP/1.1\r\nContent-Type: application/grpc-web\r\n\
+                    Transfer-Encoding: chunked\r\n\r\n3;ext\r\nabc\r\n2\r\nde\r\n0\r\n\r\n\
+                    OPTIONS /a/B HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let mut reader = &raw[..];
+        let req = read_request(&mut reader, 10).ok().unwrap().unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/a/B");
+        assert_eq!(req.header("content-type"), Some("application/grpc-web"));
+        assert_eq!(req.body, b"abcde");
+        let req = read_request(&mut reader, 10).ok().unwrap().unwrap();
+        assert_eq!(req.method, "OPTIONS");
+        assert!(req.body.is_empty());
+        assert!(read_request(&mut reader, 10).ok().unwrap().is_none());
+
+        let mut reader = &b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\n"[..];
+        match read_request(&mut reader, 10) {
+            Err(HttpError::TooLarge) => {}
+            _ => panic!("large body should be rejected"),
+        }
+        let mut reader = &b"garbage\r\n\r\n"[..];
+        match read_request(&mut reader, 10) {
+            Err(HttpError::BadRequest) => {}
+            _ => panic!("invalid request should be rejected"),
+        }
+    }
+
+    fn read_response(stream: &mut TcpStream) -> String {
+        let mut buf = [0; 1024];
+        // A rejected connection may be reset before the response is read.
+        let n = stream.read(&mut buf).unwrap_or(0);
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    #[test]
+    fn test_listener_limits() {
+        let config = ListenerConfig {
+            max_connections: 1,
+            io_timeout: Duration::from_millis(200),
+            ..ListenerConfig::default()
+        };
+        let handler: Arc<Handler> =
+            Arc::new(|_, stream: &mut TcpStream| write_empty(stream, "200 OK", ""));
+        let listener = HttpListener::bind("127.0.0.1:0", "http-test", config, handler).unwrap();
+        let addr = listener.local_addr();
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(read_response(&mut idle).starts_with("HTTP/1.1 200 OK"));
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        assert!(read_response(&mut rejected).starts_with("HTTP/1.1 503"));
+
+        // The idle connection is closed once the timeout is reached.
+        let start = Instant::now();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(idle.read(&mut [0; 16]).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // So new connections are served again.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let _ = stream.write_all(b"GET / HTTP/1.1\r\n\r\n");
+            let resp = read_response(&mut stream);
+            if resp.starts_with("HTTP/1.1 200 OK") {
+                break;
+            }
+            assert!(Instant::now() < deadline, "{}", resp);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Adapters between byte-chunk streams and `std::io`.
+//!
+//! Downloading a file through a server streaming call and uploading one through
+//! a client streaming call are very common patterns. Both adapters poll only
+//! one chunk at a time, so a slow writer or reader naturally applies
+//! backpressure to the call.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use futures::{Async, Future, Poll, Stream};
+
+use crate::call::WriteFlags;
+use crate::error::{Error, Result};
+
+/// A future that writes every chunk of a response stream into a writer.
+///
+/// It's created by [`copy_to_writer`] or [`copy_to_async_writer`].
+///
+/// [`copy_to_writer`]: fn.copy_to_writer.html
+/// [`copy_to_async_writer`]: fn.copy_to_async_writer.html
+#[must_use = "futures do nothing unless polled"]
+pub struct CopyToWriter<S: Stream, W, F> {
+    stream: Option<S>,
+    writer: Option<W>,
+    chunk: F,
+    written: u64,
+    /// The message being written and the count of its bytes written.
+    pending: Option<(S::Item, usize)>,
+    finished: bool,
+    nonblocking: bool,
+}
+
+/// Write all the messages of `stream` into `writer`.
+///
+/// `chunk` extracts the payload from a message. The future resolves to the
+/// writer and the total count of bytes written once the stream is finished
+/// and the writer is flushed.
+///
+/// The writer is expected to block, which blocks the thread polling the
+/// future, use [`copy_to_async_writer`] for non-blocking writers.
+///
+/// If writing fails, the stream is dropped immediately, which cancels the
+/// underlying call. There is no cleanup of the partial output: the data that
+/// has already been written is left in the writer as is, so write to a
+/// temporary file and rename it on success if partial files must not be
+/// seen.
+///
+/// [`copy_to_async_writer`]: fn.copy_to_async_writer.html
+pub fn copy_to_writer<S, W, F>(stream: S, writer: W, chunk: F) -> CopyToWriter<S, W, F>
+where
+    S: Stream<Error = Error>,
+    W: Write,
+    F: FnMut(&S::Item) -> &[u8],
+{
+    CopyToWriter {
+        stream: Some(stream),
+        writer: Some(writer),
+        chunk,
+        written: 0,
+        pending: None,
+        finished: false,
+        nonblocking: false,
+    }
+}
+
+/// Write all the messages of `stream` into a non-blocking `writer`, see
+/// [`copy_to_writer`].
+///
+/// The writer follows the convention of `AsyncWrite` of tokio-io, which all
+/// its implementations can be passed as: an error of `WouldBlock` means the
+/// writer is not ready and the current task will be notified once it is.
+/// The future returns `NotReady` then, and resumes writing the chunk from
+/// where it stopped when it's polled again.
+///
+/// [`copy_to_writer`]: fn.copy_to_writer.html
+pub fn copy_to_async_writer<S, W, F>(stream: S, writer: W, chunk: F) -> CopyToWriter<S, W, F>
+where
+    S: Stream<Error = Error>,
+    W: Write,
+    F: FnMut(&S::Item) -> &[u8],
+{
+    CopyToWriter {
+        nonblocking: true,
+        ..copy_to_writer(stream, writer, chunk)
+    }
+}
+
+/// Convert the result of an operation on the writer, `None` is returned if
+/// it should be retried.
+fn check_io<T>(res: io::Result<T>, nonblocking: bool) -> Option<Poll<T, Error>> {
+    match res {
+        Ok(t) => Some(Ok(Async::Ready(t))),
+        Err(ref e) if e.kind() == ErrorKind::Interrupted => None,
+        Err(ref e) if nonblocking && e.kind() == ErrorKind::WouldBlock => Some(Ok(Async::NotReady)),
+        Err(e) => Some(Err(Error::Io(e))),
+    }
+}
+
+impl<S, W, F> CopyToWriter<S, W, F>
+where
+    S: Stream<Error = Error>,
+    W: Write,
+    F: FnMut(&S::Item) -> &[u8],
+{
+    fn poll_copy(&mut self) -> Poll<(), Error> {
+        let stream = self.stream.as_mut().expect("polled after finished");
+        while !self.finished {
+            if let Some((msg, mut pos)) = self.pending.take() {
+                let writer = self.writer.as_mut().unwrap();
+                let data = (self.chunk)(&msg);
+                while pos < data.len() {
+                    let res = writer.write(&data[pos..]);
+                    let n = match check_io(res, self.nonblocking) {
+                        Some(Ok(Async::Ready(0))) => {
+                            let e = io::Error::new(ErrorKind::WriteZero, "failed to write chunk");
+                            return Err(Error::Io(e));
+                        }
+                        Some(Ok(Async::Ready(n))) => n,
+                        Some(Ok(Async::NotReady)) => {
+                            self.pending = Some((msg, pos));
+                            return Ok(Async::NotReady);
+                        }
+                        Some(Err(e)) => return Err(e),
+                        None => continue,
+                    };
+                    pos += n;
+                    self.written += n as u64;
+                }
+            }
+            match try_ready!(stream.poll()) {
+                Some(msg) => self.pending = Some((msg, 0)),
+                None => self.finished = true,
+            }
+        }
+        loop {
+            let res = self.writer.as_mut().unwrap().flush();
+            if let Some(res) = check_io(res, self.nonblocking) {
+                return res;
+            }
+        }
+    }
+}
+
+impl<S, W, F> Future for CopyToWriter<S, W, F>
+where
+    S: Stream<Error = Error>,
+    W: Write,
+    F: FnMut(&S::Item) -> &[u8],
+{
+    type Item = (W, u64);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(W, u64), Error> {
+        match self.poll_copy() {
+            Ok(Async::Ready(())) => {
+                self.stream.take();
+                Ok(Async::Ready((self.writer.take().unwrap(), self.written)))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                // Drop the stream to cancel the call as soon as possible.
+                self.stream.take();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A stream that reads a reader chunk by chunk.
+///
+/// It's created by [`read_chunks`], and is supposed to be sent with
+/// `Sink::send_all` to a client streaming call or a server streaming sink.
+///
+/// [`read_chunks`]: fn.read_chunks.html
+#[must_use = "streams do nothing unless polled"]
+pub struct ReadChunks<R, F> {
+    reader: R,
+    chunk_size: usize,
+    flags: WriteFlags,
+    msg: F,
+    done: bool,
+}
+
+/// Read `reader` into messages, each of which contains at most `chunk_size`
+/// bytes.
+///
+/// `msg` builds a message from the bytes that are read. The stream finishes
+/// when the reader reaches EOF, and fails on the first read error.
+pub fn read_chunks<R, F, T>(reader: R, chunk_size: usize, msg: F) -> ReadChunks<R, F>
+where
+    R: Read,
+    F: FnMut(Vec<u8>) -> T,
+{
+    assert!(chunk_size > 0, "chunk size should be greater than 0");
+    ReadChunks {
+        reader,
+        chunk_size,
+        flags: WriteFlags::default(),
+        msg,
+        done: false,
+    }
+}
+
+impl<R, F> ReadChunks<R, F> {
+    /// Set the write flags that are attached to every message.
+    pub fn write_flags(mut self, flags: WriteFlags) -> ReadChunks<R, F> {
+        self.flags = flags;
+        self
+    }
+}
+
+impl<R: Read, F> ReadChunks<R, F> {
+    fn read_chunk(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0; self.chunk_size];
+        let mut len = 0;
+        while len < buf.len() {
+            match self.reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+impl<R, F, T> Stream for ReadChunks<R, F>
+where
+    R: Read,
+    F: FnMut(Vec<u8>) -> T,
+{
+    type Item = (T, WriteFlags);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<(T, WriteFlags)>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        let buf = match self.read_chunk() {
+            Ok(buf) => buf,
+            Err(e) => {
+                self.done = true;
+                return Err(e);
+            }
+        };
+        if buf.len() < self.chunk_size {
+            self.done = true;
+            if buf.is_empty() {
+                return Ok(Async::Ready(None));
+            }
+        }
+        Ok(Async::Ready(Some(((self.msg)(buf), self.flags))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::io;
+
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(ErrorKind::Other, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_copy_to_writer() {
+        let chunks = vec![b"hello ".to_vec(), b"world".to_vec()];
+        let s = stream::iter_ok::<_, Error>(chunks);
+        let (buf, written) = copy_to_writer(s, vec![], |c| c.as_slice()).wait().unwrap();
+        assert_eq!(buf, b"hello world");
+        assert_eq!(written, 11);
+
+        let chunks = vec![b"hello".to_vec()];
+        let s = stream::iter_ok::<_, Error>(chunks);
+        match copy_to_writer(s, BrokenWriter, |c| c.as_slice()).wait() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::Other),
+            _ => panic!("expected io error"),
+        }
+    }
+
+    /// A writer accepting at most 3 bytes at a time, which is not ready
+    /// every other time.
+    #[derive(Default)]
+    struct SlowWriter {
+        buf: Vec<u8>,
+        busy: bool,
+        would_block: usize,
+    }
+
+    impl SlowWriter {
+        fn check_ready(&mut self) -> io::Result<()> {
+            self.busy = !self.busy;
+            if self.busy {
+                self.would_block += 1;
+                futures::task::current().notify();
+                return Err(io::Error::new(ErrorKind::WouldBlock, "busy"));
+            }
+            Ok(())
+        }
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.check_ready()?;
+            let n = data.len().min(3);
+            self.buf.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.check_ready()
+        }
+    }
+
+    #[test]
+    fn test_copy_to_async_writer() {
+        let chunks = vec![b"hello ".to_vec(), b"world".to_vec()];
+        let s = stream::iter_ok::<_, Error>(chunks);
+        let f = copy_to_async_writer(s, SlowWriter::default(), |c| c.as_slice());
+        let (w, written) = f.wait().unwrap();
+        assert_eq!(w.buf, b"hello world");
+        assert_eq!(written, 11);
+        // 4 writes and the flush are retried.
+        assert_eq!(w.would_block, 5);
+
+        // Blocking writers are not expected to return `WouldBlock`.
+        let s = stream::iter_ok::<_, Error>(vec![b"hello".to_vec()]);
+        match copy_to_writer(s, SlowWriter::default(), |c| c.as_slice()).wait() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+            _ => panic!("expected io error"),
+        }
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let data: &[u8] = b"hello world";
+        let chunks: Vec<_> = read_chunks(data, 4, |b| b)
+            .map(|(b, _)| b)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![b"hell".to_vec(), b"o wo".to_vec(), b"rld".to_vec()]
+        );
+
+        let data: &[u8] = b"abcd";
+        let chunks: Vec<_> = read_chunks(data, 2, |b| b)
+            .map(|(b, _)| b)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(chunks, vec![b"ab".to_vec(), b"cd".to_vec()]);
+    }
+}
@@ -25,6 +25,14 @@ framework that puts mobile and HTTP/2 first. grpcio is built on [gRPC Core] and
 
 - **`secure`** *(enabled by default)* - Enables support for TLS encryption and some authentication
   mechanisms.
+- **`fault-injection`** - Enables [`FaultInjector`], a TCP proxy that injects transport faults
+  for chaos testing. It is not meant to be used in production.
+
+## Stability
+
+Everything at the top level, and the [`prelude`] that covers the common needs of clients and
+servers, follows semver. [`low_level`] exposes the building blocks gRPC Core is driven with, which
+may change in any release.
 
 */
 
@@ -39,48 +47,154 @@ use grpcio_sys as grpc_sys;
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "protobuf-codec")]
+mod admin;
+#[cfg(feature = "secure")]
+mod auth_context;
+mod budget;
 mod call;
+mod call_tracer;
 mod channel;
+mod channel_pool;
+pub mod channelz;
 mod client;
 mod codec;
 mod cq;
 #[cfg(feature = "secure")]
 mod credentials;
+mod diagnostics;
 mod env;
 mod error;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod grpc_web;
+mod host_pool;
+mod http;
+mod io_util;
+mod load_report;
 mod log_util;
 mod metadata;
+mod proxy;
+mod quota;
+mod resolver;
+mod response_cache;
 mod server;
+mod stats;
 mod task;
+mod trace;
+#[cfg(feature = "http-json")]
+mod transcoding;
+mod watchdog;
 
+#[cfg(feature = "protobuf-codec")]
+pub use crate::admin::{HealthService, ServingStatus};
+#[cfg(feature = "secure")]
+pub use crate::auth_context::{
+    AuthContext, AuthProperty, AuthPropertyIter, PeerIdentity, SecurityLevel,
+};
+pub use crate::budget::{BudgetClassStats, CallBudget, CallBudgetBuilder};
 pub use crate::call::client::{
-    CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
+    CallHandle, CallOption, ClientCStreamReceiver, ClientCStreamSender, ClientDuplexReceiver,
     ClientDuplexSender, ClientSStreamReceiver, ClientUnaryReceiver, StreamingCallSink,
 };
 pub use crate::call::server::{
-    ClientStreamingSink, ClientStreamingSinkResult, Deadline, DuplexSink, DuplexSinkFailure,
-    RequestStream, RpcContext, ServerStreamingSink, ServerStreamingSinkFailure, UnarySink,
+    Cancelled, ClientStreamingSink, ClientStreamingSinkResult, Deadline, DuplexSink,
+    DuplexSinkFailure, LazyMessage, RequestStream, RpcContext, SendWithTimeout,
+    ServerStreamingSink, ServerStreamingSinkFailure, StreamingResponse, UnaryResponse, UnarySink,
     UnarySinkResult,
 };
-pub use crate::call::{MessageReader, Method, MethodType, RpcStatus, RpcStatusCode, WriteFlags};
+pub use crate::call::{
+    MessageReader, Method, MethodType, OverflowPolicy, RpcStatus, RpcStatusCode, WriteFlags,
+};
+pub use crate::call_tracer::{set_call_tracer, BatchEvent, CallTracer};
 pub use crate::channel::{
-    Channel, ChannelBuilder, CompressionAlgorithms, CompressionLevel, ConnectivityState, LbPolicy,
-    OptTarget,
+    Channel, ChannelArgs, ChannelBuilder, CompressionAlgorithms, CompressionLevel,
+    ConnectivityEvent, ConnectivityState, LbPolicy, LocalTransport, OptTarget, RawChannelArgs,
+    SubchannelState, WaitForConnected,
 };
-pub use crate::client::Client;
+pub use crate::channel_pool::{ChannelPool, ChannelPoolBuilder, PoolPolicy};
+pub use crate::client::{Client, RetryPolicy, RetryUnaryReceiver};
 
 #[cfg(feature = "protobuf-codec")]
 pub use crate::codec::pb_codec::{de as pb_de, ser as pb_ser};
 #[cfg(feature = "prost-codec")]
 pub use crate::codec::pr_codec::{de as pr_de, ser as pr_ser};
 
+pub use crate::codec::raw_codec::{de as raw_de, ser as raw_ser};
 pub use crate::codec::Marshaller;
 #[cfg(feature = "secure")]
 pub use crate::credentials::{
-    ChannelCredentials, ChannelCredentialsBuilder, ServerCredentials, ServerCredentialsBuilder,
+    AuthMetadataCallback, AuthMetadataContext, CallCredentials, CertificateConfig,
+    CertificateProvider, CertificateRequestType, ChannelCredentials, ChannelCredentialsBuilder,
+    MetadataCredentialsPlugin, ServerCredentials, ServerCredentialsBuilder,
 };
+pub use crate::diagnostics::{CallDiagnostics, DiagnosticsConfig, Histogram, MethodDiagnostics};
 pub use crate::env::{EnvBuilder, Environment};
-pub use crate::error::{Error, Result};
-pub use crate::log_util::redirect_log;
+pub use crate::error::{CallError, Error, Result};
+#[cfg(feature = "fault-injection")]
+pub use crate::fault::{FaultInjector, FaultInjectorBuilder};
+pub use crate::grpc_web::{GrpcWebServer, GrpcWebServerBuilder};
+pub use crate::host_pool::{HostPick, HostPool, HostPoolBuilder, HostStats, PooledChannel};
+pub use crate::io_util::{
+    copy_to_async_writer, copy_to_writer, read_chunks, CopyToWriter, ReadChunks,
+};
+pub use crate::load_report::LoadReport;
+pub use crate::log_util::{redirect_log, set_tracer_enabled};
 pub use crate::metadata::{Metadata, MetadataBuilder, MetadataIter};
-pub use crate::server::{Server, ServerBuilder, Service, ServiceBuilder, ShutdownFuture};
+pub use crate::quota::ResourceQuota;
+pub use crate::resolver::{
+    register_resolver, FixedTtlResolverCache, Resolution, Resolver, ResolverCacheStats,
+};
+pub use crate::response_cache::{ClientCache, ResponseCache, ResponseCacheStats};
+pub use crate::server::{
+    JoinTasks, ListenAddr, Server, ServerBuilder, Service, ServiceBuilder, ServiceSet,
+    ShutdownFuture,
+};
+#[cfg(feature = "prometheus")]
+pub use crate::stats::PrometheusStats;
+pub use crate::stats::{CallEnd, CallInfo, CallSide, NoopStatsHandler, StatsHandler};
+pub use crate::task::{interval, sleep, Delay, Interval};
+pub use crate::trace::TraceContext;
+#[cfg(feature = "http-json")]
+pub use crate::transcoding::{HttpJsonGateway, HttpJsonGatewayBuilder};
+
+/// The types needed by most clients and servers.
+///
+/// ```
+/// use grpcio::prelude::*;
+/// ```
+pub mod prelude {
+    pub use crate::{
+        CallOption, Channel, ChannelBuilder, ClientCStreamReceiver, ClientCStreamSender,
+        ClientDuplexReceiver, ClientDuplexSender, ClientSStreamReceiver, ClientStreamingSink,
+        ClientUnaryReceiver, DuplexSink, EnvBuilder, Environment, Error, Marshaller, Metadata,
+        MetadataBuilder, Method, MethodType, RequestStream, Result, RpcContext, RpcStatus,
+        RpcStatusCode, Server, ServerBuilder, ServerStreamingSink, Service, ServiceBuilder,
+        UnarySink, WriteFlags,
+    };
+}
+
+/// The building blocks that gRPC Core is driven with, for embedders that
+/// need to poll completion queues or issue batches by themselves.
+///
+/// Unlike the rest of the crate, the items here are not covered by semver
+/// and may change in any release, as they mirror the internals of gRPC Core.
+pub mod low_level {
+    pub use crate::call::BatchContext;
+    pub use crate::cq::{CompletionQueue, CompletionQueueRef, Event, EventType};
+    pub use grpcio_sys as sys;
+
+    use crate::task::CallTag;
+
+    /// Resolve the tag of a completed event, which wakes up the future or
+    /// runs the callback waiting for it.
+    ///
+    /// # Safety
+    ///
+    /// The event must be a `GRPC_OP_COMPLETE` event whose tag is created by
+    /// this crate, and it must be resolved only once.
+    pub unsafe fn resolve_event(cq: &CompletionQueue, event: Event) {
+        let tag: Box<CallTag> = Box::from_raw(event.tag as _);
+        tag.resolve(cq, event.success != 0);
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ORCA load reports that servers attach to the trailers of calls, so that
+//! clients can weight backends by their load.
+//!
+//! A report is encoded as a `xds.data.orca.v3.OrcaLoadReport` message.
+
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+
+/// The trailer that carries the load report.
+pub const LOAD_REPORT_KEY: &str = "endpoint-load-metrics-bin";
+
+/// The backend metrics of a call, see `RpcContext::set_load_report` and
+/// [`LoadReport::from_trailers`].
+///
+/// Utilizations are usually in `[0, 1]`, zero values are not sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadReport {
+    pub cpu_utilization: f64,
+    pub mem_utilization: f64,
+    /// The utilization defined by the application, which is preferred to the
+    /// CPU utilization by weighted round robin.
+    pub application_utilization: f64,
+    /// Queries per second.
+    pub rps_fractional: f64,
+    /// Errors per second.
+    pub eps: f64,
+    /// Costs of the call, like the count of database rows scanned.
+    pub request_cost: BTreeMap<String, f64>,
+    /// Utilizations of other resources.
+    pub utilization: BTreeMap<String, f64>,
+    /// Other metrics of the application.
+    pub named_metrics: BTreeMap<String, f64>,
+}
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn write_double(buf: &mut Vec<u8>, number: u32, v: f64) {
+    if v != 0.0 {
+        write_varint(buf, u64::from(number << 3 | u32::from(WIRE_FIXED64)));
+        buf.extend_from_slice(&v.to_bits().to_le_bytes());
+    }
+}
+
+fn write_map(buf: &mut Vec<u8>, number: u32, map: &BTreeMap<String, f64>) {
+    for (key, value) in map {
+        let mut entry = Vec::with_capacity(key.len() + 11);
+        entry.push(1 << 3 | WIRE_LEN);
+        write_varint(&mut entry, key.len() as u64);
+        entry.extend_from_slice(key.as_bytes());
+        write_double(&mut entry, 2, *value);
+        write_varint(buf, u64::from(number << 3 | u32::from(WIRE_LEN)));
+        write_varint(buf, entry.len() as u64);
+        buf.extend_from_slice(&entry);
+    }
+}
+
+fn invalid() -> Error {
+    Error::InvalidMetadata("invalid load report".to_owned())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut v = 0;
+        for (i, b) in self.data.iter().enumerate().take(10) {
+            v |= u64::from(b & 0x7f) << (i * 7);
+            if b & 0x80 == 0 {
+                self.data = &self.data[i + 1..];
+                return Ok(v);
+            }
+        }
+        Err(invalid())
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Read the next field, returns its number, wire type and the raw value
+    /// of non-varint fields.
+    fn read_field(&mut self) -> Result<Option<(u64, u8, &'a [u8])>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.read_varint()?;
+        let wire_type = (tag & 0x7) as u8;
+        let value = match wire_type {
+            WIRE_VARINT => {
+                self.read_varint()?;
+                &[][..]
+            }
+            WIRE_FIXED64 => self.read_bytes(8)?,
+            WIRE_LEN => {
+                let len = self.read_varint()?;
+                self.read_bytes(len as usize)?
+            }
+            WIRE_FIXED32 => self.read_bytes(4)?,
+            _ => return Err(invalid()),
+        };
+        Ok(Some((tag >> 3, wire_type, value)))
+    }
+}
+
+fn read_double(wire_type: u8, value: &[u8]) -> Result<f64> {
+    if wire_type != WIRE_FIXED64 {
+        return Err(invalid());
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(value);
+    Ok(f64::from_bits(u64::from_le_bytes(bytes)))
+}
+
+fn read_entry(map: &mut BTreeMap<String, f64>, wire_type: u8, value: &[u8]) -> Result<()> {
+    if wire_type != WIRE_LEN {
+        return Err(invalid());
+    }
+    let (mut key, mut v) = (String::new(), 0.0);
+    let mut reader = Reader { data: value };
+    while let Some((number, wire_type, value)) = reader.read_field()? {
+        match (number, wire_type) {
+            (1, WIRE_LEN) => key = String::from_utf8(value.to_vec()).map_err(|_| invalid())?,
+            (2, _) => v = read_double(wire_type, value)?,
+            _ => {}
+        }
+    }
+    map.insert(key, v);
+    Ok(())
+}
+
+impl LoadReport {
+    /// Encode the report as an `OrcaLoadReport` message.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        write_double(&mut buf, 1, self.cpu_utilization);
+        write_double(&mut buf, 2, self.mem_utilization);
+        write_map(&mut buf, 4, &self.request_cost);
+        write_map(&mut buf, 5, &self.utilization);
+        write_double(&mut buf, 6, self.rps_fractional);
+        write_double(&mut buf, 7, self.eps);
+        write_map(&mut buf, 8, &self.named_metrics);
+        write_double(&mut buf, 9, self.application_utilization);
+        buf
+    }
+
+    /// Decode an `OrcaLoadReport` message, unknown fields are ignored.
+    pub fn decode(data: &[u8]) -> Result<LoadReport> {
+        let mut report = LoadReport::default();
+        let mut reader = Reader { data };
+        while let Some((number, wire_type, value)) = reader.read_field()? {
+            match number {
+                1 => report.cpu_utilization = read_double(wire_type, value)?,
+                2 => report.mem_utilization = read_double(wire_type, value)?,
+                4 => read_entry(&mut report.request_cost, wire_type, value)?,
+                5 => read_entry(&mut report.utilization, wire_type, value)?,
+                6 => report.rps_fractional = read_double(wire_type, value)?,
+                7 => report.eps = read_double(wire_type, value)?,
+                8 => read_entry(&mut report.named_metrics, wire_type, value)?,
+                9 => report.application_utilization = read_double(wire_type, value)?,
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+
+    /// Get the report from the trailers received by a client, `None` is
+    /// returned if the server doesn't report its load.
+    pub fn from_trailers(trailers: &Metadata) -> Result<Option<LoadReport>> {
+        trailers
+            .find(LOAD_REPORT_KEY)
+            .map(LoadReport::decode)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_report() {
+        assert_eq!(LoadReport::default().encode(), vec![]);
+        assert_eq!(LoadReport::decode(&[]).unwrap(), LoadReport::default());
+
+        let mut report = LoadReport {
+            cpu_utilization: 0.5,
+            mem_utilization: 0.25,
+            application_utilization: 0.75,
+            rps_fractional: 100.0,
+            eps: 1.5,
+            ..LoadReport::default()
+        };
+        report.request_cost.insert("rows".to_owned(), 42.0);
+        report.utilization.insert("disk".to_owned(), 0.1);
+        report.named_metrics.insert("queue".to_owned(), 0.0);
+        let mut data = report.encode();
+        assert_eq!(LoadReport::decode(&data).unwrap(), report);
+
+        // The deprecated rps and other unknown fields are skipped.
+        data.extend_from_slice(&[3 << 3, 0x96, 0x01, 10 << 3 | 5, 0, 0, 0, 0]);
+        assert_eq!(LoadReport::decode(&data).unwrap(), report);
+
+        for data in &[
+            &[1 << 3 | 1, 0][..],
+            &[1 << 3, 0],
+            &[4 << 3 | 2, 2, 1 << 3 | 2],
+            &[0x80],
+            &[3 << 3 | 7],
+        ] {
+            assert!(LoadReport::decode(data).is_err(), "{:?}", data);
+        }
+    }
+}
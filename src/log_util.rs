@@ -11,10 +11,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::path::Path;
 
 use crate::grpc_sys::{self, gpr_log_func_args, gpr_log_severity};
-use log::{self, Level, LevelFilter, Record};
+use log::{self, Level, LevelFilter, Metadata, Record};
 
 #[inline]
 fn severity_to_log_level(severity: gpr_log_severity) -> Level {
@@ -25,22 +26,46 @@ fn severity_to_log_level(severity: gpr_log_severity) -> Level {
     }
 }
 
+/// Get the log target of a source file of gRPC Core.
+///
+/// For example, logs of `src/core/ext/transport/chttp2/transport/writing.cc`
+/// use `grpcio::core::ext::transport::chttp2::transport::writing`.
+fn file_to_target(file: &str) -> String {
+    let relative = match file.rfind("src/core/") {
+        Some(pos) => &file[pos + "src/core/".len()..],
+        None => file.rsplit('/').next().unwrap(),
+    };
+    let path = Path::new(relative).with_extension("");
+    let mut target = "grpcio::core".to_owned();
+    for c in path.iter() {
+        target.push_str("::");
+        target.push_str(&c.to_string_lossy());
+    }
+    target
+}
+
 extern "C" fn delegate(c_args: *mut gpr_log_func_args) {
     let args = unsafe { &*c_args };
     let level = severity_to_log_level(args.severity);
-    if !log_enabled!(level) {
+    if level > log::max_level() {
         return;
     }
 
     // can't panic.
     let file_str = unsafe { CStr::from_ptr(args.file).to_str().unwrap() };
+    let target = file_to_target(file_str);
+    let logger = log::logger();
+    if !logger.enabled(&Metadata::builder().level(level).target(&target).build()) {
+        return;
+    }
     let line = args.line as u32;
 
     let msg = unsafe { CStr::from_ptr(args.message).to_string_lossy() };
-    log::logger().log(
+    logger.log(
         &Record::builder()
             .args(format_args!("{}", msg))
             .level(level)
+            .target(&target)
             .file(file_str.into())
             .line(line.into())
             .module_path(module_path!().into())
@@ -49,6 +74,14 @@ extern "C" fn delegate(c_args: *mut gpr_log_func_args) {
 }
 
 /// Redirect grpc log to rust's log implementation.
+///
+/// Logs of gRPC Core are logged with the target derived from the source file,
+/// see `set_tracer_enabled` for an example, so the verbosity of every
+/// component can be controlled by the filters of the logger. Subscribers of
+/// `tracing` can receive them through `tracing-log`.
+///
+/// The verbosity of gRPC Core is set according to `log::max_level()`, so it
+/// should be called again after the max level is changed.
 pub fn redirect_log() {
     let level = match log::max_level() {
         LevelFilter::Off => unsafe {
@@ -66,3 +99,50 @@ pub fn redirect_log() {
         grpc_sys::gpr_set_log_function(Some(delegate));
     }
 }
+
+/// Enable or disable a tracer of gRPC Core at runtime.
+///
+/// Tracers are the ones that can be enabled by the `GRPC_TRACE` environment
+/// variable, like `http`, `tcp`, `api` or `all`. Traces are logged at info
+/// level, for example, traces of `http` are mostly logged with the target
+/// `grpcio::core::ext::transport::chttp2::transport::chttp2_transport`.
+///
+/// Returns false if the tracer is unknown.
+pub fn set_tracer_enabled(name: &str, enabled: bool) -> bool {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    unsafe { grpc_sys::grpc_tracer_set_enabled(name.as_ptr(), enabled as _) != 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_to_target() {
+        let cases = [
+            (
+                "/build/grpc/src/core/ext/transport/chttp2/transport/writing.cc",
+                "grpcio::core::ext::transport::chttp2::transport::writing",
+            ),
+            (
+                "src/core/lib/iomgr/tcp_posix.cc",
+                "grpcio::core::lib::iomgr::tcp_posix",
+            ),
+            ("/usr/include/grpc/support/log.h", "grpcio::core::log"),
+        ];
+        for (file, target) in &cases {
+            assert_eq!(file_to_target(file), *target);
+        }
+    }
+
+    #[test]
+    fn test_set_tracer_enabled() {
+        assert!(set_tracer_enabled("http", true));
+        assert!(set_tracer_enabled("http", false));
+        assert!(!set_tracer_enabled("no_such_tracer", true));
+        assert!(!set_tracer_enabled("http\0", true));
+    }
+}
@@ -13,10 +13,13 @@
 
 use crate::grpc_sys::{self, grpc_metadata_array};
 use std::borrow::Cow;
-use std::{mem, slice, str};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::{mem, result, slice, str};
 
 use libc;
 
+use crate::call::{RpcStatus, RpcStatusCode};
 use crate::error::{Error, Result};
 
 fn normalize_key(key: &str, binary: bool) -> Result<Cow<'_, str>> {
@@ -47,12 +50,12 @@ fn normalize_key(key: &str, binary: bool) -> Result<Cow<'_, str>> {
         Cow::Borrowed(key)
     };
     if binary {
-        if !key.as_bytes().ends_with(b"-bin") {
+        if !is_binary_key(&key) {
             return Err(Error::InvalidMetadata(
                 "binary key should end with '-bin'".to_owned(),
             ));
         }
-    } else if key.as_bytes().ends_with(b"-bin") {
+    } else if is_binary_key(&key) {
         return Err(Error::InvalidMetadata(
             "non-binary key should not end with '-bin'".to_owned(),
         ));
@@ -60,6 +63,70 @@ fn normalize_key(key: &str, binary: bool) -> Result<Cow<'_, str>> {
     Ok(key)
 }
 
+/// Encode the bytes in padded base64.
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut res = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let mut acc = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            acc |= u32::from(*b) << (16 - 8 * i);
+        }
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(char::from(TABLE[(acc >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+/// Decode a base64 encoded string, both padded and unpadded input is accepted.
+///
+/// Either the standard or the URL safe alphabet can be used, but not both in
+/// the same string. Padding, if any, must complete the last quantum.
+pub(crate) fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    fn value(c: u8, url_safe: &mut Option<bool>) -> Option<u32> {
+        let (v, url) = match c {
+            b'A'..=b'Z' => return Some(u32::from(c - b'A')),
+            b'a'..=b'z' => return Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => return Some(u32::from(c - b'0') + 52),
+            b'+' => (62, false),
+            b'-' => (62, true),
+            b'/' => (63, false),
+            b'_' => (63, true),
+            _ => return None,
+        };
+        if *url_safe.get_or_insert(url) == url {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    let invalid = || Error::InvalidMetadata(format!("{:?} is not valid base64", encoded));
+    let input = encoded.trim_end_matches('=').as_bytes();
+    let padding = encoded.len() - input.len();
+    if input.len() % 4 == 1 || padding > 2 || (padding > 0 && padding != 4 - input.len() % 4) {
+        return Err(invalid());
+    }
+    let mut url_safe = None;
+    let mut res = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut acc = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            let v = value(*c, &mut url_safe).ok_or_else(invalid)?;
+            acc |= v << (18 - 6 * i);
+        }
+        let bytes = [(acc >> 16) as u8, (acc >> 8) as u8, acc as u8];
+        res.extend_from_slice(&bytes[..chunk.len() - 1]);
+    }
+    Ok(res)
+}
+
 /// Builder for immutable Metadata.
 pub struct MetadataBuilder {
     arr: Metadata,
@@ -98,7 +165,7 @@ impl MetadataBuilder {
         self.add_metadata(&key, value.as_bytes())
     }
 
-    fn add_metadata(&mut self, key: &str, value: &[u8]) -> Result<&mut MetadataBuilder> {
+    pub(crate) fn add_metadata(&mut self, key: &str, value: &[u8]) -> Result<&mut MetadataBuilder> {
         unsafe {
             grpc_sys::grpcwrap_metadata_array_add(
                 &mut self.arr.0,
@@ -119,6 +186,17 @@ impl MetadataBuilder {
         self.add_metadata(&key, value)
     }
 
+    /// Add a metadata holding a binary value which is encoded in base64.
+    ///
+    /// gRPC encodes binary values on the wire automatically, so the value is
+    /// decoded before being added. It's useful when the value comes from a
+    /// text configuration. `key` needs to have suffix (-bin).
+    pub fn add_base64(&mut self, key: &str, encoded: &str) -> Result<&mut MetadataBuilder> {
+        let key = normalize_key(key, true)?;
+        let value = decode_base64(encoded)?;
+        self.add_metadata(&key, &value)
+    }
+
     /// Create `Metadata` with configured entries.
     pub fn build(mut self) -> Metadata {
         unsafe {
@@ -197,6 +275,78 @@ impl Metadata {
             index: 0,
         }
     }
+
+    /// Returns an iterator over the entries holding ASCII values.
+    ///
+    /// Entries whose value is not valid UTF-8 are skipped.
+    pub fn ascii_entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter()
+            .filter(|(k, _)| !is_binary_key(k))
+            .filter_map(|(k, v)| str::from_utf8(v).ok().map(|v| (k, v)))
+    }
+
+    /// Returns an iterator over the entries holding binary values, namely
+    /// the keys of which end with "-bin".
+    pub fn binary_entries(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.iter().filter(|(k, _)| is_binary_key(k))
+    }
+
+    /// Returns the value of the first entry with the given key.
+    ///
+    /// The key is matched case insensitively.
+    pub fn find(&self, key: &str) -> Option<&[u8]> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Returns the value of the given key as a string.
+    ///
+    /// An `INVALID_ARGUMENT` status is returned if the key is missing or the
+    /// value is not valid UTF-8, so it can be replied to the client directly.
+    pub fn get_str(&self, key: &str) -> result::Result<&str, RpcStatus> {
+        let value = self
+            .find(key)
+            .ok_or_else(|| invalid_argument(format!("metadata {:?} is required", key)))?;
+        str::from_utf8(value)
+            .map_err(|_| invalid_argument(format!("metadata {:?} is not valid UTF-8", key)))
+    }
+
+    /// Parses the value of the given key.
+    ///
+    /// It works for all types implementing `FromStr`, like integers, UUIDs
+    /// or user defined enums. An `INVALID_ARGUMENT` status is returned if the
+    /// key is missing or the value can't be parsed.
+    pub fn get_parsed<T>(&self, key: &str) -> result::Result<T, RpcStatus>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get_str(key)?
+            .parse()
+            .map_err(|e| invalid_argument(format!("metadata {:?} is invalid: {}", key, e)))
+    }
+
+    /// Same as `get_parsed`, but `None` is returned if the key is missing.
+    pub fn get_parsed_opt<T>(&self, key: &str) -> result::Result<Option<T>, RpcStatus>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if self.find(key).is_none() {
+            return Ok(None);
+        }
+        self.get_parsed(key).map(Some)
+    }
+}
+
+fn invalid_argument(details: String) -> RpcStatus {
+    RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(details))
+}
+
+#[inline]
+fn is_binary_key(key: &str) -> bool {
+    key.as_bytes().ends_with(b"-bin")
 }
 
 impl Clone for Metadata {
@@ -210,6 +360,10 @@ impl Clone for Metadata {
     }
 }
 
+// Metadata owns all its entries, it's safe to move or share it between threads.
+unsafe impl Send for Metadata {}
+unsafe impl Sync for Metadata {}
+
 impl Drop for Metadata {
     fn drop(&mut self) {
         unsafe {
@@ -282,6 +436,95 @@ mod tests {
         builder.add_str("-", "value").unwrap();
         builder.add_str(".", "value").unwrap();
         builder.add_bytes("key-bin", b"value").unwrap();
+        // Base64 value should be valid and the key should end with '-bin'.
+        assert!(builder.add_base64("key", "dmFsdWU=").is_err());
+        assert!(builder.add_base64("key-bin", "dmFsd").is_err());
+        assert!(builder.add_base64("key-bin", "dm*sdWU=").is_err());
+        builder.add_base64("key-bin", "dmFsdWU=").unwrap();
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        let cases: &[(&str, &[u8])] = &[
+            ("", b""),
+            ("YQ", b"a"),
+            ("YQ==", b"a"),
+            ("YWI=", b"ab"),
+            ("YWJj", b"abc"),
+            ("AP8-_w", &[0x00, 0xff, 0x3e, 0xff]),
+            ("AP8+/w==", &[0x00, 0xff, 0x3e, 0xff]),
+        ];
+        for (encoded, exp) in cases {
+            assert_eq!(decode_base64(encoded).unwrap(), *exp, "{}", encoded);
+        }
+        for encoded in &["YQ===", "YQ=", "YWI==", "Y===", "====", "YQ==YQ=="] {
+            assert!(decode_base64(encoded).is_err(), "{}", encoded);
+        }
+        // Alphabets can't be mixed.
+        for encoded in &["AP8+_w==", "AP8-/w", "+-", "_/"] {
+            assert!(decode_base64(encoded).is_err(), "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn test_encode_base64() {
+        for (exp, data) in &[
+            ("", &b""[..]),
+            ("YQ==", b"a"),
+            ("YWI=", b"ab"),
+            ("YWJj", b"abc"),
+        ] {
+            assert_eq!(encode_base64(data), *exp);
+        }
+        let data = [0x00, 0xff, 0x3e, 0xff];
+        assert_eq!(encode_base64(&data), "AP8+/w==");
+        assert_eq!(decode_base64(&encode_base64(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_typed_extraction() {
+        let mut builder = MetadataBuilder::new();
+        builder
+            .add_str("x-tenant", "pingcap")
+            .unwrap()
+            .add_str("x-retry", "3")
+            .unwrap()
+            .add_bytes("x-raw-bin", &[0xff])
+            .unwrap();
+        let metadata = builder.build();
+        assert_eq!(metadata.find("X-Tenant"), Some(b"pingcap".as_ref()));
+        assert_eq!(metadata.get_str("x-tenant").unwrap(), "pingcap");
+        assert_eq!(metadata.get_parsed::<u32>("x-retry").unwrap(), 3);
+        assert_eq!(metadata.get_parsed_opt::<u32>("x-missing").unwrap(), None);
+        assert_eq!(metadata.get_parsed_opt::<u32>("x-retry").unwrap(), Some(3));
+
+        for res in &[
+            metadata.get_str("x-missing").map(|_| ()),
+            metadata.get_str("x-raw-bin").map(|_| ()),
+            metadata.get_parsed::<u32>("x-tenant").map(|_| ()),
+            metadata.get_parsed_opt::<u32>("x-tenant").map(|_| ()),
+        ] {
+            let status = res.as_ref().unwrap_err();
+            assert_eq!(status.status, RpcStatusCode::INVALID_ARGUMENT);
+            assert!(status.details.is_some());
+        }
+    }
+
+    #[test]
+    fn test_typed_entries() {
+        let mut builder = MetadataBuilder::new();
+        builder
+            .add_str("k1", "v1")
+            .unwrap()
+            .add_bytes("k2-bin", &[0xff, 0x00])
+            .unwrap()
+            .add_str("k3", "v3")
+            .unwrap();
+        let metadata = builder.build();
+        let ascii: Vec<_> = metadata.ascii_entries().collect();
+        assert_eq!(ascii, vec![("k1", "v1"), ("k3", "v3")]);
+        let binary: Vec<_> = metadata.binary_entries().collect();
+        assert_eq!(binary, vec![("k2-bin", [0xff, 0x00].as_ref())]);
     }
 
     #[test]
@@ -0,0 +1,133 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Explicit HTTP CONNECT proxy configuration of channels.
+//!
+//! gRPC Core only reads proxy settings from environment variables, but it
+//! tunnels connections through a proxy whenever the corresponding channel
+//! arguments are present, which is what is used here.
+
+use std::result;
+
+use crate::metadata::encode_base64;
+
+const DEFAULT_PROXY_PORT: u16 = 80;
+
+/// A parsed `http://[user:password@]host[:port]` proxy url.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpProxy {
+    /// The address of the proxy in the form of `host:port`.
+    pub addr: String,
+    /// The value of `Proxy-Authorization` header if credentials are given.
+    pub authorization: Option<String>,
+}
+
+impl HttpProxy {
+    pub fn parse(url: &str) -> result::Result<HttpProxy, String> {
+        let rest = match url.find("://") {
+            Some(pos) if &url[..pos] == "http" => &url[pos + 3..],
+            Some(_) => return Err(format!("unsupported proxy scheme in {:?}", url)),
+            None => url,
+        };
+        let rest = rest.trim_end_matches('/');
+        if rest.contains('/') {
+            return Err(format!("proxy url {:?} should not contain a path", url));
+        }
+        let (userinfo, host_port) = match rest.rfind('@') {
+            Some(pos) => (Some(&rest[..pos]), &rest[pos + 1..]),
+            None => (None, rest),
+        };
+        if host_port.is_empty() {
+            return Err(format!("proxy url {:?} has no host", url));
+        }
+        // A port is present if the last colon is not inside an IPv6 literal.
+        let addr = match host_port.rfind(':') {
+            Some(pos) if !host_port[pos..].contains(']') => {
+                if host_port[pos + 1..].parse::<u16>().is_err() {
+                    return Err(format!("invalid port in proxy url {:?}", url));
+                }
+                host_port.to_owned()
+            }
+            _ => format!("{}:{}", host_port, DEFAULT_PROXY_PORT),
+        };
+        let authorization = userinfo.map(|u| format!("Basic {}", encode_base64(u.as_bytes())));
+        Ok(HttpProxy {
+            addr,
+            authorization,
+        })
+    }
+}
+
+/// Check whether the host should be connected directly according to the
+/// `no_proxy` rules.
+///
+/// A rule matches the host itself and all its subdomains, a leading dot is
+/// ignored and `*` matches all hosts.
+pub fn is_excluded(no_proxy: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    no_proxy.iter().any(|rule| {
+        let rule = rule.trim().trim_start_matches('.');
+        if rule == "*" {
+            return true;
+        }
+        if rule.is_empty() || host.len() < rule.len() {
+            return false;
+        }
+        let (prefix, suffix) = host.split_at(host.len() - rule.len());
+        suffix.eq_ignore_ascii_case(rule) && (prefix.is_empty() || prefix.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proxy() {
+        let p = HttpProxy::parse("http://proxy.local:3128").unwrap();
+        assert_eq!(p.addr, "proxy.local:3128");
+        assert_eq!(p.authorization, None);
+        let p = HttpProxy::parse("proxy.local").unwrap();
+        assert_eq!(p.addr, "proxy.local:80");
+        let p = HttpProxy::parse("http://[::1]/").unwrap();
+        assert_eq!(p.addr, "[::1]:80");
+        let p = HttpProxy::parse("http://user:pass@[::1]:8080").unwrap();
+        assert_eq!(p.addr, "[::1]:8080");
+        assert_eq!(p.authorization, Some("Basic dXNlcjpwYXNz".to_owned()));
+
+        for url in &[
+            "https://proxy.local",
+            "http://proxy.local/path",
+            "http://user@",
+            "proxy.local:port",
+        ] {
+            assert!(HttpProxy::parse(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_no_proxy() {
+        let rules: Vec<_> = ["example.com", ".internal", "10.0.0.1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(is_excluded(&rules, "example.com"));
+        assert!(is_excluded(&rules, "api.Example.com"));
+        assert!(is_excluded(&rules, "svc.internal"));
+        assert!(is_excluded(&rules, "10.0.0.1"));
+        assert!(!is_excluded(&rules, "badexample.com"));
+        assert!(!is_excluded(&rules, "10.0.0.10"));
+        assert!(!is_excluded(&[], "example.com"));
+        assert!(is_excluded(&["*".to_owned()], "example.com"));
+    }
+}
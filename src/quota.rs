@@ -0,0 +1,100 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::ptr;
+
+use crate::grpc_sys::{self, grpc_arg_pointer_vtable, grpc_resource_quota};
+
+/// A quota of memory and threads that gRPC Core is allowed to use.
+///
+/// When the memory quota is exhausted, gRPC Core stops reading from
+/// connections until the buffered data is consumed, which bounds the memory
+/// used under load. A quota can be shared by servers and channels via
+/// `ServerBuilder::resource_quota` and `ChannelBuilder::resource_quota`, and
+/// resized at any time. Cloning a quota only increases the reference count.
+pub struct ResourceQuota {
+    raw: *mut grpc_resource_quota,
+}
+
+unsafe impl Send for ResourceQuota {}
+unsafe impl Sync for ResourceQuota {}
+
+impl ResourceQuota {
+    /// Create an unlimited quota, `name` is used in traces.
+    pub fn new(name: Option<&str>) -> ResourceQuota {
+        let name = name.map(|n| CString::new(n).unwrap());
+        let name_ptr = name.as_ref().map_or_else(ptr::null, |n| n.as_ptr());
+        let raw = unsafe { grpc_sys::grpc_resource_quota_create(name_ptr) };
+        ResourceQuota { raw }
+    }
+
+    /// Limit the memory to at most `bytes`.
+    pub fn resize_memory(self, bytes: usize) -> ResourceQuota {
+        self.resize(bytes);
+        self
+    }
+
+    /// Limit the threads created by gRPC Core to at most `threads`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is not positive.
+    pub fn max_threads(self, threads: i32) -> ResourceQuota {
+        self.set_max_threads(threads);
+        self
+    }
+
+    /// Update the memory limit of the quota.
+    ///
+    /// The change takes effect on all servers and channels sharing it.
+    pub fn resize(&self, bytes: usize) {
+        unsafe { grpc_sys::grpc_resource_quota_resize(self.raw, bytes) }
+    }
+
+    /// Update the thread limit of the quota.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is not positive.
+    pub fn set_max_threads(&self, threads: i32) {
+        assert!(
+            threads > 0,
+            "max threads should be positive, got {}",
+            threads
+        );
+        unsafe { grpc_sys::grpc_resource_quota_set_max_threads(self.raw, threads as c_int) }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut grpc_resource_quota {
+        self.raw
+    }
+
+    pub(crate) fn arg_vtable() -> *const grpc_arg_pointer_vtable {
+        unsafe { grpc_sys::grpc_resource_quota_arg_vtable() }
+    }
+}
+
+impl Clone for ResourceQuota {
+    fn clone(&self) -> ResourceQuota {
+        unsafe { grpc_sys::grpc_resource_quota_ref(self.raw) }
+        ResourceQuota { raw: self.raw }
+    }
+}
+
+impl Drop for ResourceQuota {
+    fn drop(&mut self) {
+        unsafe { grpc_sys::grpc_resource_quota_unref(self.raw) }
+    }
+}
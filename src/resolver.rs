@@ -0,0 +1,501 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A cache for resolved addresses that can be shared by channels, and
+//! resolvers of custom schemes.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+use std::{ptr, thread};
+
+type Lookup = dyn Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync;
+
+fn system_lookup(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    (host, port).to_socket_addrs().map(Iterator::collect)
+}
+
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    refreshing: bool,
+}
+
+struct Inner {
+    ttl: Duration,
+    max_stale: Duration,
+    lookup: Box<Lookup>,
+    entries: Mutex<HashMap<(String, u16), Entry>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    stale_hits: AtomicUsize,
+}
+
+impl Inner {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let addrs = (self.lookup)(host, port)?;
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address is found for {}", host),
+            ));
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            (host.to_owned(), port),
+            Entry {
+                addrs: addrs.clone(),
+                resolved_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+        Ok(addrs)
+    }
+
+    fn refresh(inner: Arc<Inner>, host: String, port: u16) {
+        let spawned = thread::Builder::new()
+            .name("grpc-resolver".to_owned())
+            .spawn({
+                let inner = inner.clone();
+                let host = host.clone();
+                move || {
+                    if let Err(e) = inner.resolve(&host, port) {
+                        warn!("failed to refresh address of {}: {:?}", host, e);
+                        inner.finish_refresh(&host, port);
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("failed to spawn resolver thread: {:?}", e);
+            inner.finish_refresh(&host, port);
+        }
+    }
+
+    fn finish_refresh(&self, host: &str, port: u16) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(e) = entries.get_mut(&(host.to_owned(), port)) {
+            e.refreshing = false;
+        }
+    }
+}
+
+/// Statistics of a [`FixedTtlResolverCache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ResolverCacheStats {
+    /// Count of lookups served by fresh entries.
+    pub hits: usize,
+    /// Count of lookups that had to wait for resolution.
+    pub misses: usize,
+    /// Count of lookups served by expired entries while being refreshed.
+    pub stale_hits: usize,
+}
+
+/// A cache of resolved addresses with a fixed TTL.
+///
+/// Entries expire after the TTL given to the cache. It is not the TTL of the
+/// DNS records: the system resolver doesn't report them, so the TTL should be
+/// chosen to be no longer than the records are expected to be valid. Expired
+/// entries are still served for at most `max_stale` while they are refreshed
+/// in background, so callers don't wait for resolution as long as the name
+/// is used frequently enough.
+///
+/// The cache can be cloned cheaply and shared by channels via
+/// `ChannelBuilder::resolver_cache`.
+#[derive(Clone)]
+pub struct FixedTtlResolverCache {
+    inner: Arc<Inner>,
+}
+
+impl FixedTtlResolverCache {
+    /// Create a cache that uses the system resolver, whose entries expire
+    /// after `ttl`.
+    pub fn new(ttl: Duration, max_stale: Duration) -> FixedTtlResolverCache {
+        FixedTtlResolverCache::with_lookup(ttl, max_stale, system_lookup)
+    }
+
+    fn with_lookup<F>(ttl: Duration, max_stale: Duration, lookup: F) -> FixedTtlResolverCache
+    where
+        F: Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        FixedTtlResolverCache {
+            inner: Arc::new(Inner {
+                ttl,
+                max_stale,
+                lookup: Box::new(lookup),
+                entries: Mutex::new(HashMap::new()),
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+                stale_hits: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Get the cached addresses of the host, expired entries are refreshed
+    /// in background. If `background` is true, missing entries are resolved
+    /// in background too instead of being left to the caller.
+    ///
+    /// Entries without addresses are being resolved for the first time.
+    fn cached(&self, host: &str, port: u16, background: bool) -> Option<Vec<SocketAddr>> {
+        let key = (host.to_owned(), port);
+        let mut entries = self.inner.entries.lock().unwrap();
+        let e = match entries.get_mut(&key) {
+            Some(e) => e,
+            None if background => {
+                let e = Entry {
+                    addrs: vec![],
+                    resolved_at: Instant::now(),
+                    refreshing: true,
+                };
+                entries.insert(key.clone(), e);
+                drop(entries);
+                Inner::refresh(self.inner.clone(), key.0, port);
+                return None;
+            }
+            None => return None,
+        };
+        let age = Instant::now().duration_since(e.resolved_at);
+        if !e.addrs.is_empty() && age < self.inner.ttl {
+            self.inner.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(e.addrs.clone());
+        }
+        let stale = !e.addrs.is_empty() && age < self.inner.ttl + self.inner.max_stale;
+        if !stale && !background {
+            return None;
+        }
+        let addrs = if stale {
+            self.inner.stale_hits.fetch_add(1, Ordering::Relaxed);
+            Some(e.addrs.clone())
+        } else {
+            None
+        };
+        if !e.refreshing {
+            e.refreshing = true;
+            drop(entries);
+            Inner::refresh(self.inner.clone(), key.0, port);
+        }
+        addrs
+    }
+
+    /// Resolve the host to addresses, it blocks on a miss until the host is
+    /// resolved.
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        if let Some(addrs) = self.cached(host, port, false) {
+            return Ok(addrs);
+        }
+        self.inner.misses.fetch_add(1, Ordering::Relaxed);
+        self.inner.resolve(host, port)
+    }
+
+    /// Resolve the address in the form of `host:port` to a gRPC target that
+    /// can be connected directly, like `ipv4:127.0.0.1:8080`.
+    ///
+    /// If both IPv4 and IPv6 addresses are found, only IPv4 addresses are
+    /// used as gRPC Core doesn't allow mixing them in one target.
+    pub fn resolve_target(&self, addr: &str) -> io::Result<String> {
+        let (host, port) = split_host_port(addr).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid address {}", addr),
+            )
+        })?;
+        let addrs = self.resolve(host, port)?;
+        Ok(to_target(&addrs))
+    }
+
+    /// Get the target of the cached addresses of `host:port` without
+    /// blocking, see [`FixedTtlResolverCache::resolve_target`].
+    ///
+    /// `None` is returned on a miss, and the address is resolved in
+    /// background so that it's cached for later lookups. It's also returned
+    /// for IP addresses and addresses of other schemes, which need no
+    /// resolution.
+    pub(crate) fn cached_target(&self, addr: &str) -> Option<String> {
+        let (host, port) = split_host_port(addr)?;
+        if host.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        let addrs = self.cached(host, port, true);
+        if addrs.is_none() {
+            self.inner.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        addrs.map(|addrs| to_target(&addrs))
+    }
+
+    /// Remove the cached addresses of the host.
+    pub fn invalidate(&self, host: &str) {
+        let mut entries = self.inner.entries.lock().unwrap();
+        entries.retain(|(h, _), _| h != host);
+    }
+
+    /// Get the statistics of the cache.
+    pub fn stats(&self) -> ResolverCacheStats {
+        ResolverCacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            stale_hits: self.inner.stale_hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Convert addresses to a gRPC target that can be connected directly.
+///
+/// If both IPv4 and IPv6 addresses are given, only IPv4 addresses are used
+/// as gRPC Core doesn't allow mixing them in one target.
+pub(crate) fn to_target(addrs: &[SocketAddr]) -> String {
+    let v4: Vec<_> = addrs.iter().filter(|a| a.is_ipv4()).collect();
+    let (scheme, addrs) = if v4.is_empty() {
+        ("ipv6", addrs.iter().collect())
+    } else {
+        ("ipv4", v4)
+    };
+    let addrs: Vec<_> = addrs.into_iter().map(ToString::to_string).collect();
+    format!("{}:{}", scheme, addrs.join(","))
+}
+
+/// The addresses and the service config resolved from a target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution {
+    pub addrs: Vec<SocketAddr>,
+    /// The [service config] in JSON, like
+    /// `{"loadBalancingPolicy":"round_robin"}`.
+    ///
+    /// [service config]: https://github.com/grpc/grpc/blob/master/doc/service_config.md
+    pub service_config: Option<String>,
+}
+
+/// A resolver of the targets of a custom scheme, see [`register_resolver`].
+pub trait Resolver: Send + Sync {
+    /// Resolve the target, which is the part after `scheme://`, for example
+    /// `my-service` of `consul://my-service`.
+    fn resolve(&self, target: &str) -> io::Result<Resolution>;
+}
+
+type Registry = Mutex<HashMap<String, Arc<dyn Resolver>>>;
+
+fn registry() -> &'static Registry {
+    static INIT: Once = Once::new();
+    static mut REGISTRY: *const Registry = ptr::null();
+
+    INIT.call_once(|| unsafe {
+        REGISTRY = Box::into_raw(Box::new(Mutex::new(HashMap::new())));
+    });
+    unsafe { &*REGISTRY }
+}
+
+/// Register the resolver for the targets of the scheme, like
+/// `consul://my-service` for the scheme `consul`.
+///
+/// It replaces the resolver registered for the scheme before. gRPC Core
+/// doesn't expose its resolver API in C, so a target is resolved once when a
+/// channel is created, and the channel connects to the resolved addresses
+/// directly. Channels need to be recreated to pick up changes of addresses.
+/// If the resolution fails, the channel is created with the original target,
+/// which fails all calls.
+///
+/// The bundled gRPC Core predates its xDS support, so `xds:` targets are not
+/// resolved unless a resolver is registered for the scheme here, and
+/// xDS-provided security configuration is not available.
+pub fn register_resolver<R: Resolver + 'static>(scheme: &str, resolver: R) {
+    let mut resolvers = registry().lock().unwrap();
+    resolvers.insert(scheme.to_owned(), Arc::new(resolver));
+}
+
+/// Resolve the target with the registered resolver of its scheme.
+///
+/// The name in the target is returned along with the resolution. `None` is
+/// returned if no resolver is registered for the scheme.
+pub(crate) fn resolve_with_registered(addr: &str) -> Option<(&str, io::Result<Resolution>)> {
+    let pos = addr.find("://")?;
+    let resolver = registry().lock().unwrap().get(&addr[..pos])?.clone();
+    let name = &addr[pos + 3..];
+    let res = resolver.resolve(name).and_then(|r| {
+        if r.addrs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address is found for {}", addr),
+            ))
+        } else {
+            Ok(r)
+        }
+    });
+    Some((name, res))
+}
+
+/// Strip the `dns:` scheme of the address if any.
+pub(crate) fn strip_dns_scheme(addr: &str) -> &str {
+    addr.trim_start_matches("dns:///")
+        .trim_start_matches("dns:")
+}
+
+/// Split `host:port`, `dns:host:port` or `[ipv6]:port` into host and port.
+///
+/// `None` is returned for addresses of other schemes like `unix:`.
+pub(crate) fn split_host_port(addr: &str) -> Option<(&str, u16)> {
+    let addr = strip_dns_scheme(addr);
+    let pos = addr.rfind(':')?;
+    let port = addr[pos + 1..].parse().ok()?;
+    let host = &addr[..pos];
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else if host.contains(':') {
+        return None;
+    } else {
+        host
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("localhost:80"), Some(("localhost", 80)));
+        assert_eq!(split_host_port("dns:localhost:80"), Some(("localhost", 80)));
+        assert_eq!(
+            split_host_port("dns:///localhost:80"),
+            Some(("localhost", 80))
+        );
+        assert_eq!(split_host_port("[::1]:80"), Some(("::1", 80)));
+        assert_eq!(split_host_port("localhost"), None);
+        assert_eq!(split_host_port(":80"), None);
+        assert_eq!(split_host_port("ipv4:127.0.0.1:80"), None);
+        assert_eq!(split_host_port("unix:/tmp/grpc.sock"), None);
+    }
+
+    #[test]
+    fn test_registered_resolver() {
+        struct StaticResolver;
+
+        impl Resolver for StaticResolver {
+            fn resolve(&self, target: &str) -> io::Result<Resolution> {
+                let addrs = match target {
+                    "empty" => vec![],
+                    "mixed" => vec!["[::1]:80".parse().unwrap(), "10.0.0.1:80".parse().unwrap()],
+                    _ => return Err(io::Error::new(io::ErrorKind::Other, "unknown")),
+                };
+                Ok(Resolution {
+                    addrs,
+                    service_config: Some("{}".to_owned()),
+                })
+            }
+        }
+
+        register_resolver("test-static", StaticResolver);
+        let (name, res) = resolve_with_registered("test-static://mixed").unwrap();
+        assert_eq!(name, "mixed");
+        let res = res.unwrap();
+        assert_eq!(to_target(&res.addrs), "ipv4:10.0.0.1:80");
+        assert_eq!(res.service_config.unwrap(), "{}");
+        assert_eq!(to_target(&res.addrs[..1]), "ipv6:[::1]:80");
+
+        let (_, res) = resolve_with_registered("test-static://empty").unwrap();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let (_, res) = resolve_with_registered("test-static://other").unwrap();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::Other);
+        assert!(resolve_with_registered("test-unknown://mixed").is_none());
+        assert!(resolve_with_registered("127.0.0.1:80").is_none());
+    }
+
+    #[test]
+    fn test_resolver_cache() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let l = lookups.clone();
+        let cache = FixedTtlResolverCache::with_lookup(
+            Duration::from_millis(100),
+            Duration::from_secs(10),
+            move |_, port| {
+                let n = l.fetch_add(1, Ordering::SeqCst) as u8;
+                Ok(vec![SocketAddr::new([10, 0, 0, n].into(), port)])
+            },
+        );
+
+        let target = cache.resolve_target("example.com:80").unwrap();
+        assert_eq!(target, "ipv4:10.0.0.0:80");
+        assert_eq!(cache.resolve_target("example.com:80").unwrap(), target);
+        assert_eq!(
+            cache.resolve_target("127.0.0.1:80").unwrap(),
+            "ipv4:127.0.0.1:80"
+        );
+        assert_eq!(cache.resolve_target("[::1]:80").unwrap(), "ipv6:[::1]:80");
+        assert_eq!(
+            cache.stats(),
+            ResolverCacheStats {
+                hits: 1,
+                misses: 1,
+                stale_hits: 0,
+            }
+        );
+
+        // Stale entry is served while being refreshed.
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(cache.resolve_target("example.com:80").unwrap(), target);
+        assert_eq!(cache.stats().stale_hits, 1);
+        let start = Instant::now();
+        while cache.resolve("example.com", 80).unwrap()[0].ip() != IpAddr::from([10, 0, 0, 1]) {
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        cache.invalidate("example.com");
+        cache.resolve("example.com", 80).unwrap();
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cached_target() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let l = lookups.clone();
+        let cache = FixedTtlResolverCache::with_lookup(
+            Duration::from_secs(10),
+            Duration::from_secs(10),
+            move |_, port| {
+                l.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(100));
+                Ok(vec![SocketAddr::new([10, 0, 0, 1].into(), port)])
+            },
+        );
+
+        assert_eq!(cache.cached_target("127.0.0.1:80"), None);
+        assert_eq!(cache.cached_target("unix:/tmp/grpc.sock"), None);
+        // Misses don't wait for the lookup, which is done in background.
+        let start = Instant::now();
+        assert_eq!(cache.cached_target("example.com:80"), None);
+        assert_eq!(cache.cached_target("example.com:80"), None);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        while cache.cached_target("example.com:80").is_none() {
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            cache.cached_target("example.com:80").unwrap(),
+            "ipv4:10.0.0.1:80"
+        );
+        // The pending lookup is shared.
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().hits, 2);
+    }
+}
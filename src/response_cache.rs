@@ -0,0 +1,334 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caching of unary responses on both servers and clients.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Future;
+
+use crate::call::server::{execute_unary, RpcContext, UnarySink};
+use crate::call::{MessageReader, MethodType, RpcStatus, RpcStatusCode};
+use crate::codec::raw_codec;
+use crate::metadata::Metadata;
+use crate::server::{BoxHandler, CloneableHandler};
+
+// Method name and the serialized request.
+type Key = (Vec<u8>, Vec<u8>);
+
+struct Entry {
+    resp: Arc<Vec<u8>>,
+    expire_at: Instant,
+}
+
+struct Inner {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<Key, Entry>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Inner {
+    fn get(&self, key: &Key) -> Option<Arc<Vec<u8>>> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.get(key) {
+            Some(e) if e.expire_at > Instant::now() => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(e.resp.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.remove(key);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    fn insert(&self, key: Key, resp: Vec<u8>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, e| e.expire_at > now);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            // All entries share the same TTL, so the one expiring first is
+            // the oldest.
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.expire_at)
+                .map(|(k, _)| k.clone());
+            if let Some(k) = oldest {
+                entries.remove(&k);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                resp: Arc::new(resp),
+                expire_at: now + self.ttl,
+            },
+        );
+    }
+}
+
+/// Statistics of a [`ResponseCache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ResponseCacheStats {
+    /// Count of requests served from the cache.
+    pub hits: usize,
+    /// Count of requests passed to handlers.
+    pub misses: usize,
+    /// Count of responses currently cached.
+    pub entries: usize,
+}
+
+/// A cache of serialized responses of unary methods.
+///
+/// Responses are keyed by the method and the serialized request, and expire
+/// after the TTL. Once `max_entries` is reached, the oldest entry is evicted.
+/// Only successful responses are cached, and metadata sent by handlers is not
+/// replayed for cached responses.
+///
+/// It should only be used for methods that are idempotent and whose results
+/// only depend on the request, see `Service::cache_responses`. It can also be
+/// used by clients as a [`ClientCache`], in which case misses count the calls
+/// sent to servers.
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Inner>,
+}
+
+impl ResponseCache {
+    /// Create a cache holding at most `max_entries` responses for `ttl`.
+    pub fn new(ttl: Duration, max_entries: usize) -> ResponseCache {
+        ResponseCache {
+            inner: Arc::new(Inner {
+                ttl,
+                max_entries,
+                entries: Mutex::new(HashMap::new()),
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Remove all the cached responses.
+    pub fn clear(&self) {
+        self.inner.entries.lock().unwrap().clear();
+    }
+
+    /// Get the statistics of the cache.
+    pub fn stats(&self) -> ResponseCacheStats {
+        ResponseCacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            entries: self.inner.entries.lock().unwrap().len(),
+        }
+    }
+}
+
+/// A cache of responses used by `Client::cacheable_unary_call`.
+///
+/// Both requests and responses are serialized, so a cache can be shared by
+/// clients of different services. [`ResponseCache`] is the default
+/// implementation that expires responses after a TTL.
+///
+/// The headers of the call, including the default ones of the client, are
+/// part of the key, since they may select a tenant or carry credentials that
+/// change the response.
+pub trait ClientCache: Send + Sync {
+    /// Get the cached response to the request of the method, like
+    /// `/helloworld.Greeter/SayHello`, sent with the headers.
+    fn get(&self, method: &str, headers: Option<&Metadata>, req: &[u8]) -> Option<Vec<u8>>;
+
+    /// Cache a successful response to the request of the method sent with
+    /// the headers.
+    fn insert(&self, method: &str, headers: Option<&Metadata>, req: &[u8], resp: Vec<u8>);
+}
+
+/// Encode the method along with the headers, which can't be confused with
+/// other methods or headers as neither methods nor header names contain NUL.
+fn client_key(method: &str, headers: Option<&Metadata>, req: &[u8]) -> Key {
+    let mut buf = method.as_bytes().to_vec();
+    for (k, v) in headers.into_iter().flatten() {
+        buf.push(0);
+        buf.extend_from_slice(k.as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&(v.len() as u32).to_be_bytes());
+        buf.extend_from_slice(v);
+    }
+    (buf, req.to_vec())
+}
+
+impl ClientCache for ResponseCache {
+    fn get(&self, method: &str, headers: Option<&Metadata>, req: &[u8]) -> Option<Vec<u8>> {
+        let key = client_key(method, headers, req);
+        self.inner.get(&key).map(|resp| resp.to_vec())
+    }
+
+    fn insert(&self, method: &str, headers: Option<&Metadata>, req: &[u8], resp: Vec<u8>) {
+        self.inner.insert(client_key(method, headers, req), resp)
+    }
+}
+
+/// A unary handler that consults the cache before calling the wrapped handler.
+pub(crate) struct CachedHandler {
+    method: Vec<u8>,
+    inner: BoxHandler,
+    cache: ResponseCache,
+}
+
+impl CachedHandler {
+    pub fn new(method: &[u8], inner: BoxHandler, cache: ResponseCache) -> CachedHandler {
+        CachedHandler {
+            method: method.to_vec(),
+            inner,
+            cache,
+        }
+    }
+
+    fn reply(ctx: RpcContext<'_>, req: MessageReader, resp: Arc<Vec<u8>>) {
+        execute_unary(
+            ctx,
+            raw_codec::ser,
+            raw_codec::de,
+            req,
+            &mut |ctx: RpcContext<'_>, _, sink: UnarySink<Vec<u8>>| {
+                let f = sink
+                    .success(resp.to_vec())
+                    .map_err(|e| error!("failed to reply cached response: {:?}", e));
+                ctx.spawn(f);
+            },
+        )
+    }
+}
+
+impl CloneableHandler for CachedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        let req = match reqs.map(raw_codec::de) {
+            Some(Ok(req)) => req,
+            res => {
+                let status = RpcStatus::new(
+                    RpcStatusCode::INTERNAL,
+                    Some(format!("Failed to read request message: {:?}", res)),
+                );
+                let mut call = ctx.call();
+                if call.start_server_side().is_ok() {
+                    call.abort(&status);
+                }
+                return;
+            }
+        };
+        let key = (self.method.clone(), req);
+        if let Some(resp) = self.cache.inner.get(&key) {
+            let reader = MessageReader::from_bytes(&key.1);
+            return CachedHandler::reply(ctx, reader, resp);
+        }
+
+        let reader = MessageReader::from_bytes(&key.1);
+        let cache = self.cache.clone();
+        ctx.set_response_hook(Box::new(move |resp| {
+            cache.inner.insert(key, resp.to_vec());
+        }));
+        self.inner.handle(ctx, Some(reader))
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(CachedHandler {
+            method: self.method.clone(),
+            inner: self.inner.box_clone(),
+            cache: self.cache.clone(),
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        MethodType::Unary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::MetadataBuilder;
+    use std::thread;
+
+    fn key(req: &[u8]) -> Key {
+        (b"/test/Get".to_vec(), req.to_vec())
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::new(Duration::from_millis(100), 2);
+        let inner = &cache.inner;
+        assert_eq!(inner.get(&key(b"a")), None);
+        inner.insert(key(b"a"), b"1".to_vec());
+        assert_eq!(inner.get(&key(b"a")).unwrap().as_slice(), b"1");
+        // Different methods don't share entries.
+        assert_eq!(inner.get(&(b"/test/List".to_vec(), b"a".to_vec())), None);
+
+        thread::sleep(Duration::from_millis(10));
+        inner.insert(key(b"b"), b"2".to_vec());
+        inner.insert(key(b"c"), b"3".to_vec());
+        // "a" is evicted as the oldest entry.
+        assert_eq!(inner.get(&key(b"a")), None);
+        assert!(inner.get(&key(b"b")).is_some());
+        assert_eq!(
+            cache.stats(),
+            ResponseCacheStats {
+                hits: 2,
+                misses: 3,
+                entries: 2,
+            }
+        );
+
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(inner.get(&key(b"c")), None);
+        cache.clear();
+        assert_eq!(cache.stats().entries, 0);
+
+        let cache = ResponseCache::new(Duration::from_secs(1), 0);
+        cache.inner.insert(key(b"a"), b"1".to_vec());
+        assert_eq!(cache.inner.get(&key(b"a")), None);
+    }
+
+    #[test]
+    fn test_client_key() {
+        let headers = |pairs: &[(&str, &str)]| {
+            let mut builder = MetadataBuilder::new();
+            for (k, v) in pairs {
+                builder.add_str(k, v).unwrap();
+            }
+            builder.build()
+        };
+        assert_eq!(client_key("/test/Get", None, b"a"), key(b"a"));
+        let a = headers(&[("tenant", "a")]);
+        let b = headers(&[("tenant", "b")]);
+        assert_ne!(
+            client_key("/test/Get", Some(&a), b"a"),
+            client_key("/test/Get", Some(&b), b"a")
+        );
+        assert_eq!(
+            client_key("/test/Get", Some(&a), b"a"),
+            client_key("/test/Get", Some(&headers(&[("tenant", "a")])), b"a")
+        );
+    }
+}
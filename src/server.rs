@@ -11,28 +11,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::cell::UnsafeCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
-use std::net::{IpAddr, SocketAddr};
-use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{cmp, fs, io};
+use std::{mem, ptr};
 
 use crate::grpc_sys::{self, grpc_call_error, grpc_server};
-use futures::{Async, Future, Poll};
+use futures::{Async, Future, IntoFuture, Poll, Stream};
 
+#[cfg(feature = "protobuf-codec")]
+use crate::admin::{HealthService, ReflectionService};
+use crate::budget::{BudgetedHandler, CallBudget};
 use crate::call::server::*;
-use crate::call::{MessageReader, Method, MethodType};
-use crate::channel::ChannelArgs;
-use crate::cq::CompletionQueue;
+use crate::call::{MessageReader, Method, MethodType, RpcStatus, RpcStatusCode};
+use crate::channel::{
+    self, ChannelArgs, CompressionAlgorithms, LocalTransport, Options, RawChannelArgs,
+    OPT_COMPRESSION_ENABLED_ALGORITHMS_BITSET, OPT_HTTP2_BDP_PROBE, OPT_HTTP2_MAX_FRAME_SIZE,
+    OPT_HTTP2_MAX_PINGS_WITHOUT_DATA, OPT_HTTP2_MAX_PING_STRIKES,
+    OPT_HTTP2_MIN_RECV_PING_INTERVAL_WITHOUT_DATA_MS,
+    OPT_HTTP2_MIN_SENT_PING_INTERVAL_WITHOUT_DATA_MS, OPT_KEEPALIVE_PERMIT_WITHOUT_CALLS,
+    OPT_KEEPALIVE_TIMEOUT_MS, OPT_KEEPALIVE_TIME_MS, OPT_MAX_RECEIVE_MESSAGE_LENGTH,
+    OPT_MAX_SEND_MESSAGE_LENGTH, OPT_RESOURCE_QUOTA, OPT_SO_REUSE_PORT,
+    OPT_STREAM_INITIAL_WINDOW_SIZE,
+};
+use crate::channelz::{self, Kind};
+use crate::codec::raw_codec;
+use crate::cq::{CompletionQueue, CompletionQueueGuard};
+use crate::diagnostics::{CallDiagnostics, DiagnosticsConfig};
 use crate::env::Environment;
 use crate::error::{Error, Result};
-use crate::task::{CallTag, CqFuture};
+use crate::quota::ResourceQuota;
+use crate::response_cache::{CachedHandler, ResponseCache};
+use crate::stats::{StatsHandler, StatsRecordingHandler};
+use crate::task::{CallTag, CqFuture, Delay, TaskGroup};
+use crate::watchdog::WatchedHandler;
 use crate::RpcContext;
 
 const DEFAULT_REQUEST_SLOTS_PER_CQ: usize = 1024;
+/// The key of the fallback handler in the registry, paths of methods are never empty.
+const FALLBACK_METHOD: &[u8] = b"";
+const OPT_MAX_CONNECTION_IDLE_MS: &[u8] = b"grpc.max_connection_idle_ms\0";
+const OPT_MAX_CONNECTION_AGE_MS: &[u8] = b"grpc.max_connection_age_ms\0";
+const OPT_MAX_CONNECTION_AGE_GRACE_MS: &[u8] = b"grpc.max_connection_age_grace_ms\0";
 
 /// An RPC call holder.
 #[derive(Clone)]
@@ -73,16 +104,202 @@ where
     }
 }
 
+/// A handler that validates the prefixes of requests before calling the
+/// inner handler.
+struct PrefixValidatedHandler {
+    inner: BoxHandler,
+    check: PrefixCheck,
+}
+
+impl CloneableHandler for PrefixValidatedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        match self.inner.method_type() {
+            MethodType::Unary | MethodType::ServerStreaming => {
+                if let Some(Err(status)) = reqs.as_ref().map(|r| self.check.check(r)) {
+                    let mut call = ctx.call();
+                    if call.start_server_side().is_ok() {
+                        call.abort(&status);
+                    }
+                    return;
+                }
+            }
+            MethodType::ClientStreaming | MethodType::Duplex => {
+                ctx.set_prefix_check(self.check.clone());
+            }
+        }
+        self.inner.handle(ctx, reqs)
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(PrefixValidatedHandler {
+            inner: self.inner.box_clone(),
+            check: self.check.clone(),
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
+
+/// A handler that limits the size of the messages sent by the calls it
+/// handles.
+struct SendLimitedHandler {
+    inner: BoxHandler,
+    limit: usize,
+}
+
+impl CloneableHandler for SendLimitedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        ctx.set_max_send_message_len(self.limit);
+        self.inner.handle(ctx, reqs)
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(SendLimitedHandler {
+            inner: self.inner.box_clone(),
+            limit: self.limit,
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
+
+/// Releases the slot of a call in a concurrency limit once it's dropped.
+struct ConcurrencyGuard(Arc<AtomicUsize>);
+
+impl Drop for ConcurrencyGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A handler that rejects calls once the count of in flight calls reaches
+/// the limit.
+struct ConcurrencyLimitedHandler {
+    inner: BoxHandler,
+    in_flight: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl CloneableHandler for ConcurrencyLimitedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        if self.in_flight.fetch_add(1, Ordering::SeqCst) < self.limit {
+            ctx.add_call_guard(Box::new(ConcurrencyGuard(self.in_flight.clone())));
+            return self.inner.handle(ctx, reqs);
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let status = RpcStatus::new(
+            RpcStatusCode::RESOURCE_EXHAUSTED,
+            Some(format!(
+                "too many concurrent requests, the limit is {}",
+                self.limit
+            )),
+        );
+        let mut call = ctx.call();
+        if call.start_server_side().is_ok() {
+            call.abort(&status);
+        }
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(ConcurrencyLimitedHandler {
+            inner: self.inner.box_clone(),
+            in_flight: self.in_flight.clone(),
+            limit: self.limit,
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
+
+/// The prefix of hosts that are paths of Unix domain sockets.
+const UNIX_PREFIX: &str = "unix:";
+
 /// Given a host and port, creates a string of the form "host:port" or
-/// "[host]:port", depending on whether the host is an IPv6 literal.
+/// "[host]:port", depending on whether the host is an IPv6 literal. Unix
+/// domain sockets are left as is.
 fn join_host_port(host: &str, port: u16) -> String {
-    if let Ok(ip) = host.parse::<IpAddr>() {
+    if host.starts_with(UNIX_PREFIX) {
+        format!("{}\0", host)
+    } else if let Ok(ip) = host.parse::<IpAddr>() {
         format!("{}\0", SocketAddr::new(ip, port))
     } else {
         format!("{}:{}\0", host, port)
     }
 }
 
+/// How often an acceptor checks whether the server is shutting down.
+#[cfg(unix)]
+const ACCEPT_POLL_INTERVAL_MS: i32 = 100;
+
+/// Accept connections from a listener that is not bound by gRPC Core, and
+/// hand them over to the server until `stop` is set.
+#[cfg(unix)]
+fn accept_connections(core: Arc<ServerCore>, listener: TcpListener, stop: Arc<AtomicBool>) {
+    let mut pfd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    while !stop.load(Ordering::SeqCst) {
+        if unsafe { libc::poll(&mut pfd, 1, ACCEPT_POLL_INTERVAL_MS) } <= 0 {
+            continue;
+        }
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            // Other processes sharing the listener may take the connection.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                std::thread::sleep(Duration::from_millis(ACCEPT_POLL_INTERVAL_MS as u64));
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_nonblocking(true) {
+            warn!("failed to set accepted connection nonblocking: {}", e);
+            continue;
+        }
+        let _ = stream.set_nodelay(true);
+        unsafe {
+            grpc_sys::grpc_server_add_insecure_channel_from_fd(
+                core.server,
+                ptr::null_mut(),
+                stream.into_raw_fd(),
+            )
+        }
+    }
+}
+
+/// An address a server is listening on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// Resolve the host bound by gRPC Core, which listens on all the resolved
+/// addresses.
+fn resolve_listen_addrs(host: &str, port: u16) -> Vec<ListenAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        // An empty host means all the interfaces.
+        let any = SocketAddr::new(IpAddr::from([0u16; 8]), port);
+        return vec![ListenAddr::Tcp(any)];
+    }
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs.map(ListenAddr::Tcp).collect(),
+        Err(e) => {
+            warn!("failed to resolve {}: {}", host, e);
+            vec![]
+        }
+    }
+}
+
 #[cfg(feature = "secure")]
 mod imp {
     use super::join_host_port;
@@ -108,9 +325,9 @@ mod imp {
 
         pub unsafe fn bind(&mut self, server: *mut grpc_server) -> u16 {
             let addr = join_host_port(&self.host, self.port);
-            let port = match self.cred.take() {
+            let port = match self.cred {
                 None => grpc_sys::grpc_server_add_insecure_http2_port(server, addr.as_ptr() as _),
-                Some(mut cert) => grpc_sys::grpc_server_add_secure_http2_port(
+                Some(ref mut cert) => grpc_sys::grpc_server_add_secure_http2_port(
                     server,
                     addr.as_ptr() as _,
                     cert.as_mut_ptr(),
@@ -145,11 +362,71 @@ mod imp {
 
 use self::imp::Binder;
 
+/// A set of services that are registered together, see [`service_set!`].
+///
+/// [`service_set!`]: macro.service_set.html
+pub trait ServiceSet {
+    /// Create all the services of the set.
+    fn into_services(self) -> Vec<Service>;
+}
+
+/// Define a [`ServiceSet`] that holds the implementations of generated
+/// services.
+///
+/// Every field is an implementation of a service, given as
+/// `field: Type as ServiceTrait => create_fn`. The fields are public, which
+/// gives typed access to the implementations before they are registered by
+/// [`ServerBuilder::register_services`]. Adding the same service trait
+/// twice is rejected at compile time with conflicting implementations, so
+/// all the method paths of the set are unique.
+///
+/// ```ignore
+/// grpcio::service_set! {
+///     /// All the services of the app.
+///     pub struct AppServices {
+///         greeter: GreeterService as Greeter => create_greeter,
+///         route_guide: RouteGuideService as RouteGuide => create_route_guide,
+///     }
+/// }
+///
+/// let services = AppServices {
+///     greeter: GreeterService::default(),
+///     route_guide: RouteGuideService::new(features),
+/// };
+/// let server = ServerBuilder::new(env).register_services(services);
+/// ```
+#[macro_export]
+macro_rules! service_set {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($field:ident: $ty:ty as $service:path => $create:path),+ $(,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone)]
+        $vis struct $name {
+            $(pub $field: $ty,)+
+        }
+
+        impl $crate::ServiceSet for $name {
+            fn into_services(self) -> ::std::vec::Vec<$crate::Service> {
+                // Method paths are unique as long as the service traits are.
+                #[allow(dead_code)]
+                trait UniqueService<T: ?Sized> {}
+                $(impl UniqueService<dyn $service> for $name {})+
+                vec![$($create(self.$field)),+]
+            }
+        }
+    };
+}
+
 /// [`Service`] factory in order to configure the properties.
 ///
 /// Use it to build a service which can be registered to a server.
 pub struct ServiceBuilder {
     handlers: HashMap<&'static [u8], BoxHandler>,
+    file_descriptors: Vec<Vec<u8>>,
 }
 
 impl ServiceBuilder {
@@ -157,6 +434,7 @@ impl ServiceBuilder {
     pub fn new() -> ServiceBuilder {
         ServiceBuilder {
             handlers: HashMap::new(),
+            file_descriptors: Vec::new(),
         }
     }
 
@@ -203,94 +481,830 @@ impl ServiceBuilder {
         self
     }
 
-    /// Add a server streaming RPC call handler.
-    pub fn add_server_streaming_handler<Req, Resp, F>(
-        mut self,
-        method: &Method<Req, Resp>,
-        mut handler: F,
-    ) -> ServiceBuilder
-    where
-        Req: 'static,
-        Resp: 'static,
-        F: FnMut(RpcContext<'_>, Req, ServerStreamingSink<Resp>) + Send + Clone + 'static,
-    {
-        let (ser, de) = (method.resp_ser(), method.req_de());
-        let h = move |ctx: RpcContext<'_>, payload: Option<MessageReader>| {
-            execute_server_streaming(ctx, ser, de, payload.unwrap(), &mut handler)
-        };
-        let ch = Box::new(Handler::new(MethodType::ServerStreaming, h));
-        self.handlers.insert(method.name.as_bytes(), ch);
+    /// Add a server streaming RPC call handler.
+    pub fn add_server_streaming_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, Req, ServerStreamingSink<Resp>) + Send + Clone + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, payload: Option<MessageReader>| {
+            execute_server_streaming(ctx, ser, de, payload.unwrap(), &mut handler)
+        };
+        let ch = Box::new(Handler::new(MethodType::ServerStreaming, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a duplex streaming RPC call handler.
+    pub fn add_duplex_streaming_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, RequestStream<Req>, DuplexSink<Resp>) + Send + Clone + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, _: Option<MessageReader>| {
+            execute_duplex_streaming(ctx, ser, de, &mut handler)
+        };
+        let ch = Box::new(Handler::new(MethodType::Duplex, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a unary RPC call handler that receives the request without
+    /// deserializing it, see [`LazyMessage`].
+    ///
+    /// It avoids the cost of decoding for services that only forward or
+    /// persist the payloads, or only need some of them.
+    pub fn add_unary_raw_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, LazyMessage<Req>, UnarySink<Resp>) + Send + Clone + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, payload: Option<MessageReader>| {
+            let mut f = |ctx: RpcContext<'_>, reader, sink| {
+                handler(ctx, LazyMessage::new(reader, de), sink)
+            };
+            execute_unary(ctx, ser, Ok, payload.unwrap(), &mut f)
+        };
+        let ch = Box::new(Handler::new(MethodType::Unary, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a client streaming RPC call handler that receives the requests
+    /// without deserializing them, see
+    /// [`ServiceBuilder::add_unary_raw_handler`].
+    pub fn add_client_streaming_raw_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, RequestStream<LazyMessage<Req>>, ClientStreamingSink<Resp>)
+            + Send
+            + Clone
+            + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, _: Option<MessageReader>| {
+            let lazy = move |reader| Ok(LazyMessage::new(reader, de));
+            execute_client_streaming(ctx, ser, lazy, &mut handler)
+        };
+        let ch = Box::new(Handler::new(MethodType::ClientStreaming, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a server streaming RPC call handler that receives the request
+    /// without deserializing it, see
+    /// [`ServiceBuilder::add_unary_raw_handler`].
+    pub fn add_server_streaming_raw_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, LazyMessage<Req>, ServerStreamingSink<Resp>)
+            + Send
+            + Clone
+            + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, payload: Option<MessageReader>| {
+            let mut f = |ctx: RpcContext<'_>, reader, sink| {
+                handler(ctx, LazyMessage::new(reader, de), sink)
+            };
+            execute_server_streaming(ctx, ser, Ok, payload.unwrap(), &mut f)
+        };
+        let ch = Box::new(Handler::new(MethodType::ServerStreaming, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a duplex streaming RPC call handler that receives the requests
+    /// without deserializing them, see
+    /// [`ServiceBuilder::add_unary_raw_handler`].
+    pub fn add_duplex_streaming_raw_handler<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: 'static,
+        F: FnMut(RpcContext<'_>, RequestStream<LazyMessage<Req>>, DuplexSink<Resp>)
+            + Send
+            + Clone
+            + 'static,
+    {
+        let (ser, de) = (method.resp_ser(), method.req_de());
+        let h = move |ctx: RpcContext<'_>, _: Option<MessageReader>| {
+            let lazy = move |reader| Ok(LazyMessage::new(reader, de));
+            execute_duplex_streaming(ctx, ser, lazy, &mut handler)
+        };
+        let ch = Box::new(Handler::new(MethodType::Duplex, h));
+        self.handlers.insert(method.name.as_bytes(), ch);
+        self
+    }
+
+    /// Add a unary RPC call handler that returns a future of the response.
+    ///
+    /// The response is sent when the future is resolved, and the error
+    /// status is sent if the future fails. The future is dropped if the call
+    /// is cancelled before it's resolved.
+    pub fn add_unary_future_handler<Req, Resp, F, R>(
+        self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: FnMut(&RpcContext<'_>, Req) -> R + Send + Clone + 'static,
+        R: IntoFuture<Item = Resp, Error = RpcStatus>,
+        R::Future: Send + 'static,
+    {
+        self.add_unary_handler(method, move |ctx, req, sink| {
+            let f = handler(&ctx, req).into_future();
+            ctx.spawn(Responder::new(f, sink))
+        })
+    }
+
+    /// Add a client streaming RPC call handler that returns a future of the
+    /// response, see [`ServiceBuilder::add_unary_future_handler`].
+    pub fn add_client_streaming_future_handler<Req, Resp, F, R>(
+        self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: FnMut(&RpcContext<'_>, RequestStream<Req>) -> R + Send + Clone + 'static,
+        R: IntoFuture<Item = Resp, Error = RpcStatus>,
+        R::Future: Send + 'static,
+    {
+        self.add_client_streaming_handler(method, move |ctx, stream, sink| {
+            let f = handler(&ctx, stream).into_future();
+            ctx.spawn(Responder::new(f, sink))
+        })
+    }
+
+    /// Add a server streaming RPC call handler that returns a stream of the
+    /// responses.
+    ///
+    /// The responses are sent as they are yielded, and the call is finished
+    /// when the stream is exhausted. If the stream fails, the error status
+    /// is sent instead. The stream is dropped if the call is cancelled.
+    pub fn add_server_streaming_future_handler<Req, Resp, F, S>(
+        self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: FnMut(&RpcContext<'_>, Req) -> S + Send + Clone + 'static,
+        S: Stream<Item = Resp, Error = RpcStatus> + Send + 'static,
+    {
+        self.add_server_streaming_handler(method, move |ctx, req, sink| {
+            let s = handler(&ctx, req);
+            ctx.spawn(StreamResponder::new(s, sink))
+        })
+    }
+
+    /// Add a duplex streaming RPC call handler that returns a stream of the
+    /// responses, see [`ServiceBuilder::add_server_streaming_future_handler`].
+    pub fn add_duplex_streaming_future_handler<Req, Resp, F, S>(
+        self,
+        method: &Method<Req, Resp>,
+        mut handler: F,
+    ) -> ServiceBuilder
+    where
+        Req: 'static,
+        Resp: Send + 'static,
+        F: FnMut(&RpcContext<'_>, RequestStream<Req>) -> S + Send + Clone + 'static,
+        S: Stream<Item = Resp, Error = RpcStatus> + Send + 'static,
+    {
+        self.add_duplex_streaming_handler(method, move |ctx, stream, sink| {
+            let s = handler(&ctx, stream);
+            ctx.spawn(StreamResponder::new(s, sink))
+        })
+    }
+
+    /// Add a serialized `FileDescriptorProto` that describes the service.
+    ///
+    /// Descriptors of the files it depends on should be added as well,
+    /// dependencies first. They are exported by
+    /// [`Server::file_descriptor_set`].
+    pub fn add_file_descriptor(mut self, descriptor: &[u8]) -> ServiceBuilder {
+        self.file_descriptors.push(descriptor.to_vec());
+        self
+    }
+
+    /// Finalize the [`ServiceBuilder`] and build the [`Service`].
+    pub fn build(self) -> Service {
+        Service {
+            handlers: self.handlers,
+            file_descriptors: self.file_descriptors,
+        }
+    }
+}
+
+/// A gRPC service.
+///
+/// Use [`ServiceBuilder`] to build a [`Service`].
+pub struct Service {
+    handlers: HashMap<&'static [u8], BoxHandler>,
+    file_descriptors: Vec<Vec<u8>>,
+}
+
+impl Service {
+    /// Cache the responses of the unary method in the given cache.
+    ///
+    /// Requests that are identical to a previous one are replied with the
+    /// cached response directly without calling the handler until it expires.
+    /// Metadata, status messages and failed responses are not cached, so it
+    /// should only be used for idempotent methods whose responses only depend
+    /// on the request messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method is not unary.
+    pub fn cache_responses<Req, Resp>(
+        mut self,
+        method: &Method<Req, Resp>,
+        cache: ResponseCache,
+    ) -> Service {
+        match method.ty {
+            MethodType::Unary => {}
+            _ => panic!("only responses of unary methods can be cached"),
+        }
+        let name = method.name.as_bytes();
+        if let Some(inner) = self.handlers.remove(name) {
+            let h = CachedHandler::new(name, inner, cache);
+            self.handlers.insert(name, Box::new(h));
+        }
+        self
+    }
+
+    /// Validate the first `len` bytes of every request message of the method
+    /// before it's deserialized.
+    ///
+    /// The validator receives fewer bytes if the message is shorter. If it
+    /// returns an error, unary and server streaming calls are failed with
+    /// the status without calling the handler, and the request stream of
+    /// client streaming and duplex streaming calls yields the status as
+    /// `Error::RpcFailure` instead of the message.
+    ///
+    /// It can be used to reject huge messages with an invalid magic header
+    /// or an embedded token without paying for decoding them. Note that gRPC
+    /// Core only delivers complete messages, so they still need to be
+    /// received, use `grpc.max_receive_message_length` to limit the size.
+    pub fn validate_prefix<Req, Resp, F>(
+        mut self,
+        method: &Method<Req, Resp>,
+        len: usize,
+        validator: F,
+    ) -> Service
+    where
+        F: Fn(&[u8]) -> std::result::Result<(), RpcStatus> + Send + Sync + 'static,
+    {
+        let name = method.name.as_bytes();
+        if let Some(inner) = self.handlers.remove(name) {
+            let check = PrefixCheck::new(len, Arc::new(validator));
+            let h = PrefixValidatedHandler { inner, check };
+            self.handlers.insert(name, Box::new(h));
+        }
+        self
+    }
+
+    /// Assign all the methods of the service to the class of the budget.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the class is not added to the budget.
+    pub fn budget(mut self, budget: &CallBudget, class: &str) -> Service {
+        let class = budget.class_index(class);
+        self.handlers = self
+            .handlers
+            .drain()
+            .map(|(name, inner)| {
+                let h: BoxHandler = Box::new(BudgetedHandler::new(inner, budget.clone(), class));
+                (name, h)
+            })
+            .collect();
+        self
+    }
+
+    /// Assign the method to the class of the budget.
+    ///
+    /// If the method is also assigned to a class by [`Service::budget`],
+    /// calls need to be admitted by both classes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the class is not added to the budget.
+    pub fn method_budget<Req, Resp>(
+        mut self,
+        method: &Method<Req, Resp>,
+        budget: &CallBudget,
+        class: &str,
+    ) -> Service {
+        let class = budget.class_index(class);
+        let name = method.name.as_bytes();
+        if let Some(inner) = self.handlers.remove(name) {
+            let h = BudgetedHandler::new(inner, budget.clone(), class);
+            self.handlers.insert(name, Box::new(h));
+        }
+        self
+    }
+}
+
+/// [`Server`] factory in order to configure the properties.
+pub struct ServerBuilder {
+    env: Arc<Environment>,
+    binders: Vec<Binder>,
+    listeners: Vec<TcpListener>,
+    args: Option<ChannelArgs>,
+    options: HashMap<Cow<'static, [u8]>, Options>,
+    slots_per_cq: usize,
+    max_slots_per_cq: Option<usize>,
+    handlers: HashMap<&'static [u8], BoxHandler>,
+    file_descriptors: FileDescriptors,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    diagnostics: Option<Arc<CallDiagnostics>>,
+    batch_watchdog: Option<Duration>,
+    max_concurrent_requests: Option<usize>,
+    method_concurrency: HashMap<&'static [u8], usize>,
+}
+
+impl ServerBuilder {
+    /// Initialize a new [`ServerBuilder`].
+    pub fn new(env: Arc<Environment>) -> ServerBuilder {
+        ServerBuilder {
+            env,
+            binders: Vec::new(),
+            listeners: Vec::new(),
+            args: None,
+            options: HashMap::new(),
+            slots_per_cq: DEFAULT_REQUEST_SLOTS_PER_CQ,
+            max_slots_per_cq: None,
+            handlers: HashMap::new(),
+            file_descriptors: FileDescriptors::default(),
+            stats_handler: None,
+            diagnostics: None,
+            batch_watchdog: None,
+            max_concurrent_requests: None,
+            method_concurrency: HashMap::new(),
+        }
+    }
+
+    /// Bind to an address.
+    ///
+    /// This function can be called multiple times to bind to multiple ports.
+    /// If the port is 0, an unused port is picked, which can be got by
+    /// [`Server::bind_addrs`] or [`Server::listen_addrs`] after the server
+    /// is built.
+    pub fn bind<S: Into<String>>(mut self, host: S, port: u16) -> ServerBuilder {
+        self.binders.push(Binder::new(host.into(), port));
+        self
+    }
+
+    /// Bind to a Unix domain socket at the path.
+    ///
+    /// An existing socket file at the path is removed first. Clients can
+    /// connect to it with the target `unix:<path>`.
+    pub fn bind_unix<P: AsRef<Path>>(mut self, path: P) -> ServerBuilder {
+        let host = format!("{}{}", UNIX_PREFIX, path.as_ref().display());
+        self.binders.push(Binder::new(host, 0));
+        self
+    }
+
+    /// Bind to a local transport, see [`LocalTransport`].
+    ///
+    /// An error is returned if the transport is not supported on the
+    /// platform.
+    pub fn bind_local(mut self, transport: &LocalTransport) -> Result<ServerBuilder> {
+        self.binders.push(Binder::new(transport.target()?, 0));
+        Ok(self)
+    }
+
+    /// Serve plaintext connections accepted from a listener that is already
+    /// bound, e.g. one passed by systemd socket activation or by the parent
+    /// process during a zero-downtime restart.
+    ///
+    /// The connections are accepted by a dedicated thread once the server
+    /// starts. There is no secure variant: gRPC Core can only adopt accepted
+    /// connections as insecure channels, so the connections are never served
+    /// with TLS, even if credentials are used by other binds. Put a TLS
+    /// terminating proxy in front of the listener if the traffic leaves the
+    /// host. Socket options of the builder are not applied to the listener.
+    #[cfg(unix)]
+    pub fn bind_insecure_listener(mut self, listener: TcpListener) -> ServerBuilder {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Serve plaintext connections accepted from the listening socket `fd`,
+    /// see [`ServerBuilder::bind_insecure_listener`].
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a bound and listening TCP socket, and the server takes
+    /// the ownership of it.
+    #[cfg(unix)]
+    pub unsafe fn bind_insecure_fd(self, fd: RawFd) -> ServerBuilder {
+        self.bind_insecure_listener(TcpListener::from_raw_fd(fd))
+    }
+
+    /// Add additional configuration for each incoming channel.
+    ///
+    /// Options set by other methods of the builder, including `raw_args`,
+    /// replace the arguments with the same keys.
+    #[doc(hidden)]
+    pub fn channel_args(mut self, args: ChannelArgs) -> ServerBuilder {
+        self.args = Some(args);
+        self
+    }
+
+    /// Check the liveness of clients by PING frames.
+    ///
+    /// When a connection has been idle for `interval`, a PING frame is sent
+    /// to the client. If the ack is not received within `timeout`, the
+    /// connection is closed and all the streams on it are aborted. Without
+    /// it, a half-open connection, e.g. the client host goes away without
+    /// closing its socket, keeps long-lived streams alive forever.
+    ///
+    /// PING is a connection level frame in HTTP/2. Streams sharing the same
+    /// connection are aborted together.
+    ///
+    /// It's a shortcut of `keepalive_time` and `keepalive_timeout` that also
+    /// stops the PING frames from being throttled while there is no data to
+    /// send.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either duration is less than 1ms.
+    pub fn stream_liveness(self, interval: Duration, timeout: Duration) -> ServerBuilder {
+        self.keepalive_time(interval)
+            .keepalive_timeout(timeout)
+            .http2_min_sent_ping_interval_without_data(interval)
+            .http2_max_pings_without_data(0)
+    }
+
+    /// After a duration of this time the server pings the client to see if
+    /// the transport is still alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is less than 1ms.
+    pub fn keepalive_time(mut self, time: Duration) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_KEEPALIVE_TIME_MS),
+            Options::Integer(channel::keepalive_ms(time, "keepalive time")),
+        );
+        self
+    }
+
+    /// After waiting for a duration of this time, if the keepalive ping is not
+    /// acked, the server closes the transport.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is less than 1ms.
+    pub fn keepalive_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_KEEPALIVE_TIMEOUT_MS),
+            Options::Integer(channel::keepalive_ms(timeout, "keepalive timeout")),
+        );
+        self
+    }
+
+    /// Set maximum message length that the server can receive. `-1` means
+    /// unlimited, the default is 4MiB.
+    ///
+    /// Calls receiving larger messages are failed with `RESOURCE_EXHAUSTED`
+    /// and details containing both the limit and the actual size.
+    pub fn max_receive_message_len(mut self, len: i32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_RECEIVE_MESSAGE_LENGTH),
+            Options::Integer(len),
+        );
+        self
+    }
+
+    /// Set maximum message length that the server can send. `-1` means
+    /// unlimited, which is the default.
+    ///
+    /// Sending a larger message from a sink fails with `Error::RpcFailure` of
+    /// `RESOURCE_EXHAUSTED`, and a larger unary response fails the call with
+    /// that status instead of being sent, so the client sees the limit and
+    /// the actual size.
+    pub fn max_send_message_len(mut self, len: i32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_SEND_MESSAGE_LENGTH),
+            Options::Integer(len),
+        );
+        self
+    }
+
+    /// Is it permissible to send keepalive pings without any outstanding streams.
+    pub fn keepalive_permit_without_calls(mut self, allow: bool) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_KEEPALIVE_PERMIT_WITHOUT_CALLS),
+            Options::Integer(allow as i32),
+        );
+        self
+    }
+
+    /// Minimum time between sending successive ping frames without receiving any
+    /// data frame.
+    pub fn http2_min_sent_ping_interval_without_data(
+        mut self,
+        interval: Duration,
+    ) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_MIN_SENT_PING_INTERVAL_WITHOUT_DATA_MS),
+            Options::Integer(channel::dur_to_ms(interval)),
+        );
+        self
+    }
+
+    /// Minimum allowed time between receiving successive ping frames without
+    /// sending any data frame.
+    ///
+    /// Pings from clients that are more frequent are counted as strikes, see
+    /// `http2_max_ping_strikes`. Clients with a shorter `keepalive_time` should
+    /// be allowed here, otherwise their connections are closed.
+    pub fn http2_min_recv_ping_interval_without_data(
+        mut self,
+        interval: Duration,
+    ) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_MIN_RECV_PING_INTERVAL_WITHOUT_DATA_MS),
+            Options::Integer(channel::dur_to_ms(interval)),
+        );
+        self
+    }
+
+    /// How many pings can be sent before needing to send a data frame or header
+    /// frame? (0 indicates that an infinite number of pings can be sent without
+    /// sending a data frame or header frame)
+    pub fn http2_max_pings_without_data(mut self, num: u32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_MAX_PINGS_WITHOUT_DATA),
+            Options::Integer(channel::ping_count(num)),
+        );
+        self
+    }
+
+    /// How many misbehaving pings the server can bear before sending goaway and
+    /// closing the transport? (0 indicates that the server can bear an infinite
+    /// number of misbehaving pings)
+    pub fn http2_max_ping_strikes(mut self, num: u32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_MAX_PING_STRIKES),
+            Options::Integer(channel::ping_count(num)),
+        );
+        self
+    }
+
+    /// Close connections that have existed for the duration.
+    ///
+    /// A GOAWAY frame is sent when a connection reaches the age, so clients
+    /// reconnect for new calls, which is useful for rebalancing connections
+    /// behind L4 load balancers. The actual age is randomized by +/-10% to
+    /// avoid connection storms.
+    pub fn max_connection_age(mut self, age: Duration) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_CONNECTION_AGE_MS),
+            Options::Integer(channel::dur_to_ms(age)),
+        );
+        self
+    }
+
+    /// Time allowed for in-flight calls to complete after a connection
+    /// reaches its max age, see [`ServerBuilder::max_connection_age`].
+    ///
+    /// Calls that are still running after it are cancelled when the
+    /// connection is closed forcibly.
+    pub fn max_connection_age_grace(mut self, grace: Duration) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_CONNECTION_AGE_GRACE_MS),
+            Options::Integer(channel::dur_to_ms(grace)),
+        );
+        self
+    }
+
+    /// Close connections that have no outstanding calls for the duration.
+    ///
+    /// A GOAWAY frame is sent when closing, clients reconnect once there are
+    /// new calls.
+    pub fn max_connection_idle(mut self, idle: Duration) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_MAX_CONNECTION_IDLE_MS),
+            Options::Integer(channel::dur_to_ms(idle)),
+        );
+        self
+    }
+
+    /// Set the initial window size of streams, which is how much data a
+    /// client can send on a stream before the server reads it. Defaults to
+    /// 64KB. Larger values help throughput on high-latency connections.
+    pub fn stream_initial_window_size(mut self, window_size: i32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_STREAM_INITIAL_WINDOW_SIZE),
+            Options::Integer(window_size),
+        );
+        self
+    }
+
+    /// Set the compression algorithms that are enabled, all algorithms are
+    /// enabled by default.
+    ///
+    /// Only enabled algorithms are advertised to clients in
+    /// `grpc-accept-encoding`, and calls that send messages compressed by
+    /// other algorithms fail with `UNIMPLEMENTED`. `GRPC_COMPRESS_NONE` is
+    /// always enabled.
+    pub fn enabled_compression_algorithms(
+        mut self,
+        algos: &[CompressionAlgorithms],
+    ) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_COMPRESSION_ENABLED_ALGORITHMS_BITSET),
+            Options::Integer(channel::compression_bitset(algos)),
+        );
+        self
+    }
+
+    /// Set the largest HTTP/2 frame the server is willing to receive, it
+    /// should be in `[16384, 16777215]`.
+    ///
+    /// Larger values lower the CPU usage for large messages, at the cost of
+    /// more head of line blocking for small messages.
+    pub fn http2_max_frame_size(mut self, size: i32) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_MAX_FRAME_SIZE),
+            Options::Integer(size),
+        );
+        self
+    }
+
+    /// Set whether to enable BDP probing. Defaults to `true`.
+    ///
+    /// gRPC Core grows the connection and stream windows to the estimated
+    /// bandwidth-delay product of the link, the connection window has no
+    /// fixed size to set otherwise.
+    pub fn http2_bdp_probe(mut self, enable: bool) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_HTTP2_BDP_PROBE),
+            Options::Integer(enable as i32),
+        );
+        self
+    }
+
+    /// Set whether to allow the use of `SO_REUSEPORT` if available. Defaults
+    /// to `true`.
+    ///
+    /// With it, multiple processes can listen on the same port and the
+    /// kernel balances incoming connections among them.
+    pub fn reuse_port(mut self, reuse: bool) -> ServerBuilder {
+        let opt = if reuse { 1 } else { 0 };
+        self.options
+            .insert(Cow::Borrowed(OPT_SO_REUSE_PORT), Options::Integer(opt));
+        self
+    }
+
+    /// Set `TCP_USER_TIMEOUT` of the listening sockets, which is how long
+    /// transmitted data may remain unacknowledged before the connection is
+    /// closed.
+    ///
+    /// gRPC Core applies socket options to the listening sockets only, so
+    /// accepted connections get them only if the OS inherits them from the
+    /// listener. It's ignored on platforms that don't support the option.
+    pub fn tcp_user_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        channel::socket_options(&mut self.options).tcp_user_timeout_ms =
+            channel::dur_to_ms(timeout);
+        self
+    }
+
+    /// Set whether to enable `TCP_NODELAY` on the listening sockets, see
+    /// [`tcp_user_timeout`](#method.tcp_user_timeout). gRPC Core enables it
+    /// on accepted connections by default.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> ServerBuilder {
+        channel::socket_options(&mut self.options).tcp_nodelay = if nodelay { 1 } else { 0 };
+        self
+    }
+
+    /// Set `SO_RCVBUF` of the listening sockets, see
+    /// [`tcp_user_timeout`](#method.tcp_user_timeout).
+    pub fn recv_buffer_size(mut self, bytes: i32) -> ServerBuilder {
+        channel::socket_options(&mut self.options).recv_buffer_size = bytes;
+        self
+    }
+
+    /// Set `SO_SNDBUF` of the listening sockets, see
+    /// [`tcp_user_timeout`](#method.tcp_user_timeout).
+    pub fn send_buffer_size(mut self, bytes: i32) -> ServerBuilder {
+        channel::socket_options(&mut self.options).send_buffer_size = bytes;
         self
     }
 
-    /// Add a duplex streaming RPC call handler.
-    pub fn add_duplex_streaming_handler<Req, Resp, F>(
-        mut self,
-        method: &Method<Req, Resp>,
-        mut handler: F,
-    ) -> ServiceBuilder
-    where
-        Req: 'static,
-        Resp: 'static,
-        F: FnMut(RpcContext<'_>, RequestStream<Req>, DuplexSink<Resp>) + Send + Clone + 'static,
-    {
-        let (ser, de) = (method.resp_ser(), method.req_de());
-        let h = move |ctx: RpcContext<'_>, _: Option<MessageReader>| {
-            execute_duplex_streaming(ctx, ser, de, &mut handler)
-        };
-        let ch = Box::new(Handler::new(MethodType::Duplex, h));
-        self.handlers.insert(method.name.as_bytes(), ch);
+    /// Set arguments of gRPC Core that are not wrapped by the builder.
+    ///
+    /// Like other methods, an argument replaces the option set before with
+    /// the same key.
+    pub fn raw_args(mut self, args: RawChannelArgs) -> ServerBuilder {
+        args.merge_into(&mut self.options);
         self
     }
 
-    /// Finalize the [`ServiceBuilder`] and build the [`Service`].
-    pub fn build(self) -> Service {
-        Service {
-            handlers: self.handlers,
-        }
+    /// Set the memory quota of the server, it can be shared with other
+    /// servers and channels.
+    pub fn resource_quota(mut self, quota: ResourceQuota) -> ServerBuilder {
+        self.options.insert(
+            Cow::Borrowed(OPT_RESOURCE_QUOTA),
+            Options::ResourceQuota(quota),
+        );
+        self
     }
-}
 
-/// A gRPC service.
-///
-/// Use [`ServiceBuilder`] to build a [`Service`].
-pub struct Service {
-    handlers: HashMap<&'static [u8], BoxHandler>,
-}
+    /// Set the handler that is notified of the events of calls handled by
+    /// the server, see [`StatsHandler`] for details.
+    ///
+    /// Calls to unimplemented methods are not reported.
+    pub fn stats_handler(mut self, handler: Arc<dyn StatsHandler>) -> ServerBuilder {
+        self.stats_handler = Some(handler);
+        self
+    }
 
-/// [`Server`] factory in order to configure the properties.
-pub struct ServerBuilder {
-    env: Arc<Environment>,
-    binders: Vec<Binder>,
-    args: Option<ChannelArgs>,
-    slots_per_cq: usize,
-    handlers: HashMap<&'static [u8], BoxHandler>,
-}
+    /// Record the message size and latency histograms of every method, and
+    /// log the calls that exceed the thresholds of `config` with their peers.
+    ///
+    /// The diagnostics can be got by [`Server::call_diagnostics`], calls to
+    /// unimplemented methods are not recorded.
+    ///
+    /// [`Server::call_diagnostics`]: struct.Server.html#method.call_diagnostics
+    pub fn enable_call_diagnostics(mut self, config: DiagnosticsConfig) -> ServerBuilder {
+        self.diagnostics = Some(Arc::new(CallDiagnostics::new(config)));
+        self
+    }
 
-impl ServerBuilder {
-    /// Initialize a new [`ServerBuilder`].
-    pub fn new(env: Arc<Environment>) -> ServerBuilder {
-        ServerBuilder {
-            env,
-            binders: Vec::new(),
-            args: None,
-            slots_per_cq: DEFAULT_REQUEST_SLOTS_PER_CQ,
-            handlers: HashMap::new(),
-        }
+    /// Abort calls handled by the server if any of their batches, like
+    /// sending or receiving a message, is not completed within the bound.
+    ///
+    /// See `CallOption::batch_watchdog` for details.
+    pub fn batch_watchdog(mut self, bound: Duration) -> ServerBuilder {
+        self.batch_watchdog = Some(bound);
+        self
     }
 
-    /// Bind to an address.
+    /// Limit the count of calls that are handled concurrently by the server.
     ///
-    /// This function can be called multiple times to bind to multiple ports.
-    pub fn bind<S: Into<String>>(mut self, host: S, port: u16) -> ServerBuilder {
-        self.binders.push(Binder::new(host.into(), port));
+    /// A call is counted from the time its handler is called until its sinks
+    /// and request streams are dropped. Calls beyond the limit are rejected
+    /// with `RESOURCE_EXHAUSTED` without calling their handlers, so a slow
+    /// downstream can't pile up an unbounded count of accepted calls.
+    ///
+    /// Methods limited by [`ServerBuilder::method_max_concurrent_requests`]
+    /// are not counted.
+    pub fn max_concurrent_requests(mut self, limit: usize) -> ServerBuilder {
+        self.max_concurrent_requests = Some(limit);
         self
     }
 
-    /// Add additional configuration for each incoming channel.
-    #[doc(hidden)]
-    pub fn channel_args(mut self, args: ChannelArgs) -> ServerBuilder {
-        self.args = Some(args);
+    /// Limit the count of calls of the method that are handled concurrently,
+    /// instead of counting them in the limit set by
+    /// [`ServerBuilder::max_concurrent_requests`].
+    pub fn method_max_concurrent_requests<Req, Resp>(
+        mut self,
+        method: &Method<Req, Resp>,
+        limit: usize,
+    ) -> ServerBuilder {
+        self.method_concurrency
+            .insert(method.name.as_bytes(), limit);
         self
     }
 
@@ -300,36 +1314,161 @@ impl ServerBuilder {
         self
     }
 
+    /// Allow the request slots of a completion queue to grow up to `slots`
+    /// under burst load.
+    ///
+    /// A slot is taken by an incoming call until the call is dispatched to
+    /// its handler, a unary call keeps it until the request message is
+    /// received. When all the slots are taken, a new one is added so that
+    /// following calls don't have to wait in gRPC Core, and the extra slots
+    /// are dropped once enough of them are idle again. Defaults to the
+    /// number set by [`ServerBuilder::requests_slot_per_cq`], which means
+    /// no scaling.
+    pub fn max_requests_slot_per_cq(mut self, slots: usize) -> ServerBuilder {
+        self.max_slots_per_cq = Some(slots);
+        self
+    }
+
     /// Register a service.
     pub fn register_service(mut self, service: Service) -> ServerBuilder {
         self.handlers.extend(service.handlers);
+        add_file_descriptors(&self.file_descriptors, service.file_descriptors);
+        self
+    }
+
+    /// Register the admin services, the health checking service with the
+    /// statuses of `health` and the reflection service of the descriptors of
+    /// all the registered services, including the ones registered later.
+    ///
+    /// Use [`Server::admin_services`] instead to serve them on another port
+    /// with different credentials.
+    #[cfg(feature = "protobuf-codec")]
+    pub fn add_admin_services(self, health: &HealthService) -> ServerBuilder {
+        let service = admin_services(health, &self.file_descriptors);
+        self.register_service(service)
+    }
+
+    /// Set the handler of the calls to the methods that are not registered,
+    /// which are rejected with `UNIMPLEMENTED` by default.
+    ///
+    /// Messages are passed to the handler as is, and the path of the method
+    /// can be got by [`RpcContext::method`]. As the type of the method is
+    /// unknown, all calls are handled as duplex streaming ones, which works
+    /// for clients of any type as long as the handler sends as many responses
+    /// as the method is supposed to.
+    pub fn set_fallback<F>(mut self, mut handler: F) -> ServerBuilder
+    where
+        F: FnMut(RpcContext<'_>, RequestStream<Vec<u8>>, DuplexSink<Vec<u8>>)
+            + Send
+            + Clone
+            + 'static,
+    {
+        let h = move |ctx: RpcContext<'_>, _: Option<MessageReader>| {
+            execute_duplex_streaming(ctx, raw_codec::ser, raw_codec::de, &mut handler)
+        };
+        let ch = Box::new(Handler::new(MethodType::Duplex, h));
+        self.handlers.insert(FALLBACK_METHOD, ch);
+        self
+    }
+
+    /// Register all the services in the set, see [`service_set!`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if a method of the set has already been registered.
+    ///
+    /// [`service_set!`]: macro.service_set.html
+    pub fn register_services<S: ServiceSet>(mut self, set: S) -> ServerBuilder {
+        for service in set.into_services() {
+            for name in service.handlers.keys() {
+                if self.handlers.contains_key(name) {
+                    panic!(
+                        "method {} is registered twice",
+                        String::from_utf8_lossy(name)
+                    );
+                }
+            }
+            self = self.register_service(service);
+        }
         self
     }
 
     /// Finalize the [`ServerBuilder`] and build the [`Server`].
     pub fn build(mut self) -> Result<Server> {
+        let send_limit = channel::send_limit(&self.options);
+        if !self.options.is_empty() {
+            let args = channel::build_channel_args(&self.options);
+            self.args = Some(match self.args.take() {
+                Some(explicit) => explicit.merge(&args),
+                None => args,
+            });
+        }
+        let global = self
+            .max_concurrent_requests
+            .map(|limit| (Arc::new(AtomicUsize::new(0)), limit));
+        let wrapper = HandlerWrapper {
+            global,
+            method_concurrency: mem::take(&mut self.method_concurrency),
+            stats_handler: self.stats_handler.take(),
+            diagnostics: self.diagnostics.take(),
+            send_limit,
+            batch_watchdog: self.batch_watchdog,
+        };
+        let handlers = self
+            .handlers
+            .drain()
+            .map(|(name, inner)| (name, wrapper.wrap(name, inner)))
+            .collect();
+        let mut listener_addrs = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            // The acceptor polls the listener, accepting shouldn't block.
+            listener.set_nonblocking(true).map_err(Error::Io)?;
+            listener_addrs.push(listener.local_addr().map_err(Error::Io)?);
+        }
         let args = self
             .args
             .as_ref()
             .map_or_else(ptr::null, ChannelArgs::as_ptr);
+        // The queues are registered to the server, so they can't be shutdown
+        // until the server is destroyed.
+        let cq_guards = self
+            .env
+            .completion_queues()
+            .iter()
+            .map(CompletionQueue::guard)
+            .collect::<Result<Vec<_>>>()?;
         unsafe {
-            let server = grpc_sys::grpc_server_create(args, ptr::null_mut());
+            let (server, channelz_id) = channelz::create_and_find_id(Kind::Server, || {
+                grpc_sys::grpc_server_create(args, ptr::null_mut())
+            });
             let mut bind_addrs = Vec::with_capacity(self.binders.len());
-            for mut binder in self.binders.drain(..) {
+            let mut listen_addrs = Vec::with_capacity(self.binders.len());
+            for binder in &mut self.binders {
                 let bind_port = binder.bind(server);
                 if bind_port == 0 {
                     grpc_sys::grpc_server_destroy(server);
-                    return Err(Error::BindFail(binder.host, binder.port));
+                    return Err(Error::BindFail(binder.host.clone(), binder.port));
                 }
 
-                bind_addrs.push((binder.host, bind_port as u16));
+                if binder.host.starts_with(UNIX_PREFIX) {
+                    // gRPC Core reports 1 for Unix domain sockets.
+                    let path = PathBuf::from(&binder.host[UNIX_PREFIX.len()..]);
+                    listen_addrs.push(ListenAddr::Unix(path));
+                    bind_addrs.push((binder.host.clone(), 0));
+                } else {
+                    listen_addrs.extend(resolve_listen_addrs(&binder.host, bind_port));
+                    bind_addrs.push((binder.host.clone(), bind_port));
+                }
+            }
+            for addr in listener_addrs {
+                listen_addrs.push(ListenAddr::Tcp(addr));
+                bind_addrs.push((addr.ip().to_string(), addr.port()));
             }
 
-            for cq in self.env.completion_queues() {
-                let cq_ref = cq.borrow()?;
+            for guard in &cq_guards {
                 grpc_sys::grpc_server_register_completion_queue(
                     server,
-                    cq_ref.as_ptr(),
+                    guard.as_ptr(),
                     ptr::null_mut(),
                 );
             }
@@ -340,9 +1479,24 @@ impl ServerBuilder {
                     server,
                     shutdown: AtomicBool::new(false),
                     bind_addrs,
+                    listen_addrs,
                     slots_per_cq: self.slots_per_cq,
+                    max_slots_per_cq: cmp::max(
+                        self.slots_per_cq,
+                        self.max_slots_per_cq.unwrap_or(0),
+                    ),
+                    tasks: TaskGroup::new(),
+                    handlers: Mutex::new(handlers),
+                    handlers_version: AtomicUsize::new(0),
+                    _binders: self.binders,
+                    cq_guards,
                 }),
-                handlers: self.handlers,
+                wrapper,
+                file_descriptors: self.file_descriptors,
+                channelz_id,
+                listeners: self.listeners,
+                acceptors: Vec::new(),
+                stop_accepting: Arc::new(AtomicBool::new(false)),
             })
         }
     }
@@ -350,9 +1504,13 @@ impl ServerBuilder {
 
 #[cfg(feature = "secure")]
 mod secure_server {
+    use std::path::Path;
+
     use crate::credentials::ServerCredentials;
 
-    use super::{Binder, ServerBuilder};
+    use crate::error::Result;
+
+    use super::{Binder, LocalTransport, ServerBuilder, UNIX_PREFIX};
 
     impl ServerBuilder {
         /// Bind to an address for secure connection.
@@ -367,14 +1525,92 @@ mod secure_server {
             self.binders.push(Binder::with_cred(host.into(), port, c));
             self
         }
+
+        /// Bind to a Unix domain socket at the path for secure connection,
+        /// see [`ServerBuilder::bind_unix`].
+        pub fn bind_unix_secure<P: AsRef<Path>>(
+            mut self,
+            path: P,
+            c: ServerCredentials,
+        ) -> ServerBuilder {
+            let host = format!("{}{}", UNIX_PREFIX, path.as_ref().display());
+            self.binders.push(Binder::with_cred(host, 0, c));
+            self
+        }
+
+        /// Bind to a local transport for secure connection, see
+        /// [`ServerBuilder::bind_local`].
+        ///
+        /// It's usually used with `ServerCredentials::local`.
+        pub fn bind_local_secure(
+            mut self,
+            transport: &LocalTransport,
+            c: ServerCredentials,
+        ) -> Result<ServerBuilder> {
+            self.binders
+                .push(Binder::with_cred(transport.target()?, 0, c));
+            Ok(self)
+        }
+    }
+}
+
+/// Wraps handlers with the limits and hooks set by the builder, it's kept
+/// by the server for the services added after building.
+struct HandlerWrapper {
+    global: Option<(Arc<AtomicUsize>, usize)>,
+    method_concurrency: HashMap<&'static [u8], usize>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    diagnostics: Option<Arc<CallDiagnostics>>,
+    send_limit: Option<usize>,
+    batch_watchdog: Option<Duration>,
+}
+
+impl HandlerWrapper {
+    fn wrap(&self, name: &'static [u8], mut h: BoxHandler) -> BoxHandler {
+        let limit = match self.method_concurrency.get(name) {
+            Some(limit) => Some((Arc::new(AtomicUsize::new(0)), *limit)),
+            None => self.global.clone(),
+        };
+        if let Some((in_flight, limit)) = limit {
+            h = Box::new(ConcurrencyLimitedHandler {
+                inner: h,
+                in_flight,
+                limit,
+            });
+        }
+        if self.stats_handler.is_some() || self.diagnostics.is_some() {
+            h = Box::new(StatsRecordingHandler::new(
+                h,
+                self.stats_handler.clone(),
+                self.diagnostics.clone(),
+            ));
+        }
+        if let Some(limit) = self.send_limit {
+            h = Box::new(SendLimitedHandler { inner: h, limit });
+        }
+        if let Some(bound) = self.batch_watchdog {
+            h = Box::new(WatchedHandler::new(h, bound));
+        }
+        h
     }
 }
 
 struct ServerCore {
     server: *mut grpc_server,
     bind_addrs: Vec<(String, u16)>,
+    listen_addrs: Vec<ListenAddr>,
     slots_per_cq: usize,
+    max_slots_per_cq: usize,
     shutdown: AtomicBool,
+    tasks: TaskGroup,
+    /// The handlers of all the methods with a version that is bumped on
+    /// every change. Completion queues keep their own replicas, as handlers
+    /// are not `Sync`, and refresh them once the version changes.
+    handlers: Mutex<HashMap<&'static [u8], BoxHandler>>,
+    handlers_version: AtomicUsize,
+    // Credentials may be used by listeners until the server is destroyed.
+    _binders: Vec<Binder>,
+    cq_guards: Vec<CompletionQueueGuard>,
 }
 
 impl Drop for ServerCore {
@@ -386,12 +1622,88 @@ impl Drop for ServerCore {
 unsafe impl Send for ServerCore {}
 unsafe impl Sync for ServerCore {}
 
+impl ServerCore {
+    fn replicate_handlers(&self) -> Registry {
+        let handlers = self.handlers.lock().unwrap();
+        Registry {
+            version: self.handlers_version.load(Ordering::SeqCst),
+            handlers: handlers.iter().map(|(k, v)| (*k, v.box_clone())).collect(),
+        }
+    }
+
+    fn update_handlers<F: FnOnce(&mut HashMap<&'static [u8], BoxHandler>)>(&self, f: F) {
+        let mut handlers = self.handlers.lock().unwrap();
+        f(&mut handlers);
+        self.handlers_version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The replica of the handlers of a completion queue.
+struct Registry {
+    version: usize,
+    handlers: HashMap<&'static [u8], BoxHandler>,
+}
+
 pub type BoxHandler = Box<dyn CloneableHandler>;
 
+/// The request slots of a completion queue.
+///
+/// The number of slots grows by one when all of them are taken, up to
+/// `max`, and shrinks back to `min` when there are enough idle slots.
+struct RequestSlots {
+    idle: AtomicUsize,
+    total: AtomicUsize,
+    min: usize,
+    max: usize,
+}
+
+impl RequestSlots {
+    fn new(min: usize, max: usize) -> RequestSlots {
+        RequestSlots {
+            idle: AtomicUsize::new(0),
+            total: AtomicUsize::new(min),
+            min,
+            max,
+        }
+    }
+
+    /// A slot is requested, and is idle until a call comes.
+    fn request(&self) {
+        self.idle.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// A slot is taken by a call, returns true if a new slot should be
+    /// requested.
+    fn take(&self) -> bool {
+        if self.idle.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return false;
+        }
+        let total = self.total.load(Ordering::SeqCst);
+        total < self.max
+            && self
+                .total
+                .compare_exchange(total, total + 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+    }
+
+    /// The call of a taken slot is dispatched, returns true if the slot
+    /// should be requested again.
+    fn release(&self) -> bool {
+        let total = self.total.load(Ordering::SeqCst);
+        if total <= self.min || self.idle.load(Ordering::SeqCst) < self.min {
+            return true;
+        }
+        self.total
+            .compare_exchange(total, total - 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+    }
+}
+
 #[derive(Clone)]
 pub struct RequestCallContext {
     server: Arc<ServerCore>,
-    registry: Arc<UnsafeCell<HashMap<&'static [u8], BoxHandler>>>,
+    registry: Arc<UnsafeCell<Registry>>,
+    slots: Arc<RequestSlots>,
 }
 
 impl RequestCallContext {
@@ -400,8 +1712,19 @@ impl RequestCallContext {
     #[inline]
     pub unsafe fn get_handler(&mut self, path: &[u8]) -> Option<&mut BoxHandler> {
         let registry = &mut *self.registry.get();
+        if registry.version != self.server.handlers_version.load(Ordering::SeqCst) {
+            *registry = self.server.replicate_handlers();
+        }
+        let registry = &mut registry.handlers;
+        if !registry.contains_key(path) {
+            return registry.get_mut(FALLBACK_METHOD);
+        }
         registry.get_mut(path)
     }
+
+    pub fn task_group(&self) -> TaskGroup {
+        self.server.tasks.clone()
+    }
 }
 
 // Apparently, its life time is guaranteed by the ref count, hence is safe to be sent
@@ -413,16 +1736,19 @@ pub fn request_call(ctx: RequestCallContext, cq: &CompletionQueue) {
     if ctx.server.shutdown.load(Ordering::Relaxed) {
         return;
     }
-    let cq_ref = match cq.borrow() {
+    let cq_ref = match cq.try_ref() {
         // Shutting down, skip.
         Err(_) => return,
         Ok(c) => c,
     };
     let server_ptr = ctx.server.server;
+    let slots = ctx.slots.clone();
     let prom = CallTag::request(ctx);
     let request_ptr = prom.request_ctx().unwrap().as_ptr();
     let prom_box = Box::new(prom);
     let tag = Box::into_raw(prom_box);
+    // The call may arrive and take the slot before the function returns.
+    slots.request();
     let code = unsafe {
         grpc_sys::grpcwrap_server_request_call(
             server_ptr,
@@ -437,9 +1763,54 @@ pub fn request_call(ctx: RequestCallContext, cq: &CompletionQueue) {
     }
 }
 
+/// Called when a call comes in a request slot, a new slot is requested if
+/// all of them are taken.
+pub fn on_call_arrived(ctx: &RequestCallContext, cq: &CompletionQueue) {
+    if ctx.slots.take() {
+        request_call(ctx.clone(), cq);
+    }
+}
+
+/// Called when the call of a request slot is dispatched, the slot is
+/// requested again unless there are enough idle slots.
+pub fn release_request_slot(ctx: RequestCallContext, cq: &CompletionQueue) {
+    if ctx.slots.release() {
+        request_call(ctx, cq);
+    }
+}
+
 /// A `Future` that will resolve when shutdown completes.
 pub struct ShutdownFuture {
     cq_f: CqFuture<()>,
+    core: Arc<ServerCore>,
+}
+
+impl ShutdownFuture {
+    /// Get the count of futures spawned by [`RpcContext::spawn`] that are
+    /// not finished yet.
+    ///
+    /// [`RpcContext::spawn`]: struct.RpcContext.html#method.spawn
+    pub fn outstanding_tasks(&self) -> usize {
+        self.core.tasks.outstanding()
+    }
+
+    /// Wait for the shutdown and all the futures spawned by [`RpcContext::spawn`]
+    /// to finish.
+    ///
+    /// If the futures are still running after `grace_period` since the
+    /// returned future is polled, all the calls are cancelled and the running
+    /// futures are dropped. The returned future resolves to the count of
+    /// futures that are cancelled.
+    ///
+    /// [`RpcContext::spawn`]: struct.RpcContext.html#method.spawn
+    pub fn join_tasks(self, grace_period: Duration) -> JoinTasks {
+        JoinTasks {
+            shutdown: self,
+            grace_period,
+            delay: None,
+            cancelled: None,
+        }
+    }
 }
 
 impl Future for ShutdownFuture {
@@ -452,6 +1823,47 @@ impl Future for ShutdownFuture {
     }
 }
 
+/// A `Future` that will resolve when shutdown completes and all the spawned
+/// futures are finished or cancelled.
+///
+/// It's created by [`ShutdownFuture::join_tasks`].
+///
+/// [`ShutdownFuture::join_tasks`]: struct.ShutdownFuture.html#method.join_tasks
+#[must_use = "futures do nothing unless polled"]
+pub struct JoinTasks {
+    shutdown: ShutdownFuture,
+    grace_period: Duration,
+    delay: Option<Delay>,
+    cancelled: Option<usize>,
+}
+
+impl Future for JoinTasks {
+    type Item = usize;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<usize, Error> {
+        if self.delay.is_none() {
+            self.delay = Some(Delay::new(Instant::now() + self.grace_period));
+        }
+        let core = self.shutdown.core.clone();
+        let tasks = &core.tasks;
+        if self.cancelled.is_none() {
+            if let Ok(Async::Ready(())) = self.delay.as_mut().unwrap().poll() {
+                // Calls need to be cancelled so that futures waiting for
+                // them can be woken up.
+                self.cancelled = Some(tasks.outstanding());
+                tasks.cancel();
+                unsafe { grpc_sys::grpc_server_cancel_all_calls(core.server) }
+            }
+        }
+        try_ready!(self.shutdown.poll());
+        if let Async::NotReady = tasks.poll_idle() {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(self.cancelled.unwrap_or(0)))
+    }
+}
+
 /// A gRPC server.
 ///
 /// A single server can serve arbitrary number of services and can listen on more than one port.
@@ -460,26 +1872,40 @@ impl Future for ShutdownFuture {
 pub struct Server {
     env: Arc<Environment>,
     core: Arc<ServerCore>,
-    handlers: HashMap<&'static [u8], BoxHandler>,
+    wrapper: HandlerWrapper,
+    file_descriptors: FileDescriptors,
+    channelz_id: Option<u64>,
+    listeners: Vec<TcpListener>,
+    acceptors: Vec<JoinHandle<()>>,
+    stop_accepting: Arc<AtomicBool>,
 }
 
 impl Server {
     /// Shutdown the server asynchronously.
     pub fn shutdown(&mut self) -> ShutdownFuture {
+        // Connections can't be added once the server is shutting down.
+        self.stop_accepting.store(true, Ordering::SeqCst);
+        for acceptor in self.acceptors.drain(..) {
+            let _ = acceptor.join();
+        }
+        self.listeners.clear();
         let (cq_f, prom) = CallTag::shutdown_pair();
         let prom_box = Box::new(prom);
         let tag = Box::into_raw(prom_box);
         unsafe {
-            // Since env still exists, no way can cq been shutdown.
-            let cq_ref = self.env.completion_queues()[0].borrow().unwrap();
+            // The queues are held by the core, so they are not shutdown even if
+            // the environment is.
             grpc_sys::grpc_server_shutdown_and_notify(
                 self.core.server,
-                cq_ref.as_ptr(),
+                self.core.cq_guards[0].as_ptr(),
                 tag as *mut _,
             )
         }
         self.core.shutdown.store(true, Ordering::SeqCst);
-        ShutdownFuture { cq_f }
+        ShutdownFuture {
+            cq_f,
+            core: self.core.clone(),
+        }
     }
 
     /// Cancel all in-progress calls.
@@ -496,26 +1922,179 @@ impl Server {
             for cq in self.env.completion_queues() {
                 // Handlers are Send and Clone, but not Sync. So we need to
                 // provide a replica for each completion queue.
-                let registry = self
-                    .handlers
-                    .iter()
-                    .map(|(k, v)| (k.to_owned(), v.box_clone()))
-                    .collect();
+                let registry = self.core.replicate_handlers();
                 let rc = RequestCallContext {
                     server: self.core.clone(),
                     registry: Arc::new(UnsafeCell::new(registry)),
+                    slots: Arc::new(RequestSlots::new(
+                        self.core.slots_per_cq,
+                        self.core.max_slots_per_cq,
+                    )),
                 };
                 for _ in 0..self.core.slots_per_cq {
                     request_call(rc.clone(), cq);
                 }
             }
         }
+        #[cfg(unix)]
+        for listener in self.listeners.drain(..) {
+            let (core, stop) = (self.core.clone(), self.stop_accepting.clone());
+            let acceptor = std::thread::Builder::new()
+                .name("grpc-acceptor".to_owned())
+                .spawn(move || accept_connections(core, listener, stop))
+                .unwrap();
+            self.acceptors.push(acceptor);
+        }
+    }
+
+    /// Add a service to the server, it can be called after the server is
+    /// started.
+    ///
+    /// The methods replace the ones registered with the same names, calls
+    /// in progress still use the old handlers. Handlers are wrapped with the
+    /// limits and stats handler set by the builder, like the ones registered
+    /// by [`ServerBuilder::register_service`].
+    pub fn add_service(&mut self, service: Service) {
+        let (wrapper, new_handlers) = (&self.wrapper, service.handlers);
+        self.core.update_handlers(|handlers| {
+            for (name, h) in new_handlers {
+                handlers.insert(name, wrapper.wrap(name, h));
+            }
+        });
+        add_file_descriptors(&self.file_descriptors, service.file_descriptors);
+    }
+
+    /// Remove all the methods of the service with the full name, like
+    /// `helloworld.Greeter`. Returns false if the service is not found.
+    ///
+    /// Following calls to the methods are handled like the ones to any other
+    /// unknown methods, calls in progress are not affected. The descriptors
+    /// of the service are still kept, as they may be shared by others.
+    pub fn remove_service(&mut self, name: &str) -> bool {
+        let prefix = format!("/{}/", name);
+        let mut removed = false;
+        self.core.update_handlers(|handlers| {
+            handlers.retain(|k, _| {
+                let matched = k.starts_with(prefix.as_bytes());
+                removed |= matched;
+                !matched
+            });
+        });
+        removed
+    }
+
+    /// Get the count of futures spawned by [`RpcContext::spawn`] that are
+    /// not finished yet.
+    ///
+    /// [`RpcContext::spawn`]: struct.RpcContext.html#method.spawn
+    pub fn outstanding_tasks(&self) -> usize {
+        self.core.tasks.outstanding()
     }
 
     /// Get binded addresses.
+    ///
+    /// They are the hosts given to the builder with the ports that are
+    /// actually bound, the port of a Unix domain socket is 0.
     pub fn bind_addrs(&self) -> &[(String, u16)] {
         &self.core.bind_addrs
     }
+
+    /// Get the diagnostics of calls if they are enabled by
+    /// `ServerBuilder::enable_call_diagnostics`.
+    pub fn call_diagnostics(&self) -> Option<&CallDiagnostics> {
+        self.wrapper.diagnostics.as_deref()
+    }
+
+    /// Get the addresses the server is listening on.
+    ///
+    /// Unlike [`Server::bind_addrs`], the hosts are resolved, so binding to
+    /// `localhost` may listen on both the IPv4 and IPv6 loopback addresses.
+    pub fn listen_addrs(&self) -> &[ListenAddr] {
+        &self.core.listen_addrs
+    }
+
+    /// Get the channelz id of the server.
+    ///
+    /// `None` is returned if channelz is disabled.
+    pub fn channelz_id(&self) -> Option<u64> {
+        self.channelz_id
+    }
+
+    /// Get the channelz state of the server, including its call counts and
+    /// references to listen sockets.
+    ///
+    /// Sockets of accepted connections can be listed by
+    /// `channelz::get_server_sockets`. See [`channelz`](channelz/index.html)
+    /// for the format.
+    pub fn channelz(&self) -> Option<String> {
+        self.channelz_id.and_then(channelz::get_server)
+    }
+
+    /// Build the admin services of the server, see
+    /// [`ServerBuilder::add_admin_services`].
+    ///
+    /// The service can be registered to a separate server, which usually
+    /// listens on a local port or uses its own credentials.
+    #[cfg(feature = "protobuf-codec")]
+    pub fn admin_services(&self, health: &HealthService) -> Service {
+        admin_services(health, &self.file_descriptors)
+    }
+
+    /// Get the serialized `FileDescriptorSet` of all the registered services.
+    ///
+    /// It contains the descriptors added by
+    /// [`ServiceBuilder::add_file_descriptor`] in the order of registration,
+    /// which is the same data a reflection service would serve. Services
+    /// registered without descriptors are not included.
+    pub fn file_descriptor_set(&self) -> Vec<u8> {
+        encode_file_descriptor_set(&self.file_descriptors.read().unwrap())
+    }
+
+    /// Write the `FileDescriptorSet` of all the registered services to the
+    /// file, see [`Server::file_descriptor_set`].
+    pub fn write_file_descriptor_set<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.file_descriptor_set())
+    }
+}
+
+/// Descriptors of the registered services, which are shared with the
+/// reflection service.
+pub(crate) type FileDescriptors = Arc<RwLock<Vec<Vec<u8>>>>;
+
+#[cfg(feature = "protobuf-codec")]
+fn admin_services(health: &HealthService, descriptors: &FileDescriptors) -> Service {
+    let builder = health.add_handlers(ServiceBuilder::new());
+    ReflectionService::new(descriptors.clone())
+        .add_handlers(builder)
+        .build()
+}
+
+fn add_file_descriptors(descriptors: &FileDescriptors, new: Vec<Vec<u8>>) {
+    let mut descriptors = descriptors.write().unwrap();
+    // Services generated from the same file share descriptors.
+    for fd in new {
+        if !descriptors.contains(&fd) {
+            descriptors.push(fd);
+        }
+    }
+}
+
+/// Encode the file descriptors as the repeated field `file = 1` of a
+/// `FileDescriptorSet`.
+fn encode_file_descriptor_set(files: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(files.iter().map(|f| f.len() + 6).sum());
+    for f in files {
+        // Field number 1 with wire type 2 (length-delimited).
+        buf.push(0x0a);
+        let mut len = f.len();
+        while len >= 0x80 {
+            buf.push((len & 0x7f) as u8 | 0x80);
+            len >>= 7;
+        }
+        buf.push(len as u8);
+        buf.extend_from_slice(f);
+    }
+    buf
 }
 
 impl Drop for Server {
@@ -536,7 +2115,8 @@ impl Debug for Server {
 
 #[cfg(test)]
 mod tests {
-    use super::join_host_port;
+    use super::{encode_file_descriptor_set, join_host_port, RequestSlots};
+    use std::sync::atomic::Ordering;
 
     #[test]
     fn test_join_host_port() {
@@ -555,4 +2135,41 @@ mod tests {
             assert_eq!(join_host_port(h, *p), e.to_owned());
         }
     }
+
+    #[test]
+    fn test_encode_file_descriptor_set() {
+        assert!(encode_file_descriptor_set(&[]).is_empty());
+        let files = vec![b"ab".to_vec(), vec![7; 300]];
+        let buf = encode_file_descriptor_set(&files);
+        assert_eq!(&buf[..4], &[0x0a, 2, b'a', b'b']);
+        assert_eq!(&buf[4..7], &[0x0a, 0xac, 0x02]);
+        assert_eq!(&buf[7..], &files[1][..]);
+    }
+
+    #[test]
+    fn test_request_slots() {
+        let slots = RequestSlots::new(1, 3);
+        slots.request();
+        // Slots grow when all of them are taken.
+        for _ in 0..2 {
+            assert!(slots.take());
+            slots.request();
+        }
+        assert!(!slots.take());
+
+        // The first released slot is requested again as none is idle, the
+        // others are dropped until the minimum is left.
+        assert!(slots.release());
+        slots.request();
+        assert!(!slots.release());
+        assert!(!slots.release());
+        assert_eq!(slots.total.load(Ordering::SeqCst), 1);
+        assert_eq!(slots.idle.load(Ordering::SeqCst), 1);
+
+        // No scaling if the maximum is the minimum.
+        let slots = RequestSlots::new(2, 2);
+        slots.request();
+        assert!(!slots.take());
+        assert!(slots.release());
+    }
 }
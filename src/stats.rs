@@ -0,0 +1,568 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hooks for collecting metrics of calls.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::call::server::RpcContext;
+use crate::call::{MessageReader, MethodType, RpcStatusCode};
+use crate::diagnostics::CallDiagnostics;
+use crate::server::{BoxHandler, CloneableHandler};
+
+/// The side of a call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallSide {
+    Client,
+    Server,
+}
+
+/// Information of a call passed to [`StatsHandler`].
+#[derive(Debug)]
+pub struct CallInfo {
+    method: String,
+    side: CallSide,
+    start_time: Instant,
+}
+
+impl CallInfo {
+    /// The full method name, like `/helloworld.Greeter/SayHello`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn side(&self) -> CallSide {
+        self.side
+    }
+
+    /// The time the call is started.
+    pub fn start_time(&self) -> Instant {
+        self.start_time
+    }
+}
+
+/// Statistics of a finished call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallEnd {
+    /// The status code of the call.
+    ///
+    /// It's `CANCELLED` if the call is dropped before the status is known.
+    pub status: RpcStatusCode,
+    /// The time since the call is started.
+    pub elapsed: Duration,
+    pub sent_messages: usize,
+    /// The count of sent bytes before compression.
+    pub sent_bytes: usize,
+    pub received_messages: usize,
+    /// The count of received bytes after decompression.
+    pub received_bytes: usize,
+}
+
+/// A handler that is notified of the events of calls, which can be used for
+/// exporting metrics to systems like Prometheus or OpenTelemetry.
+///
+/// It can be set by `ChannelBuilder::stats_handler` for the calls made by a
+/// channel, and `ServerBuilder::stats_handler` for the calls handled by a
+/// server. All the methods are called in the thread that drives the call,
+/// usually the polling thread, so they should not block.
+pub trait StatsHandler: Send + Sync {
+    /// Called when a call is started.
+    fn call_start(&self, _call: &CallInfo) {}
+
+    /// Called when a message of `bytes` is going to be sent.
+    fn message_sent(&self, _call: &CallInfo, _bytes: usize) {}
+
+    /// Called when a message of `bytes` is received.
+    fn message_received(&self, _call: &CallInfo, _bytes: usize) {}
+
+    /// Called once the status of the call is sent or received.
+    fn call_end(&self, _call: &CallInfo, _end: &CallEnd) {}
+
+    /// Called when a host of a `HostPool` is picked, with the count of its
+    /// in flight picks including the new one, and the time the pick has
+    /// been queued.
+    fn host_picked(&self, _host: &str, _in_flight: usize, _queue_wait: Duration) {}
+}
+
+/// A [`StatsHandler`] that ignores all events.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopStatsHandler;
+
+impl StatsHandler for NoopStatsHandler {}
+
+/// Statistics recorder of a call.
+pub(crate) struct CallStats {
+    handler: Arc<dyn StatsHandler>,
+    info: CallInfo,
+    sent_messages: AtomicUsize,
+    sent_bytes: AtomicUsize,
+    received_messages: AtomicUsize,
+    received_bytes: AtomicUsize,
+    ended: AtomicBool,
+}
+
+impl CallStats {
+    pub fn new(handler: Arc<dyn StatsHandler>, method: &[u8], side: CallSide) -> Arc<CallStats> {
+        let info = CallInfo {
+            method: String::from_utf8_lossy(method).into_owned(),
+            side,
+            start_time: Instant::now(),
+        };
+        handler.call_start(&info);
+        Arc::new(CallStats {
+            handler,
+            info,
+            sent_messages: AtomicUsize::new(0),
+            sent_bytes: AtomicUsize::new(0),
+            received_messages: AtomicUsize::new(0),
+            received_bytes: AtomicUsize::new(0),
+            ended: AtomicBool::new(false),
+        })
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.handler.message_sent(&self.info, bytes);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.handler.message_received(&self.info, bytes);
+    }
+
+    /// Report the end of the call, only the first status is reported.
+    pub fn finish(&self, status: RpcStatusCode) {
+        if self.ended.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let end = CallEnd {
+            status,
+            elapsed: self.info.start_time.elapsed(),
+            sent_messages: self.sent_messages.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            received_messages: self.received_messages.load(Ordering::Relaxed),
+            received_bytes: self.received_bytes.load(Ordering::Relaxed),
+        };
+        self.handler.call_end(&self.info, &end);
+    }
+}
+
+impl Drop for CallStats {
+    fn drop(&mut self) {
+        self.finish(RpcStatusCode::CANCELLED);
+    }
+}
+
+/// A handler that records the statistics of calls to the wrapped handler.
+///
+/// At least one of the handler and the diagnostics should be set.
+pub(crate) struct StatsRecordingHandler {
+    inner: BoxHandler,
+    handler: Option<Arc<dyn StatsHandler>>,
+    diagnostics: Option<Arc<CallDiagnostics>>,
+}
+
+impl StatsRecordingHandler {
+    pub fn new(
+        inner: BoxHandler,
+        handler: Option<Arc<dyn StatsHandler>>,
+        diagnostics: Option<Arc<CallDiagnostics>>,
+    ) -> StatsRecordingHandler {
+        StatsRecordingHandler {
+            inner,
+            handler,
+            diagnostics,
+        }
+    }
+}
+
+impl CloneableHandler for StatsRecordingHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        let handler = match self.diagnostics {
+            Some(ref d) => d.recorder(&ctx, self.handler.clone()),
+            None => self.handler.clone().unwrap(),
+        };
+        let stats = CallStats::new(handler, ctx.method(), CallSide::Server);
+        if let Some(ref req) = reqs {
+            stats.received(req.pending_bytes_count());
+        }
+        ctx.set_call_stats(stats);
+        self.inner.handle(ctx, reqs)
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(StatsRecordingHandler {
+            inner: self.inner.box_clone(),
+            handler: self.handler.clone(),
+            diagnostics: self.diagnostics.clone(),
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::PrometheusStats;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::sync::Mutex;
+
+    use super::{CallEnd, CallInfo, CallSide, StatsHandler};
+    use crate::call::RpcStatusCode;
+
+    /// Upper bounds in seconds of the buckets of the latency histograms.
+    const LATENCY_BUCKETS: &[f64] = &[
+        0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ];
+
+    fn code_name(code: RpcStatusCode) -> &'static str {
+        match code {
+            RpcStatusCode::OK => "OK",
+            RpcStatusCode::CANCELLED => "Canceled",
+            RpcStatusCode::UNKNOWN => "Unknown",
+            RpcStatusCode::INVALID_ARGUMENT => "InvalidArgument",
+            RpcStatusCode::DEADLINE_EXCEEDED => "DeadlineExceeded",
+            RpcStatusCode::NOT_FOUND => "NotFound",
+            RpcStatusCode::ALREADY_EXISTS => "AlreadyExists",
+            RpcStatusCode::PERMISSION_DENIED => "PermissionDenied",
+            RpcStatusCode::RESOURCE_EXHAUSTED => "ResourceExhausted",
+            RpcStatusCode::FAILED_PRECONDITION => "FailedPrecondition",
+            RpcStatusCode::ABORTED => "Aborted",
+            RpcStatusCode::OUT_OF_RANGE => "OutOfRange",
+            RpcStatusCode::UNIMPLEMENTED => "Unimplemented",
+            RpcStatusCode::INTERNAL => "Internal",
+            RpcStatusCode::UNAVAILABLE => "Unavailable",
+            RpcStatusCode::DATA_LOSS => "DataLoss",
+            RpcStatusCode::UNAUTHENTICATED => "Unauthenticated",
+            _ => "Unknown",
+        }
+    }
+
+    /// Split `/package.Service/Method` into the service and the method.
+    fn split_method(method: &str) -> (&str, &str) {
+        let method = method.trim_start_matches('/');
+        match method.rfind('/') {
+            Some(pos) => (&method[..pos], &method[pos + 1..]),
+            None => ("", method),
+        }
+    }
+
+    #[derive(Default)]
+    struct MethodMetrics {
+        started: u64,
+        handled: BTreeMap<&'static str, u64>,
+        msg_sent: u64,
+        msg_received: u64,
+        bytes_sent: u64,
+        bytes_received: u64,
+        latency_buckets: Vec<u64>,
+        latency_sum: f64,
+    }
+
+    type Key = (&'static str, String, String);
+    // Name, help and the getter of a counter.
+    type Counter = (&'static str, &'static str, fn(&MethodMetrics) -> u64);
+
+    /// A [`StatsHandler`] that exports the metrics in the Prometheus text
+    /// format, using the same names as the go-grpc-prometheus package.
+    ///
+    /// A handler can be shared by multiple channels and servers, and the
+    /// output of [`PrometheusStats::render`] is meant to be served by the
+    /// metrics endpoint of the application.
+    #[derive(Default)]
+    pub struct PrometheusStats {
+        metrics: Mutex<BTreeMap<Key, MethodMetrics>>,
+    }
+
+    impl PrometheusStats {
+        pub fn new() -> PrometheusStats {
+            PrometheusStats::default()
+        }
+
+        fn update<F: FnOnce(&mut MethodMetrics)>(&self, call: &CallInfo, f: F) {
+            let side = match call.side() {
+                CallSide::Client => "client",
+                CallSide::Server => "server",
+            };
+            let (service, method) = split_method(call.method());
+            let mut metrics = self.metrics.lock().unwrap();
+            let m = metrics
+                .entry((side, service.to_owned(), method.to_owned()))
+                .or_insert_with(|| MethodMetrics {
+                    latency_buckets: vec![0; LATENCY_BUCKETS.len()],
+                    ..MethodMetrics::default()
+                });
+            f(m)
+        }
+
+        /// Render all the metrics in the Prometheus text exposition format.
+        pub fn render(&self) -> String {
+            let metrics = self.metrics.lock().unwrap();
+            let mut out = String::new();
+            for side in &["client", "server"] {
+                let methods: Vec<_> = metrics.iter().filter(|((s, ..), _)| s == side).collect();
+                if methods.is_empty() {
+                    continue;
+                }
+                let counters: &[Counter] = &[
+                    ("started_total", "Total number of RPCs started.", |m| {
+                        m.started
+                    }),
+                    ("msg_sent_total", "Total number of messages sent.", |m| {
+                        m.msg_sent
+                    }),
+                    (
+                        "msg_received_total",
+                        "Total number of messages received.",
+                        |m| m.msg_received,
+                    ),
+                    ("bytes_sent_total", "Total number of bytes sent.", |m| {
+                        m.bytes_sent
+                    }),
+                    (
+                        "bytes_received_total",
+                        "Total number of bytes received.",
+                        |m| m.bytes_received,
+                    ),
+                ];
+                for (name, help, get) in counters {
+                    writeln!(out, "# HELP grpc_{}_{} {}", side, name, help).unwrap();
+                    writeln!(out, "# TYPE grpc_{}_{} counter", side, name).unwrap();
+                    for ((_, service, method), m) in &methods {
+                        writeln!(
+                            out,
+                            "grpc_{}_{}{{grpc_service=\"{}\",grpc_method=\"{}\"}} {}",
+                            side,
+                            name,
+                            service,
+                            method,
+                            get(m)
+                        )
+                        .unwrap();
+                    }
+                }
+
+                writeln!(
+                    out,
+                    "# HELP grpc_{}_handled_total Total number of RPCs completed.",
+                    side
+                )
+                .unwrap();
+                writeln!(out, "# TYPE grpc_{}_handled_total counter", side).unwrap();
+                for ((_, service, method), m) in &methods {
+                    for (code, count) in &m.handled {
+                        writeln!(
+                            out,
+                            "grpc_{}_handled_total{{grpc_service=\"{}\",grpc_method=\"{}\",grpc_code=\"{}\"}} {}",
+                            side, service, method, code, count
+                        )
+                        .unwrap();
+                    }
+                }
+
+                let name = format!("grpc_{}_handling_seconds", side);
+                writeln!(out, "# HELP {} Latency of completed RPCs.", name).unwrap();
+                writeln!(out, "# TYPE {} histogram", name).unwrap();
+                for ((_, service, method), m) in &methods {
+                    let labels = format!("grpc_service=\"{}\",grpc_method=\"{}\"", service, method);
+                    let mut acc = 0;
+                    for (bound, count) in LATENCY_BUCKETS.iter().zip(&m.latency_buckets) {
+                        acc += count;
+                        writeln!(
+                            out,
+                            "{}_bucket{{{},le=\"{}\"}} {}",
+                            name, labels, bound, acc
+                        )
+                        .unwrap();
+                    }
+                    let total: u64 = m.handled.values().sum();
+                    writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, total).unwrap();
+                    writeln!(out, "{}_sum{{{}}} {}", name, labels, m.latency_sum).unwrap();
+                    writeln!(out, "{}_count{{{}}} {}", name, labels, total).unwrap();
+                }
+            }
+            out
+        }
+    }
+
+    impl StatsHandler for PrometheusStats {
+        fn call_start(&self, call: &CallInfo) {
+            self.update(call, |m| m.started += 1);
+        }
+
+        fn message_sent(&self, call: &CallInfo, bytes: usize) {
+            self.update(call, |m| {
+                m.msg_sent += 1;
+                m.bytes_sent += bytes as u64;
+            });
+        }
+
+        fn message_received(&self, call: &CallInfo, bytes: usize) {
+            self.update(call, |m| {
+                m.msg_received += 1;
+                m.bytes_received += bytes as u64;
+            });
+        }
+
+        fn call_end(&self, call: &CallInfo, end: &CallEnd) {
+            let secs = end.elapsed.as_secs_f64();
+            self.update(call, |m| {
+                *m.handled.entry(code_name(end.status)).or_insert(0) += 1;
+                if let Some(i) = LATENCY_BUCKETS.iter().position(|b| secs <= *b) {
+                    m.latency_buckets[i] += 1;
+                }
+                m.latency_sum += secs;
+            });
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::time::{Duration, Instant};
+
+        #[test]
+        fn test_prometheus_stats() {
+            assert_eq!(
+                split_method("/helloworld.Greeter/SayHello"),
+                ("helloworld.Greeter", "SayHello")
+            );
+            assert_eq!(split_method("SayHello"), ("", "SayHello"));
+
+            let stats = PrometheusStats::new();
+            let call = CallInfo {
+                method: "/helloworld.Greeter/SayHello".to_owned(),
+                side: CallSide::Server,
+                start_time: Instant::now(),
+            };
+            stats.call_start(&call);
+            stats.message_received(&call, 10);
+            stats.message_sent(&call, 20);
+            let end = CallEnd {
+                status: RpcStatusCode::OK,
+                elapsed: Duration::from_millis(20),
+                sent_messages: 1,
+                sent_bytes: 20,
+                received_messages: 1,
+                received_bytes: 10,
+            };
+            stats.call_end(&call, &end);
+
+            let out = stats.render();
+            let labels = "grpc_service=\"helloworld.Greeter\",grpc_method=\"SayHello\"";
+            for line in &[
+                format!("grpc_server_started_total{{{}}} 1", labels),
+                format!("grpc_server_bytes_sent_total{{{}}} 20", labels),
+                format!("grpc_server_bytes_received_total{{{}}} 10", labels),
+                format!("grpc_server_handled_total{{{},grpc_code=\"OK\"}} 1", labels),
+                format!(
+                    "grpc_server_handling_seconds_bucket{{{},le=\"0.01\"}} 0",
+                    labels
+                ),
+                format!(
+                    "grpc_server_handling_seconds_bucket{{{},le=\"0.025\"}} 1",
+                    labels
+                ),
+                format!("grpc_server_handling_seconds_count{{{}}} 1", labels),
+            ] {
+                assert!(out.contains(line.as_str()), "{} not found in {}", line, out);
+            }
+            assert!(!out.contains("grpc_client"), "{}", out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+        ends: Mutex<Vec<CallEnd>>,
+    }
+
+    impl StatsHandler for Recorder {
+        fn call_start(&self, call: &CallInfo) {
+            let event = format!("start {} {:?}", call.method(), call.side());
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn message_sent(&self, _: &CallInfo, bytes: usize) {
+            self.events.lock().unwrap().push(format!("sent {}", bytes));
+        }
+
+        fn message_received(&self, _: &CallInfo, bytes: usize) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("received {}", bytes));
+        }
+
+        fn call_end(&self, _: &CallInfo, end: &CallEnd) {
+            self.events.lock().unwrap().push("end".to_owned());
+            self.ends.lock().unwrap().push(*end);
+        }
+    }
+
+    #[test]
+    fn test_call_stats() {
+        let recorder = Arc::new(Recorder::default());
+        let stats = CallStats::new(recorder.clone(), b"/test/Get", CallSide::Client);
+        stats.sent(3);
+        stats.received(5);
+        stats.received(7);
+        stats.finish(RpcStatusCode::NOT_FOUND);
+        // Only the first status is reported.
+        stats.finish(RpcStatusCode::OK);
+        drop(stats);
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            vec![
+                "start /test/Get Client",
+                "sent 3",
+                "received 5",
+                "received 7",
+                "end"
+            ]
+        );
+        let end = recorder.ends.lock().unwrap()[0];
+        assert_eq!(end.status, RpcStatusCode::NOT_FOUND);
+        assert_eq!(
+            (
+                end.sent_messages,
+                end.sent_bytes,
+                end.received_messages,
+                end.received_bytes
+            ),
+            (1, 3, 2, 12)
+        );
+
+        // Calls dropped without status are reported as cancelled.
+        let stats = CallStats::new(recorder.clone(), b"/test/Get", CallSide::Server);
+        drop(stats);
+        let ends = recorder.ends.lock().unwrap();
+        assert_eq!(ends.len(), 2);
+        assert_eq!(ends[1].status, RpcStatusCode::CANCELLED);
+    }
+}
@@ -32,13 +32,14 @@ impl Request {
 
     pub fn resolve(mut self, cq: &CompletionQueue, success: bool) {
         let mut rc = self.ctx.take_request_call_context().unwrap();
+        server::on_call_arrived(&rc, cq);
         if !success {
-            server::request_call(rc, cq);
+            server::release_request_slot(rc, cq);
             return;
         }
 
         match self.ctx.handle_stream_req(cq, &mut rc) {
-            Ok(_) => server::request_call(rc, cq),
+            Ok(_) => server::release_request_slot(rc, cq),
             Err(ctx) => ctx.handle_unary_req(rc, cq),
         }
     }
@@ -65,13 +66,13 @@ impl UnaryRequest {
     pub fn resolve(mut self, cq: &CompletionQueue, success: bool) {
         let mut rc = self.ctx.take_request_call_context().unwrap();
         if !success {
-            server::request_call(rc, cq);
+            server::release_request_slot(rc, cq);
             return;
         }
 
         let reader = self.ctx.batch_ctx_mut().recv_message();
         self.ctx.handle(&mut rc, cq, reader);
-        server::request_call(rc, cq);
+        server::release_request_slot(rc, cq);
     }
 }
 
@@ -20,7 +20,7 @@ use futures::{Async, Future};
 use super::lock::SpinLock;
 use super::CallTag;
 use crate::call::Call;
-use crate::cq::CompletionQueue;
+use crate::cq::{CompletionQueue, SpawnSlot};
 use crate::error::{Error, Result};
 use crate::grpc_sys::{self, grpc_call_error};
 
@@ -41,7 +41,7 @@ impl Kicker {
 
     /// Kick its completion queue.
     pub fn kick(&self, tag: Box<CallTag>) -> Result<()> {
-        let _ref = self.call.cq.borrow()?;
+        let _ref = self.call.cq.try_ref()?;
         unsafe {
             let ptr = Box::into_raw(tag);
             let status = grpc_sys::grpcwrap_call_kick_completion_queue(self.call.call, ptr as _);
@@ -65,7 +65,13 @@ impl Clone for Kicker {
         };
         let cq = self.call.cq.clone();
         Kicker {
-            call: Call { call, cq },
+            call: Call {
+                call,
+                cq,
+                stats: None,
+                watchdog: None,
+                max_send_message_len: None,
+            },
         }
     }
 }
@@ -182,6 +188,30 @@ impl<'a> Executor<'a> {
     where
         F: Future<Item = (), Error = ()> + Send + 'static,
     {
+        let slot = self.cq.spawn_slot(false).unwrap();
+        self.spawn_in_slot(f, kicker, slot)
+    }
+
+    /// Like `spawn`, but fails with `Error::Overloaded` if the spawn limit of
+    /// the completion queue is reached.
+    pub fn try_spawn<F>(&self, f: F, kicker: Kicker) -> Result<()>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let slot = self.cq.spawn_slot(true)?;
+        self.spawn_in_slot(f, kicker, slot);
+        Ok(())
+    }
+
+    fn spawn_in_slot<F>(&self, f: F, kicker: Kicker, slot: SpawnSlot)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        // The slot is released once the future is finished or dropped.
+        let f = f.then(move |r| {
+            drop(slot);
+            r
+        });
         let s = executor::spawn(Box::new(f) as BoxFuture<_, _>);
         let notify = Arc::new(SpawnNotify::new(s, kicker, self.cq.worker_id()));
         poll(&notify, false)
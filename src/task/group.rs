@@ -0,0 +1,197 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A group that tracks all the futures spawned for a server.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::task::{self, AtomicTask, Task};
+use futures::{Async, Future, Poll};
+
+#[derive(Default)]
+struct State {
+    next_id: usize,
+    tasks: HashMap<usize, Arc<AtomicTask>>,
+    waiters: Vec<Task>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    cancelled: AtomicBool,
+}
+
+/// Tracks futures so that they can be waited or cancelled as a whole.
+#[derive(Clone, Default)]
+pub struct TaskGroup {
+    inner: Arc<Inner>,
+}
+
+impl TaskGroup {
+    pub fn new() -> TaskGroup {
+        TaskGroup::default()
+    }
+
+    /// Wrap the future so that it's tracked by the group until it's dropped.
+    pub fn track<F>(&self, f: F) -> Tracked<F>
+    where
+        F: Future<Item = (), Error = ()>,
+    {
+        let task = Arc::new(AtomicTask::new());
+        let mut state = self.inner.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+        state.tasks.insert(id, task.clone());
+        Tracked {
+            f,
+            id,
+            task,
+            group: self.clone(),
+        }
+    }
+
+    /// Get the count of futures that are not finished yet.
+    pub fn outstanding(&self) -> usize {
+        self.inner.state.lock().unwrap().tasks.len()
+    }
+
+    /// Cancel all the outstanding futures.
+    ///
+    /// The futures will be dropped the next time they are polled, and they
+    /// are woken up immediately. Futures that are tracked after cancellation
+    /// are dropped on their first poll.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let state = self.inner.state.lock().unwrap();
+        for t in state.tasks.values() {
+            t.notify();
+        }
+    }
+
+    /// Check whether all tracked futures are finished, registering current
+    /// task to be notified when they are if not.
+    pub fn poll_idle(&self) -> Async<()> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.tasks.is_empty() {
+            return Async::Ready(());
+        }
+        if !state.waiters.iter().any(Task::will_notify_current) {
+            state.waiters.push(task::current());
+        }
+        Async::NotReady
+    }
+
+    fn untrack(&self, id: usize) {
+        let waiters = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.tasks.remove(&id);
+            if !state.tasks.is_empty() {
+                return;
+            }
+            state.waiters.drain(..).collect::<Vec<_>>()
+        };
+        for w in waiters {
+            w.notify();
+        }
+    }
+}
+
+/// A future tracked by a [`TaskGroup`].
+pub struct Tracked<F> {
+    f: F,
+    id: usize,
+    task: Arc<AtomicTask>,
+    group: TaskGroup,
+}
+
+impl<F: Future<Item = (), Error = ()>> Future for Tracked<F> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if self.group.inner.cancelled.load(Ordering::SeqCst) {
+            return Ok(Async::Ready(()));
+        }
+        self.task.register();
+        self.f.poll()
+    }
+}
+
+impl<F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        self.group.untrack(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use futures::sync::oneshot;
+    use std::thread;
+
+    struct Idle(TaskGroup);
+
+    impl Future for Idle {
+        type Item = ();
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<(), ()> {
+            Ok(self.0.poll_idle())
+        }
+    }
+
+    #[test]
+    fn test_task_group() {
+        let group = TaskGroup::new();
+        Idle(group.clone()).wait().unwrap();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let f = group.track(rx.map_err(|_| ()));
+        assert_eq!(group.outstanding(), 1);
+        let h = thread::spawn(move || f.wait());
+        tx.send(()).unwrap();
+        h.join().unwrap().unwrap();
+        assert_eq!(group.outstanding(), 0);
+
+        // Cancelled futures should be resolved without being completed.
+        let (_tx, rx) = oneshot::channel::<()>();
+        let f = group.track(rx.map_err(|_| ()));
+        let h = thread::spawn(move || f.wait());
+        let g = group.clone();
+        thread::spawn(move || g.cancel());
+        Idle(group.clone()).wait().unwrap();
+        h.join().unwrap().unwrap();
+        assert_eq!(group.outstanding(), 0);
+
+        let mut f = group.track(future::empty());
+        assert_eq!(f.poll(), Ok(Async::Ready(())));
+    }
+
+    #[test]
+    fn test_poll_idle_dedup() {
+        let group = TaskGroup::new();
+        let _f = group.track(future::empty());
+        future::lazy(|| {
+            for _ in 0..3 {
+                assert_eq!(group.poll_idle(), Async::NotReady);
+            }
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(group.inner.state.lock().unwrap().waiters.len(), 1);
+    }
+}
@@ -13,8 +13,10 @@
 
 mod callback;
 mod executor;
+mod group;
 mod lock;
 mod promise;
+mod timer;
 
 use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
@@ -24,16 +26,23 @@ use futures::{Async, Future, Poll};
 
 use self::callback::{Abort, Request as RequestCallback, UnaryRequest as UnaryRequestCallback};
 use self::executor::SpawnNotify;
-use self::promise::{Batch as BatchPromise, Shutdown as ShutdownPromise};
+use self::promise::{
+    Batch as BatchPromise, CancelNotifier, Shutdown as ShutdownPromise,
+    WatchState as WatchStatePromise,
+};
 use crate::call::server::RequestContext;
-use crate::call::{BatchContext, Call, MessageReader};
+use crate::call::{BatchContext, BatchGuard, Call, MessageReader};
 use crate::cq::CompletionQueue;
 use crate::error::{Error, Result};
 use crate::server::RequestCallContext;
 
 pub(crate) use self::executor::{Executor, Kicker};
+pub(crate) use self::group::TaskGroup;
 pub use self::lock::SpinLock;
-pub use self::promise::BatchType;
+pub(crate) use self::promise::CancelState;
+pub use self::promise::{BatchType, ResponseMetadata};
+pub use self::timer::{interval, sleep, Delay, Interval};
+pub(crate) use self::timer::{pause_timer, resume_timer};
 
 /// A handle that is used to notify future that the task finishes.
 pub struct NotifyHandle<T> {
@@ -127,13 +136,33 @@ pub enum CallTag {
     Abort(Abort),
     Shutdown(ShutdownPromise),
     Spawn(SpawnNotify),
+    WatchState(WatchStatePromise),
 }
 
 impl CallTag {
     /// Generate a Future/CallTag pair for batch jobs.
-    pub fn batch_pair(ty: BatchType) -> (BatchFuture, CallTag) {
+    ///
+    /// If `metadata` is given, the received metadata will be stored into it
+    /// once the batch finishes. `guard` is notified once the batch is resolved.
+    pub fn batch_pair(
+        ty: BatchType,
+        metadata: Option<Arc<SpinLock<ResponseMetadata>>>,
+        guard: BatchGuard,
+    ) -> (BatchFuture, CallTag) {
         let inner = new_inner();
-        let batch = BatchPromise::new(ty, inner.clone());
+        let batch = BatchPromise::new(ty, inner.clone(), metadata, guard);
+        (CqFuture::new(inner), CallTag::Batch(batch))
+    }
+
+    /// Generate a Future/CallTag pair for the batch that receives the close
+    /// of a server side call, `cancel` is updated once it's resolved.
+    pub fn server_close_pair(
+        cancel: Arc<SpinLock<CancelState>>,
+        guard: BatchGuard,
+    ) -> (BatchFuture, CallTag) {
+        let inner = new_inner();
+        let batch = BatchPromise::new(BatchType::Finish, inner.clone(), None, guard)
+            .with_cancel_notifier(CancelNotifier::new(cancel));
         (CqFuture::new(inner), CallTag::Batch(batch))
     }
 
@@ -150,6 +179,14 @@ impl CallTag {
         (CqFuture::new(inner), CallTag::Shutdown(shutdown))
     }
 
+    /// Generate a Future/CallTag pair for watching the connectivity state of
+    /// a channel.
+    pub fn watch_state_pair() -> (CqFuture<bool>, CallTag) {
+        let inner = new_inner();
+        let watch = WatchStatePromise::new(inner.clone());
+        (CqFuture::new(inner), CallTag::WatchState(watch))
+    }
+
     /// Generate a CallTag for abort call before handler is called.
     pub fn abort(call: Call) -> CallTag {
         CallTag::Abort(Abort::new(call))
@@ -189,6 +226,7 @@ impl CallTag {
             CallTag::Abort(_) => {}
             CallTag::Shutdown(prom) => prom.resolve(success),
             CallTag::Spawn(notify) => notify.resolve(success),
+            CallTag::WatchState(prom) => prom.resolve(success),
         }
     }
 }
@@ -202,6 +240,7 @@ impl Debug for CallTag {
             CallTag::Abort(_) => write!(f, "CallTag::Abort(..)"),
             CallTag::Shutdown(_) => write!(f, "CallTag::Shutdown"),
             CallTag::Spawn(_) => write!(f, "CallTag::Spawn"),
+            CallTag::WatchState(_) => write!(f, "CallTag::WatchState"),
         }
     }
 }
@@ -14,9 +14,13 @@
 use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
 
-use super::Inner;
-use crate::call::{BatchContext, MessageReader, RpcStatusCode};
+use futures::task::{self, Task};
+use futures::{Async, Poll};
+
+use super::{Inner, SpinLock};
+use crate::call::{BatchContext, BatchGuard, MessageReader, RpcStatusCode};
 use crate::error::Error;
+use crate::metadata::Metadata;
 
 /// Batch job type.
 #[derive(PartialEq, Debug)]
@@ -27,6 +31,78 @@ pub enum BatchType {
     Read,
     /// Check the rpc code and then extract one message.
     CheckRead,
+    /// Receive the initial metadata only.
+    RecvHeaders,
+}
+
+/// Metadata received from the remote side of a call.
+#[derive(Default)]
+pub struct ResponseMetadata {
+    /// Initial metadata, `None` if not received yet.
+    pub headers: Option<Metadata>,
+    /// Trailing metadata, `None` if not received yet.
+    pub trailers: Option<Metadata>,
+}
+
+/// Whether a server side call is cancelled, which is known once the call
+/// is finished.
+#[derive(Default)]
+pub struct CancelState {
+    /// `Some(cancelled)` once the call is finished.
+    finished: Option<bool>,
+    tasks: Vec<Task>,
+}
+
+impl CancelState {
+    pub fn is_cancelled(&self) -> bool {
+        self.finished == Some(true)
+    }
+
+    /// Resolves if the call is cancelled, fails if the call is finished
+    /// without being cancelled.
+    pub fn poll_cancelled(&mut self) -> Poll<(), Error> {
+        match self.finished {
+            Some(true) => Ok(Async::Ready(())),
+            Some(false) => Err(Error::RpcFinished(None)),
+            None => {
+                if !self.tasks.iter().any(Task::will_notify_current) {
+                    self.tasks.push(task::current());
+                }
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Updates the `CancelState` once the call is finished.
+///
+/// The call is regarded as cancelled if it's dropped without being resolved.
+pub struct CancelNotifier(Arc<SpinLock<CancelState>>);
+
+impl CancelNotifier {
+    pub fn new(state: Arc<SpinLock<CancelState>>) -> CancelNotifier {
+        CancelNotifier(state)
+    }
+
+    fn finish(&self, cancelled: bool) {
+        let tasks = {
+            let mut state = self.0.lock();
+            if state.finished.is_some() {
+                return;
+            }
+            state.finished = Some(cancelled);
+            std::mem::take(&mut state.tasks)
+        };
+        for t in tasks {
+            t.notify();
+        }
+    }
+}
+
+impl Drop for CancelNotifier {
+    fn drop(&mut self) {
+        self.finish(true);
+    }
 }
 
 /// A promise used to resolve batch jobs.
@@ -34,21 +110,44 @@ pub struct Batch {
     ty: BatchType,
     ctx: BatchContext,
     inner: Arc<Inner<Option<MessageReader>>>,
+    metadata: Option<Arc<SpinLock<ResponseMetadata>>>,
+    cancel: Option<CancelNotifier>,
+    /// The guards that are notified once the batch is resolved.
+    guard: BatchGuard,
 }
 
 impl Batch {
-    pub fn new(ty: BatchType, inner: Arc<Inner<Option<MessageReader>>>) -> Batch {
+    pub fn new(
+        ty: BatchType,
+        inner: Arc<Inner<Option<MessageReader>>>,
+        metadata: Option<Arc<SpinLock<ResponseMetadata>>>,
+        guard: BatchGuard,
+    ) -> Batch {
         Batch {
             ty,
             ctx: BatchContext::new(),
             inner,
+            metadata,
+            cancel: None,
+            guard,
         }
     }
 
+    /// Notify `cancel` once the batch is resolved, the batch should receive
+    /// the close of a server side call.
+    pub fn with_cancel_notifier(mut self, cancel: CancelNotifier) -> Batch {
+        self.cancel = Some(cancel);
+        self
+    }
+
     pub fn context(&self) -> &BatchContext {
         &self.ctx
     }
 
+    pub fn guard_mut(&mut self) -> &mut BatchGuard {
+        &mut self.guard
+    }
+
     fn read_one_msg(&mut self, success: bool) {
         let task = {
             let mut guard = self.inner.lock();
@@ -80,6 +179,42 @@ impl Batch {
         task.map(|t| t.notify());
     }
 
+    fn recv_headers(&mut self, succeed: bool) {
+        let task = {
+            let mut guard = self.inner.lock();
+            if succeed {
+                guard.set_result(Ok(None))
+            } else {
+                guard.set_result(Err(Error::RemoteStopped))
+            }
+        };
+        task.map(|t| t.notify());
+    }
+
+    /// Copy the received metadata out before the batch context is destroyed.
+    ///
+    /// A `CheckRead` batch receives both headers and trailers, while a `Finish`
+    /// batch is only attached with metadata when it receives the status.
+    fn collect_metadata(&self) {
+        let mut metadata = match self.metadata {
+            Some(ref m) => m.lock(),
+            None => return,
+        };
+        match self.ty {
+            BatchType::CheckRead => {
+                metadata.headers = Some(self.ctx.recv_initial_metadata().clone());
+                metadata.trailers = Some(self.ctx.recv_trailing_metadata().clone());
+            }
+            BatchType::Finish => {
+                metadata.trailers = Some(self.ctx.recv_trailing_metadata().clone());
+            }
+            BatchType::RecvHeaders => {
+                metadata.headers = Some(self.ctx.recv_initial_metadata().clone());
+            }
+            BatchType::Read => {}
+        }
+    }
+
     fn handle_unary_response(&mut self) {
         let task = {
             let mut guard = self.inner.lock();
@@ -94,17 +229,29 @@ impl Batch {
     }
 
     pub fn resolve(mut self, success: bool) {
+        if success {
+            self.collect_metadata();
+        }
+        // Report before waking up the future, so the completion is traced
+        // before the operations that follow.
+        self.guard.resolve(success);
         match self.ty {
             BatchType::CheckRead => {
                 assert!(success);
                 self.handle_unary_response();
             }
             BatchType::Finish => {
+                if let Some(cancel) = self.cancel.take() {
+                    cancel.finish(!success || self.ctx.recv_close_on_server_cancelled());
+                }
                 self.finish_response(success);
             }
             BatchType::Read => {
                 self.read_one_msg(success);
             }
+            BatchType::RecvHeaders => {
+                self.recv_headers(success);
+            }
         }
     }
 }
@@ -137,3 +284,23 @@ impl Shutdown {
         task.map(|t| t.notify());
     }
 }
+
+/// A promise used to resolve the result of watching the connectivity state
+/// of a channel, which is whether the state changed before the deadline.
+pub struct WatchState {
+    inner: Arc<Inner<bool>>,
+}
+
+impl WatchState {
+    pub fn new(inner: Arc<Inner<bool>>) -> WatchState {
+        WatchState { inner }
+    }
+
+    pub fn resolve(self, success: bool) {
+        let task = {
+            let mut guard = self.inner.lock();
+            guard.set_result(Ok(success))
+        };
+        task.map(|t| t.notify());
+    }
+}
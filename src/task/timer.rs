@@ -0,0 +1,378 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A simple timer that wakes up futures or runs callbacks when their deadlines
+//! are reached.
+//!
+//! gRPC Core doesn't expose alarms in its C API, so a dedicated thread is used
+//! for tracking deadlines instead.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, Once, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
+
+struct State {
+    deadline: Instant,
+    /// The time of the entry in the queue that is responsible for this state.
+    scheduled: Option<Instant>,
+    fired: bool,
+    task: Option<Task>,
+    callback: Option<Box<dyn FnOnce() + Send>>,
+}
+
+struct Entry {
+    at: Instant,
+    state: Weak<Mutex<State>>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+struct Timer {
+    entries: Mutex<BinaryHeap<Reverse<Entry>>>,
+    cond: Condvar,
+    // Whether the thread should exit, it's only changed with `entries` locked.
+    stopped: AtomicBool,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Timer {
+    fn global() -> &'static Timer {
+        static INIT: Once = Once::new();
+        static mut TIMER: *const Timer = ptr::null();
+
+        INIT.call_once(|| {
+            let timer = Box::new(Timer {
+                entries: Mutex::new(BinaryHeap::new()),
+                cond: Condvar::new(),
+                stopped: AtomicBool::new(false),
+                thread: Mutex::new(None),
+            });
+            unsafe {
+                TIMER = Box::into_raw(timer);
+                *(*TIMER).thread.lock().unwrap() = Some(Timer::spawn());
+            }
+        });
+        unsafe { &*TIMER }
+    }
+
+    fn spawn() -> JoinHandle<()> {
+        thread::Builder::new()
+            .name("grpc-timer".to_owned())
+            .spawn(|| Timer::global().run())
+            .unwrap()
+    }
+
+    /// Stop the timer thread, so that no lock is held by it when forking.
+    fn pause(&self) {
+        let handle = match self.thread.lock().unwrap().take() {
+            Some(h) => h,
+            None => return,
+        };
+        {
+            let _entries = self.entries.lock().unwrap();
+            self.stopped.store(true, AtomicOrdering::SeqCst);
+            self.cond.notify_all();
+        }
+        handle.join().unwrap();
+    }
+
+    /// Restart the timer thread, the delays that expire while it's paused
+    /// fire once it's resumed.
+    fn resume(&self) {
+        let mut thread = self.thread.lock().unwrap();
+        if thread.is_none() {
+            self.stopped.store(false, AtomicOrdering::SeqCst);
+            *thread = Some(Timer::spawn());
+        }
+    }
+
+    fn schedule(&self, at: Instant, state: &Arc<Mutex<State>>) {
+        let mut entries = self.entries.lock().unwrap();
+        let earliest = match entries.peek() {
+            Some(e) => at < (e.0).at,
+            None => true,
+        };
+        entries.push(Reverse(Entry {
+            at,
+            state: Arc::downgrade(state),
+        }));
+        if earliest {
+            self.cond.notify_one();
+        }
+    }
+
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            if self.stopped.load(AtomicOrdering::SeqCst) {
+                return;
+            }
+            let now = Instant::now();
+            let at = match entries.peek() {
+                Some(e) => (e.0).at,
+                None => {
+                    entries = self.cond.wait(entries).unwrap();
+                    continue;
+                }
+            };
+            if at > now {
+                entries = self.cond.wait_timeout(entries, at - now).unwrap().0;
+                continue;
+            }
+
+            let entry = entries.pop().unwrap().0;
+            let state = match entry.state.upgrade() {
+                Some(s) => s,
+                None => continue,
+            };
+            let mut state = state.lock().unwrap();
+            if state.scheduled != Some(entry.at) {
+                // A stale entry, the state has been rescheduled.
+                continue;
+            }
+            if state.deadline <= now {
+                state.fired = true;
+                state.scheduled = None;
+                if let Some(t) = state.task.take() {
+                    t.notify();
+                }
+                if let Some(f) = state.callback.take() {
+                    f();
+                }
+            } else {
+                // Deadline has been extended, try again later.
+                state.scheduled = Some(state.deadline);
+                entries.push(Reverse(Entry {
+                    at: state.deadline,
+                    state: entry.state,
+                }));
+            }
+        }
+    }
+}
+
+/// Stop the timer thread before `fork`, see `Environment::prepare_fork`.
+pub(crate) fn pause_timer() {
+    Timer::global().pause()
+}
+
+/// Restart the timer thread after `fork`, in either the parent or the child.
+pub(crate) fn resume_timer() {
+    Timer::global().resume()
+}
+
+/// A future that resolves once the deadline is reached.
+///
+/// It's driven by a dedicated timer thread, so it can be used anywhere,
+/// including the futures spawned into gRPC poll threads.
+pub struct Delay {
+    state: Arc<Mutex<State>>,
+}
+
+impl Delay {
+    /// Create a delay that resolves at `deadline`.
+    pub fn new(deadline: Instant) -> Delay {
+        Delay::with_callback(deadline, None)
+    }
+
+    /// Create a delay that also runs the callback on the timer thread once
+    /// the deadline is reached, unless the delay is dropped before that.
+    ///
+    /// The callback is called with the internal locks held, so it should be
+    /// quick and must not use any other delay.
+    pub(crate) fn with_callback(
+        deadline: Instant,
+        callback: Option<Box<dyn FnOnce() + Send>>,
+    ) -> Delay {
+        let state = Arc::new(Mutex::new(State {
+            deadline,
+            scheduled: Some(deadline),
+            fired: false,
+            task: None,
+            callback,
+        }));
+        Timer::global().schedule(deadline, &state);
+        Delay { state }
+    }
+
+    /// Get the deadline of the delay.
+    pub fn deadline(&self) -> Instant {
+        self.state.lock().unwrap().deadline
+    }
+
+    /// Reset the deadline, no matter the delay has fired or not.
+    pub fn reset(&mut self, deadline: Instant) {
+        let reschedule = {
+            let mut state = self.state.lock().unwrap();
+            state.deadline = deadline;
+            state.fired = false;
+            // A later deadline will be picked up when the scheduled entry expires.
+            let reschedule = match state.scheduled {
+                Some(s) => deadline < s,
+                None => true,
+            };
+            if reschedule {
+                state.scheduled = Some(deadline);
+            }
+            reschedule
+        };
+        // Timer thread locks the queue before locking a state, so the state
+        // lock must be released before scheduling.
+        if reschedule {
+            Timer::global().schedule(deadline, &self.state);
+        }
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let mut state = self.state.lock().unwrap();
+        if state.fired {
+            return Ok(Async::Ready(()));
+        }
+        if state.task.is_none() || !state.task.as_ref().unwrap().will_notify_current() {
+            state.task = Some(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// Create a future that resolves after `duration`.
+pub fn sleep(duration: Duration) -> Delay {
+    Delay::new(Instant::now() + duration)
+}
+
+/// A stream that yields every period, see [`interval`].
+pub struct Interval {
+    delay: Delay,
+    period: Duration,
+}
+
+/// Create a stream that yields every `period`, the first item is yielded
+/// after a period.
+///
+/// Ticks are scheduled by their previous deadlines, so they don't drift when
+/// the stream is polled late, but missed ticks are skipped.
+pub fn interval(period: Duration) -> Interval {
+    Interval {
+        delay: sleep(period),
+        period,
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<()>, ()> {
+        try_ready!(self.delay.poll());
+        let now = Instant::now();
+        let mut next = self.delay.deadline() + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+        self.delay.reset(next);
+        Ok(Async::Ready(Some(())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_delay() {
+        let start = Instant::now();
+        Delay::new(start + Duration::from_millis(50))
+            .wait()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let mut delay = Delay::new(start + Duration::from_secs(10));
+        delay.reset(Instant::now() + Duration::from_millis(10));
+        delay.wait().unwrap();
+
+        let start = Instant::now();
+        let mut delay = Delay::new(start + Duration::from_millis(10));
+        delay.reset(start + Duration::from_millis(100));
+        delay.wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_interval() {
+        let start = Instant::now();
+        let ticks = interval(Duration::from_millis(20))
+            .take(3)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(ticks.len(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+
+        let start = Instant::now();
+        sleep(Duration::from_millis(30)).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_delay_callback() {
+        use std::sync::mpsc;
+
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        let tx1 = tx.clone();
+        let _delay = Delay::with_callback(
+            start + Duration::from_millis(50),
+            Some(Box::new(move || tx1.send(1).unwrap())),
+        );
+        // Dropped delays never fire.
+        let delay = Delay::with_callback(
+            start + Duration::from_millis(10),
+            Some(Box::new(move || tx.send(2).unwrap())),
+        );
+        drop(delay);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Propagation of distributed tracing context in metadata.
+//!
+//! Both the W3C `traceparent` header and the binary `grpc-trace-bin` header
+//! used by OpenCensus are supported, so traces continue across services
+//! using either of them.
+
+use std::collections::hash_map::RandomState;
+use std::fmt::Write;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::metadata::{Metadata, MetadataBuilder};
+
+const TRACEPARENT_KEY: &str = "traceparent";
+const GRPC_TRACE_BIN_KEY: &str = "grpc-trace-bin";
+const FLAG_SAMPLED: u8 = 0x1;
+
+/// Generate a random non-zero id.
+fn random_u64() -> u64 {
+    loop {
+        // Every RandomState is seeded differently.
+        let id = RandomState::new().build_hasher().finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+fn parse_hex(s: &str, buf: &mut [u8]) -> Option<()> {
+    if s.len() != buf.len() * 2 {
+        return None;
+    }
+    for (i, b) in buf.iter_mut().enumerate() {
+        let digits = &s[i * 2..i * 2 + 2];
+        // Upper case is not allowed by the spec.
+        if digits.bytes().any(|c| c.is_ascii_uppercase()) {
+            return None;
+        }
+        *b = u8::from_str_radix(digits, 16).ok()?;
+    }
+    Some(())
+}
+
+/// The context of a span of a distributed trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    flags: u8,
+}
+
+impl TraceContext {
+    /// Create a context, `None` is returned if any of the ids is all zero.
+    pub fn new(trace_id: [u8; 16], span_id: [u8; 8], sampled: bool) -> Option<TraceContext> {
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        let flags = if sampled { FLAG_SAMPLED } else { 0 };
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            flags,
+        })
+    }
+
+    /// Start a new trace with random ids.
+    pub fn new_root(sampled: bool) -> TraceContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&random_u64().to_be_bytes());
+        trace_id[8..].copy_from_slice(&random_u64().to_be_bytes());
+        let span_id = random_u64().to_be_bytes();
+        TraceContext::new(trace_id, span_id, sampled).unwrap()
+    }
+
+    /// Create the context of a child span in the same trace.
+    pub fn child(&self) -> TraceContext {
+        TraceContext {
+            span_id: random_u64().to_be_bytes(),
+            ..*self
+        }
+    }
+
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    pub fn span_id(&self) -> [u8; 8] {
+        self.span_id
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    /// Parse the value of a W3C `traceparent` header.
+    pub fn from_traceparent(value: &str) -> Option<TraceContext> {
+        let value = value.trim();
+        if !value.is_ascii() || value.len() < 55 {
+            return None;
+        }
+        let mut version = [0; 1];
+        parse_hex(&value[..2], &mut version)?;
+        // Later versions may append fields.
+        let valid_len = match version[0] {
+            0 => value.len() == 55,
+            0xff => false,
+            _ => value.len() == 55 || value.as_bytes()[55] == b'-',
+        };
+        let bytes = value.as_bytes();
+        if !valid_len || bytes[2] != b'-' || bytes[35] != b'-' || bytes[52] != b'-' {
+            return None;
+        }
+        let (mut trace_id, mut span_id, mut flags) = ([0; 16], [0; 8], [0; 1]);
+        parse_hex(&value[3..35], &mut trace_id)?;
+        parse_hex(&value[36..52], &mut span_id)?;
+        parse_hex(&value[53..55], &mut flags)?;
+        let mut ctx = TraceContext::new(trace_id, span_id, false)?;
+        ctx.flags = flags[0];
+        Some(ctx)
+    }
+
+    /// Format the context as the value of a W3C `traceparent` header.
+    pub fn to_traceparent(&self) -> String {
+        let mut s = String::with_capacity(55);
+        s.push_str("00-");
+        for b in &self.trace_id {
+            write!(s, "{:02x}", b).unwrap();
+        }
+        s.push('-');
+        for b in &self.span_id {
+            write!(s, "{:02x}", b).unwrap();
+        }
+        write!(s, "-{:02x}", self.flags).unwrap();
+        s
+    }
+
+    /// Parse the value of a `grpc-trace-bin` header.
+    pub fn from_grpc_trace_bin(value: &[u8]) -> Option<TraceContext> {
+        if value.first() != Some(&0) {
+            return None;
+        }
+        let (mut trace_id, mut span_id, mut flags) = (None, None, 0);
+        let mut rest = &value[1..];
+        while let Some((&field, data)) = rest.split_first() {
+            let len = match field {
+                0 => 16,
+                1 => 8,
+                2 => 1,
+                // Fields are ordered, unknown fields end the parsing.
+                _ => break,
+            };
+            if data.len() < len {
+                return None;
+            }
+            match field {
+                0 => {
+                    let mut id = [0; 16];
+                    id.copy_from_slice(&data[..16]);
+                    trace_id = Some(id);
+                }
+                1 => {
+                    let mut id = [0; 8];
+                    id.copy_from_slice(&data[..8]);
+                    span_id = Some(id);
+                }
+                _ => flags = data[0],
+            }
+            rest = &data[len..];
+        }
+        let mut ctx = TraceContext::new(trace_id?, span_id?, false)?;
+        ctx.flags = flags;
+        Some(ctx)
+    }
+
+    /// Encode the context as the value of a `grpc-trace-bin` header.
+    pub fn to_grpc_trace_bin(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(29);
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&self.trace_id);
+        buf.push(1);
+        buf.extend_from_slice(&self.span_id);
+        buf.extend_from_slice(&[2, self.flags]);
+        buf
+    }
+
+    /// Extract the context from metadata, `traceparent` is preferred over
+    /// `grpc-trace-bin`.
+    pub fn from_metadata(metadata: &Metadata) -> Option<TraceContext> {
+        let ctx = metadata
+            .find(TRACEPARENT_KEY)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .and_then(TraceContext::from_traceparent);
+        ctx.or_else(|| {
+            metadata
+                .find(GRPC_TRACE_BIN_KEY)
+                .and_then(TraceContext::from_grpc_trace_bin)
+        })
+    }
+
+    /// Add the context to metadata in both formats.
+    pub fn add_to_metadata(&self, builder: &mut MetadataBuilder) {
+        builder
+            .add_str(TRACEPARENT_KEY, &self.to_traceparent())
+            .unwrap();
+        builder
+            .add_bytes(GRPC_TRACE_BIN_KEY, &self.to_grpc_trace_bin())
+            .unwrap();
+    }
+
+    /// Check whether the metadata key is used by trace contexts.
+    pub(crate) fn is_trace_key(key: &str) -> bool {
+        key.eq_ignore_ascii_case(TRACEPARENT_KEY) || key.eq_ignore_ascii_case(GRPC_TRACE_BIN_KEY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_traceparent() {
+        let ctx = TraceContext::from_traceparent(TRACEPARENT).unwrap();
+        assert_eq!(ctx.trace_id()[..2], [0x4b, 0xf9]);
+        assert_eq!(ctx.span_id()[7], 0xb7);
+        assert!(ctx.is_sampled());
+        assert_eq!(ctx.to_traceparent(), TRACEPARENT);
+
+        // Future versions may append fields.
+        let future = format!("cc{}-extra", &TRACEPARENT[2..]);
+        assert_eq!(TraceContext::from_traceparent(&future), Some(ctx));
+
+        for s in &[
+            "",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00_4bf92f3577b34da6a3ce929d0e0e4736_00f067aa0ba902b7_01",
+        ] {
+            assert_eq!(TraceContext::from_traceparent(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_grpc_trace_bin() {
+        let ctx = TraceContext::from_traceparent(TRACEPARENT).unwrap();
+        let bin = ctx.to_grpc_trace_bin();
+        assert_eq!(bin.len(), 29);
+        assert_eq!(TraceContext::from_grpc_trace_bin(&bin), Some(ctx));
+
+        // Options are optional and unknown fields are ignored.
+        let mut bin = ctx.to_grpc_trace_bin();
+        bin.truncate(27);
+        bin.extend_from_slice(&[3, 4, 5]);
+        let parsed = TraceContext::from_grpc_trace_bin(&bin).unwrap();
+        assert_eq!(parsed.trace_id(), ctx.trace_id());
+        assert!(!parsed.is_sampled());
+
+        assert_eq!(TraceContext::from_grpc_trace_bin(&[]), None);
+        assert_eq!(TraceContext::from_grpc_trace_bin(&bin[..10]), None);
+        assert_eq!(TraceContext::from_grpc_trace_bin(&[1, 0]), None);
+    }
+
+    #[test]
+    fn test_child() {
+        let root = TraceContext::new_root(true);
+        let child = root.child();
+        assert_eq!(root.trace_id(), child.trace_id());
+        assert_ne!(root.span_id(), child.span_id());
+        assert!(child.is_sampled());
+        assert_ne!(root.trace_id(), TraceContext::new_root(true).trace_id());
+    }
+}
@@ -0,0 +1,705 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion between JSON and the protobuf wire format, following the
+//! proto3 JSON mapping, driven by the descriptors of the messages.
+//!
+//! Well known types are converted as ordinary messages.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use protobuf::descriptor::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FieldDescriptorProto_Label,
+    FieldDescriptorProto_Type, FileDescriptorProto,
+};
+
+use super::json::Json;
+use crate::metadata;
+
+const MAX_DEPTH: usize = 100;
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn write_tag(buf: &mut Vec<u8>, number: u32, wire_type: u8) {
+    write_varint(buf, u64::from(number) << 3 | u64::from(wire_type));
+}
+
+pub fn write_len(buf: &mut Vec<u8>, number: u32, data: &[u8]) {
+    write_tag(buf, number, WIRE_LEN);
+    write_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+/// A reader of messages in the protobuf wire format.
+pub struct WireReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(data: &'a [u8]) -> WireReader<'a> {
+        WireReader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("truncated message".to_owned());
+        }
+        let (res, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(res)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, String> {
+        let mut res = 0;
+        for i in 0..10 {
+            let b = self.take(1)?[0];
+            res |= u64::from(b & 0x7f) << (7 * i);
+            if b < 0x80 {
+                return Ok(res);
+            }
+        }
+        Err("invalid varint".to_owned())
+    }
+
+    fn read_fixed32(&mut self) -> Result<u32, String> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_fixed64(&mut self) -> Result<u64, String> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_varint()? as usize;
+        self.take(len)
+    }
+
+    /// Read the field number and wire type of the next field.
+    pub fn read_tag(&mut self) -> Result<Option<(u32, u8)>, String> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.read_varint()?;
+        Ok(Some(((tag >> 3) as u32, (tag & 0x7) as u8)))
+    }
+
+    /// Read the value of a length delimited field.
+    pub fn read_len(&mut self, wire_type: u8) -> Result<&'a [u8], String> {
+        if wire_type != WIRE_LEN {
+            return Err(format!("unexpected wire type {}", wire_type));
+        }
+        self.read_bytes()
+    }
+
+    pub fn skip(&mut self, wire_type: u8) -> Result<(), String> {
+        match wire_type {
+            WIRE_VARINT => self.read_varint().map(|_| ()),
+            WIRE_FIXED64 => self.take(8).map(|_| ()),
+            WIRE_LEN => self.read_bytes().map(|_| ()),
+            WIRE_FIXED32 => self.take(4).map(|_| ()),
+            _ => Err(format!("unsupported wire type {}", wire_type)),
+        }
+    }
+}
+
+fn json_name(field: &FieldDescriptorProto) -> String {
+    if field.has_json_name() {
+        return field.get_json_name().to_owned();
+    }
+    let mut res = String::with_capacity(field.get_name().len());
+    let mut upper = false;
+    for c in field.get_name().chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            res.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+fn is_repeated(field: &FieldDescriptorProto) -> bool {
+    field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED
+}
+
+fn wire_type(field: &FieldDescriptorProto) -> u8 {
+    use protobuf::descriptor::FieldDescriptorProto_Type::*;
+
+    match field.get_field_type() {
+        TYPE_DOUBLE | TYPE_FIXED64 | TYPE_SFIXED64 => WIRE_FIXED64,
+        TYPE_FLOAT | TYPE_FIXED32 | TYPE_SFIXED32 => WIRE_FIXED32,
+        TYPE_STRING | TYPE_BYTES | TYPE_MESSAGE | TYPE_GROUP => WIRE_LEN,
+        _ => WIRE_VARINT,
+    }
+}
+
+/// Get the text of a scalar, which can be either a JSON number or string.
+fn scalar_text<'a>(value: &'a Json, field: &FieldDescriptorProto) -> Result<&'a str, String> {
+    match value {
+        Json::Number(n) => Ok(n),
+        Json::String(s) => Ok(s),
+        _ => Err(format!("invalid value for field {}", field.get_name())),
+    }
+}
+
+fn parse_int<T: FromStr>(value: &Json, field: &FieldDescriptorProto) -> Result<T, String> {
+    let text = scalar_text(value, field)?;
+    if let Ok(v) = text.parse() {
+        return Ok(v);
+    }
+    // Integers in exponent notation, like `1e3`, are accepted too.
+    let f: f64 = text
+        .parse()
+        .map_err(|_| format!("invalid integer for field {}", field.get_name()))?;
+    if f.fract() == 0.0 {
+        if let Ok(v) = format!("{:.0}", f).parse() {
+            return Ok(v);
+        }
+    }
+    Err(format!("invalid integer for field {}", field.get_name()))
+}
+
+fn parse_float(value: &Json, field: &FieldDescriptorProto) -> Result<f64, String> {
+    match scalar_text(value, field)? {
+        "NaN" => Ok(f64::NAN),
+        "Infinity" => Ok(f64::INFINITY),
+        "-Infinity" => Ok(f64::NEG_INFINITY),
+        text => text
+            .parse()
+            .map_err(|_| format!("invalid number for field {}", field.get_name())),
+    }
+}
+
+fn float_json<F: Display + Into<f64> + Copy>(f: F) -> Json {
+    let v: f64 = f.into();
+    if v.is_nan() {
+        Json::String("NaN".to_owned())
+    } else if v.is_infinite() {
+        let s = if v > 0.0 { "Infinity" } else { "-Infinity" };
+        Json::String(s.to_owned())
+    } else {
+        Json::Number(f.to_string())
+    }
+}
+
+/// The value of a field that is not present in a map entry.
+fn default_json(field: &FieldDescriptorProto) -> Json {
+    use protobuf::descriptor::FieldDescriptorProto_Type::*;
+
+    match field.get_field_type() {
+        TYPE_INT64 | TYPE_UINT64 | TYPE_FIXED64 | TYPE_SFIXED64 | TYPE_SINT64 => {
+            Json::String("0".to_owned())
+        }
+        TYPE_BOOL => Json::Bool(false),
+        TYPE_STRING | TYPE_BYTES => Json::String(String::new()),
+        TYPE_MESSAGE | TYPE_GROUP => Json::Object(vec![]),
+        _ => Json::Number("0".to_owned()),
+    }
+}
+
+/// The types of messages and enums of the added files.
+#[derive(Default)]
+pub struct Registry {
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, EnumDescriptorProto>,
+}
+
+impl Registry {
+    /// Add the types of the file.
+    pub fn add_file(&mut self, file: &FileDescriptorProto) {
+        let prefix = if file.get_package().is_empty() {
+            String::new()
+        } else {
+            format!(".{}", file.get_package())
+        };
+        for msg in file.get_message_type() {
+            self.add_message(&prefix, msg);
+        }
+        for e in file.get_enum_type() {
+            self.enums
+                .insert(format!("{}.{}", prefix, e.get_name()), e.clone());
+        }
+    }
+
+    fn add_message(&mut self, prefix: &str, msg: &DescriptorProto) {
+        let name = format!("{}.{}", prefix, msg.get_name());
+        for nested in msg.get_nested_type() {
+            self.add_message(&name, nested);
+        }
+        for e in msg.get_enum_type() {
+            self.enums
+                .insert(format!("{}.{}", name, e.get_name()), e.clone());
+        }
+        self.messages.insert(name, msg.clone());
+    }
+
+    /// Check whether the message type is added, the name is fully qualified
+    /// with a leading dot.
+    pub fn contains(&self, type_name: &str) -> bool {
+        self.messages.contains_key(type_name)
+    }
+
+    fn message(&self, type_name: &str) -> Result<&DescriptorProto, String> {
+        self.messages
+            .get(type_name)
+            .ok_or_else(|| format!("unknown message type {}", type_name))
+    }
+
+    fn map_entry(&self, field: &FieldDescriptorProto) -> Option<&DescriptorProto> {
+        if field.get_field_type() != FieldDescriptorProto_Type::TYPE_MESSAGE {
+            return None;
+        }
+        self.messages
+            .get(field.get_type_name())
+            .filter(|m| m.get_options().get_map_entry())
+    }
+
+    /// Encode the JSON value as a message of the type.
+    pub fn encode(&self, type_name: &str, value: &Json) -> Result<Vec<u8>, String> {
+        let mut buf = vec![];
+        self.encode_message(type_name, value, &mut buf, 0)?;
+        Ok(buf)
+    }
+
+    fn encode_message(
+        &self,
+        type_name: &str,
+        value: &Json,
+        buf: &mut Vec<u8>,
+        depth: usize,
+    ) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("message nested too deep".to_owned());
+        }
+        let msg = self.message(type_name)?;
+        let members = match value {
+            Json::Object(members) => members,
+            Json::Null => return Ok(()),
+            _ => return Err(format!("expect an object for {}", type_name)),
+        };
+        for (key, value) in members {
+            let field = msg
+                .get_field()
+                .iter()
+                .find(|f| f.get_name() == key || json_name(f) == *key)
+                .ok_or_else(|| format!("unknown field {} of {}", key, type_name))?;
+            if *value == Json::Null {
+                continue;
+            }
+            if !is_repeated(field) {
+                self.encode_field(field, value, buf, depth)?;
+            } else if let Some(entry) = self.map_entry(field) {
+                let entries = match value {
+                    Json::Object(members) => members,
+                    _ => return Err(format!("expect an object for field {}", key)),
+                };
+                let (key_field, value_field) = match entry.get_field() {
+                    [k, v] => (k, v),
+                    _ => return Err(format!("invalid map entry of field {}", key)),
+                };
+                for (k, v) in entries {
+                    let mut entry_buf = vec![];
+                    self.encode_field(key_field, &Json::String(k.clone()), &mut entry_buf, depth)?;
+                    self.encode_field(value_field, v, &mut entry_buf, depth)?;
+                    write_len(buf, field.get_number() as u32, &entry_buf);
+                }
+            } else {
+                let items = match value {
+                    Json::Array(items) => items,
+                    _ => return Err(format!("expect an array for field {}", key)),
+                };
+                for item in items {
+                    self.encode_field(field, item, buf, depth)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_field(
+        &self,
+        field: &FieldDescriptorProto,
+        value: &Json,
+        buf: &mut Vec<u8>,
+        depth: usize,
+    ) -> Result<(), String> {
+        use protobuf::descriptor::FieldDescriptorProto_Type::*;
+
+        let number = field.get_number() as u32;
+        let wire_type = wire_type(field);
+        match field.get_field_type() {
+            TYPE_STRING => {
+                match value {
+                    Json::String(s) => write_len(buf, number, s.as_bytes()),
+                    _ => return Err(format!("expect a string for field {}", field.get_name())),
+                }
+                return Ok(());
+            }
+            TYPE_BYTES => {
+                let data = match value {
+                    Json::String(s) => metadata::decode_base64(s).ok(),
+                    _ => None,
+                };
+                match data {
+                    Some(data) => write_len(buf, number, &data),
+                    None => return Err(format!("expect base64 for field {}", field.get_name())),
+                }
+                return Ok(());
+            }
+            TYPE_MESSAGE => {
+                let mut sub = vec![];
+                self.encode_message(field.get_type_name(), value, &mut sub, depth + 1)?;
+                write_len(buf, number, &sub);
+                return Ok(());
+            }
+            TYPE_GROUP => return Err(format!("group field {} is not supported", field.get_name())),
+            _ => {}
+        }
+
+        write_tag(buf, number, wire_type);
+        match field.get_field_type() {
+            TYPE_DOUBLE => {
+                buf.extend_from_slice(&parse_float(value, field)?.to_bits().to_le_bytes())
+            }
+            TYPE_FLOAT => {
+                let v = parse_float(value, field)? as f32;
+                buf.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+            TYPE_INT64 => write_varint(buf, parse_int::<i64>(value, field)? as u64),
+            TYPE_UINT64 => write_varint(buf, parse_int(value, field)?),
+            TYPE_INT32 => write_varint(buf, i64::from(parse_int::<i32>(value, field)?) as u64),
+            TYPE_UINT32 => write_varint(buf, u64::from(parse_int::<u32>(value, field)?)),
+            TYPE_SINT32 => {
+                let v = parse_int::<i32>(value, field)?;
+                write_varint(buf, u64::from(((v << 1) ^ (v >> 31)) as u32));
+            }
+            TYPE_SINT64 => {
+                let v = parse_int::<i64>(value, field)?;
+                write_varint(buf, ((v << 1) ^ (v >> 63)) as u64);
+            }
+            TYPE_FIXED64 => buf.extend_from_slice(&parse_int::<u64>(value, field)?.to_le_bytes()),
+            TYPE_SFIXED64 => buf.extend_from_slice(&parse_int::<i64>(value, field)?.to_le_bytes()),
+            TYPE_FIXED32 => buf.extend_from_slice(&parse_int::<u32>(value, field)?.to_le_bytes()),
+            TYPE_SFIXED32 => buf.extend_from_slice(&parse_int::<i32>(value, field)?.to_le_bytes()),
+            TYPE_BOOL => {
+                let v = match value {
+                    Json::Bool(b) => *b,
+                    Json::String(s) if s == "true" => true,
+                    Json::String(s) if s == "false" => false,
+                    _ => return Err(format!("expect a bool for field {}", field.get_name())),
+                };
+                write_varint(buf, v as u64);
+            }
+            TYPE_ENUM => {
+                let number = match value {
+                    Json::String(name) => self
+                        .enums
+                        .get(field.get_type_name())
+                        .and_then(|e| e.get_value().iter().find(|v| v.get_name() == name))
+                        .map(|v| v.get_number()),
+                    _ => None,
+                };
+                let number = match number {
+                    Some(number) => number,
+                    None => parse_int::<i32>(value, field)?,
+                };
+                write_varint(buf, i64::from(number) as u64);
+            }
+            TYPE_STRING | TYPE_BYTES | TYPE_MESSAGE | TYPE_GROUP => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Decode the message of the type as JSON. Fields that are not present
+    /// are omitted.
+    pub fn decode(&self, type_name: &str, data: &[u8]) -> Result<Json, String> {
+        self.decode_message(type_name, data, 0)
+    }
+
+    fn decode_message(&self, type_name: &str, data: &[u8], depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err("message nested too deep".to_owned());
+        }
+        let msg = self.message(type_name)?;
+        let fields = msg.get_field();
+        let mut values: Vec<Option<Json>> = vec![None; fields.len()];
+        let mut reader = WireReader::new(data);
+        while let Some((number, wire_type)) = reader.read_tag()? {
+            let idx = match fields.iter().position(|f| f.get_number() as u32 == number) {
+                Some(idx) => idx,
+                None => {
+                    reader.skip(wire_type)?;
+                    continue;
+                }
+            };
+            let field = &fields[idx];
+            if !is_repeated(field) {
+                values[idx] = Some(self.decode_field(field, wire_type, &mut reader, depth)?);
+                continue;
+            }
+            if let Some(entry) = self.map_entry(field) {
+                let mut entry_reader = WireReader::new(reader.read_len(wire_type)?);
+                let (mut key, mut value) = (None, None);
+                while let Some((number, wire_type)) = entry_reader.read_tag()? {
+                    match entry
+                        .get_field()
+                        .iter()
+                        .find(|f| f.get_number() as u32 == number)
+                    {
+                        Some(f) if f.get_number() == 1 => {
+                            key = Some(self.decode_field(f, wire_type, &mut entry_reader, depth)?)
+                        }
+                        Some(f) => {
+                            value =
+                                Some(self.decode_field(f, wire_type, &mut entry_reader, depth)?)
+                        }
+                        None => entry_reader.skip(wire_type)?,
+                    }
+                }
+                let key = match key {
+                    Some(Json::Number(s)) | Some(Json::String(s)) => s,
+                    Some(Json::Bool(b)) => b.to_string(),
+                    _ => String::new(),
+                };
+                let value = value.unwrap_or_else(|| default_json(&entry.get_field()[1]));
+                values[idx]
+                    .get_or_insert_with(|| Json::Object(vec![]))
+                    .set(&key, value);
+                continue;
+            }
+            let items = match values[idx].get_or_insert_with(|| Json::Array(vec![])) {
+                Json::Array(items) => items,
+                _ => unreachable!(),
+            };
+            if wire_type == WIRE_LEN && self::wire_type(field) != WIRE_LEN {
+                // Packed scalars.
+                let mut packed = WireReader::new(reader.read_bytes()?);
+                while !packed.data.is_empty() {
+                    items.push(self.decode_field(
+                        field,
+                        self::wire_type(field),
+                        &mut packed,
+                        depth,
+                    )?);
+                }
+            } else {
+                items.push(self.decode_field(field, wire_type, &mut reader, depth)?);
+            }
+        }
+        let members = fields
+            .iter()
+            .zip(values)
+            .filter_map(|(f, v)| v.map(|v| (json_name(f), v)))
+            .collect();
+        Ok(Json::Object(members))
+    }
+
+    fn decode_field(
+        &self,
+        field: &FieldDescriptorProto,
+        wire_type: u8,
+        reader: &mut WireReader<'_>,
+        depth: usize,
+    ) -> Result<Json, String> {
+        use protobuf::descriptor::FieldDescriptorProto_Type::*;
+
+        if wire_type != self::wire_type(field) {
+            return Err(format!(
+                "unexpected wire type {} of field {}",
+                wire_type,
+                field.get_name()
+            ));
+        }
+        let value = match field.get_field_type() {
+            TYPE_DOUBLE => float_json(f64::from_bits(reader.read_fixed64()?)),
+            TYPE_FLOAT => float_json(f32::from_bits(reader.read_fixed32()?)),
+            TYPE_INT64 => Json::String((reader.read_varint()? as i64).to_string()),
+            TYPE_UINT64 => Json::String(reader.read_varint()?.to_string()),
+            TYPE_INT32 => Json::Number((reader.read_varint()? as i32).to_string()),
+            TYPE_UINT32 => Json::Number((reader.read_varint()? as u32).to_string()),
+            TYPE_SINT32 => {
+                let v = reader.read_varint()? as u32;
+                Json::Number((((v >> 1) as i32) ^ -((v & 1) as i32)).to_string())
+            }
+            TYPE_SINT64 => {
+                let v = reader.read_varint()?;
+                Json::String((((v >> 1) as i64) ^ -((v & 1) as i64)).to_string())
+            }
+            TYPE_FIXED64 => Json::String(reader.read_fixed64()?.to_string()),
+            TYPE_SFIXED64 => Json::String((reader.read_fixed64()? as i64).to_string()),
+            TYPE_FIXED32 => Json::Number(reader.read_fixed32()?.to_string()),
+            TYPE_SFIXED32 => Json::Number((reader.read_fixed32()? as i32).to_string()),
+            TYPE_BOOL => Json::Bool(reader.read_varint()? != 0),
+            TYPE_ENUM => {
+                let number = reader.read_varint()? as i32;
+                let name = self
+                    .enums
+                    .get(field.get_type_name())
+                    .and_then(|e| e.get_value().iter().find(|v| v.get_number() == number));
+                match name {
+                    Some(v) => Json::String(v.get_name().to_owned()),
+                    None => Json::Number(number.to_string()),
+                }
+            }
+            TYPE_STRING => {
+                let s = std::str::from_utf8(reader.read_bytes()?)
+                    .map_err(|_| format!("invalid utf-8 in field {}", field.get_name()))?;
+                Json::String(s.to_owned())
+            }
+            TYPE_BYTES => Json::String(metadata::encode_base64(reader.read_bytes()?)),
+            TYPE_MESSAGE => {
+                self.decode_message(field.get_type_name(), reader.read_bytes()?, depth + 1)?
+            }
+            TYPE_GROUP => return Err(format!("group field {} is not supported", field.get_name())),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protobuf::descriptor::{self, FileDescriptorProto, UninterpretedOption};
+    use protobuf::Message;
+
+    fn registry() -> Registry {
+        let mut registry = Registry::default();
+        registry.add_file(descriptor::file_descriptor_proto());
+        registry
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let registry = registry();
+        let json = Json::parse(
+            r#"{"name": "a.proto", "package": "pkg", "dependency": ["b.proto", "c.proto"],
+                "messageType": [{"name": "A", "field": [
+                    {"name": "f", "number": 1, "label": "LABEL_REPEATED", "type": 9, "json_name": "f"}
+                ]}],
+                "options": {"javaMultipleFiles": true, "optimizeFor": "CODE_SIZE"},
+                "syntax": null}"#,
+        )
+        .unwrap();
+        let data = registry
+            .encode(".google.protobuf.FileDescriptorProto", &json)
+            .unwrap();
+        let mut file = FileDescriptorProto::new();
+        file.merge_from_bytes(&data).unwrap();
+        assert_eq!(file.get_name(), "a.proto");
+        assert_eq!(file.get_dependency(), ["b.proto", "c.proto"]);
+        let field = &file.get_message_type()[0].get_field()[0];
+        assert_eq!(
+            field.get_label(),
+            FieldDescriptorProto_Label::LABEL_REPEATED
+        );
+        assert_eq!(
+            field.get_field_type(),
+            FieldDescriptorProto_Type::TYPE_STRING
+        );
+        assert!(file.get_options().get_java_multiple_files());
+
+        let decoded = registry
+            .decode(".google.protobuf.FileDescriptorProto", &data)
+            .unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            r#"{"name":"a.proto","package":"pkg","dependency":["b.proto","c.proto"],"messageType":[{"name":"A","field":[{"name":"f","number":1,"label":"LABEL_REPEATED","type":"TYPE_STRING","jsonName":"f"}]}],"options":{"javaMultipleFiles":true,"optimizeFor":"CODE_SIZE"}}"#
+        );
+    }
+
+    #[test]
+    fn test_scalars() {
+        let registry = registry();
+        let json = Json::parse(
+            r#"{"positiveIntValue": "18446744073709551615", "negativeIntValue": -9e3,
+                "doubleValue": "-Infinity", "stringValue": "AP8-_w"}"#,
+        )
+        .unwrap();
+        let data = registry
+            .encode(".google.protobuf.UninterpretedOption", &json)
+            .unwrap();
+        let mut opt = UninterpretedOption::new();
+        opt.merge_from_bytes(&data).unwrap();
+        assert_eq!(opt.get_positive_int_value(), u64::MAX);
+        assert_eq!(opt.get_negative_int_value(), -9000);
+        assert_eq!(opt.get_double_value(), f64::NEG_INFINITY);
+        assert_eq!(opt.get_string_value(), [0x00, 0xff, 0x3e, 0xff]);
+
+        let decoded = registry
+            .decode(".google.protobuf.UninterpretedOption", &data)
+            .unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            r#"{"positiveIntValue":"18446744073709551615","negativeIntValue":"-9000","doubleValue":"-Infinity","stringValue":"AP8+/w=="}"#
+        );
+        let mut opt = UninterpretedOption::default();
+        opt.set_double_value(0.5);
+        let data = opt.write_to_bytes().unwrap();
+        let decoded = registry
+            .decode(".google.protobuf.UninterpretedOption", &data)
+            .unwrap();
+        assert_eq!(decoded.to_string(), r#"{"doubleValue":0.5}"#);
+
+        for json in &[
+            r#"{"unknown": 1}"#,
+            r#"{"doubleValue": true}"#,
+            r#"{"negativeIntValue": 1.5}"#,
+            r#"{"stringValue": "!"}"#,
+            r#"[]"#,
+        ] {
+            let json = Json::parse(json).unwrap();
+            assert!(registry
+                .encode(".google.protobuf.UninterpretedOption", &json)
+                .is_err());
+        }
+        assert!(registry.encode(".unknown.Type", &Json::Null).is_err());
+    }
+
+    #[test]
+    fn test_wire_reader() {
+        let mut buf = vec![];
+        write_varint(&mut buf, 300);
+        write_len(&mut buf, 2, b"ab");
+        let mut reader = WireReader::new(&buf);
+        assert_eq!(reader.read_varint().unwrap(), 300);
+        assert_eq!(reader.read_tag().unwrap(), Some((2, WIRE_LEN)));
+        assert_eq!(reader.read_len(WIRE_LEN).unwrap(), b"ab");
+        assert_eq!(reader.read_tag().unwrap(), None);
+
+        let mut reader = WireReader::new(&[0x80]);
+        assert!(reader.read_varint().is_err());
+        let mut reader = WireReader::new(&[0x05, 0x01]);
+        assert!(reader.read_bytes().is_err());
+    }
+}
@@ -0,0 +1,499 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A gateway transcoding HTTP/JSON requests into gRPC calls.
+
+mod codec;
+mod json;
+mod rule;
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use protobuf::descriptor::FileDescriptorProto;
+
+use self::codec::Registry;
+use self::json::Json;
+use self::rule::{percent_decode, HttpRule};
+use crate::call::{RpcStatus, RpcStatusCode};
+use crate::channel::Channel;
+use crate::client::Client;
+use crate::http::{self, relay_call, HttpListener, ListenerConfig, Request};
+use crate::metadata::{self, Metadata};
+
+/// Map the status code to an HTTP status, in the same way as grpc-gateway.
+fn http_status(code: RpcStatusCode) -> &'static str {
+    match code {
+        RpcStatusCode::OK => "200 OK",
+        RpcStatusCode::CANCELLED => "499 Client Closed Request",
+        RpcStatusCode::INVALID_ARGUMENT
+        | RpcStatusCode::FAILED_PRECONDITION
+        | RpcStatusCode::OUT_OF_RANGE => "400 Bad Request",
+        RpcStatusCode::DEADLINE_EXCEEDED => "504 Gateway Timeout",
+        RpcStatusCode::NOT_FOUND => "404 Not Found",
+        RpcStatusCode::ALREADY_EXISTS | RpcStatusCode::ABORTED => "409 Conflict",
+        RpcStatusCode::PERMISSION_DENIED => "403 Forbidden",
+        RpcStatusCode::RESOURCE_EXHAUSTED => "429 Too Many Requests",
+        RpcStatusCode::UNIMPLEMENTED => "501 Not Implemented",
+        RpcStatusCode::UNAVAILABLE => "503 Service Unavailable",
+        RpcStatusCode::UNAUTHENTICATED => "401 Unauthorized",
+        _ => "500 Internal Server Error",
+    }
+}
+
+fn status_json(status: &RpcStatus) -> Json {
+    let code: i32 = status.status.into();
+    let message = status.details.clone().unwrap_or_default();
+    Json::Object(vec![
+        ("code".to_owned(), Json::Number(code.to_string())),
+        ("message".to_owned(), Json::String(message)),
+    ])
+}
+
+/// Format the entries of metadata as header lines with the prefix.
+fn write_metadata(meta: &Metadata, prefix: &str, out: &mut String) {
+    for (key, value) in meta.ascii_entries() {
+        write!(out, "{}{}: {}\r\n", prefix, key, value).unwrap();
+    }
+    for (key, value) in meta.binary_entries() {
+        write!(
+            out,
+            "{}{}: {}\r\n",
+            prefix,
+            key,
+            metadata::encode_base64(value)
+        )
+        .unwrap();
+    }
+}
+
+fn write_json(stream: &mut TcpStream, status: &str, headers: &str, body: &Json) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{}\r\n{}",
+        status,
+        body.len(),
+        headers,
+        body
+    )?;
+    stream.flush()
+}
+
+fn write_error(stream: &mut TcpStream, status: &RpcStatus, headers: &str) -> io::Result<()> {
+    write_json(
+        stream,
+        http_status(status.status),
+        headers,
+        &status_json(status),
+    )
+}
+
+struct Route {
+    rule: HttpRule,
+    /// The path of the gRPC method, like `/helloworld.Greeter/SayHello`.
+    grpc_path: String,
+    input_type: String,
+    output_type: String,
+    server_streaming: bool,
+}
+
+struct Gateway {
+    client: Client,
+    registry: Registry,
+    routes: Vec<Route>,
+}
+
+impl Gateway {
+    /// Build the request message from the body, the path variables and the
+    /// query parameters.
+    fn build_request(
+        &self,
+        route: &Route,
+        req: &Request,
+        vars: Vec<(String, String)>,
+        query: &str,
+    ) -> Result<Vec<u8>, String> {
+        let mut msg = Json::Object(vec![]);
+        if route.rule.body != "*" {
+            let mut params: Vec<(String, Json)> = vec![];
+            for pair in query.split('&').filter(|p| !p.is_empty()) {
+                let (key, value) = match pair.find('=') {
+                    Some(pos) => (&pair[..pos], &pair[pos + 1..]),
+                    None => (pair, ""),
+                };
+                let key = percent_decode(key, true).ok_or("invalid query")?;
+                let value = Json::String(percent_decode(value, true).ok_or("invalid query")?);
+                // Repeated parameters are collected into an array.
+                match params.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, Json::Array(items))) => items.push(value),
+                    Some((_, v)) => *v = Json::Array(vec![v.clone(), value]),
+                    None => params.push((key, value)),
+                }
+            }
+            for (key, value) in params {
+                msg.set_path(&key, value);
+            }
+        }
+        if !route.rule.body.is_empty() {
+            let body = std::str::from_utf8(&req.body).map_err(|_| "invalid utf-8 in body")?;
+            let body = if body.trim().is_empty() {
+                Json::Object(vec![])
+            } else {
+                Json::parse(body)?
+            };
+            if route.rule.body == "*" {
+                msg = body;
+            } else {
+                msg.set_path(&route.rule.body, body);
+            }
+        }
+        for (field_path, value) in vars {
+            msg.set_path(&field_path, Json::String(value));
+        }
+        self.registry.encode(&route.input_type, &msg)
+    }
+
+    /// Decode the response message, only the response body field is kept
+    /// if it's set.
+    fn decode_response(&self, route: &Route, data: &[u8]) -> Result<Json, String> {
+        let msg = self.registry.decode(&route.output_type, data)?;
+        if route.rule.response_body.is_empty() {
+            return Ok(msg);
+        }
+        // The field is looked up by the name in JSON too.
+        let body = msg
+            .get(&route.rule.response_body)
+            .or_else(|| msg.get(&route.rule.response_body.replace('_', "")));
+        Ok(body.cloned().unwrap_or(Json::Null))
+    }
+
+    fn handle(&self, req: Request, stream: &mut TcpStream) -> io::Result<()> {
+        let (path, query) = match req.path.find('?') {
+            Some(pos) => (&req.path[..pos], &req.path[pos + 1..]),
+            None => (req.path.as_str(), ""),
+        };
+        let mut path_matched = false;
+        let mut matched = None;
+        for route in &self.routes {
+            if let Some(vars) = route.rule.template.matches(path) {
+                path_matched = true;
+                if route.rule.method == req.method {
+                    matched = Some((route, vars));
+                    break;
+                }
+            }
+        }
+        let (route, vars) = match matched {
+            Some(m) => m,
+            None if path_matched => {
+                let status = RpcStatus::new(
+                    RpcStatusCode::UNIMPLEMENTED,
+                    Some("Method Not Allowed".to_owned()),
+                );
+                let body = status_json(&status);
+                return write_json(stream, "405 Method Not Allowed", "", &body);
+            }
+            None => {
+                let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, Some("Not Found".to_owned()));
+                return write_error(stream, &status, "");
+            }
+        };
+        let payload = match self.build_request(route, &req, vars, query) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let status = RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some(e));
+                return write_error(stream, &status, "");
+            }
+        };
+
+        if route.server_streaming {
+            return self.relay_stream(route, &req, payload, stream);
+        }
+        let mut resp = None;
+        let opt = req.call_option();
+        let (mut status, rx) = relay_call(
+            &self.client,
+            &route.grpc_path,
+            opt,
+            vec![payload],
+            |_, msg| {
+                resp = Some(msg);
+                Ok(())
+            },
+        )?;
+        let mut headers = String::new();
+        if let Some(rx) = rx {
+            if let Some(meta) = rx.headers() {
+                write_metadata(&meta, "grpc-metadata-", &mut headers);
+            }
+            if let Some(meta) = rx.trailers() {
+                write_metadata(&meta, "grpc-trailer-", &mut headers);
+            }
+        }
+        if status.status == RpcStatusCode::OK {
+            let body = resp
+                .ok_or_else(|| "missing response".to_owned())
+                .and_then(|data| self.decode_response(route, &data));
+            match body {
+                Ok(body) => return write_json(stream, "200 OK", &headers, &body),
+                Err(e) => status = RpcStatus::new(RpcStatusCode::INTERNAL, Some(e)),
+            }
+        }
+        write_error(stream, &status, &headers)
+    }
+
+    /// Relay a server streaming call, every response is sent as a line of
+    /// `{"result": ...}` in chunked encoding. If the call fails after some
+    /// responses are sent, the status is sent as a line of `{"error": ...}`.
+    fn relay_stream(
+        &self,
+        route: &Route,
+        req: &Request,
+        payload: Vec<u8>,
+        stream: &mut TcpStream,
+    ) -> io::Result<()> {
+        let mut head_sent = false;
+        let opt = req.call_option();
+        let mut decode_error = None;
+        let (mut status, rx) = relay_call(
+            &self.client,
+            &route.grpc_path,
+            opt,
+            vec![payload],
+            |rx, msg| {
+                if decode_error.is_some() {
+                    return Ok(());
+                }
+                let msg = match self.decode_response(route, &msg) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        decode_error = Some(e);
+                        return Ok(());
+                    }
+                };
+                if !head_sent {
+                    let mut head = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                                    transfer-encoding: chunked\r\n"
+                        .to_owned();
+                    if let Some(meta) = rx.headers() {
+                        write_metadata(&meta, "grpc-metadata-", &mut head);
+                    }
+                    write!(stream, "{}\r\n", head)?;
+                    head_sent = true;
+                }
+                let line = Json::Object(vec![("result".to_owned(), msg)]);
+                http::write_chunk(stream, format!("{}\n", line).as_bytes())
+            },
+        )?;
+        if let Some(e) = decode_error {
+            status = RpcStatus::new(RpcStatusCode::INTERNAL, Some(e));
+        }
+        if !head_sent {
+            let mut headers = String::new();
+            if let Some(meta) = rx.as_ref().and_then(|rx| rx.headers()) {
+                write_metadata(&meta, "grpc-metadata-", &mut headers);
+            }
+            if status.status == RpcStatusCode::OK {
+                return write_json(stream, "200 OK", &headers, &Json::Array(vec![]));
+            }
+            return write_error(stream, &status, &headers);
+        }
+        if status.status != RpcStatusCode::OK {
+            let line = Json::Object(vec![("error".to_owned(), status_json(&status))]);
+            http::write_chunk(stream, format!("{}\n", line).as_bytes())?;
+        }
+        http::write_last_chunk(stream)
+    }
+}
+
+/// A builder for [`HttpJsonGateway`].
+pub struct HttpJsonGatewayBuilder {
+    channel: Channel,
+    registry: Registry,
+    routes: Vec<Route>,
+    errors: Vec<String>,
+    config: ListenerConfig,
+}
+
+impl HttpJsonGatewayBuilder {
+    /// Create a builder forwarding requests through the channel.
+    pub fn new(channel: Channel) -> HttpJsonGatewayBuilder {
+        HttpJsonGatewayBuilder {
+            channel,
+            registry: Registry::default(),
+            routes: vec![],
+            errors: vec![],
+            config: ListenerConfig::default(),
+        }
+    }
+
+    /// Add the messages of a proto file, and routes for the methods of its
+    /// services that have `google.api.http` annotations.
+    ///
+    /// The descriptor is usually the one embedded in the generated code,
+    /// namely `file_descriptor_proto()` of the module generated for the
+    /// file. The files that define the types used by the methods need to be
+    /// added too.
+    pub fn add_file(mut self, file: &FileDescriptorProto) -> HttpJsonGatewayBuilder {
+        self.registry.add_file(file);
+        let package = file.get_package();
+        for service in file.get_service() {
+            let service_name = if package.is_empty() {
+                service.get_name().to_owned()
+            } else {
+                format!("{}.{}", package, service.get_name())
+            };
+            for method in service.get_method() {
+                let grpc_path = format!("/{}/{}", service_name, method.get_name());
+                let rules = match HttpRule::from_options(method.get_options()) {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        self.errors.push(format!("{}: {}", grpc_path, e));
+                        continue;
+                    }
+                };
+                if !rules.is_empty() && method.get_client_streaming() {
+                    self.errors.push(format!(
+                        "{}: client streaming methods can't be transcoded",
+                        grpc_path
+                    ));
+                    continue;
+                }
+                for rule in rules {
+                    self.routes.push(Route {
+                        rule,
+                        grpc_path: grpc_path.clone(),
+                        input_type: method.get_input_type().to_owned(),
+                        output_type: method.get_output_type().to_owned(),
+                        server_streaming: method.get_server_streaming(),
+                    });
+                }
+            }
+        }
+        self
+    }
+
+    /// Set the max size of a request body, 4 MiB by default. Larger requests
+    /// are rejected with `413 Payload Too Large`.
+    pub fn max_body_len(mut self, len: usize) -> HttpJsonGatewayBuilder {
+        self.config.max_body_len = len;
+        self
+    }
+
+    /// Set the max number of connections that are served at the same time,
+    /// 1024 by default. Other connections are answered with
+    /// `503 Service Unavailable` and closed.
+    pub fn max_connections(mut self, count: usize) -> HttpJsonGatewayBuilder {
+        self.config.max_connections = count;
+        self
+    }
+
+    /// Set how long a request can take to be received, 30 seconds by
+    /// default. Connections are closed if the next request is not received
+    /// in time, or if a write to them blocks longer than that.
+    pub fn io_timeout(mut self, timeout: Duration) -> HttpJsonGatewayBuilder {
+        self.config.io_timeout = timeout;
+        self
+    }
+
+    /// Listen on the address and start serving requests.
+    ///
+    /// An error of `InvalidInput` is returned if any of the annotations is
+    /// invalid, or if any of the types used by the annotated methods is not
+    /// added.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> io::Result<HttpJsonGateway> {
+        let mut errors = self.errors;
+        for route in &self.routes {
+            for t in &[&route.input_type, &route.output_type] {
+                if !self.registry.contains(t) {
+                    errors.push(format!("{}: unknown type {}", route.grpc_path, t));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                errors.join("; "),
+            ));
+        }
+        let gateway = Gateway {
+            client: Client::new(self.channel),
+            registry: self.registry,
+            routes: self.routes,
+        };
+        let handler = Arc::new(move |req, stream: &mut TcpStream| gateway.handle(req, stream));
+        let listener = HttpListener::bind(addr, "http-json", self.config, handler)?;
+        Ok(HttpJsonGateway { listener })
+    }
+}
+
+/// A server exposing gRPC methods as a REST/JSON API, following their
+/// `google.api.http` annotations.
+///
+/// Requests are transcoded into calls forwarded to a gRPC server through a
+/// [`Channel`], so the gateway can be served by the same binary as the
+/// server, on its own port. Path variables, query parameters and the body
+/// are mapped onto the request message as grpc-gateway does, and messages
+/// are converted with the proto3 JSON mapping. Unary responses are sent as
+/// JSON objects, and the responses of server streaming methods as a stream
+/// of lines of `{"result": ...}`. Failed calls are sent with the mapped
+/// HTTP status and a body of `{"code": ..., "message": ...}`.
+///
+/// Headers are forwarded as metadata, and the metadata sent by the server
+/// is sent back in headers prefixed with `grpc-metadata-` and
+/// `grpc-trailer-`.
+///
+/// Every connection is served in its own thread, up to the limit set by
+/// `max_connections`, and connections that don't send a request within
+/// `io_timeout` are closed. The gateway stops
+/// accepting connections when it's shut down or dropped, connections that
+/// are already accepted are served until they are closed.
+pub struct HttpJsonGateway {
+    listener: HttpListener,
+}
+
+impl HttpJsonGateway {
+    /// The address the gateway is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    /// Stop accepting connections and wait for the listening thread to exit.
+    pub fn shutdown(&mut self) {
+        self.listener.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_status() {
+        assert_eq!(http_status(RpcStatusCode::OK), "200 OK");
+        assert_eq!(http_status(RpcStatusCode::NOT_FOUND), "404 Not Found");
+        assert_eq!(
+            http_status(RpcStatusCode::DATA_LOSS),
+            "500 Internal Server Error"
+        );
+        let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, Some("\"x\"".to_owned()));
+        assert_eq!(
+            status_json(&status).to_string(),
+            r#"{"code":5,"message":"\"x\""}"#
+        );
+    }
+}
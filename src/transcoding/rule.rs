@@ -0,0 +1,368 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `google.api.http` annotations and their path templates.
+
+use protobuf::descriptor::MethodOptions;
+use protobuf::Message;
+
+use super::codec::WireReader;
+
+/// The field number of the `google.api.http` extension of method options.
+pub const HTTP_RULE_EXTENSION: u32 = 72_295_728;
+
+/// A binding of a method to an HTTP method and path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRule {
+    pub method: String,
+    pub template: PathTemplate,
+    /// The request field that the body is mapped to, `*` for the whole request.
+    pub body: String,
+    /// The response field that is sent as the body, empty for the whole
+    /// response.
+    pub response_body: String,
+}
+
+fn read_str(reader: &mut WireReader<'_>, wire_type: u8) -> Result<String, String> {
+    let data = reader.read_len(wire_type)?;
+    String::from_utf8(data.to_vec()).map_err(|_| "invalid utf-8 in http rule".to_owned())
+}
+
+impl HttpRule {
+    /// Get the rules of a method, including the additional bindings.
+    pub fn from_options(options: &MethodOptions) -> Result<Vec<HttpRule>, String> {
+        let mut rules = vec![];
+        if let Some(values) = options.get_unknown_fields().get(HTTP_RULE_EXTENSION) {
+            for data in &values.length_delimited {
+                HttpRule::parse(data, true, &mut rules)?;
+            }
+        }
+        Ok(rules)
+    }
+
+    fn parse(data: &[u8], top_level: bool, rules: &mut Vec<HttpRule>) -> Result<(), String> {
+        let (mut pattern, mut body, mut response_body) = (None, String::new(), String::new());
+        let mut additional = vec![];
+        let mut reader = WireReader::new(data);
+        while let Some((number, wire_type)) = reader.read_tag()? {
+            let method = match number {
+                2 => "GET",
+                3 => "PUT",
+                4 => "POST",
+                5 => "DELETE",
+                6 => "PATCH",
+                7 => {
+                    body = read_str(&mut reader, wire_type)?;
+                    continue;
+                }
+                8 => {
+                    let mut custom = WireReader::new(reader.read_len(wire_type)?);
+                    let (mut kind, mut path) = (String::new(), String::new());
+                    while let Some((number, wire_type)) = custom.read_tag()? {
+                        match number {
+                            1 => kind = read_str(&mut custom, wire_type)?,
+                            2 => path = read_str(&mut custom, wire_type)?,
+                            _ => custom.skip(wire_type)?,
+                        }
+                    }
+                    pattern = Some((kind, path));
+                    continue;
+                }
+                11 if top_level => {
+                    additional.push(reader.read_len(wire_type)?);
+                    continue;
+                }
+                12 => {
+                    response_body = read_str(&mut reader, wire_type)?;
+                    continue;
+                }
+                _ => {
+                    reader.skip(wire_type)?;
+                    continue;
+                }
+            };
+            pattern = Some((method.to_owned(), read_str(&mut reader, wire_type)?));
+        }
+        let (method, path) = pattern.ok_or_else(|| "http rule without pattern".to_owned())?;
+        rules.push(HttpRule {
+            method,
+            template: PathTemplate::parse(&path)?,
+            body,
+            response_body,
+        });
+        for data in additional {
+            HttpRule::parse(data, false, rules)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `*`, matches a segment.
+    Wildcard,
+    /// `**`, matches the rest segments.
+    DoubleWildcard,
+}
+
+/// A variable bound to the segments in `[start, end)`.
+#[derive(Debug, Clone, PartialEq)]
+struct Variable {
+    field_path: String,
+    start: usize,
+    end: usize,
+}
+
+/// A path template of http rules, like `/v1/{name=shelves/*}/books`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+    variables: Vec<Variable>,
+    verb: Option<String>,
+}
+
+fn parse_segments(text: &str, segments: &mut Vec<Segment>) -> Result<(), String> {
+    for s in text.split('/') {
+        let segment = match s {
+            "" => return Err(format!("empty segment in {}", text)),
+            "*" => Segment::Wildcard,
+            "**" => Segment::DoubleWildcard,
+            s if s.contains(|c| "{}=*".contains(c)) => {
+                return Err(format!("invalid segment {}", s));
+            }
+            s => Segment::Literal(s.to_owned()),
+        };
+        segments.push(segment);
+    }
+    Ok(())
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<PathTemplate, String> {
+        let invalid = || format!("invalid path template {}", template);
+        if !template.starts_with('/') {
+            return Err(invalid());
+        }
+        let mut rest = &template[1..];
+        // The verb follows the last segment, which can't be in a variable.
+        let mut verb = None;
+        if let Some(pos) = rest.rfind(':') {
+            if !rest[pos..].contains(&['/', '}'][..]) {
+                verb = Some(rest[pos + 1..].to_owned());
+                rest = &rest[..pos];
+            }
+        }
+
+        let (mut segments, mut variables) = (vec![], vec![]);
+        while !rest.is_empty() {
+            if rest.starts_with('{') {
+                let end = rest.find('}').ok_or_else(invalid)?;
+                let var = &rest[1..end];
+                let (field_path, pattern) = match var.find('=') {
+                    Some(pos) => (&var[..pos], &var[pos + 1..]),
+                    None => (var, "*"),
+                };
+                if field_path.is_empty() || pattern.contains('{') {
+                    return Err(invalid());
+                }
+                let start = segments.len();
+                parse_segments(pattern, &mut segments)?;
+                variables.push(Variable {
+                    field_path: field_path.to_owned(),
+                    start,
+                    end: segments.len(),
+                });
+                rest = &rest[end + 1..];
+            } else {
+                let end = rest.find('/').unwrap_or(rest.len());
+                parse_segments(&rest[..end], &mut segments)?;
+                rest = &rest[end..];
+            }
+            if rest.starts_with('/') {
+                rest = &rest[1..];
+                if rest.is_empty() {
+                    return Err(invalid());
+                }
+            } else if !rest.is_empty() {
+                return Err(invalid());
+            }
+        }
+        if segments
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|s| *s == Segment::DoubleWildcard)
+        {
+            return Err(format!("** must be the last segment in {}", template));
+        }
+        Ok(PathTemplate {
+            segments,
+            variables,
+            verb,
+        })
+    }
+
+    /// Match a path without query, the percent decoded values of the
+    /// variables are returned with their field paths.
+    pub fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let mut path = path.strip_prefix('/')?;
+        if let Some(ref verb) = self.verb {
+            path = path.strip_suffix(verb.as_str())?.strip_suffix(':')?;
+        }
+        let parts: Vec<&str> = if path.is_empty() {
+            vec![]
+        } else {
+            path.split('/').collect()
+        };
+        let mut matched = 0;
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(l) => {
+                    if parts.get(matched) != Some(&l.as_str()) {
+                        return None;
+                    }
+                    matched += 1;
+                }
+                Segment::Wildcard => match parts.get(matched) {
+                    Some(p) if !p.is_empty() => matched += 1,
+                    _ => return None,
+                },
+                Segment::DoubleWildcard => matched = parts.len(),
+            }
+        }
+        if matched != parts.len() {
+            return None;
+        }
+        let mut values = Vec::with_capacity(self.variables.len());
+        for var in &self.variables {
+            let end = match self.segments[var.end - 1] {
+                Segment::DoubleWildcard => parts.len(),
+                _ => var.end,
+            };
+            let value = percent_decode(&parts[var.start..end].join("/"), false)?;
+            values.push((var.field_path.clone(), value));
+        }
+        Some(values)
+    }
+}
+
+/// Decode a percent encoded string, `+` is decoded as space if `query` is
+/// true.
+pub fn percent_decode(s: &str, query: bool) -> Option<String> {
+    let mut buf = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                buf.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' if query => buf.push(b' '),
+            b => buf.push(b),
+        }
+    }
+    String::from_utf8(buf).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::codec::write_len;
+    use super::*;
+
+    #[test]
+    fn test_path_template() {
+        let t = PathTemplate::parse("/v1/{name=shelves/*/books/*}").unwrap();
+        assert_eq!(
+            t.matches("/v1/shelves/1/books/a%20b"),
+            Some(vec![("name".to_owned(), "shelves/1/books/a b".to_owned())])
+        );
+        assert_eq!(t.matches("/v1/shelves/1/books"), None);
+        assert_eq!(t.matches("/v1/shelves/1/books/2/3"), None);
+        assert_eq!(t.matches("/v2/shelves/1/books/2"), None);
+
+        let t = PathTemplate::parse("/v1/{book.id}/{path=files/**}:get").unwrap();
+        assert_eq!(
+            t.matches("/v1/7/files/a/b:get"),
+            Some(vec![
+                ("book.id".to_owned(), "7".to_owned()),
+                ("path".to_owned(), "files/a/b".to_owned()),
+            ])
+        );
+        assert_eq!(t.matches("/v1/7/files/a/b"), None);
+        assert_eq!(
+            t.matches("/v1/7/files:get"),
+            Some(vec![
+                ("book.id".to_owned(), "7".to_owned()),
+                ("path".to_owned(), "files".to_owned()),
+            ])
+        );
+
+        let t = PathTemplate::parse("/").unwrap();
+        assert_eq!(t.matches("/"), Some(vec![]));
+        assert_eq!(t.matches("/a"), None);
+
+        for s in &[
+            "v1", "/v1/", "/v1//a", "/{}", "/{a", "/**/a", "/{a={b}}", "/a*",
+        ] {
+            assert!(PathTemplate::parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Fb+c", false).unwrap(), "a/b+c");
+        assert_eq!(percent_decode("a%2fb+c", true).unwrap(), "a/b c");
+        assert_eq!(percent_decode("%e4%bd%a0", false).unwrap(), "你");
+        assert_eq!(percent_decode("%4", false), None);
+        assert_eq!(percent_decode("%ff", false), None);
+    }
+
+    #[test]
+    fn test_http_rule() {
+        let mut options = MethodOptions::new();
+        assert_eq!(HttpRule::from_options(&options).unwrap(), vec![]);
+
+        // get: "/v1/{name}", body: "*", response_body: "message",
+        // additional_bindings { post: "/v1:hello" body: "*" }
+        let mut buf = vec![];
+        write_len(&mut buf, 2, b"/v1/{name}");
+        write_len(&mut buf, 12, b"message");
+        let mut additional = vec![];
+        write_len(&mut additional, 4, b"/v1:hello");
+        write_len(&mut additional, 7, b"*");
+        write_len(&mut buf, 11, &additional);
+        options
+            .mut_unknown_fields()
+            .add_length_delimited(HTTP_RULE_EXTENSION, buf);
+        let rules = HttpRule::from_options(&options).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].method, "GET");
+        assert_eq!(rules[0].response_body, "message");
+        assert!(rules[0].body.is_empty());
+        assert!(rules[0].template.matches("/v1/a").is_some());
+        assert_eq!(rules[1].method, "POST");
+        assert_eq!(rules[1].body, "*");
+        assert!(rules[1].template.matches("/v1:hello").is_some());
+
+        // A rule without pattern is invalid.
+        let mut buf = vec![];
+        write_len(&mut buf, 7, b"*");
+        let mut options = MethodOptions::new();
+        options
+            .mut_unknown_fields()
+            .add_length_delimited(HTTP_RULE_EXTENSION, buf);
+        assert!(HttpRule::from_options(&options).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A watchdog that aborts calls whose batches are stuck.
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::call::{MessageReader, MethodType, RpcStatusCode};
+use crate::grpc_sys::{self, grpc_call, grpc_call_error};
+use crate::server::{BoxHandler, CloneableHandler};
+use crate::task::Delay;
+use crate::RpcContext;
+
+/// A reference of a call that keeps it alive.
+struct CallRef(*mut grpc_call);
+
+unsafe impl Send for CallRef {}
+
+impl CallRef {
+    fn new(call: *mut grpc_call) -> CallRef {
+        unsafe { grpc_sys::grpc_call_ref(call) };
+        CallRef(call)
+    }
+
+    fn peer(&self) -> String {
+        unsafe {
+            let p = grpc_sys::grpc_call_get_peer(self.0);
+            let peer = CStr::from_ptr(p).to_string_lossy().into_owned();
+            grpc_sys::gpr_free(p as _);
+            peer
+        }
+    }
+}
+
+impl Drop for CallRef {
+    fn drop(&mut self) {
+        unsafe { grpc_sys::grpc_call_unref(self.0) }
+    }
+}
+
+/// Aborts a call if any of its batches is not completed within the bound.
+pub(crate) struct Watchdog {
+    bound: Duration,
+    method: String,
+}
+
+impl Watchdog {
+    pub fn new(bound: Duration, method: &[u8]) -> Arc<Watchdog> {
+        Arc::new(Watchdog {
+            bound,
+            method: String::from_utf8_lossy(method).into_owned(),
+        })
+    }
+
+    /// Watch a batch of the call, the call is aborted unless the returned
+    /// guard is dropped within the bound.
+    pub fn watch(self: &Arc<Self>, call: *mut grpc_call, op: &'static str) -> Box<dyn Send> {
+        let call = CallRef::new(call);
+        let watchdog = self.clone();
+        let start = Instant::now();
+        let abort = move || {
+            let status = RpcStatusCode::ABORTED;
+            warn!(
+                "{} of call {} to {} is not completed in {:?}, aborting with {:?}",
+                op,
+                watchdog.method,
+                call.peer(),
+                start.elapsed(),
+                status
+            );
+            let details =
+                CString::new(format!("{} is not completed in {:?}", op, watchdog.bound)).unwrap();
+            let code = unsafe {
+                grpc_sys::grpc_call_cancel_with_status(
+                    call.0,
+                    status.into(),
+                    details.as_ptr(),
+                    ptr::null_mut(),
+                )
+            };
+            if code != grpc_call_error::GRPC_CALL_OK {
+                error!("failed to abort call {}: {:?}", watchdog.method, code);
+            }
+        };
+        Box::new(Delay::with_callback(
+            start + self.bound,
+            Some(Box::new(abort)),
+        ))
+    }
+}
+
+/// A handler that watches the batches of the calls it handles.
+pub(crate) struct WatchedHandler {
+    inner: BoxHandler,
+    bound: Duration,
+}
+
+impl WatchedHandler {
+    pub fn new(inner: BoxHandler, bound: Duration) -> WatchedHandler {
+        WatchedHandler { inner, bound }
+    }
+}
+
+impl CloneableHandler for WatchedHandler {
+    fn handle(&mut self, mut ctx: RpcContext<'_>, reqs: Option<MessageReader>) {
+        ctx.set_watchdog(Watchdog::new(self.bound, ctx.method()));
+        self.inner.handle(ctx, reqs)
+    }
+
+    fn box_clone(&self) -> BoxHandler {
+        Box::new(WatchedHandler {
+            inner: self.inner.box_clone(),
+            bound: self.bound,
+        })
+    }
+
+    fn method_type(&self) -> MethodType {
+        self.inner.method_type()
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_response_cache() {
+    #[derive(Clone)]
+    struct CountingService(Arc<AtomicUsize>);
+
+    impl Greeter for CountingService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let n = self.0.fetch_add(1, Ordering::SeqCst);
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("{} {}", req.get_name(), n));
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    // Generated method descriptors are private.
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let counter = Arc::new(AtomicUsize::new(0));
+    let cache = ResponseCache::new(Duration::from_millis(500), 16);
+    let service =
+        create_greeter(CountingService(counter.clone())).cache_responses(&METHOD, cache.clone());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("a".to_owned());
+    for _ in 0..3 {
+        let resp = client.say_hello(&req).unwrap();
+        assert_eq!(resp.get_message(), "a 0");
+    }
+    req.set_name("b".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "b 1");
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 2));
+
+    // Expired responses are computed again.
+    thread::sleep(Duration::from_millis(600));
+    req.set_name("a".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "a 2");
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_cacheable_unary_call() {
+    const SAY_HELLO: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    #[derive(Clone)]
+    struct CountingService(Arc<AtomicUsize>);
+
+    impl Greeter for CountingService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("{} {}", req.get_name(), count));
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(CountingService(counter.clone())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .max_get_payload_size(1024)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    // Without a cache, every call reaches the server.
+    let mut req = HelloRequest::default();
+    req.set_name("a".to_owned());
+    for i in 1..=2 {
+        let resp = client.cacheable_unary_call(&SAY_HELLO, &req, CallOption::default());
+        assert_eq!(resp.unwrap().get_message(), format!("a {}", i));
+    }
+
+    let cache = ResponseCache::new(Duration::from_secs(60), 10);
+    let cached = client.with_cache(cache.clone());
+    for _ in 0..3 {
+        let resp = cached.cacheable_unary_call(&SAY_HELLO, &req, CallOption::default());
+        assert_eq!(resp.unwrap().get_message(), "a 3");
+    }
+    req.set_name("b".to_owned());
+    let resp = cached.cacheable_unary_call(&SAY_HELLO, &req, CallOption::default());
+    assert_eq!(resp.unwrap().get_message(), "b 4");
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 2, 2));
+
+    // Plain calls bypass the cache.
+    let resp = cached.unary_call(&SAY_HELLO, &req, CallOption::default());
+    assert_eq!(resp.unwrap().get_message(), "b 5");
+
+    // Derived clients with different headers don't share responses.
+    let tenant = |name| {
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("x-tenant", name).unwrap();
+        builder.build()
+    };
+    let tenants = [
+        cached.with_default_metadata(tenant("a")),
+        cached.with_default_metadata(tenant("b")),
+    ];
+    for (client, exp) in tenants.iter().zip(&["b 6", "b 7"]) {
+        for _ in 0..2 {
+            let resp = client.cacheable_unary_call(&SAY_HELLO, &req, CallOption::default());
+            assert_eq!(resp.unwrap().get_message(), *exp);
+        }
+    }
+    let resp = cached.cacheable_unary_call(&SAY_HELLO, &req, CallOption::default());
+    assert_eq!(resp.unwrap().get_message(), "b 4");
+    assert_eq!(counter.load(Ordering::SeqCst), 7);
+}
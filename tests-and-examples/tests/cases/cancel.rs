@@ -264,6 +264,59 @@ fn test_server_cancel_on_dropping() {
     check_cancel(rx);
 }
 
+#[test]
+fn test_cancel_with_handle() {
+    let (service, client, _server) = prepare_suite();
+
+    // Report once the server observes the cancellation.
+    let (tx, rx) = std_mpsc::channel();
+    let tx = Mutex::new(tx);
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(move |stream, sink| {
+        let tx = tx.lock().unwrap().clone();
+        let f = stream.for_each(|_| Ok(())).then(move |res| {
+            let _sink = sink;
+            tx.send(res.is_err()).unwrap();
+            Ok(())
+        });
+        Box::new(f)
+    }));
+    let (_tx, resp) = client.route_chat().unwrap();
+    let handle = resp.call_handle();
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.cancel_with_reason("shutting down");
+    });
+    match resp.into_future().wait() {
+        Err((Error::RpcFailure(s), _)) => {
+            assert_eq!(s.status, RpcStatusCode::CANCELLED);
+            assert_eq!(
+                s.details.as_ref().map(String::as_str),
+                Some("shutting down")
+            );
+        }
+        Err((e, _)) => panic!("expected cancel, but got: {:?}", e),
+        Ok(_) => panic!("expected error, but got: Ok(_)"),
+    }
+    t.join().unwrap();
+    assert!(rx.recv_timeout(Duration::from_secs(3)).unwrap());
+
+    // Cancelling a finished call has no effect.
+    *service.record_route_handler.lock().unwrap() = Some(Box::new(|stream, sink| {
+        let f = stream
+            .for_each(|_| Ok(()))
+            .map_err(|_| ())
+            .and_then(|_| sink.success(RouteSummary::default()).map_err(|_| ()));
+        Box::new(f)
+    }));
+    let (tx, resp) = client.record_route().unwrap();
+    let handle = tx.call_handle();
+    let mut tx = tx;
+    future::poll_fn(|| tx.close()).wait().unwrap();
+    resp.wait().unwrap();
+    handle.cancel();
+    handle.clone().cancel_with_reason("late");
+}
+
 #[test]
 fn test_early_exit() {
     let (service, client, _server) = prepare_suite();
@@ -281,3 +334,59 @@ fn test_early_exit() {
 
     rx.recv_timeout(Duration::from_secs(1)).unwrap();
 }
+
+#[test]
+fn test_stream_idle_timeout() {
+    let (service, client, _server) = prepare_suite();
+
+    // Keep the call alive without sending any message.
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(|stream, sink| {
+        let f = stream.for_each(|_| Ok(())).then(|_| {
+            let _sink = sink;
+            Ok(())
+        });
+        Box::new(f)
+    }));
+    let opt = CallOption::default()
+        .no_deadline()
+        .stream_idle_timeout(Duration::from_millis(200));
+    assert!(opt.get_no_deadline());
+    let (_tx, rx) = client.route_chat_opt(opt).unwrap();
+    match rx.into_future().wait() {
+        Err((Error::RpcFailure(s), _)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        Err((e, _)) => panic!("expected deadline exceeded, but got: {:?}", e),
+        Ok(_) => panic!("expected error, but got: Ok(_)"),
+    }
+}
+
+#[test]
+fn test_stream_idle_timeout_slow_consumer() {
+    let (service, client, _server) = prepare_suite();
+
+    *service.route_chat_handler.lock().unwrap() = Some(Box::new(|_, sink| {
+        let notes = streams::iter_ok::<_, Error>(0..3).and_then(|i| {
+            let mut note = RouteNote::default();
+            note.set_message(i.to_string());
+            sleep(Duration::from_millis(50))
+                .map(move |_| (note, WriteFlags::default()))
+                .map_err(|_| Error::RemoteStopped)
+        });
+        let f = sink
+            .send_all(notes)
+            .map(|_| ())
+            .map_err(|e| panic!("failed to send notes: {:?}", e));
+        Box::new(f)
+    }));
+    let opt = CallOption::default()
+        .no_deadline()
+        .stream_idle_timeout(Duration::from_millis(200));
+    let (_tx, rx) = client.route_chat_opt(opt).unwrap();
+    // The messages arrive in time, but they are taken after the timeout.
+    let mut rx = rx.wait();
+    let mut messages = vec![];
+    while let Some(note) = rx.next() {
+        messages.push(note.unwrap().get_message().to_owned());
+        thread::sleep(Duration::from_millis(400));
+    }
+    assert_eq!(messages, vec!["0", "1", "2"]);
+}
@@ -0,0 +1,642 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_resolver_cache() {
+    #[derive(Clone)]
+    struct AuthorityService;
+
+    impl Greeter for AuthorityService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(String::from_utf8_lossy(ctx.host()).into_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(AuthorityService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("localhost:{}", server.bind_addrs()[0].1);
+
+    let cache = FixedTtlResolverCache::new(Duration::from_secs(60), Duration::from_secs(60));
+    cache
+        .resolve("localhost", server.bind_addrs()[0].1)
+        .unwrap();
+    for _ in 0..2 {
+        let ch = ChannelBuilder::new(env.clone())
+            .resolver_cache(cache.clone())
+            .connect(&addr);
+        let client = GreeterClient::new(ch);
+        let resp = client.say_hello(&HelloRequest::default()).unwrap();
+        // The original host should still be used as authority.
+        assert_eq!(resp.get_message(), addr);
+    }
+    let stats = cache.stats();
+    assert_eq!((stats.misses, stats.hits), (1, 2));
+}
+
+#[test]
+fn test_custom_resolver() {
+    #[derive(Clone)]
+    struct NamedService(usize);
+
+    impl Greeter for NamedService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(self.0.to_string());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    struct StaticResolver(Vec<std::net::SocketAddr>);
+
+    impl Resolver for StaticResolver {
+        fn resolve(&self, target: &str) -> std::io::Result<Resolution> {
+            assert_eq!(target, "greeter");
+            Ok(Resolution {
+                addrs: self.0.clone(),
+                service_config: Some(r#"{"loadBalancingPolicy":"round_robin"}"#.to_owned()),
+            })
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut servers = vec![];
+    let mut addrs = vec![];
+    for i in 0..2 {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(NamedService(i)))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        addrs.push(([127, 0, 0, 1], server.bind_addrs()[0].1).into());
+        servers.push(server);
+    }
+    register_resolver("static-test", StaticResolver(addrs));
+
+    let ch = ChannelBuilder::new(env.clone()).connect("static-test://greeter");
+    let client = GreeterClient::new(ch.clone());
+    client.say_hello(&HelloRequest::default()).unwrap();
+    // The service config enables round robin.
+    for _ in 0..100 {
+        let ready = ch
+            .subchannel_states()
+            .iter()
+            .filter(|s| s.state == ConnectivityState::GRPC_CHANNEL_READY)
+            .count();
+        if ready == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut counts = vec![0; 2];
+    for _ in 0..10 {
+        let resp = client.say_hello(&HelloRequest::default()).unwrap();
+        counts[resp.get_message().parse::<usize>().unwrap()] += 1;
+    }
+    assert_eq!(counts, vec![5, 5]);
+
+    // Unregistered schemes are left to gRPC Core, which fails the calls.
+    let ch = ChannelBuilder::new(env).connect("unknown-test://greeter");
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_millis(500));
+    assert!(client.say_hello_opt(&HelloRequest::default(), opt).is_err());
+}
+
+#[test]
+fn test_channel_pool() {
+    #[derive(Clone)]
+    struct HoldService {
+        peers: Arc<Mutex<Vec<String>>>,
+        held: Arc<Mutex<Vec<UnarySink<HelloReply>>>>,
+    }
+
+    impl Greeter for HoldService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            self.peers.lock().unwrap().push(ctx.peer());
+            if req.get_name() == "hold" {
+                self.held.lock().unwrap().push(sink);
+                return;
+            }
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = HoldService {
+        peers: Arc::default(),
+        held: Arc::default(),
+    };
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(service.clone()))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+
+    // Every channel of the pool has its own connection.
+    let pool = ChannelPoolBuilder::new(3).connect(&addr, || ChannelBuilder::new(env.clone()));
+    assert_eq!(pool.size(), 3);
+    for _ in 0..6 {
+        let client = GreeterClient::new(pool.channel());
+        client.say_hello(&HelloRequest::default()).unwrap();
+    }
+    let mut peers = service.peers.lock().unwrap().clone();
+    assert_eq!(peers[..3], peers[3..]);
+    peers.sort();
+    peers.dedup();
+    assert_eq!(peers.len(), 3, "{:?}", peers);
+    assert_eq!(pool.in_flight(), vec![0, 0, 0]);
+
+    let pool = ChannelPoolBuilder::new(2)
+        .policy(PoolPolicy::LeastLoaded)
+        .connect(&addr, || ChannelBuilder::new(env.clone()));
+    let mut hold = HelloRequest::default();
+    hold.set_name("hold".to_owned());
+    let first = GreeterClient::new(pool.channel());
+    let held1 = first.say_hello_async(&hold).unwrap();
+    assert_eq!(pool.in_flight(), vec![1, 0]);
+    let held2 = GreeterClient::new(pool.channel())
+        .say_hello_async(&hold)
+        .unwrap();
+    assert_eq!(pool.in_flight(), vec![1, 1]);
+
+    // Release the call of the first channel, then it's the least loaded.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while service.held.lock().unwrap().len() < 2 {
+        assert!(Instant::now() < deadline, "calls are not received");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let sink = service.held.lock().unwrap().remove(0);
+    sink.success(HelloReply::default()).wait().unwrap();
+    held1.wait().unwrap();
+    assert_eq!(pool.in_flight(), vec![0, 1]);
+    let held3 = GreeterClient::new(pool.channel())
+        .say_hello_async(&hold)
+        .unwrap();
+    assert_eq!(pool.in_flight(), vec![1, 1]);
+
+    for sink in service.held.lock().unwrap().drain(..) {
+        sink.success(HelloReply::default()).wait().unwrap();
+    }
+    held2.wait().unwrap();
+    held3.wait().unwrap();
+    assert_eq!(pool.in_flight(), vec![0, 0]);
+}
+
+#[test]
+fn test_host_pool() {
+    #[derive(Clone)]
+    struct HostService(&'static str);
+
+    impl Greeter for HostService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(self.0.to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, usize)>>);
+
+    impl StatsHandler for Recorder {
+        fn host_picked(&self, host: &str, in_flight: usize, _: Duration) {
+            self.0.lock().unwrap().push((host.to_owned(), in_flight));
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut servers = vec![];
+    let mut builder = HostPoolBuilder::new()
+        .max_concurrency(1)
+        .shed_to_least_loaded(true);
+    for name in &["a", "b"] {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(HostService(name)))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+        let ch = ChannelBuilder::new(env.clone()).connect(&addr);
+        builder = builder.host(*name, ch);
+        servers.push(server);
+    }
+    let recorder = Arc::new(Recorder::default());
+    let pool = builder.stats_handler(recorder.clone()).build();
+
+    let a = pool.pick().wait().unwrap();
+    let b = pool.pick().wait().unwrap();
+    assert_eq!((a.host(), b.host()), ("a", "b"));
+    let client = GreeterClient::new(b.channel().clone());
+    let resp = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(resp.get_message(), "b");
+
+    // "a" is picked in turn, but it's at the limit.
+    drop(b);
+    let b = pool.pick().wait().unwrap();
+    assert_eq!(b.host(), "b");
+
+    // All hosts are at the limit, the pick is queued and then moved from
+    // "b" to "a" once "a" is released.
+    let p = pool.clone();
+    let handle = thread::spawn(move || p.pick().wait().unwrap().host().to_owned());
+    thread::sleep(Duration::from_millis(200));
+    drop(a);
+    assert_eq!(handle.join().unwrap(), "a");
+    drop(b);
+
+    let stats = pool.stats();
+    assert_eq!(
+        stats
+            .iter()
+            .map(|s| (s.host.as_str(), s.in_flight, s.picked, s.shed))
+            .collect::<Vec<_>>(),
+        vec![("a", 0, 2, 1), ("b", 0, 2, 1)]
+    );
+    assert!(stats[0].max_queue_wait >= Duration::from_millis(200));
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        vec![
+            ("a".to_owned(), 1),
+            ("b".to_owned(), 1),
+            ("b".to_owned(), 1),
+            ("a".to_owned(), 1)
+        ]
+    );
+}
+
+#[test]
+fn test_load_balancing_policy() {
+    #[derive(Clone)]
+    struct NamedService(usize);
+
+    impl Greeter for NamedService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(self.0.to_string());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut servers = vec![];
+    let mut addrs = vec![];
+    for i in 0..3 {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(NamedService(i)))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        addrs.push(format!("127.0.0.1:{}", server.bind_addrs()[0].1));
+        servers.push(server);
+    }
+    // The sockaddr resolver resolves the target to all the addresses.
+    let target = format!("ipv4:{}", addrs.join(","));
+
+    let distribution = |policy| {
+        let ch = ChannelBuilder::new(env.clone())
+            .load_balancing_policy(policy)
+            .connect(&target);
+        let client = GreeterClient::new(ch.clone());
+        // Wait for the connections to be established.
+        client.say_hello(&HelloRequest::default()).unwrap();
+        let mut states = vec![];
+        for _ in 0..100 {
+            states = ch.subchannel_states();
+            let ready = states
+                .iter()
+                .filter(|s| s.state == ConnectivityState::GRPC_CHANNEL_READY)
+                .count();
+            if policy == LbPolicy::PickFirst || ready == addrs.len() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut counts = vec![0; addrs.len()];
+        for _ in 0..30 {
+            let resp = client.say_hello(&HelloRequest::default()).unwrap();
+            counts[resp.get_message().parse::<usize>().unwrap()] += 1;
+        }
+        (states, counts)
+    };
+
+    let (states, counts) = distribution(LbPolicy::RoundRobin);
+    let mut targets: Vec<_> = states.iter().map(|s| s.target.clone()).collect();
+    targets.sort();
+    let mut expected: Vec<_> = addrs.iter().map(|a| format!("ipv4:{}", a)).collect();
+    expected.sort();
+    assert_eq!(targets, expected);
+    assert!(states
+        .iter()
+        .all(|s| s.state == ConnectivityState::GRPC_CHANNEL_READY));
+    assert_eq!(counts, vec![10; 3]);
+
+    let (_, counts) = distribution(LbPolicy::PickFirst);
+    assert_eq!(
+        counts.iter().filter(|c| **c == 30).count(),
+        1,
+        "{:?}",
+        counts
+    );
+}
+
+#[test]
+fn test_connectivity_events() {
+    #[derive(Clone)]
+    struct EmptyService;
+
+    impl Greeter for EmptyService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut servers = vec![];
+    let mut addrs = vec![];
+    for _ in 0..2 {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_greeter(EmptyService))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        addrs.push(format!("127.0.0.1:{}", server.bind_addrs()[0].1));
+        servers.push(server);
+    }
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let ch = ChannelBuilder::new(env)
+        .load_balancing_policy(LbPolicy::RoundRobin)
+        .on_connectivity_change(move |e| tx.lock().unwrap().send(e.clone()).unwrap())
+        .connect(&format!("ipv4:{}", addrs.join(",")));
+    let client = GreeterClient::new(ch);
+    client.say_hello(&HelloRequest::default()).unwrap();
+
+    // Wait until the transitions of every address to ready are reported.
+    let wait_for = |address: &str, state: ConnectivityState| loop {
+        let e = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(e.address.starts_with("ipv4:"), "{:?}", e);
+        assert_ne!(e.old_state, e.new_state);
+        if e.address == address && e.new_state == state {
+            return e;
+        }
+    };
+    let ready = ConnectivityState::GRPC_CHANNEL_READY;
+    for addr in &addrs {
+        wait_for(&format!("ipv4:{}", addr), ready);
+    }
+
+    // Stopping a backend is reported as a transition from ready.
+    let server = servers.pop().unwrap();
+    drop(server);
+    let address = format!("ipv4:{}", addrs[1]);
+    let e = loop {
+        let e = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        if e.address == address {
+            break e;
+        }
+    };
+    assert_eq!(e.old_state, ready);
+    assert_ne!(e.new_state, ready);
+}
+
+#[test]
+fn test_user_agent() {
+    #[derive(Clone)]
+    struct UserAgentService;
+
+    impl Greeter for UserAgentService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(ctx.user_agent().unwrap().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(UserAgentService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let addr = format!("127.0.0.1:{}", port);
+
+    let client = GreeterClient::new(ChannelBuilder::new(env.clone()).connect(&addr));
+    let agent = client
+        .say_hello(&HelloRequest::default())
+        .unwrap()
+        .take_message();
+    assert!(agent.starts_with("grpc-rust/"), "{}", agent);
+
+    let ch = ChannelBuilder::new(env.clone())
+        .primary_user_agent("fleet-client/1.2.3")
+        .secondary_user_agent("canary")
+        .connect(&addr);
+    let client = GreeterClient::new(ch);
+    let agent = client
+        .say_hello(&HelloRequest::default())
+        .unwrap()
+        .take_message();
+    assert!(
+        agent.starts_with("fleet-client/1.2.3 grpc-rust/"),
+        "{}",
+        agent
+    );
+    assert!(agent.ends_with(" canary"), "{}", agent);
+}
+
+#[test]
+fn test_raw_channel_args() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            mut req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.take_name());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let check_exhausted = |res: Result<HelloReply>| match res {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        res => panic!("expect resource exhausted, but got {:?}", res),
+    };
+    let hello = |name: &str| {
+        let mut req = HelloRequest::default();
+        req.set_name(name.to_owned());
+        req
+    };
+
+    let token = Arc::new(AtomicUsize::new(0));
+    let env = Arc::new(EnvBuilder::new().build());
+    let args = unsafe {
+        RawChannelArgs::new()
+            .set_int("grpc.max_receive_message_length", 64)
+            .set_pointer("grpcio.test.unknown_pointer", token.clone())
+    };
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .max_receive_message_len(1024)
+        .raw_args(args)
+        .build()
+        .unwrap();
+    server.start();
+    assert!(Arc::strong_count(&token) > 1);
+    let port = server.bind_addrs()[0].1;
+    let addr = format!("127.0.0.1:{}", port);
+
+    let client = GreeterClient::new(ChannelBuilder::new(env.clone()).connect(&addr));
+    assert_eq!(
+        client.say_hello(&hello("hello")).unwrap().get_message(),
+        "hello"
+    );
+    check_exhausted(client.say_hello(&hello(&"a".repeat(100))));
+
+    let args = RawChannelArgs::new()
+        .set_int("grpc.max_receive_message_length", 4)
+        .set_string("grpc.primary_user_agent", "raw-args");
+    let ch = ChannelBuilder::new(env.clone())
+        .raw_args(args)
+        .connect(&addr);
+    let client = GreeterClient::new(ch);
+    check_exhausted(client.say_hello(&hello("hello")));
+
+    // Options set by the builder override explicit channel args.
+    let explicit = ChannelBuilder::new(env.clone())
+        .max_receive_message_len(1024)
+        .build_args();
+    let args = RawChannelArgs::new().set_int("grpc.max_receive_message_length", 64);
+    let mut explicit_server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .channel_args(explicit)
+        .raw_args(args)
+        .build()
+        .unwrap();
+    explicit_server.start();
+    let port = explicit_server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let explicit_client = GreeterClient::new(ch);
+    check_exhausted(explicit_client.say_hello(&hello(&"a".repeat(100))));
+
+    drop(client);
+    drop(server);
+    // Core releases its references asynchronously.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Arc::strong_count(&token) > 1 {
+        assert!(Instant::now() < deadline, "pointer argument leaked");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_http2_settings() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .stream_initial_window_size(4 * 1024 * 1024)
+        .http2_max_frame_size(1024 * 1024)
+        .http2_bdp_probe(false)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .stream_initial_window_size(4 * 1024 * 1024)
+        .http2_max_frame_size(1024 * 1024)
+        .http2_bdp_probe(false)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(2 * 1024 * 1024));
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), req.get_name());
+}
@@ -0,0 +1,400 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::time::*;
+
+#[test]
+fn test_generic_call() {
+    use protobuf::Message;
+
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("generic".to_owned());
+    let payload = req.write_to_bytes().unwrap();
+    let resp = client
+        .generic_unary_call(
+            "/helloworld.Greeter/SayHello",
+            &payload,
+            CallOption::default(),
+        )
+        .unwrap();
+    let resp = HelloReply::parse_from_bytes(&resp).unwrap();
+    assert_eq!(resp.get_message(), "generic");
+
+    // Unary methods can be called as streaming ones as well.
+    let (tx, rx) = client
+        .generic_duplex_streaming("/helloworld.Greeter/SayHello", CallOption::default())
+        .unwrap();
+    let mut tx = tx.send((payload, WriteFlags::default())).wait().unwrap();
+    future::poll_fn(|| tx.close()).wait().unwrap();
+    let resps: Vec<_> = rx.collect().wait().unwrap();
+    assert_eq!(resps.len(), 1);
+    let resp = HelloReply::parse_from_bytes(&resps[0]).unwrap();
+    assert_eq!(resp.get_message(), "generic");
+
+    match client.generic_unary_call("/helloworld.Greeter/Unknown", &[], CallOption::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+        r => panic!("call should fail: {:?}", r),
+    }
+}
+
+#[test]
+fn test_default_timeout() {
+    #[derive(Clone)]
+    struct DeadlineService;
+
+    impl Greeter for DeadlineService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let remaining = ctx.remaining_time().map(|d| d.as_secs().to_string());
+            let mut resp = HelloReply::default();
+            resp.set_message(remaining.unwrap_or_else(|| "none".to_owned()));
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(DeadlineService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let mut client = Client::new(ch);
+    let req = HelloRequest::default();
+    let remaining = |client: &Client, opt: CallOption| {
+        client
+            .unary_call(&METHOD, &req, opt)
+            .unwrap()
+            .take_message()
+    };
+
+    assert_eq!(remaining(&client, CallOption::default()), "none");
+    client.set_default_timeout(Duration::from_secs(100));
+    let res = remaining(&client, CallOption::default());
+    assert!(res == "99" || res == "98", "{}", res);
+    client.set_method_timeout(METHOD.name, Duration::from_secs(50));
+    let res = remaining(&client, CallOption::default());
+    assert!(res == "49" || res == "48", "{}", res);
+
+    // Options of the call take precedence.
+    let opt = CallOption::default().timeout(Duration::from_secs(20));
+    let res = remaining(&client, opt);
+    assert!(res == "19" || res == "18", "{}", res);
+    assert_eq!(
+        remaining(&client, CallOption::default().no_deadline()),
+        "none"
+    );
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let opt = CallOption::default().deadline(deadline);
+    assert_eq!(opt.get_deadline(), Some(deadline));
+    assert_eq!(opt.get_timeout(), None);
+    let res = remaining(&client, opt);
+    assert!(res == "29" || res == "28", "{}", res);
+
+    // A call created after its deadline fails immediately.
+    let opt = CallOption::default().deadline(Instant::now());
+    match client.unary_call(&METHOD, &req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("call should time out: {:?}", r),
+    }
+}
+
+#[test]
+fn test_call_error() {
+    #[derive(Clone)]
+    struct FailService;
+
+    impl Greeter for FailService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let status =
+                RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, Some("bad name".to_owned()))
+                    .with_details_bin(vec![1, 2, 3]);
+            ctx.spawn(
+                sink.fail(status)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(FailService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let e = CallError::from(client.say_hello(&HelloRequest::default()).unwrap_err());
+    match e {
+        CallError::Remote(ref status) => {
+            assert_eq!(status.status, RpcStatusCode::INVALID_ARGUMENT);
+            assert_eq!(status.details.as_ref().unwrap(), "bad name");
+            assert_eq!(status.details_bin(), Some(&[1, 2, 3][..]));
+        }
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    // Nothing listens on the address after the server is shut down.
+    let _ = server.shutdown().wait();
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_millis(500));
+    let e = CallError::from(
+        client
+            .say_hello_opt(&HelloRequest::default(), opt)
+            .unwrap_err(),
+    );
+    assert!(
+        matches!(e, CallError::Transport(_) | CallError::DeadlineExceeded(_)),
+        "{:?}",
+        e
+    );
+}
+
+#[test]
+fn test_unary_call_with_retry() {
+    #[derive(Clone)]
+    struct FlakyService(Arc<AtomicUsize>);
+
+    impl Greeter for FlakyService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let attempt = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            let f = match req.get_name() {
+                "flaky" if attempt < 3 => {
+                    sink.fail(RpcStatus::new(RpcStatusCode::UNAVAILABLE, None))
+                }
+                "invalid" => sink.fail(RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, None)),
+                _ => {
+                    let mut resp = HelloReply::default();
+                    resp.set_message(attempt.to_string());
+                    sink.success(resp)
+                }
+            };
+            ctx.spawn(f.map_err(|e| panic!("failed to reply {:?}", e)));
+        }
+    }
+
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(FlakyService(attempts.clone())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    let policy = RetryPolicy::new(3).backoff(Duration::from_millis(10), Duration::from_millis(50));
+
+    let mut req = HelloRequest::default();
+    req.set_name("flaky".to_owned());
+    let resp = client
+        .unary_call_with_retry(&METHOD, &req, CallOption::default(), &policy)
+        .unwrap();
+    assert_eq!(resp.get_message(), "3");
+
+    // The attempts are exhausted.
+    attempts.store(0, Ordering::SeqCst);
+    let policy = RetryPolicy::new(2).backoff(Duration::from_millis(10), Duration::from_millis(50));
+    match client.unary_call_with_retry(&METHOD, &req, CallOption::default(), &policy) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNAVAILABLE),
+        r => panic!("call should fail: {:?}", r),
+    }
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+    // Other failures are not retried.
+    attempts.store(0, Ordering::SeqCst);
+    req.set_name("invalid".to_owned());
+    match client.unary_call_with_retry(&METHOD, &req, CallOption::default(), &policy) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::INVALID_ARGUMENT),
+        r => panic!("call should fail: {:?}", r),
+    }
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+    // The async version waits by timers, so it can run on the poll threads.
+    attempts.store(0, Ordering::SeqCst);
+    req.set_name("flaky".to_owned());
+    let policy = RetryPolicy::new(3).backoff(Duration::from_millis(10), Duration::from_millis(50));
+    let f = client
+        .unary_call_with_retry_async(&METHOD, &req, CallOption::default(), &policy)
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+    client.spawn(f.then(move |res| {
+        tx.send(res).unwrap();
+        Ok(())
+    }));
+    let resp = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(resp.get_message(), "3");
+}
+
+#[test]
+fn test_derived_clients() {
+    #[derive(Clone)]
+    struct HeaderService(Arc<Mutex<Vec<UnarySink<HelloReply>>>>);
+
+    impl Greeter for HeaderService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            if req.get_name() == "hang" {
+                self.0.lock().unwrap().push(sink);
+                return;
+            }
+            let headers = ctx.request_headers();
+            let msg: Vec<String> = ["x-tenant", "x-token"]
+                .iter()
+                .map(|k| String::from_utf8(headers.find(k).unwrap_or(b"-").to_vec()).unwrap())
+                .collect();
+            let mut resp = HelloReply::default();
+            resp.set_message(msg.join(","));
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(HeaderService(Arc::default())))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+    let call = |client: &Client, opt| {
+        client
+            .unary_call(&METHOD, &HelloRequest::default(), opt)
+            .unwrap()
+            .take_message()
+    };
+
+    let headers = |k, v| {
+        let mut builder = MetadataBuilder::new();
+        builder.add_str(k, v).unwrap();
+        builder.build()
+    };
+    let tenant = client.with_default_metadata(headers("x-tenant", "a"));
+    let authed = tenant.with_default_metadata(headers("x-token", "t1"));
+    assert_eq!(call(&client, CallOption::default()), "-,-");
+    assert_eq!(call(&tenant, CallOption::default()), "a,-");
+    assert_eq!(call(&authed, CallOption::default()), "a,t1");
+    // Headers of the call take precedence.
+    let opt = CallOption::default().headers(headers("x-token", "t2"));
+    assert_eq!(call(&authed, opt), "a,t2");
+
+    let mut req = HelloRequest::default();
+    req.set_name("hang".to_owned());
+    let fast = authed.with_default_timeout(Duration::from_millis(100));
+    let start = Instant::now();
+    match fast.unary_call(&METHOD, &req, CallOption::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("call should time out: {:?}", r),
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // The derived client keeps the headers.
+    assert_eq!(call(&fast, CallOption::default()), "a,t1");
+}
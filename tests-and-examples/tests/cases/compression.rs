@@ -0,0 +1,135 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::*;
+
+#[test]
+fn test_stream_compression() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut builder = MetadataBuilder::new();
+    builder.add_str("x-user", "tester").unwrap();
+    let opt = CallOption::default()
+        .headers(builder.build())
+        .stream_compression(true);
+    assert!(opt.get_stream_compression());
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(4096));
+    let resp = client.say_hello_opt(&req, opt.clone()).unwrap();
+    assert_eq!(resp.get_message(), req.get_name());
+    // Internal headers should not leak to user headers.
+    assert_eq!(opt.get_headers().unwrap().len(), 1);
+}
+
+#[test]
+fn test_compression_algorithms() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    // Other typed options still apply along with the enabled algorithms.
+    let mut server = ServerBuilder::new(env.clone())
+        .enabled_compression_algorithms(&[CompressionAlgorithms::GRPC_COMPRESS_GZIP])
+        .max_receive_message_len(8 * 1024)
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .enabled_compression_algorithms(&[
+            CompressionAlgorithms::GRPC_COMPRESS_DEFLATE,
+            CompressionAlgorithms::GRPC_COMPRESS_GZIP,
+        ])
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(4096));
+    let opt = CallOption::default().compress(CompressionAlgorithms::GRPC_COMPRESS_GZIP);
+    assert_eq!(
+        opt.get_compression(),
+        Some(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+    );
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message(), req.get_name());
+
+    // Messages can still be sent uncompressed.
+    let opt = CallOption::default()
+        .compress(CompressionAlgorithms::GRPC_COMPRESS_DEFLATE)
+        .write_flags(WriteFlags::default().force_no_compress(true));
+    client.say_hello_opt(&req, opt).unwrap();
+
+    // The server doesn't enable deflate.
+    let opt = CallOption::default().compress(CompressionAlgorithms::GRPC_COMPRESS_DEFLATE);
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+        r => panic!("expected unimplemented, got {:?}", r),
+    }
+
+    req.set_name("a".repeat(16 * 1024));
+    let opt = CallOption::default().write_flags(WriteFlags::default().force_no_compress(true));
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected resource exhausted, got {:?}", r),
+    }
+}
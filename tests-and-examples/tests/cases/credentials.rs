@@ -0,0 +1,442 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::Future;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use grpcio_proto::util;
+
+#[derive(Clone)]
+struct GreeterService;
+
+impl Greeter for GreeterService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        // Reply with all the auth related headers.
+        let mut headers = vec![];
+        for (key, value) in ctx.request_headers() {
+            if key == "authorization" || key == "x-user" {
+                headers.push(format!("{}={}", key, String::from_utf8_lossy(value)));
+            }
+        }
+        let mut resp = HelloReply::default();
+        resp.set_message(headers.join(","));
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+/// A plugin that mints tokens in another thread.
+struct UserPlugin {
+    user: Option<&'static str>,
+}
+
+impl MetadataCredentialsPlugin for UserPlugin {
+    fn get_metadata(&self, ctx: AuthMetadataContext, callback: AuthMetadataCallback) {
+        assert_eq!(ctx.method_name(), "SayHello");
+        let user = self.user;
+        thread::spawn(move || match user {
+            Some(user) => {
+                let mut builder = MetadataBuilder::new();
+                builder.add_str("x-user", user).unwrap();
+                callback.success(builder.build());
+            }
+            None => callback.fail(RpcStatus::new(
+                RpcStatusCode::UNAUTHENTICATED,
+                Some("unknown user".to_owned()),
+            )),
+        });
+    }
+}
+
+/// A plugin that returns metadata synchronously.
+struct SyncPlugin;
+
+impl MetadataCredentialsPlugin for SyncPlugin {
+    fn get_metadata(&self, _: AuthMetadataContext, callback: AuthMetadataCallback) {
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("x-user", "sync").unwrap();
+        callback.success(builder.build());
+    }
+}
+
+#[test]
+fn test_call_credentials() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = create_greeter(GreeterService);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind_secure("127.0.0.1", 0, util::create_test_server_credentials())
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .override_ssl_target("foo.test.google.fr")
+        .secure_connect(
+            &format!("127.0.0.1:{}", port),
+            util::create_test_channel_credentials(),
+        );
+    let client = GreeterClient::new(ch);
+    let req = HelloRequest::default();
+
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), "");
+
+    let creds = CallCredentials::access_token("token");
+    let opt = CallOption::default().credentials(creds.clone());
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message(), "authorization=Bearer token");
+
+    let user = CallCredentials::from_plugin(UserPlugin { user: Some("tom") });
+    let opt = CallOption::default().credentials(creds.compose(&user));
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message(), "authorization=Bearer token,x-user=tom");
+
+    let opt = CallOption::default().credentials(CallCredentials::from_plugin(SyncPlugin));
+    let resp = client.say_hello_opt(&req, opt).unwrap();
+    assert_eq!(resp.get_message(), "x-user=sync");
+
+    let opt =
+        CallOption::default().credentials(CallCredentials::from_plugin(UserPlugin { user: None }));
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(_)) => {}
+        r => panic!("expected rpc failure, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_invalid_google_credentials() {
+    let res = CallCredentials::service_account_jwt_access("{}", Duration::from_secs(60));
+    match res {
+        Err(Error::GoogleAuthenticationFailed) => {}
+        _ => panic!("expected authentication failure"),
+    }
+    match CallCredentials::google_refresh_token("not a json") {
+        Err(Error::GoogleAuthenticationFailed) => {}
+        _ => panic!("expected authentication failure"),
+    }
+    let res = CallCredentials::service_account_jwt_access_from_file(
+        "/path/not/exist.json",
+        Duration::from_secs(60),
+    );
+    match res {
+        Err(Error::Io(_)) => {}
+        _ => panic!("expected io error"),
+    }
+}
+
+#[derive(Clone)]
+struct PeerIdentityService;
+
+impl Greeter for PeerIdentityService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let auth_ctx = ctx.auth_context().unwrap();
+        assert_eq!(auth_ctx.transport_security_type(), Some("ssl"));
+        assert!(auth_ctx.x509_pem_cert().is_some());
+        assert_eq!(
+            auth_ctx.security_level(),
+            SecurityLevel::PrivacyAndIntegrity
+        );
+        let mut resp = HelloReply::default();
+        resp.set_message(format!(
+            "{} {:?} {}",
+            auth_ctx.peer_is_authenticated(),
+            auth_ctx.x509_common_name(),
+            auth_ctx.x509_subject_alternative_names().join(",")
+        ));
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+#[test]
+fn test_client_certificate() {
+    let ca = include_str!("../../../proto/data/ca.pem");
+    let cert = include_str!("../../../proto/data/server1.pem");
+    let key = include_str!("../../../proto/data/server1.key");
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let server_creds = ServerCredentialsBuilder::new()
+        .add_cert(cert.into(), key.into())
+        .root_cert(ca, false)
+        .client_certificate_request_type(
+            CertificateRequestType::GRPC_SSL_REQUEST_AND_REQUIRE_CLIENT_CERTIFICATE_AND_VERIFY,
+        )
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(PeerIdentityService))
+        .bind_secure("127.0.0.1", 0, server_creds)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+
+    let creds = ChannelCredentialsBuilder::new()
+        .root_cert(ca.into())
+        .cert(cert.into(), key.into())
+        .build();
+    let ch = ChannelBuilder::new(env.clone())
+        .override_ssl_target("foo.test.google.fr")
+        .secure_connect(&addr, creds);
+    let client = GreeterClient::new(ch);
+    let resp = client.say_hello(&HelloRequest::default()).unwrap();
+    // Whether IP addresses are included depends on the version of gRPC Core.
+    let expected = "true Some(\"*.test.google.com\") *.test.google.fr,waterzooi.test.google.be";
+    assert!(resp.get_message().starts_with(expected), "{:?}", resp);
+
+    // Client certificate is required.
+    let ch = ChannelBuilder::new(env)
+        .override_ssl_target("foo.test.google.fr")
+        .secure_connect(&addr, util::create_test_channel_credentials());
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_secs(3));
+    assert!(client.say_hello_opt(&HelloRequest::default(), opt).is_err());
+}
+
+#[derive(Clone)]
+struct IdentityCacheService {
+    identities: Arc<Mutex<HashMap<PeerIdentity, usize>>>,
+}
+
+impl Greeter for IdentityCacheService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let identity = ctx.peer_identity().unwrap();
+        assert_eq!(identity.transport_security_type(), Some("ssl"));
+        assert!(identity.values().count() > 0);
+        *self.identities.lock().unwrap().entry(identity).or_insert(0) += 1;
+        let mut resp = HelloReply::default();
+        resp.set_message(ctx.peer());
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+#[test]
+fn test_peer_identity() {
+    let ca = include_str!("../../../proto/data/ca.pem");
+    let cert = include_str!("../../../proto/data/server1.pem");
+    let key = include_str!("../../../proto/data/server1.key");
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let server_creds = ServerCredentialsBuilder::new()
+        .add_cert(cert.into(), key.into())
+        .root_cert(ca, false)
+        .client_certificate_request_type(
+            CertificateRequestType::GRPC_SSL_REQUEST_AND_REQUIRE_CLIENT_CERTIFICATE_AND_VERIFY,
+        )
+        .build();
+    let identities = Arc::new(Mutex::new(HashMap::new()));
+    let service = IdentityCacheService {
+        identities: identities.clone(),
+    };
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(service))
+        .bind_secure("127.0.0.1", 0, server_creds)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+
+    let mut peers = vec![];
+    for i in 0..2 {
+        let creds = ChannelCredentialsBuilder::new()
+            .root_cert(ca.into())
+            .cert(cert.into(), key.into())
+            .build();
+        // Different arguments make sure a new connection is used.
+        let ch = ChannelBuilder::new(env.clone())
+            .override_ssl_target("foo.test.google.fr")
+            .primary_user_agent(&format!("client-{}", i))
+            .secure_connect(&addr, creds);
+        let client = GreeterClient::new(ch);
+        for _ in 0..2 {
+            let resp = client.say_hello(&HelloRequest::default()).unwrap();
+            peers.push(resp.get_message().to_owned());
+        }
+    }
+    assert_ne!(peers[0], peers[2]);
+    // All calls share the same identity.
+    let identities = identities.lock().unwrap();
+    assert_eq!(identities.len(), 1, "{:?}", identities);
+    assert_eq!(identities.values().next(), Some(&4));
+}
+
+struct ReloadProvider {
+    pending: Arc<Mutex<Option<CertificateConfig>>>,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl CertificateProvider for ReloadProvider {
+    fn fetch(&self) -> Result<Option<CertificateConfig>> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+        Ok(self.pending.lock().unwrap().take())
+    }
+}
+
+#[test]
+fn test_certificate_provider() {
+    let cert = include_str!("../../../proto/data/server1.pem");
+    let key = include_str!("../../../proto/data/server1.key");
+    let config = || CertificateConfig::new().add_cert(cert.into(), key.into());
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let pending = Arc::new(Mutex::new(Some(config())));
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let provider = ReloadProvider {
+        pending: pending.clone(),
+        fetches: fetches.clone(),
+    };
+    let server_creds = ServerCredentialsBuilder::new()
+        .with_provider(provider)
+        .build();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind_secure("127.0.0.1", 0, server_creds)
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+    assert!(pending.lock().unwrap().is_none());
+
+    let connect = || {
+        let ch = ChannelBuilder::new(env.clone())
+            .override_ssl_target("foo.test.google.fr")
+            .secure_connect(&addr, util::create_test_channel_credentials());
+        GreeterClient::new(ch)
+    };
+    connect().say_hello(&HelloRequest::default()).unwrap();
+    let count = fetches.load(Ordering::SeqCst);
+    assert!(count >= 1, "{}", count);
+
+    // New handshakes should pick up the rotated certificates.
+    *pending.lock().unwrap() = Some(config());
+    connect().say_hello(&HelloRequest::default()).unwrap();
+    assert!(pending.lock().unwrap().is_none());
+    assert!(fetches.load(Ordering::SeqCst) > count);
+
+    // The first fetch must provide certificates.
+    let provider = ReloadProvider {
+        pending: Arc::new(Mutex::new(None)),
+        fetches: Arc::new(AtomicUsize::new(0)),
+    };
+    let server_creds = ServerCredentialsBuilder::new()
+        .with_provider(provider)
+        .build();
+    let res = ServerBuilder::new(env)
+        .register_service(create_greeter(GreeterService))
+        .bind_secure("127.0.0.1", 0, server_creds)
+        .build();
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_verify_peer() {
+    let ca = include_str!("../../../proto/data/ca.pem");
+    let cert = include_str!("../../../proto/data/server1.pem");
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind_secure("127.0.0.1", 0, util::create_test_server_credentials())
+        .build()
+        .unwrap();
+    server.start();
+    let addr = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+
+    let connect = |accept: bool| {
+        let verified = Arc::new(AtomicUsize::new(0));
+        let v = verified.clone();
+        let creds = ChannelCredentialsBuilder::new()
+            .root_cert(ca.into())
+            .verify_peer(move |_, pem| {
+                assert_eq!(pem.trim(), cert.trim());
+                v.fetch_add(1, Ordering::SeqCst);
+                accept
+            })
+            .build();
+        let ch = ChannelBuilder::new(env.clone())
+            .override_ssl_target("foo.test.google.fr")
+            .secure_connect(&addr, creds);
+        let opt = CallOption::default().timeout(Duration::from_secs(3));
+        let res = GreeterClient::new(ch).say_hello_opt(&HelloRequest::default(), opt);
+        assert!(verified.load(Ordering::SeqCst) > 0);
+        res
+    };
+    connect(true).unwrap();
+    assert!(connect(false).is_err());
+}
+
+#[test]
+fn test_local_credentials() {
+    #[derive(Clone)]
+    struct LocalService;
+
+    impl Greeter for LocalService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let security_type = ctx
+                .auth_context()
+                .and_then(|auth_ctx| auth_ctx.transport_security_type().map(str::to_owned));
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("{:?}", security_type));
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let secure = LocalTransport::UnixSocket(dir.join(format!("grpcio-local-{}.sock", pid)));
+    let insecure = LocalTransport::UnixSocket(dir.join(format!("grpcio-plain-{}.sock", pid)));
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(LocalService))
+        .bind_local_secure(&secure, ServerCredentials::local())
+        .unwrap()
+        .bind_local(&insecure)
+        .unwrap()
+        .build()
+        .unwrap();
+    server.start();
+
+    let ch = ChannelBuilder::new(env.clone())
+        .secure_connect_local(&secure, ChannelCredentials::local())
+        .unwrap();
+    let resp = GreeterClient::new(ch).say_hello(&HelloRequest::default());
+    assert_eq!(resp.unwrap().get_message(), "Some(\"local\")");
+    let ch = ChannelBuilder::new(env.clone())
+        .connect_local(&insecure)
+        .unwrap();
+    let resp = GreeterClient::new(ch).say_hello(&HelloRequest::default());
+    assert_eq!(resp.unwrap().get_message(), "None");
+
+    drop(server);
+    for transport in &[secure, insecure] {
+        let LocalTransport::UnixSocket(path) = transport;
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_channelz() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+    let mut req = HelloRequest::default();
+    req.set_name("channelz".to_owned());
+    client.say_hello(&req).unwrap();
+
+    let server_id = server.channelz_id().unwrap();
+    let json = server.channelz().unwrap();
+    assert!(
+        json.contains(&format!("\"serverId\":\"{}\"", server_id)),
+        "{}",
+        json
+    );
+    let servers = channelz::get_servers(server_id);
+    assert!(
+        servers.contains(&format!("\"serverId\":\"{}\"", server_id)),
+        "{}",
+        servers
+    );
+
+    let channel_id = ch.channelz_id().unwrap();
+    let json = ch.channelz_state().unwrap();
+    assert!(
+        json.contains(&format!("\"channelId\":\"{}\"", channel_id)),
+        "{}",
+        json
+    );
+    assert!(json.contains("callsSucceeded"), "{}", json);
+    let top = channelz::get_top_channels(0);
+    assert!(
+        top.contains(&format!("\"channelId\":\"{}\"", channel_id)),
+        "{}",
+        top
+    );
+    assert_eq!(channelz::get_channel(u64::MAX), None);
+}
+
+#[test]
+fn test_stats_handler() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            if req.get_name().is_empty() {
+                let status = RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, None);
+                ctx.spawn(sink.fail(status).map_err(|_| ()));
+                return;
+            }
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        started: AtomicUsize,
+        ends: Mutex<Vec<(String, CallSide, CallEnd)>>,
+    }
+
+    impl StatsHandler for Recorder {
+        fn call_start(&self, _: &CallInfo) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn call_end(&self, call: &CallInfo, end: &CallEnd) {
+            let record = (call.method().to_owned(), call.side(), *end);
+            self.ends.lock().unwrap().push(record);
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let server_stats = Arc::new(Recorder::default());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .stats_handler(server_stats.clone())
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let client_stats = Arc::new(Recorder::default());
+    let ch = ChannelBuilder::new(env)
+        .stats_handler(client_stats.clone())
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("stats".to_owned());
+    client.say_hello(&req).unwrap();
+    assert!(client.say_hello(&HelloRequest::default()).is_err());
+
+    // A tag, a length and the 5 bytes of the name.
+    let req_len = 7;
+    for (stats, side) in &[
+        (&client_stats, CallSide::Client),
+        (&server_stats, CallSide::Server),
+    ] {
+        assert_eq!(stats.started.load(Ordering::SeqCst), 2);
+        let ends = stats.ends.lock().unwrap();
+        assert_eq!(ends.len(), 2);
+        let (ref method, s, ref end) = ends[0];
+        assert_eq!(method, "/helloworld.Greeter/SayHello");
+        assert_eq!(s, *side);
+        assert_eq!(end.status, RpcStatusCode::OK);
+        assert_eq!((end.sent_messages, end.received_messages), (1, 1));
+        let (sent, received) = match side {
+            CallSide::Client => (end.sent_bytes, end.received_bytes),
+            CallSide::Server => (end.received_bytes, end.sent_bytes),
+        };
+        // The reply echoes the name, so it has the same size.
+        assert_eq!((sent, received), (req_len, req_len));
+        assert_eq!(ends[1].2.status, RpcStatusCode::INVALID_ARGUMENT);
+    }
+}
+
+#[test]
+fn test_call_diagnostics() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let config = DiagnosticsConfig::new()
+        .slow_call_threshold(Duration::from_secs(10))
+        .large_message_threshold(100)
+        .log_headers(true);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .enable_call_diagnostics(config)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    for name in &["small", &"large".repeat(100)] {
+        req.set_name(name.to_string());
+        client.say_hello(&req).unwrap();
+    }
+
+    // The call ends on the server once the status is sent.
+    let diagnostics = server.call_diagnostics().unwrap();
+    let timer = Instant::now();
+    let method = loop {
+        match diagnostics.method("/helloworld.Greeter/SayHello") {
+            Some(m) if m.calls == 2 => break m,
+            _ => {
+                assert!(timer.elapsed() < Duration::from_secs(3));
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    assert_eq!(method.slow_calls, 0);
+    assert_eq!(method.latency.count(), 2);
+    assert_eq!(method.request_size.count(), 2);
+    assert_eq!(method.response_size.count(), 2);
+    assert_eq!(method.request_size.sum(), method.response_size.sum());
+    assert_eq!(method.request_size.buckets()[0], (Some(64), 1));
+    assert_eq!(diagnostics.methods().len(), 1);
+
+    let server = ServerBuilder::new(Arc::new(EnvBuilder::new().build()))
+        .build()
+        .unwrap();
+    assert!(server.call_diagnostics().is_none());
+}
+
+#[test]
+fn test_call_tracer() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        started: Mutex<Vec<BatchEvent>>,
+        completed: Mutex<Vec<(BatchEvent, bool)>>,
+    }
+
+    impl CallTracer for Recorder {
+        fn batch_started(&self, event: &BatchEvent) {
+            self.started.lock().unwrap().push(event.clone());
+        }
+
+        fn batch_completed(&self, event: &BatchEvent, success: bool, elapsed: Duration) {
+            assert!(event.started_at() + elapsed <= Instant::now());
+            self.completed
+                .lock()
+                .unwrap()
+                .push((event.clone(), success));
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let recorder = Arc::new(Recorder::default());
+    set_call_tracer(Some(recorder.clone()));
+    let mut req = HelloRequest::default();
+    req.set_name("traced".to_owned());
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), "traced");
+    // The server may report its batches after the client has received the
+    // response.
+    thread::sleep(Duration::from_millis(100));
+    set_call_tracer(None);
+
+    // Other tests may run calls at the same time, so only the ops are checked.
+    let started = recorder.started.lock().unwrap();
+    let completed = recorder.completed.lock().unwrap();
+    for op in &["unary_call", "send_status_from_server"] {
+        let event = started.iter().find(|e| e.op() == *op).unwrap();
+        let (done, success) = completed
+            .iter()
+            .find(|(e, _)| e.tag() == event.tag())
+            .unwrap();
+        assert_eq!(done.op(), *op);
+        assert_eq!(done.call_id(), event.call_id());
+        assert!(*success, "{}", op);
+    }
+}
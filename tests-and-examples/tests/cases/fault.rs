@@ -0,0 +1,75 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+
+#[derive(Clone)]
+struct GreeterService;
+
+impl Greeter for GreeterService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        ctx.spawn(
+            sink.success(HelloReply::default())
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+fn say_hello(
+    env: &Arc<Environment>,
+    port: u16,
+    builder: FaultInjectorBuilder,
+) -> Result<HelloReply> {
+    let injector = builder.build(("127.0.0.1", port)).unwrap();
+    let ch = ChannelBuilder::new(env.clone()).connect(&injector.addr().to_string());
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().timeout(Duration::from_secs(3));
+    client.say_hello_opt(&HelloRequest::default(), opt)
+}
+
+#[test]
+fn test_fault_injection() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+
+    say_hello(&env, port, FaultInjectorBuilder::new()).unwrap();
+
+    let start = Instant::now();
+    let builder = FaultInjectorBuilder::new().settings_delay(Duration::from_millis(300));
+    say_hello(&env, port, builder).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    let builder = FaultInjectorBuilder::new().corrupt_trailers_probability(1.0);
+    match say_hello(&env, port, builder) {
+        Err(Error::RpcFailure(_)) => {}
+        r => panic!("expected failure, got {:?}", r),
+    }
+
+    let builder = FaultInjectorBuilder::new().close_probability(1.0);
+    match say_hello(&env, port, builder) {
+        Err(Error::RpcFailure(_)) => {}
+        r => panic!("expected failure, got {:?}", r),
+    }
+}
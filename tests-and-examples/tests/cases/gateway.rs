@@ -0,0 +1,279 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::*;
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::new();
+    for chunk in data.chunks(3) {
+        let mut acc = 0u32;
+        for (i, b) in chunk.iter().enumerate() {
+            acc |= u32::from(*b) << (16 - 8 * i);
+        }
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(char::from(TABLE[(acc >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+#[test]
+fn test_grpc_web() {
+    use protobuf::Message;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let name = req.get_name().to_owned();
+            if name.is_empty() {
+                let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, Some("no name".to_owned()));
+                ctx.spawn(sink.fail(status).map_err(|_| ()));
+                return;
+            }
+            let mut resp = HelloReply::default();
+            resp.set_message(name);
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    // Send a request and return the head and the dechunked body of the
+    // response.
+    fn request(addr: std::net::SocketAddr, content_type: &str, body: &[u8]) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /helloworld.Greeter/SayHello HTTP/1.1\r\nhost: localhost\r\n\
+             origin: http://example.com\r\ncontent-type: {}\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut resp = vec![];
+        stream.read_to_end(&mut resp).unwrap();
+        let pos = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(resp[..pos].to_vec()).unwrap();
+        let mut rest = &resp[pos + 4..];
+        let mut body = vec![];
+        loop {
+            let pos = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = std::str::from_utf8(&rest[..pos]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&rest[pos + 2..pos + 2 + size]);
+            rest = &rest[pos + 4 + size..];
+        }
+        (head, body)
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let web = GrpcWebServerBuilder::new(ch).bind("127.0.0.1:0").unwrap();
+
+    let mut req = HelloRequest::default();
+    req.set_name("web".to_owned());
+    let body = frame(&req.write_to_bytes().unwrap());
+    let (head, body) = request(web.local_addr(), "application/grpc-web+proto", &body);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(
+        head.contains("access-control-allow-origin: http://example.com"),
+        "{}",
+        head
+    );
+    let mut reply = HelloReply::default();
+    reply.set_message("web".to_owned());
+    let mut expected = frame(&reply.write_to_bytes().unwrap());
+    let trailer = b"grpc-status: 0\r\n";
+    expected.push(0x80);
+    expected.extend_from_slice(&(trailer.len() as u32).to_be_bytes());
+    expected.extend_from_slice(trailer);
+    assert_eq!(body, expected);
+
+    // Text requests are base64 encoded as well as the responses.
+    let body = base64(&frame(&HelloRequest::default().write_to_bytes().unwrap()));
+    let (head, body) = request(
+        web.local_addr(),
+        "application/grpc-web-text",
+        body.as_bytes(),
+    );
+    assert!(
+        head.contains("content-type: application/grpc-web-text"),
+        "{}",
+        head
+    );
+    let body = String::from_utf8(body).unwrap();
+    let trailer = b"grpc-status: 5\r\ngrpc-message: no name\r\n";
+    let mut expected = vec![0x80];
+    expected.extend_from_slice(&(trailer.len() as u32).to_be_bytes());
+    expected.extend_from_slice(trailer);
+    assert_eq!(body, base64(&expected));
+}
+
+#[cfg(feature = "http-json")]
+#[test]
+fn test_http_json_gateway() {
+    use protobuf::Message;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let name = req.get_name().to_owned();
+            if name.is_empty() {
+                let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, Some("no name".to_owned()));
+                ctx.spawn(sink.fail(status).map_err(|_| ()));
+                return;
+            }
+            let mut resp = HelloReply::default();
+            resp.set_message(name);
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    // Encode a length delimited field, all the fields used here are short.
+    fn field(number: u8, data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![number << 3 | 2, data.len() as u8];
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    // Send a request and return the head and the body of the response.
+    fn request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nhost: localhost\r\ncontent-length: {}\r\n\
+             connection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        let pos = resp.find("\r\n\r\n").unwrap();
+        (resp[..pos].to_owned(), resp[pos + 4..].to_owned())
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    // get: "/v1/greeter/{name}"
+    // additional_bindings { post: "/v1/greeter:hello" body: "*" }
+    let mut rule = field(2, b"/v1/greeter/{name}");
+    let mut additional = field(4, b"/v1/greeter:hello");
+    additional.extend(field(7, b"*"));
+    rule.extend(field(11, &additional));
+    let mut file = grpcio_proto::example::helloworld::file_descriptor_proto().clone();
+    file.mut_service()[0].mut_method()[0]
+        .mut_options()
+        .mut_unknown_fields()
+        .add_length_delimited(72_295_728, rule);
+    let gateway = HttpJsonGatewayBuilder::new(ch)
+        .add_file(&file)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let addr = gateway.local_addr();
+
+    let (head, body) = request(addr, "GET", "/v1/greeter/a%20b", "");
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert!(head.contains("content-type: application/json"), "{}", head);
+    assert_eq!(body, r#"{"message":"a b"}"#);
+
+    let (head, body) = request(addr, "POST", "/v1/greeter:hello", r#"{"name": "post"}"#);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(body, r#"{"message":"post"}"#);
+
+    // Failed calls are mapped to HTTP status.
+    let (head, body) = request(addr, "POST", "/v1/greeter:hello", "");
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+    assert_eq!(body, r#"{"code":5,"message":"no name"}"#);
+
+    let (head, _) = request(addr, "POST", "/v1/greeter:hello", r#"{"name": 1}"#);
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+    let (head, _) = request(addr, "DELETE", "/v1/greeter/a", "");
+    assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+    let (head, _) = request(addr, "GET", "/v2/greeter/a", "");
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+
+    // Types used by the annotated methods must be added.
+    file.clear_message_type();
+    let env = Arc::new(EnvBuilder::new().build());
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let res = HttpJsonGatewayBuilder::new(ch)
+        .add_file(&file)
+        .bind("127.0.0.1:0");
+    assert_eq!(res.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
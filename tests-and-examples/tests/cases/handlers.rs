@@ -0,0 +1,451 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_future_handlers() {
+    use grpcio_proto::example::route_guide::{Feature, Rectangle};
+    use grpcio_proto::example::route_guide_grpc::RouteGuideClient;
+
+    const SAY_HELLO: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+    const LIST_FEATURES: Method<Rectangle, Feature> = Method {
+        ty: MethodType::ServerStreaming,
+        name: "/routeguide.RouteGuide/ListFeatures",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    /// A response that never resolves, and records when it's dropped.
+    struct Hang(Arc<AtomicBool>);
+
+    impl Future for Hang {
+        type Item = HelloReply;
+        type Error = RpcStatus;
+
+        fn poll(&mut self) -> Poll<HelloReply, RpcStatus> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    impl Drop for Hang {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    let d = dropped.clone();
+    let service = ServiceBuilder::new()
+        .add_unary_future_handler(
+            &SAY_HELLO,
+            move |_, req: HelloRequest| -> UnaryResponse<HelloReply> {
+                match req.get_name() {
+                    "fail" => Box::new(future::err(RpcStatus::new(
+                        RpcStatusCode::INVALID_ARGUMENT,
+                        Some("bad name".to_owned()),
+                    ))),
+                    "hang" => Box::new(Hang(d.clone())),
+                    name => {
+                        let mut resp = HelloReply::default();
+                        resp.set_message(format!("hello {}", name));
+                        Box::new(future::ok(resp))
+                    }
+                }
+            },
+        )
+        .add_server_streaming_future_handler(
+            &LIST_FEATURES,
+            |_, rect: Rectangle| -> StreamingResponse<Feature> {
+                let features: Vec<_> = (0..3)
+                    .map(|i| {
+                        let mut f = Feature::default();
+                        f.set_name(i.to_string());
+                        f
+                    })
+                    .collect();
+                let s = stream::iter_ok(features);
+                if rect.get_lo().get_latitude() == 0 {
+                    return Box::new(s);
+                }
+                let status = RpcStatus::new(RpcStatusCode::NOT_FOUND, None);
+                Box::new(s.chain(stream::once(Err(status))))
+            },
+        )
+        .build();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    let client = GreeterClient::new(ch.clone());
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "hello world");
+    req.set_name("fail".to_owned());
+    match client.say_hello(&req) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::INVALID_ARGUMENT);
+            assert_eq!(s.details.as_ref().map(String::as_str), Some("bad name"));
+        }
+        r => panic!("call should fail: {:?}", r),
+    }
+    // The response is dropped once the call exceeds its deadline.
+    req.set_name("hang".to_owned());
+    let opt = CallOption::default().timeout(Duration::from_millis(100));
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("call should time out: {:?}", r),
+    }
+    let start = Instant::now();
+    while !dropped.load(Ordering::SeqCst) {
+        assert!(start.elapsed() < Duration::from_secs(3));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let client = RouteGuideClient::new(ch);
+    let features: Vec<_> = client
+        .list_features(&Rectangle::default())
+        .unwrap()
+        .collect()
+        .wait()
+        .unwrap();
+    let names: Vec<_> = features.iter().map(Feature::get_name).collect();
+    assert_eq!(names, ["0", "1", "2"]);
+
+    let mut rect = Rectangle::default();
+    rect.mut_lo().set_latitude(1);
+    let mut received = 0;
+    for res in client.list_features(&rect).unwrap().wait() {
+        match res {
+            Ok(_) => received += 1,
+            Err(Error::RpcFailure(s)) => {
+                assert_eq!(s.status, RpcStatusCode::NOT_FOUND);
+                assert_eq!(received, 3);
+                return;
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+    }
+    panic!("stream should fail");
+}
+
+#[test]
+fn test_raw_handlers() {
+    use grpcio_proto::example::route_guide::{Point, RouteSummary};
+    use grpcio_proto::example::route_guide_grpc::RouteGuideClient;
+    use protobuf::Message;
+
+    const SAY_HELLO: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+    const RECORD_ROUTE: Method<Point, RouteSummary> = Method {
+        ty: MethodType::ClientStreaming,
+        name: "/routeguide.RouteGuide/RecordRoute",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let service = ServiceBuilder::new()
+        .add_unary_raw_handler(
+            &SAY_HELLO,
+            |ctx, req: LazyMessage<HelloRequest>, sink: UnarySink<HelloReply>| {
+                let len = req.len();
+                let f = match req.parse() {
+                    Ok(req) => {
+                        let mut resp = HelloReply::default();
+                        resp.set_message(format!("{} {}", len, req.get_name()));
+                        sink.success(resp)
+                    }
+                    Err(e) => sink.fail(RpcStatus::new(
+                        RpcStatusCode::INVALID_ARGUMENT,
+                        Some(e.to_string()),
+                    )),
+                };
+                ctx.spawn(f.map_err(|e| panic!("failed to reply {:?}", e)));
+            },
+        )
+        .add_client_streaming_raw_handler(
+            &RECORD_ROUTE,
+            |ctx, stream: RequestStream<LazyMessage<Point>>, sink| {
+                // Only the sizes of the points are examined.
+                let f = stream
+                    .fold((0, 0), |(count, bytes), point| {
+                        let bytes = bytes + point.into_bytes()?.len();
+                        Ok::<_, Error>((count + 1, bytes))
+                    })
+                    .and_then(move |(count, bytes)| {
+                        let mut summary = RouteSummary::default();
+                        summary.set_point_count(count);
+                        summary.set_distance(bytes as i32);
+                        sink.success(summary)
+                    })
+                    .map_err(|e| panic!("failed to reply {:?}", e));
+                ctx.spawn(f);
+            },
+        )
+        .build();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    let client = GreeterClient::new(ch.clone());
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "7 world");
+
+    let client = RouteGuideClient::new(ch);
+    let (tx, rx) = client.record_route().unwrap();
+    let mut point = Point::default();
+    point.set_latitude(1);
+    point.set_longitude(2);
+    let points = vec![(point.clone(), WriteFlags::default()); 3];
+    tx.send_all(stream::iter_ok::<_, Error>(points))
+        .and_then(|(mut tx, _)| future::poll_fn(move || tx.close()))
+        .wait()
+        .unwrap();
+    let summary = rx.wait().unwrap();
+    assert_eq!(summary.get_point_count(), 3);
+    assert_eq!(summary.get_distance(), 3 * point.compute_size() as i32);
+}
+
+#[test]
+fn test_fallback() {
+    #[derive(Clone)]
+    struct EmptyService;
+
+    impl Greeter for EmptyService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(sink.success(HelloReply::default()).map_err(|_| ()));
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EmptyService))
+        .set_fallback(
+            |ctx: RpcContext<'_>, reqs: RequestStream<Vec<u8>>, sink: DuplexSink<Vec<u8>>| {
+                let path = ctx.method().to_vec();
+                let resps = reqs.map(move |req| {
+                    let mut resp = path.clone();
+                    resp.push(b' ');
+                    resp.extend_from_slice(&req);
+                    (resp, WriteFlags::default())
+                });
+                let f = sink
+                    .send_all(resps)
+                    .map(|_| ())
+                    .map_err(|e| panic!("failed to echo: {:?}", e));
+                ctx.spawn(f)
+            },
+        )
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+    // Registered methods are not affected.
+    let client = GreeterClient::new(ch.clone());
+    client.say_hello(&HelloRequest::default()).unwrap();
+
+    let client = Client::new(ch);
+    let resp = client
+        .generic_unary_call("/unknown.Service/Echo", b"hello", CallOption::default())
+        .unwrap();
+    assert_eq!(resp, b"/unknown.Service/Echo hello");
+    let resps: Vec<_> = client
+        .generic_server_streaming("/unknown.Service/Echo", b"stream", CallOption::default())
+        .unwrap()
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(resps, [b"/unknown.Service/Echo stream".to_vec()]);
+}
+
+#[test]
+fn test_validate_prefix() {
+    #[derive(Clone)]
+    struct CountingService(Arc<AtomicUsize>);
+
+    impl Greeter for CountingService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let counter = Arc::new(AtomicUsize::new(0));
+    // Names should start with the magic "ok", the first two bytes are the tag
+    // and the length of the field.
+    let service =
+        create_greeter(CountingService(counter.clone())).validate_prefix(&METHOD, 4, |prefix| {
+            if prefix.len() == 4 && &prefix[2..] == b"ok" {
+                Ok(())
+            } else {
+                let msg = format!("bad prefix {:?}", prefix);
+                Err(RpcStatus::new(RpcStatusCode::PERMISSION_DENIED, Some(msg)))
+            }
+        });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name(format!("ok{}", "a".repeat(100)));
+    assert_eq!(
+        client.say_hello(&req).unwrap().get_message(),
+        req.get_name()
+    );
+
+    for (name, prefix) in &[("bad", "[10, 3, 98, 97]"), ("", "[]")] {
+        req.set_name(name.to_string());
+        match client.say_hello(&req) {
+            Err(Error::RpcFailure(s)) => {
+                assert_eq!(s.status, RpcStatusCode::PERMISSION_DENIED);
+                assert_eq!(s.details.unwrap(), format!("bad prefix {}", prefix));
+            }
+            res => panic!("expected failure, got {:?}", res),
+        }
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_dynamic_services() {
+    #[derive(Clone)]
+    struct NamedService(&'static str);
+
+    impl Greeter for NamedService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let mut resp = HelloReply::default();
+            resp.set_message(self.0.to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(NamedService("v1")))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let say_hello = || client.say_hello(&HelloRequest::default());
+    assert_eq!(say_hello().unwrap().get_message(), "v1");
+
+    assert!(!server.remove_service("helloworld.Unknown"));
+    assert!(server.remove_service("helloworld.Greeter"));
+    // Every completion queue should see the change, whichever the call
+    // comes from.
+    for _ in 0..4 {
+        match say_hello() {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::UNIMPLEMENTED),
+            res => panic!("expect unimplemented, but got {:?}", res),
+        }
+    }
+
+    server.add_service(create_greeter(NamedService("v2")));
+    for _ in 0..4 {
+        assert_eq!(say_hello().unwrap().get_message(), "v2");
+    }
+}
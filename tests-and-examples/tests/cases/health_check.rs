@@ -13,6 +13,8 @@
 
 use futures::*;
 use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
 use grpcio_proto::health::v1::health::*;
 use grpcio_proto::health::v1::health_grpc::*;
 use std::collections::*;
@@ -103,3 +105,73 @@ fn test_health_check() {
         e => panic!("unexpected error: {:?}", e),
     }
 }
+
+#[derive(Clone)]
+struct EchoService;
+
+impl Greeter for EchoService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message(req.get_name().to_owned());
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+service_set! {
+    /// Services that are registered together.
+    struct AppServices {
+        health: HealthService as Health => create_health,
+        greeter: EchoService as Greeter => create_greeter,
+    }
+}
+
+#[test]
+fn test_service_set() {
+    let env = Arc::new(Environment::new(1));
+    let services = AppServices {
+        health: HealthService {
+            status: Arc::default(),
+        },
+        greeter: EchoService,
+    };
+    // Fields give typed access to the implementations.
+    let status = services.health.status.clone();
+    let mut server = ServerBuilder::new(env.clone())
+        .register_services(services)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let (_, port) = server.bind_addrs()[0];
+
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    check_health(
+        &HealthClient::new(ch.clone()),
+        &status,
+        "test",
+        HealthCheckResponse_ServingStatus::SERVING,
+    );
+    let mut req = HelloRequest::default();
+    req.set_name("set".to_owned());
+    let resp = GreeterClient::new(ch).say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), "set");
+}
+
+#[test]
+#[should_panic(expected = "registered twice")]
+fn test_service_set_duplicated_method() {
+    let env = Arc::new(Environment::new(1));
+    let status: Arc<RwLock<StatusRegistry>> = Arc::default();
+    let services = AppServices {
+        health: HealthService {
+            status: status.clone(),
+        },
+        greeter: EchoService,
+    };
+    ServerBuilder::new(env)
+        .register_service(create_health(HealthService { status }))
+        .register_services(services);
+}
@@ -0,0 +1,425 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::atomic::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_message_size_check() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let sent2 = sent.clone();
+    let opt = CallOption::default().message_size_check(move |size| {
+        if size > 10 {
+            return Err(RpcStatus::new(RpcStatusCode::RESOURCE_EXHAUSTED, None));
+        }
+        sent2.fetch_add(size, Ordering::SeqCst);
+        Ok(())
+    });
+
+    let mut req = HelloRequest::default();
+    req.set_name("small".to_owned());
+    client.say_hello_opt(&req, opt.clone()).unwrap();
+    // Name is encoded with a tag and a length prefix.
+    assert_eq!(sent.load(Ordering::SeqCst), 7);
+
+    req.set_name("a very large name".to_owned());
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("expected failure, got {:?}", r),
+    }
+    assert_eq!(sent.load(Ordering::SeqCst), 7);
+}
+
+#[test]
+fn test_max_message_len() {
+    #[derive(Clone)]
+    struct RepeatService;
+
+    impl Greeter for RepeatService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().repeat(4));
+            ctx.spawn(sink.success(resp).map_err(|_| {}));
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .max_receive_message_len(1024)
+        .max_send_message_len(1024)
+        .register_service(create_greeter(RepeatService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .max_send_message_len(2048)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let check_exhausted = |name_len: usize, expect: &str| {
+        let mut req = HelloRequest::default();
+        req.set_name("a".repeat(name_len));
+        match client.say_hello(&req) {
+            Err(Error::RpcFailure(s)) => {
+                assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+                let details = s.details.unwrap();
+                assert!(details.contains(expect), "{}", details);
+            }
+            r => panic!("expected resource exhausted, got {:?}", r),
+        }
+    };
+
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(100));
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message().len(), 400);
+
+    // The request is too large for the client to send.
+    check_exhausted(3000, "Sent message larger than max (3003 vs. 2048)");
+    // The request is too large for the server to receive.
+    check_exhausted(1500, "vs. 1024)");
+    // The response is too large for the server to send.
+    check_exhausted(300, "Sent message larger than max (1203 vs. 1024)");
+}
+
+#[test]
+fn test_resource_quota() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let quota = ResourceQuota::new(Some("test_quota"))
+        .resize_memory(4 * 1024 * 1024)
+        .max_threads(16);
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .resource_quota(quota.clone())
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .resource_quota(quota.clone())
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("a".repeat(64 * 1024));
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), req.get_name());
+
+    // The quota can be resized while being used.
+    quota.resize(8 * 1024 * 1024);
+    drop(quota);
+    let resp = client.say_hello(&req).unwrap();
+    assert_eq!(resp.get_message(), req.get_name());
+}
+
+#[test]
+fn test_call_budget() {
+    #[derive(Clone)]
+    struct PendingService(Arc<Mutex<Vec<UnarySink<HelloReply>>>>);
+
+    impl Greeter for PendingService {
+        fn say_hello(&mut self, _: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            self.0.lock().unwrap().push(sink);
+        }
+    }
+
+    let starved = Arc::new(Mutex::new(vec![]));
+    let s = starved.clone();
+    let budget = CallBudget::builder(2)
+        .class("control", 1)
+        .class("data", 1)
+        .on_starvation(move |class, stats| s.lock().unwrap().push((class.to_owned(), *stats)))
+        .build();
+
+    // Servers can share the budget.
+    let env = Arc::new(EnvBuilder::new().build());
+    let pending = Arc::new(Mutex::new(vec![]));
+    let mut servers = vec![];
+    let mut clients = vec![];
+    for class in &["data", "control"] {
+        let service = create_greeter(PendingService(pending.clone())).budget(&budget, class);
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs()[0].1;
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        clients.push(GreeterClient::new(ch));
+        servers.push(server);
+    }
+
+    // Data borrows the share of the idle control class.
+    let data_calls: Vec<_> = (0..2)
+        .map(|_| {
+            clients[0]
+                .say_hello_async(&HelloRequest::default())
+                .unwrap()
+        })
+        .collect();
+    let start = Instant::now();
+    while pending.lock().unwrap().len() < 2 {
+        assert!(start.elapsed() < Duration::from_secs(3));
+        thread::sleep(Duration::from_millis(10));
+    }
+    match clients[0].say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("data should be throttled: {:?}", r),
+    }
+    match clients[1].say_hello(&HelloRequest::default()) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED),
+        r => panic!("control should be starved: {:?}", r),
+    }
+    {
+        let starved = starved.lock().unwrap();
+        assert_eq!(starved.len(), 1);
+        assert_eq!(starved[0].0, "control");
+        assert_eq!(starved[0].1.starved, 1);
+    }
+    let stats = budget.stats("data").unwrap();
+    assert_eq!((stats.in_flight, stats.throttled), (2, 1));
+
+    for sink in pending.lock().unwrap().drain(..) {
+        sink.success(HelloReply::default()).wait().unwrap();
+    }
+    for call in data_calls {
+        call.wait().unwrap();
+    }
+    assert_eq!(budget.stats("data").unwrap().in_flight, 0);
+    // Control gets its share back once data is done.
+    let control_call = clients[1]
+        .say_hello_async(&HelloRequest::default())
+        .unwrap();
+    let start = Instant::now();
+    while pending.lock().unwrap().is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(3));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(budget.stats("control").unwrap().in_flight, 1);
+    let sink = pending.lock().unwrap().pop().unwrap();
+    sink.success(HelloReply::default()).wait().unwrap();
+    control_call.wait().unwrap();
+    assert_eq!(budget.stats("control").unwrap().in_flight, 0);
+}
+
+#[test]
+fn test_max_concurrent_requests() {
+    #[derive(Clone)]
+    struct PendingService(Arc<Mutex<Vec<UnarySink<HelloReply>>>>);
+
+    impl Greeter for PendingService {
+        fn say_hello(&mut self, _: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            self.0.lock().unwrap().push(sink);
+        }
+    }
+
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Unary,
+        name: "/helloworld.Greeter/SayHello",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let env = Arc::new(EnvBuilder::new().build());
+    // The method limit overrides the server limit.
+    for (method_limit, expected) in &[(None, 2), (Some(3), 3)] {
+        let pending = Arc::new(Mutex::new(vec![]));
+        let mut builder = ServerBuilder::new(env.clone())
+            .max_concurrent_requests(2)
+            .register_service(create_greeter(PendingService(pending.clone())))
+            .bind("127.0.0.1", 0);
+        if let Some(limit) = method_limit {
+            builder = builder.method_max_concurrent_requests(&METHOD, *limit);
+        }
+        let mut server = builder.build().unwrap();
+        server.start();
+        let port = server.bind_addrs()[0].1;
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let client = GreeterClient::new(ch);
+
+        let calls: Vec<_> = (0..*expected)
+            .map(|_| client.say_hello_async(&HelloRequest::default()).unwrap())
+            .collect();
+        let start = Instant::now();
+        while pending.lock().unwrap().len() < *expected {
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(10));
+        }
+        match client.say_hello(&HelloRequest::default()) {
+            Err(Error::RpcFailure(s)) => {
+                assert_eq!(s.status, RpcStatusCode::RESOURCE_EXHAUSTED);
+                let details = s.details.unwrap();
+                assert!(details.ends_with(&expected.to_string()), "{}", details);
+            }
+            r => panic!("call should be rejected: {:?}", r),
+        }
+
+        for sink in pending.lock().unwrap().drain(..) {
+            sink.success(HelloReply::default()).wait().unwrap();
+        }
+        for call in calls {
+            call.wait().unwrap();
+        }
+        // Slots are released once the calls finish.
+        let call = client.say_hello_async(&HelloRequest::default()).unwrap();
+        let start = Instant::now();
+        while pending.lock().unwrap().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let sink = pending.lock().unwrap().pop().unwrap();
+        sink.success(HelloReply::default()).wait().unwrap();
+        call.wait().unwrap();
+    }
+}
+
+#[test]
+fn test_request_slots_scaling() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .requests_slot_per_cq(1)
+        .max_requests_slot_per_cq(4)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    for _ in 0..3 {
+        let calls: Vec<_> = (0..16)
+            .map(|i| {
+                let mut req = HelloRequest::default();
+                req.set_name(i.to_string());
+                client.say_hello_async(&req).unwrap()
+            })
+            .collect();
+        for (i, call) in calls.into_iter().enumerate() {
+            assert_eq!(call.wait().unwrap().get_message(), i.to_string());
+        }
+    }
+}
+
+#[test]
+fn test_spawn_limit() {
+    let env = Arc::new(EnvBuilder::new().cq_count(1).spawn_limit(1).build());
+    let ch = ChannelBuilder::new(env.clone()).connect("127.0.0.1:0");
+    let client = Client::new(ch);
+    let cq = &env.completion_queues()[0];
+
+    let (tx, rx) = sync::oneshot::channel::<()>();
+    client.try_spawn(rx.map_err(|_| ())).unwrap();
+    assert_eq!(cq.spawned(), 1);
+    match client.try_spawn(future::ok(())) {
+        Err(Error::Overloaded) => {}
+        r => panic!("expected overloaded, got {:?}", r),
+    }
+    // `spawn` is never rejected.
+    let (tx2, rx2) = mpsc::channel();
+    client.spawn(future::lazy(move || {
+        tx2.send(()).unwrap();
+        Ok(())
+    }));
+    rx2.recv_timeout(Duration::from_secs(3)).unwrap();
+
+    tx.send(()).unwrap();
+    let timer = Instant::now();
+    while cq.spawned() > 0 {
+        assert!(timer.elapsed() < Duration::from_secs(3));
+        thread::sleep(Duration::from_millis(10));
+    }
+    client.try_spawn(future::ok(())).unwrap();
+}
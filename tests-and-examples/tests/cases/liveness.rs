@@ -0,0 +1,345 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Sink, Stream};
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use grpcio_proto::example::route_guide::*;
+use grpcio_proto::example::route_guide_grpc::*;
+
+#[derive(Clone)]
+struct ChatService {
+    tx: Arc<Mutex<Sender<&'static str>>>,
+}
+
+impl RouteGuide for ChatService {
+    fn get_feature(&mut self, _: RpcContext<'_>, _: Point, _: UnarySink<Feature>) {
+        unimplemented!()
+    }
+
+    fn list_features(&mut self, _: RpcContext<'_>, _: Rectangle, _: ServerStreamingSink<Feature>) {
+        unimplemented!()
+    }
+
+    fn record_route(
+        &mut self,
+        _: RpcContext<'_>,
+        _: RequestStream<Point>,
+        _: ClientStreamingSink<RouteSummary>,
+    ) {
+        unimplemented!()
+    }
+
+    fn route_chat(
+        &mut self,
+        ctx: RpcContext<'_>,
+        stream: RequestStream<RouteNote>,
+        sink: DuplexSink<RouteNote>,
+    ) {
+        let (tx1, tx2) = (self.tx.clone(), self.tx.clone());
+        let f = stream
+            .for_each(move |_| {
+                tx1.lock().unwrap().send("message").unwrap();
+                Ok(())
+            })
+            .then(move |_| {
+                // Keep the sink until the stream is aborted.
+                let _sink = sink;
+                tx2.lock().unwrap().send("finished").unwrap();
+                Ok(())
+            });
+        ctx.spawn(f);
+    }
+}
+
+/// Forward data between `from` and `to` until `blackhole` is set, after that
+/// all data is discarded silently, just like the remote host is gone.
+fn forward(mut from: TcpStream, mut to: TcpStream, blackhole: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            let n = match from.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            if !blackhole.load(Ordering::SeqCst) && to.write_all(&buf[..n]).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Start a proxy that forwards one connection to `port`.
+fn start_proxy(port: u16, blackhole: Arc<AtomicBool>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy_port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (client, _) = listener.accept().unwrap();
+        let server = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        forward(
+            client.try_clone().unwrap(),
+            server.try_clone().unwrap(),
+            blackhole.clone(),
+        );
+        forward(server, client, blackhole);
+    });
+    proxy_port
+}
+
+#[test]
+fn test_half_open_connection() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = ChatService {
+        tx: Arc::new(Mutex::new(tx)),
+    };
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_route_guide(service))
+        .stream_liveness(Duration::from_millis(200), Duration::from_millis(200))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let blackhole = Arc::new(AtomicBool::new(false));
+    let proxy_port = start_proxy(port, blackhole.clone());
+
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", proxy_port));
+    let client = RouteGuideClient::new(ch);
+    let (tx, _rx) = client.route_chat().unwrap();
+    let _tx = tx
+        .send((RouteNote::default(), WriteFlags::default()))
+        .wait()
+        .unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(3)), Ok("message"));
+
+    // The stream should be still alive when PING frames are acked.
+    thread::sleep(Duration::from_secs(1));
+    assert!(rx.try_recv().is_err());
+
+    blackhole.store(true, Ordering::SeqCst);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(3)), Ok("finished"));
+}
+
+#[derive(Clone)]
+struct GreeterService;
+
+impl Greeter for GreeterService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+        let mut resp = HelloReply::default();
+        resp.set_message(ctx.peer());
+        ctx.spawn(
+            sink.success(resp)
+                .map_err(|e| panic!("failed to reply {:?}", e)),
+        );
+    }
+}
+
+#[test]
+fn test_keepalive() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .keepalive_permit_without_calls(true)
+        .http2_min_recv_ping_interval_without_data(Duration::from_millis(50))
+        .http2_max_ping_strikes(1)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+
+    let ch = ChannelBuilder::new(env)
+        .keepalive_time(Duration::from_millis(100))
+        .keepalive_timeout(Duration::from_secs(1))
+        .keepalive_permit_without_calls(true)
+        .http2_max_pings_without_data(0)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+    client.say_hello(&HelloRequest::default()).unwrap();
+    // Frequent pings are allowed by the server, so the connection should not
+    // be closed.
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+    client.say_hello(&HelloRequest::default()).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_invalid_keepalive() {
+    let env = Arc::new(EnvBuilder::new().build());
+    ServerBuilder::new(env).keepalive_time(Duration::from_millis(0));
+}
+
+#[test]
+#[should_panic]
+fn test_invalid_stream_liveness() {
+    let env = Arc::new(EnvBuilder::new().build());
+    ServerBuilder::new(env).stream_liveness(Duration::from_secs(1), Duration::from_millis(0));
+}
+
+#[test]
+fn test_max_connection_age() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .max_connection_age(Duration::from_millis(300))
+        .max_connection_age_grace(Duration::from_millis(100))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let peer = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(
+        client
+            .say_hello(&HelloRequest::default())
+            .unwrap()
+            .get_message(),
+        peer.get_message()
+    );
+    // After GOAWAY, new calls should be sent over a new connection.
+    thread::sleep(Duration::from_millis(800));
+    let new_peer = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_ne!(new_peer.get_message(), peer.get_message());
+}
+
+#[test]
+fn test_max_connection_idle() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .max_connection_idle(Duration::from_millis(200))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .idle_timeout(Duration::from_secs(60))
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch.clone());
+
+    let peer = client.say_hello(&HelloRequest::default()).unwrap();
+    thread::sleep(Duration::from_millis(800));
+    // The idle connection should be closed by the server.
+    assert_ne!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+    let new_peer = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_ne!(new_peer.get_message(), peer.get_message());
+}
+
+#[test]
+fn test_wait_for_connected() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(GreeterService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+    assert_eq!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_IDLE
+    );
+    ch.connect();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    assert!(ch.wait_for_connected(deadline).wait().unwrap());
+    assert_eq!(
+        ch.check_connectivity_state(false),
+        ConnectivityState::GRPC_CHANNEL_READY
+    );
+
+    // Nothing is listening on the port once the listener is dropped.
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(300);
+    assert!(!ch.wait_for_connected(deadline).wait().unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(250));
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[derive(Clone)]
+struct StuckService {
+    sinks: Arc<Mutex<Vec<UnarySink<HelloReply>>>>,
+}
+
+impl Greeter for StuckService {
+    fn say_hello(&mut self, ctx: RpcContext<'_>, req: HelloRequest, sink: UnarySink<HelloReply>) {
+        if req.get_name() == "stuck" {
+            // Neither reply nor drop the sink, so the call is never finished.
+            self.sinks.lock().unwrap().push(sink);
+            return;
+        }
+        let mut resp = HelloReply::default();
+        resp.set_message(req.get_name().to_owned());
+        ctx.spawn(sink.success(resp).map_err(|_| ()));
+    }
+}
+
+#[test]
+fn test_batch_watchdog() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let service = StuckService {
+        sinks: Arc::default(),
+    };
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(service.clone()))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let opt = CallOption::default().batch_watchdog(Duration::from_millis(300));
+
+    let mut req = HelloRequest::default();
+    req.set_name("fine".to_owned());
+    let resp = client.say_hello_opt(&req, opt.clone()).unwrap();
+    assert_eq!(resp.get_message(), "fine");
+
+    req.set_name("stuck".to_owned());
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => {
+            assert_eq!(s.status, RpcStatusCode::ABORTED);
+            assert!(s.details.unwrap().contains("unary_call"));
+        }
+        res => panic!("expected failure, got {:?}", res),
+    }
+    assert_eq!(service.sinks.lock().unwrap().len(), 1);
+}
@@ -29,21 +29,47 @@ impl Greeter for GreeterService {
         &mut self,
         ctx: RpcContext<'_>,
         mut req: HelloRequest,
-        sink: UnarySink<HelloReply>,
+        mut sink: UnarySink<HelloReply>,
     ) {
         for (key, value) in ctx.request_headers() {
             self.tx.send((key.to_owned(), value.to_owned())).unwrap();
         }
+        if let Some(trace) = ctx.trace_context() {
+            let value = trace.to_traceparent().into_bytes();
+            self.tx.send(("trace-context".to_owned(), value)).unwrap();
+        }
+
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("header", "h1").unwrap();
+        ctx.send_initial_metadata(builder.build()).unwrap();
+        // Initial metadata can only be sent once.
+        assert!(ctx
+            .send_initial_metadata(MetadataBuilder::new().build())
+            .is_err());
+
+        let mut builder = MetadataBuilder::new();
+        builder.add_str("trailer", "t1").unwrap();
+        sink.set_trailers(builder.build());
 
-        let mut resp = HelloReply::default();
-        resp.set_message(format!("hello {}", req.take_name()));
-        ctx.spawn(
+        let name = req.take_name();
+        let f = if name == "fail" {
+            sink.fail(RpcStatus::new(RpcStatusCode::INVALID_ARGUMENT, None))
+        } else {
+            let mut resp = HelloReply::default();
+            resp.set_message(format!("hello {}", name));
             sink.success(resp)
-                .map_err(|e| panic!("failed to reply {:?}", e)),
-        );
+        };
+        ctx.spawn(f.map_err(|e| panic!("failed to reply {:?}", e)));
     }
 }
 
+fn check_response_metadata<T>(receiver: &ClientUnaryReceiver<T>) {
+    let headers = receiver.headers().unwrap();
+    assert_eq!(headers.get(0), Some(("header", b"h1".as_ref())));
+    let trailers = receiver.trailers().unwrap();
+    assert_eq!(trailers.get(0), Some(("trailer", b"t1".as_ref())));
+}
+
 // TODO: test it in interop tests once trailer is supported.
 #[test]
 fn test_metadata() {
@@ -79,3 +105,145 @@ fn test_metadata() {
     let metadata = rx.recv_timeout(Duration::from_secs(1)).unwrap();
     assert_eq!(metadata, ("k1-bin".to_owned(), vec![0x00, 0x01, 0x02]));
 }
+
+#[test]
+fn test_response_metadata() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, _rx) = mpsc::channel();
+    let service = create_greeter(GreeterService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    let mut receiver = client.say_hello_async(&req).unwrap();
+    let resp = (&mut receiver).wait().unwrap();
+    assert_eq!(resp.get_message(), "hello world");
+    check_response_metadata(&receiver);
+
+    req.set_name("fail".to_owned());
+    let mut receiver = client.say_hello_async(&req).unwrap();
+    match (&mut receiver).wait() {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::INVALID_ARGUMENT),
+        res => panic!("expected failure, got {:?}", res),
+    }
+    check_response_metadata(&receiver);
+}
+
+#[test]
+fn test_trace_context() {
+    let env = Arc::new(EnvBuilder::new().build());
+    let (tx, rx) = mpsc::channel();
+    let service = create_greeter(GreeterService { tx });
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let trace = TraceContext::new_root(true);
+    // Stale trace headers should be replaced.
+    let mut builder = MetadataBuilder::new();
+    builder
+        .add_str("traceparent", &trace.child().to_traceparent())
+        .unwrap();
+    let call_opt = CallOption::default()
+        .headers(builder.build())
+        .trace_context(trace);
+
+    let mut req = HelloRequest::default();
+    req.set_name("world".to_owned());
+    client.say_hello_opt(&req, call_opt).unwrap();
+
+    let mut headers = vec![];
+    while let Ok(header) = rx.recv_timeout(Duration::from_secs(1)) {
+        headers.push(header);
+    }
+    let values = |key: &str| -> Vec<Vec<u8>> {
+        headers
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
+    };
+    let traceparent = trace.to_traceparent().into_bytes();
+    assert_eq!(values("traceparent"), vec![traceparent.clone()]);
+    assert_eq!(values("grpc-trace-bin"), vec![trace.to_grpc_trace_bin()]);
+    assert_eq!(values("trace-context"), vec![traceparent]);
+
+    // Only the binary format is sent by some implementations.
+    let child = trace.child();
+    let mut builder = MetadataBuilder::new();
+    builder
+        .add_bytes("grpc-trace-bin", &child.to_grpc_trace_bin())
+        .unwrap();
+    let call_opt = CallOption::default().headers(builder.build());
+    client.say_hello_opt(&req, call_opt).unwrap();
+    let trace_context = loop {
+        let (k, v) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        if k == "trace-context" {
+            break v;
+        }
+    };
+    assert_eq!(trace_context, child.to_traceparent().into_bytes());
+}
+
+#[test]
+fn test_load_report() {
+    #[derive(Clone)]
+    struct LoadService;
+
+    impl Greeter for LoadService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut report = LoadReport::default();
+            report.cpu_utilization = 0.5;
+            report.named_metrics.insert("queue".to_owned(), 3.0);
+            ctx.set_load_report(&report);
+            let f = if req.get_name() == "fail" {
+                sink.fail(RpcStatus::new(RpcStatusCode::INTERNAL, None))
+            } else {
+                sink.success(HelloReply::default())
+            };
+            ctx.spawn(f.map_err(|e| panic!("failed to reply {:?}", e)));
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(LoadService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let mut req = HelloRequest::default();
+    for name in &["ok", "fail"] {
+        req.set_name(name.to_string());
+        let mut receiver = client.say_hello_async(&req).unwrap();
+        let _ = (&mut receiver).wait();
+        let report = LoadReport::from_trailers(&receiver.trailers().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.cpu_utilization, 0.5);
+        assert_eq!(report.named_metrics["queue"], 3.0);
+    }
+}
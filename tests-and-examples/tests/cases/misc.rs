@@ -28,6 +28,9 @@ fn test_peer() {
     impl Greeter for PeerService {
         fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
             let peer = ctx.peer();
+            let addr = ctx.peer_addr().unwrap();
+            assert!(addr.ip().is_loopback(), "{}", addr);
+            assert!(peer.ends_with(&addr.port().to_string()), "{}", peer);
             let mut resp = HelloReply::default();
             resp.set_message(peer);
             ctx.spawn(
@@ -141,3 +144,297 @@ fn test_soundness() {
     }
     assert_eq!(counter.load(Ordering::SeqCst), 9000);
 }
+
+#[test]
+fn test_join_tasks() {
+    #[derive(Clone)]
+    struct HangService;
+
+    impl Greeter for HangService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            if req.get_name() == "hang" {
+                // Never reply.
+                ctx.spawn(future::empty().map(move |()| drop(sink)));
+                return;
+            }
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(HangService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(server.outstanding_tasks(), 0);
+
+    let mut req = HelloRequest::default();
+    req.set_name("hang".to_owned());
+    let resp = client.say_hello_async(&req).unwrap();
+    for _ in 0..100 {
+        if server.outstanding_tasks() == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let shutdown = server.shutdown();
+    assert_eq!(shutdown.outstanding_tasks(), 1);
+    let cancelled = shutdown
+        .join_tasks(Duration::from_millis(100))
+        .wait()
+        .unwrap();
+    assert_eq!(cancelled, 1);
+    assert_eq!(server.outstanding_tasks(), 0);
+    assert!(resp.wait().is_err());
+}
+
+#[test]
+fn test_cancelled() {
+    #[derive(Clone)]
+    struct SlowService {
+        tx: Arc<Mutex<mpsc::Sender<(bool, Result<()>)>>>,
+        sinks: Arc<Mutex<Vec<UnarySink<HelloReply>>>>,
+    }
+
+    impl Greeter for SlowService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let tx = self.tx.lock().unwrap().clone();
+            let cancelled = ctx.cancelled();
+            let checked = ctx.is_cancelled();
+            ctx.spawn(cancelled.then(move |res| {
+                tx.send((checked, res)).unwrap();
+                Ok(())
+            }));
+            if req.get_name() == "fast" {
+                ctx.spawn(sink.success(HelloReply::default()).map_err(|_| ()));
+            } else {
+                self.sinks.lock().unwrap().push(sink);
+            }
+        }
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let service = SlowService {
+        tx: Arc::new(Mutex::new(tx)),
+        sinks: Arc::default(),
+    };
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(service))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    // The future fails if the call finishes normally.
+    let mut req = HelloRequest::default();
+    req.set_name("fast".to_owned());
+    client.say_hello(&req).unwrap();
+    match rx.recv_timeout(Duration::from_secs(3)).unwrap() {
+        (false, Err(Error::RpcFinished(None))) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    req.set_name("slow".to_owned());
+    let opt = CallOption::default().timeout(Duration::from_millis(100));
+    match client.say_hello_opt(&req, opt) {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        r => panic!("call should time out: {:?}", r),
+    }
+    match rx.recv_timeout(Duration::from_secs(3)).unwrap() {
+        (false, Ok(())) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_remaining_time() {
+    #[derive(Clone)]
+    struct DeadlineService(Arc<Mutex<mpsc::Sender<Option<Duration>>>>);
+
+    impl Greeter for DeadlineService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            let remaining = ctx.remaining_time();
+            assert_eq!(remaining.is_some(), ctx.deadline().system_time().is_some());
+            self.0.lock().unwrap().send(remaining).unwrap();
+            ctx.spawn(sink.success(HelloReply::default()).map_err(|_| ()));
+        }
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(DeadlineService(Arc::new(Mutex::new(tx)))))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(rx.recv().unwrap(), None);
+
+    let opt = CallOption::default().timeout(Duration::from_secs(10));
+    client.say_hello_opt(&HelloRequest::default(), opt).unwrap();
+    let remaining = rx.recv().unwrap().unwrap();
+    assert!(remaining <= Duration::from_secs(10), "{:?}", remaining);
+    assert!(remaining > Duration::from_secs(5), "{:?}", remaining);
+}
+
+#[test]
+fn test_sleep_in_handler() {
+    #[derive(Clone)]
+    struct SleepService;
+
+    impl Greeter for SleepService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            // Reply after three ticks without blocking the poll thread.
+            let f = grpcio::interval(Duration::from_millis(10))
+                .take(3)
+                .fold(0, |n, _| Ok::<_, ()>(n + 1))
+                .join(grpcio::sleep(Duration::from_millis(50)))
+                .then(move |r| {
+                    let (ticks, _) = r.unwrap();
+                    let mut resp = HelloReply::default();
+                    resp.set_message(format!("{}", ticks));
+                    sink.success(resp).map_err(|_| ())
+                });
+            ctx.spawn(f);
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(SleepService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+
+    let start = Instant::now();
+    let reply = client.say_hello(&HelloRequest::default()).unwrap();
+    assert_eq!(reply.get_message(), "3");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn test_env_shutdown() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let client_env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let ch = ChannelBuilder::new(client_env.clone()).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    client.say_hello(&HelloRequest::default()).unwrap();
+
+    client_env.shutdown();
+    assert!(client_env.is_shutdown());
+    match client.say_hello(&HelloRequest::default()) {
+        Err(Error::QueueShutdown) => {}
+        r => panic!("expected queue shutdown, got {:?}", r),
+    }
+    match client.say_hello_async(&HelloRequest::default()) {
+        Err(Error::QueueShutdown) => {}
+        r => panic!("expected queue shutdown, got {:?}", r.map(|_| ())),
+    }
+
+    // Servers keep their queues until they are shutdown.
+    env.shutdown();
+    assert!(ServerBuilder::new(env.clone()).build().is_err());
+    server.shutdown().wait().unwrap();
+}
+
+#[test]
+fn test_fork_handlers() {
+    #[derive(Clone)]
+    struct EchoService;
+
+    impl Greeter for EchoService {
+        fn say_hello(
+            &mut self,
+            ctx: RpcContext<'_>,
+            req: HelloRequest,
+            sink: UnarySink<HelloReply>,
+        ) {
+            let mut resp = HelloReply::default();
+            resp.set_message(req.get_name().to_owned());
+            ctx.spawn(
+                sink.success(resp)
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let env = Arc::new(EnvBuilder::new().cq_count(2).build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EchoService))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let client_env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let ch = ChannelBuilder::new(client_env).connect(&format!("127.0.0.1:{}", port));
+    let client = GreeterClient::new(ch);
+    let mut req = HelloRequest::default();
+    req.set_name("before".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "before");
+
+    // Calls are not handled while the server is prepared for fork.
+    env.prepare_fork().unwrap();
+    req.set_name("during".to_owned());
+    let f = client
+        .say_hello_async_opt(&req, CallOption::default().timeout(Duration::from_secs(5)))
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    env.post_fork_parent();
+    assert_eq!(f.wait().unwrap().get_message(), "during");
+
+    req.set_name("after".to_owned());
+    assert_eq!(client.say_hello(&req).unwrap().get_message(), "after");
+}
@@ -11,8 +11,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod cache;
 mod cancel;
+mod channel;
+mod client;
+mod compression;
+mod credentials;
+mod diagnostics;
+mod fault;
+mod gateway;
+mod handlers;
 mod health_check;
 mod kick;
+mod limits;
+mod liveness;
 mod metadata;
 mod misc;
+mod proxy;
+mod server;
+mod streaming;
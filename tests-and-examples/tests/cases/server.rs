@@ -0,0 +1,290 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use grpcio_proto::example::helloworld_grpc::*;
+use std::sync::*;
+use std::time::*;
+
+#[test]
+fn test_listen_addrs() {
+    #[derive(Clone)]
+    struct EmptyService;
+
+    impl Greeter for EmptyService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("grpcio-test-{}.sock", std::process::id()));
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EmptyService))
+        .bind("127.0.0.1", 0)
+        .bind_unix(&path)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    assert_ne!(port, 0);
+    assert_eq!(server.bind_addrs()[1].1, 0);
+    assert_eq!(
+        server.listen_addrs(),
+        &[
+            ListenAddr::Tcp(format!("127.0.0.1:{}", port).parse().unwrap()),
+            ListenAddr::Unix(path.clone()),
+        ]
+    );
+
+    for target in &[
+        format!("127.0.0.1:{}", port),
+        format!("unix:{}", path.display()),
+    ] {
+        let ch = ChannelBuilder::new(env.clone()).connect(target);
+        let client = GreeterClient::new(ch);
+        client.say_hello(&HelloRequest::default()).unwrap();
+    }
+    drop(server);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bind_insecure_listener() {
+    #[derive(Clone)]
+    struct EmptyService;
+
+    impl Greeter for EmptyService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(create_greeter(EmptyService))
+        .bind_insecure_listener(listener)
+        .build()
+        .unwrap();
+    assert_eq!(server.listen_addrs(), &[ListenAddr::Tcp(addr)]);
+    assert_eq!(
+        server.bind_addrs(),
+        &[("127.0.0.1".to_owned(), addr.port())]
+    );
+    server.start();
+
+    let ch = ChannelBuilder::new(env.clone()).connect(&addr.to_string());
+    let client = GreeterClient::new(ch);
+    for _ in 0..3 {
+        client.say_hello(&HelloRequest::default()).unwrap();
+    }
+    let ch = ChannelBuilder::new(env).connect(&addr.to_string());
+    GreeterClient::new(ch)
+        .say_hello(&HelloRequest::default())
+        .unwrap();
+    server.shutdown().wait().unwrap();
+}
+
+#[test]
+fn test_socket_options() {
+    #[derive(Clone)]
+    struct EmptyService;
+
+    impl Greeter for EmptyService {
+        fn say_hello(&mut self, ctx: RpcContext<'_>, _: HelloRequest, sink: UnarySink<HelloReply>) {
+            ctx.spawn(
+                sink.success(HelloReply::default())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        }
+    }
+
+    let build_server = |env: Arc<Environment>, port: u16, path: &std::path::Path| {
+        ServerBuilder::new(env)
+            .register_service(create_greeter(EmptyService))
+            .reuse_port(true)
+            .tcp_nodelay(false)
+            .tcp_user_timeout(Duration::from_secs(10))
+            .recv_buffer_size(64 * 1024)
+            .send_buffer_size(64 * 1024)
+            .bind("127.0.0.1", port)
+            .bind_unix(path)
+            .build()
+            .unwrap()
+    };
+    let path = std::env::temp_dir().join(format!("grpcio-opts-{}.sock", std::process::id()));
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = build_server(env.clone(), 0, &path);
+    server.start();
+    let port = server.bind_addrs()[0].1;
+
+    // Another server can share the port with `SO_REUSEPORT`.
+    #[cfg(target_os = "linux")]
+    {
+        let path2 = path.with_extension("2.sock");
+        let mut server2 = build_server(env.clone(), port, &path2);
+        server2.start();
+        assert_eq!(server2.bind_addrs()[0].1, port);
+        drop(server2);
+        let _ = std::fs::remove_file(&path2);
+    }
+
+    for target in &[
+        format!("127.0.0.1:{}", port),
+        format!("unix:{}", path.display()),
+    ] {
+        let ch = ChannelBuilder::new(env.clone())
+            .tcp_nodelay(false)
+            .tcp_user_timeout(Duration::from_secs(10))
+            .recv_buffer_size(64 * 1024)
+            .send_buffer_size(64 * 1024)
+            .connect(target);
+        let client = GreeterClient::new(ch);
+        client.say_hello(&HelloRequest::default()).unwrap();
+    }
+    drop(server);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_file_descriptor_set() {
+    use protobuf::descriptor::{FileDescriptorProto, FileDescriptorSet};
+    use protobuf::Message;
+
+    let mut base = FileDescriptorProto::default();
+    base.set_name("base.proto".to_owned());
+    let base = base.write_to_bytes().unwrap();
+    let mut api = FileDescriptorProto::default();
+    api.set_name("api.proto".to_owned());
+    api.mut_dependency().push("base.proto".to_owned());
+    let api = api.write_to_bytes().unwrap();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let api_service = ServiceBuilder::new()
+        .add_file_descriptor(&base)
+        .add_file_descriptor(&api)
+        .build();
+    let base_service = ServiceBuilder::new().add_file_descriptor(&base).build();
+    let server = ServerBuilder::new(env)
+        .register_service(api_service)
+        .register_service(base_service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+
+    let bytes = server.file_descriptor_set();
+    let set = FileDescriptorSet::parse_from_bytes(&bytes).unwrap();
+    let names: Vec<_> = set.get_file().iter().map(|f| f.get_name()).collect();
+    assert_eq!(names, vec!["base.proto", "api.proto"]);
+    assert_eq!(
+        set.get_file()[1].get_dependency(),
+        &["base.proto".to_owned()]
+    );
+
+    let path = std::env::temp_dir().join(format!("grpc-fds-{}.bin", server.bind_addrs()[0].1));
+    server.write_file_descriptor_set(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_admin_services() {
+    use protobuf::descriptor::{FileDescriptorProto, ServiceDescriptorProto};
+    use protobuf::Message;
+
+    const RAW: Marshaller<Vec<u8>> = Marshaller {
+        ser: raw_ser,
+        de: raw_de,
+    };
+    const CHECK: Method<Vec<u8>, Vec<u8>> = Method {
+        ty: MethodType::Unary,
+        name: "/grpc.health.v1.Health/Check",
+        req_mar: RAW,
+        resp_mar: RAW,
+    };
+    const REFLECTION: Method<Vec<u8>, Vec<u8>> = Method {
+        ty: MethodType::Duplex,
+        name: "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+        req_mar: RAW,
+        resp_mar: RAW,
+    };
+
+    let mut file = FileDescriptorProto::default();
+    file.set_name("helloworld.proto".to_owned());
+    file.set_package("helloworld".to_owned());
+    let mut service = ServiceDescriptorProto::default();
+    service.set_name("Greeter".to_owned());
+    file.mut_service().push(service);
+    let file = file.write_to_bytes().unwrap();
+
+    let env = Arc::new(EnvBuilder::new().build());
+    let health = HealthService::new();
+    let greeter = ServiceBuilder::new().add_file_descriptor(&file).build();
+    let mut server = ServerBuilder::new(env.clone())
+        .add_admin_services(&health)
+        .register_service(greeter)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    // The admin services can also be served by another server.
+    let mut admin_server = ServerBuilder::new(env.clone())
+        .register_service(server.admin_services(&health))
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    admin_server.start();
+
+    for port in &[server.bind_addrs()[0].1, admin_server.bind_addrs()[0].1] {
+        let ch = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let client = Client::new(ch);
+
+        // An empty request checks the server as a whole.
+        let resp = client.unary_call(&CHECK, &vec![], CallOption::default());
+        assert_eq!(resp.unwrap(), vec![0x08, 1]);
+        match client.unary_call(&CHECK, &vec![0x0a, 1, b'x'], CallOption::default()) {
+            Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::NOT_FOUND),
+            res => panic!("expected not found, got {:?}", res),
+        }
+
+        // A `list_services` request.
+        let (sink, receiver) = client
+            .duplex_streaming(&REFLECTION, CallOption::default())
+            .unwrap();
+        let req = vec![0x3a, 0];
+        let reqs = stream::iter_ok::<_, Error>(vec![(req, WriteFlags::default())]);
+        let _sink = sink.send_all(reqs).wait().unwrap();
+        let resps: Vec<Vec<u8>> = receiver.collect().wait().unwrap();
+        assert_eq!(resps.len(), 1);
+        // It starts with the original request.
+        assert_eq!(&resps[0][..4], &[0x12, 2, 0x3a, 0]);
+        let name = b"helloworld.Greeter";
+        assert!(resps[0].windows(name.len()).any(|w| w == name));
+    }
+
+    health.set_serving_status("", ServingStatus::NotServing);
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", server.bind_addrs()[0].1));
+    let resp = Client::new(ch).unary_call(&CHECK, &vec![], CallOption::default());
+    assert_eq!(resp.unwrap(), vec![0x08, 2]);
+}
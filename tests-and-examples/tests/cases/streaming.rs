@@ -0,0 +1,332 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::*;
+use grpcio::*;
+use grpcio_proto::example::helloworld::*;
+use std::sync::*;
+use std::thread;
+use std::time::*;
+
+#[test]
+fn test_cork_streaming_sink() {
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Duplex,
+        name: "/test.Corked/Echo",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let service = ServiceBuilder::new()
+        .add_duplex_streaming_handler(&METHOD, |ctx, stream, mut sink| {
+            sink.cork();
+            let replies = stream.map(|req| {
+                let mut resp = HelloReply::default();
+                resp.set_message(req.get_name().to_owned());
+                (resp, WriteFlags::default())
+            });
+            ctx.spawn(
+                sink.send_all(replies)
+                    .map(|_| ())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        })
+        .build();
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let (mut sink, receiver) = client
+        .duplex_streaming(&METHOD, CallOption::default())
+        .unwrap();
+    sink.cork();
+    let names: Vec<String> = (0..10).map(|i| format!("msg-{}", i)).collect();
+    let reqs = names.iter().map(|name| {
+        let mut req = HelloRequest::default();
+        req.set_name(name.clone());
+        (req, WriteFlags::default())
+    });
+    // Closing the sink flushes the messages, including the held last one.
+    let _sink = sink
+        .send_all(stream::iter_ok::<_, Error>(reqs))
+        .wait()
+        .unwrap();
+    let replies: Vec<String> = receiver
+        .map(|mut resp| resp.take_message())
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(replies, names);
+}
+
+#[test]
+fn test_streaming_combinators() {
+    const ECHO: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::Duplex,
+        name: "/test.Combinators/Echo",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+    const REPEAT: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::ServerStreaming,
+        name: "/test.Combinators/Repeat",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let reply = |name: &str| {
+        let mut resp = HelloReply::default();
+        resp.set_message(name.to_owned());
+        (resp, WriteFlags::default())
+    };
+    let service = ServiceBuilder::new()
+        .add_duplex_streaming_handler(&ECHO, move |ctx, stream, sink| {
+            ctx.spawn(
+                stream
+                    .map(move |req| reply(req.get_name()))
+                    .forward(sink)
+                    .map(|_| ())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        })
+        .add_server_streaming_handler(&REPEAT, move |ctx, req, sink| {
+            let replies = stream::repeat::<_, Error>(reply(req.get_name())).take(3);
+            ctx.spawn(
+                replies
+                    .forward(sink)
+                    .map(|_| ())
+                    .map_err(|e| panic!("failed to reply {:?}", e)),
+            );
+        })
+        .build();
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    // Requests are produced by another thread through a bounded channel.
+    let (tx, rx) = futures::sync::mpsc::channel(1);
+    let producer = thread::spawn(move || {
+        let mut tx = tx;
+        for i in 0..10 {
+            let mut req = HelloRequest::default();
+            req.set_name(format!("echo-{}", i));
+            tx = tx.send(req).wait().unwrap();
+        }
+    });
+    let (sink, echo) = client
+        .duplex_streaming(&ECHO, CallOption::default())
+        .unwrap();
+    let forward = rx
+        .map(|req| (req, WriteFlags::default()))
+        .map_err(|_| Error::RemoteStopped)
+        .forward(sink);
+
+    let mut req = HelloRequest::default();
+    req.set_name("repeat".to_owned());
+    let repeat = client
+        .server_streaming(&REPEAT, &req, CallOption::default())
+        .unwrap();
+    let replies = echo.select(repeat).map(|mut resp| resp.take_message());
+    let (_, mut replies) = forward.join(replies.collect()).wait().unwrap();
+    producer.join().unwrap();
+
+    replies.sort();
+    let mut expected: Vec<String> = (0..10).map(|i| format!("echo-{}", i)).collect();
+    expected.extend(vec!["repeat".to_owned(); 3]);
+    expected.sort();
+    assert_eq!(replies, expected);
+}
+
+#[test]
+fn test_send_with_timeout() {
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::ServerStreaming,
+        name: "/test.Stalled/Stream",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(&METHOD, move |ctx, _, sink| {
+            let mut resp = HelloReply::default();
+            resp.set_message("a".repeat(100 * 1024));
+            let tx = Mutex::new(tx.clone());
+            let f = stream::iter_ok::<_, Error>(0..20)
+                .fold(sink, move |sink, _| {
+                    let item = (resp.clone(), WriteFlags::default());
+                    sink.send_with_timeout(item, Duration::from_millis(200))
+                })
+                .then(move |res| {
+                    tx.lock().unwrap().send(res.map(|_| ())).unwrap();
+                    Ok(())
+                });
+            ctx.spawn(f);
+        })
+        .build();
+    let env = Arc::new(EnvBuilder::new().build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env)
+        .stream_initial_window_size(64 * 1024)
+        .http2_bdp_probe(false)
+        .connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    // The client never reads, so the first message can't be written.
+    let receiver = client
+        .server_streaming(&METHOD, &HelloRequest::default(), CallOption::default())
+        .unwrap();
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        Err(Error::RpcFailure(s)) => assert_eq!(s.status, RpcStatusCode::DEADLINE_EXCEEDED),
+        res => panic!("expect deadline exceeded, but got {:?}", res),
+    }
+    assert!(receiver.collect().wait().is_err());
+}
+
+#[test]
+fn test_bounded_queue() {
+    const METHOD: Method<HelloRequest, HelloReply> = Method {
+        ty: MethodType::ServerStreaming,
+        name: "/test.Feed/Subscribe",
+        req_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+        resp_mar: Marshaller {
+            ser: pb_ser,
+            de: pb_de,
+        },
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let service = ServiceBuilder::new()
+        .add_server_streaming_handler(&METHOD, move |ctx, req, mut sink| {
+            let policy = match req.get_name() {
+                "block" => OverflowPolicy::Block,
+                "error" => OverflowPolicy::Error,
+                _ => OverflowPolicy::DropOldest,
+            };
+            sink.bounded_queue(3, policy);
+            let tx = Mutex::new(tx.clone());
+            // The first write can't finish before the future yields, as
+            // there is only one polling thread.
+            let f = future::lazy(move || {
+                let mut outcomes = vec![];
+                for i in 0..10 {
+                    let mut resp = HelloReply::default();
+                    resp.set_message(i.to_string());
+                    outcomes.push(match sink.start_send((resp, WriteFlags::default())) {
+                        Ok(AsyncSink::Ready) => "ready",
+                        Ok(AsyncSink::NotReady(_)) => "not_ready",
+                        Err(Error::RpcFailure(ref s))
+                            if s.status == RpcStatusCode::RESOURCE_EXHAUSTED =>
+                        {
+                            "full"
+                        }
+                        Err(e) => panic!("unexpected error {:?}", e),
+                    });
+                }
+                let queued = sink.queued_len();
+                tx.lock().unwrap().send((outcomes, queued)).unwrap();
+                future::poll_fn(move || sink.close())
+            })
+            .map_err(|e| panic!("failed to close {:?}", e));
+            ctx.spawn(f);
+        })
+        .build();
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let mut server = ServerBuilder::new(env.clone())
+        .register_service(service)
+        .bind("127.0.0.1", 0)
+        .build()
+        .unwrap();
+    server.start();
+    let port = server.bind_addrs()[0].1;
+    let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+    let client = Client::new(ch);
+
+    let cases = vec![
+        ("block", "not_ready", vec!["0", "1", "2", "3"]),
+        ("error", "full", vec!["0", "1", "2", "3"]),
+        ("drop_oldest", "ready", vec!["0", "7", "8", "9"]),
+    ];
+    for (policy, overflow, expected) in cases {
+        let mut req = HelloRequest::default();
+        req.set_name(policy.to_owned());
+        let replies = client
+            .server_streaming(&METHOD, &req, CallOption::default())
+            .unwrap()
+            .collect()
+            .wait()
+            .unwrap();
+        let messages: Vec<_> = replies.iter().map(HelloReply::get_message).collect();
+        assert_eq!(messages, expected, "{}", policy);
+
+        let (outcomes, queued) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(queued, 3, "{}", policy);
+        assert!(
+            outcomes[..4].iter().all(|o| *o == "ready"),
+            "{:?}",
+            outcomes
+        );
+        assert!(
+            outcomes[4..].iter().all(|o| *o == overflow),
+            "{:?}",
+            outcomes
+        );
+    }
+}
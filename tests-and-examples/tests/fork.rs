@@ -0,0 +1,76 @@
+// Copyright 2019 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Forking is only sound when no other thread is using gRPC, so the test is
+// kept in its own binary instead of running along with the other cases.
+
+#![cfg(unix)]
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::sync::oneshot;
+use futures::Future;
+use grpcio::{sleep, ChannelBuilder, Client, EnvBuilder};
+
+/// Check that both the timer thread and the polling thread work.
+fn check_env(client: &Client) -> bool {
+    let (tx, rx) = oneshot::channel();
+    client.spawn(sleep(Duration::from_millis(10)).then(move |_| {
+        let _ = tx.send(());
+        Ok(())
+    }));
+    let timeout = sleep(Duration::from_secs(5)).map(|_| false);
+    rx.map(|_| true)
+        .map_err(|_| ())
+        .select(timeout)
+        .wait()
+        .map_or(false, |(ok, _)| ok)
+}
+
+fn wait_child(pid: libc::pid_t) -> Option<i32> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        let mut status = 0;
+        let res = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+        if res == pid {
+            return Some(libc::WEXITSTATUS(status));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+    None
+}
+
+#[test]
+fn test_fork() {
+    // It's read when gRPC is initialized.
+    std::env::set_var("GRPC_ENABLE_FORK_SUPPORT", "1");
+    let env = Arc::new(EnvBuilder::new().cq_count(1).build());
+    let ch = ChannelBuilder::new(env.clone()).connect("127.0.0.1:1");
+    let client = Client::new(ch);
+    assert!(check_env(&client));
+
+    env.prepare_fork().unwrap();
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+    if pid == 0 {
+        env.post_fork_child();
+        let code = if check_env(&client) { 0 } else { 1 };
+        unsafe { libc::_exit(code) };
+    }
+    env.post_fork_parent();
+    assert!(check_env(&client));
+    assert_eq!(wait_child(pid), Some(0));
+}